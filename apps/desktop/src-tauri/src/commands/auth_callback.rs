@@ -199,7 +199,9 @@ fn percent_decode(value: &str) -> String {
                 index += 1;
             }
             b'%' if index + 2 < bytes.len() => {
-                if let (Some(high), Some(low)) = (from_hex(bytes[index + 1]), from_hex(bytes[index + 2])) {
+                if let (Some(high), Some(low)) =
+                    (from_hex(bytes[index + 1]), from_hex(bytes[index + 2]))
+                {
                     output.push((high << 4) | low);
                     index += 3;
                 } else {
@@ -267,10 +269,22 @@ fn parse_callback_query_params(query: &str) -> CallbackQueryParams {
     let params = parse_query_params(query);
 
     CallbackQueryParams {
-        attempt: params.get("attempt").cloned().filter(|value| !value.is_empty()),
-        nonce: params.get("nonce").cloned().filter(|value| !value.is_empty()),
-        token: params.get("token").cloned().filter(|value| !value.is_empty()),
-        error: params.get("error").cloned().filter(|value| !value.is_empty()),
+        attempt: params
+            .get("attempt")
+            .cloned()
+            .filter(|value| !value.is_empty()),
+        nonce: params
+            .get("nonce")
+            .cloned()
+            .filter(|value| !value.is_empty()),
+        token: params
+            .get("token")
+            .cloned()
+            .filter(|value| !value.is_empty()),
+        error: params
+            .get("error")
+            .cloned()
+            .filter(|value| !value.is_empty()),
     }
 }
 
@@ -367,7 +381,9 @@ async fn run_google_auth_callback_server(
 
         log::info!("Accepted Google auth callback connection from {}", address);
 
-        if let Some(payload) = handle_callback_connection(stream, &expected_attempt, &expected_nonce).await {
+        if let Some(payload) =
+            handle_callback_connection(stream, &expected_attempt, &expected_nonce).await
+        {
             break Ok(payload);
         }
     };
@@ -382,9 +398,12 @@ pub async fn ns_start_google_auth_callback_server(
     let listener = TcpListener::bind(AUTH_CALLBACK_BIND_ADDRESS)
         .await
         .map_err(|error| format!("Failed to start Google login callback listener: {}", error))?;
-    let local_addr = listener
-        .local_addr()
-        .map_err(|error| format!("Failed to read Google login callback listener address: {}", error))?;
+    let local_addr = listener.local_addr().map_err(|error| {
+        format!(
+            "Failed to read Google login callback listener address: {}",
+            error
+        )
+    })?;
 
     let server_id = Uuid::new_v4().to_string();
     let nonce = Uuid::new_v4().to_string();
@@ -408,11 +427,7 @@ pub async fn ns_start_google_auth_callback_server(
     );
 
     tokio::spawn(run_google_auth_callback_server(
-        listener,
-        attempt,
-        nonce,
-        result_tx,
-        cancel_rx,
+        listener, attempt, nonce, result_tx, cancel_rx,
     ));
 
     Ok(GoogleAuthCallbackServerStartResponse {
@@ -1,10 +1,17 @@
 use crate::{
-    core::device::{
-        CommandError, DeviceInfoWithFingerprint, DeviceInfoWithKey, DeviceManager, KeyDer,
+    core::{
+        device::{
+            key::{DEFAULT_ROTATION_GRACE_HOURS, MAX_ROTATION_GRACE_HOURS},
+            trust::normalize_fingerprint,
+            CommandError, DeviceInfoWithFingerprint, DeviceInfoWithKey, DeviceManager, KeyDer,
+            RotationStatement, TrustedDevice,
+        },
+        socket::SocketManager,
     },
     state::GlobalState,
 };
 use std::sync::Arc;
+use tauri::State;
 
 fn get_manager() -> Arc<DeviceManager> {
     GlobalState::get::<DeviceManager>()
@@ -34,3 +41,70 @@ pub fn ns_get_key() -> Result<KeyDer, CommandError> {
 
     Ok(key)
 }
+
+/// Replaces the device key, which is presented from now on. Peers that pinned the old key
+/// keep accepting it for `grace_hours` (default a week, at most 30 days) and are told about
+/// its successor; open sessions right away, others when they next connect.
+#[tauri::command]
+pub async fn ns_rotate_device_key(
+    state: State<'_, Arc<SocketManager>>,
+    grace_hours: Option<u32>,
+) -> Result<RotationStatement, CommandError> {
+    let grace_hours = grace_hours
+        .map(i64::from)
+        .unwrap_or(DEFAULT_ROTATION_GRACE_HOURS)
+        .min(MAX_ROTATION_GRACE_HOURS);
+
+    let statement = get_manager()
+        .rotate_key(chrono::Duration::hours(grace_hours))
+        .map_err(CommandError::from)?;
+
+    let announced = state.announce_key_rotation().await;
+    log::info!("Announced key rotation to {} connected peers", announced);
+
+    Ok(statement)
+}
+
+#[tauri::command]
+pub fn ns_get_key_rotation() -> Option<RotationStatement> {
+    get_manager().pending_rotation()
+}
+
+#[tauri::command]
+pub fn ns_list_trusted_devices() -> Vec<TrustedDevice> {
+    get_manager().trust_store().list()
+}
+
+#[tauri::command]
+pub fn ns_trust_device(
+    fingerprint: String,
+    device_id: Option<String>,
+    name: Option<String>,
+) -> Result<TrustedDevice, CommandError> {
+    let trust_store = get_manager().trust_store();
+
+    trust_store
+        .add(&fingerprint, device_id, name)
+        .map_err(CommandError::from)
+}
+
+/// Removes the device from the allowlist and drops any session it still has open.
+#[tauri::command]
+pub async fn ns_revoke_trusted_device(
+    state: State<'_, Arc<SocketManager>>,
+    fingerprint: String,
+) -> Result<bool, CommandError> {
+    let trust_store = get_manager().trust_store();
+    let revoked = trust_store
+        .revoke(&fingerprint)
+        .map_err(CommandError::from)?;
+
+    if let Some(fingerprint) = normalize_fingerprint(&fingerprint) {
+        let closed = state.disconnect_fingerprint(&fingerprint).await;
+        if closed > 0 {
+            log::info!("Closed {} connections to revoked device", closed);
+        }
+    }
+
+    Ok(revoked)
+}
@@ -206,7 +206,7 @@ fn format_file_size(size: u64) -> String {
 }
 
 #[inline]
-fn get_file_type(name: &str, is_dir: bool) -> &'static str {
+pub(crate) fn get_file_type(name: &str, is_dir: bool) -> &'static str {
     if is_dir {
         return "folder";
     }
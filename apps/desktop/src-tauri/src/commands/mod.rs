@@ -3,4 +3,5 @@ pub mod device;
 pub mod file;
 pub mod search;
 pub mod socket;
+pub mod transfer;
 pub mod transfer_history;
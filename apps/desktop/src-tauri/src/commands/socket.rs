@@ -1,22 +1,43 @@
+use dashmap::DashMap;
+use nktcp::packets::{
+    BundleEntry, FileAccept, FileAck, FileChunk, FileChunkCompressed, FileFinish, FileOffer,
+    FilePause, FileReject, FileResume,
+};
+use nktcp::Packet;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use tauri::{AppHandle, Emitter, State};
 use tauri_plugin_store::StoreExt;
 use thiserror::Error;
-use tokio::{fs::File, io::AsyncReadExt};
+use tokio::{fs::File, io::AsyncReadExt, task::JoinSet, time::Duration};
 use uuid::Uuid;
 
 use crate::{
+    commands::file::get_file_type,
     core::{
         device::DeviceManager,
         socket::{
-            handlers::file::{set_receive_base_dir, set_transfer_event_app_handle},
+            compression::{ChunkCodec, ChunkCompressor},
+            handlers::{
+                file::{set_collision_policy, set_receive_base_dir, set_transfer_event_app_handle},
+                receive_path::CollisionPolicy,
+            },
+            handshake::CAP_FILE_BUNDLES,
             ids::{LinkKey, RouteKind},
-            Connection, PacketType, SocketClientConfig, SocketManager, TransferConfig,
+            pairing::PairingService,
+            Connection, ConnectionServerConfig, PacketType, SocketClientConfig, SocketError,
+            SocketManager, TransferConfig,
+        },
+        transfer_history::{
+            clear_outgoing_transfer, load_outgoing_transfer, persist_outgoing_transfer,
+            persist_transfer_progress_event, reopen_outgoing_transfer, FolderProgressTracker,
+            OutgoingTransfer, TransferProgressEventPayload,
         },
-        transfer_history::{persist_transfer_progress_event, TransferProgressEventPayload},
     },
     state::GlobalState,
 };
@@ -32,12 +53,44 @@ fn now_timestamp_ms() -> i64 {
 }
 
 const SEND_PROGRESS_EMIT_STEP: u64 = 1024 * 1024;
-
-#[derive(serde::Serialize)]
-struct FileMetadata {
-    id: String,
-    name: String,
+// Longer than the receiver's own decision timeout so its rejection arrives first.
+const OFFER_REPLY_TIMEOUT: Duration = Duration::from_secs(150);
+// Counts from when FileFinish is queued behind the chunks still in flight, and covers the
+// receiver's final sync to disk.
+const FINISH_REPLY_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_BUNDLE_ENTRIES: usize = 512;
+const MAX_BUNDLE_BYTES: u64 = 32 * 1024 * 1024;
+
+/// One file of a batch. Files found by walking a dropped folder keep their path relative
+/// to the folder's parent so the receiver can rebuild the tree.
+struct SendEntry {
+    path: String,
+    relative_path: Option<String>,
     size: u64,
+    folder: Option<Arc<FolderProgressTracker>>,
+}
+
+impl SendEntry {
+    fn folder_name(&self) -> Option<String> {
+        self.folder.as_ref().map(|folder| folder.name().to_string())
+    }
+
+    fn folder_total_bytes(&self) -> Option<u64> {
+        self.folder.as_ref().map(|folder| folder.total_bytes())
+    }
+}
+
+/// What one send worker picks up next: a file on its own, or a run of small files
+/// streamed under a single offer.
+enum SendItem {
+    File(SendEntry),
+    Bundle(Vec<SendEntry>),
+}
+
+/// Totals for the whole batch, shown to the receiver when it is asked to accept.
+struct BatchSummary {
+    file_count: u32,
+    total_bytes: u64,
 }
 
 #[derive(Clone)]
@@ -49,10 +102,14 @@ struct SendTransferContext {
     source_device_id: String,
     source_device_name: Option<String>,
     target_device_id: String,
+    folders: Arc<DashMap<String, Arc<FolderProgressTracker>>>,
 }
 
 impl SendTransferContext {
-    fn emit_event(&self, event: TransferProgressEventPayload) {
+    fn emit_event(&self, mut event: TransferProgressEventPayload) {
+        if let Some(folder) = self.folders.get(&event.file_id) {
+            event.folder = Some(folder.record(&event.file_id, event.sent_bytes));
+        }
         persist_transfer_progress_event(event.clone());
         let _ = self.app_handle.emit("transfer-progress", event);
     }
@@ -76,6 +133,7 @@ impl SendTransferContext {
             status: "processing".to_string(),
             error: None,
             timestamp_ms: now_timestamp_ms(),
+            folder: None,
         });
     }
 
@@ -111,6 +169,7 @@ impl SendTransferContext {
             status: "processing".to_string(),
             error: None,
             timestamp_ms: now_timestamp_ms(),
+            folder: None,
         });
     }
 
@@ -133,6 +192,37 @@ impl SendTransferContext {
             status: "success".to_string(),
             error: None,
             timestamp_ms: now_timestamp_ms(),
+            folder: None,
+        });
+    }
+
+    fn emit_failed(
+        &self,
+        file_id: &str,
+        file_path: &str,
+        file_name: &str,
+        total_bytes: u64,
+        error_message: String,
+    ) {
+        self.emit_event(TransferProgressEventPayload {
+            transfer_id: self.transfer_id.clone(),
+            file_id: file_id.to_string(),
+            file_path: file_path.to_string(),
+            file_name: file_name.to_string(),
+            direction: "send".to_string(),
+            source_user_id: self.source_user_id.clone(),
+            source_user_name: self.source_user_name.clone(),
+            source_device_id: Some(self.source_device_id.clone()),
+            source_device_name: self.source_device_name.clone(),
+            same_account: Some(true),
+            target_device_id: self.target_device_id.clone(),
+            total_bytes,
+            sent_bytes: 0,
+            progress_percent: 0.0,
+            status: "failed".to_string(),
+            error: Some(error_message),
+            timestamp_ms: now_timestamp_ms(),
+            folder: None,
         });
     }
 
@@ -155,6 +245,7 @@ impl SendTransferContext {
             status: "failed".to_string(),
             error: Some(error_message),
             timestamp_ms: now_timestamp_ms(),
+            folder: None,
         });
     }
 }
@@ -170,40 +261,160 @@ fn file_name_from_path(path: &Path) -> String {
         .to_string()
 }
 
+/// Derives a file id that stays the same when a transfer is retried, so the receiver
+/// can match a re-offer against the partial file it kept from the previous attempt.
+fn stable_file_id(transfer_id: &str, path_str: &str) -> String {
+    let digest = Sha256::digest(path_str.as_bytes());
+    format!("{}:{}", transfer_id, hex::encode(&digest[..16]))
+}
+
+/// Sends the offer and waits for the receiver to accept or reject it. An accepted offer
+/// carries the byte offset to continue from (0 for a fresh transfer).
 async fn send_file_offer(
     connection: &Arc<Connection>,
-    file_id: &str,
-    file_name: &str,
-    total_size: u64,
-) -> Result<(), SocketCommandError> {
-    let header = FileMetadata {
-        id: file_id.to_string(),
-        name: file_name.to_string(),
-        size: total_size,
+    offer: &FileOffer,
+) -> Result<u64, SocketCommandError> {
+    let (reply_type, response) = connection
+        .request_message_with_timeout(offer, OFFER_REPLY_TIMEOUT)
+        .await
+        .map_err(|e| match e.downcast_ref::<SocketError>() {
+            Some(SocketError::RequestTimedOut { .. }) => {
+                map_transfer_error("Offer error", "timed out waiting for the receiver")
+            }
+            _ => map_transfer_error("Send offer error", e),
+        })?;
+
+    let (replied_id, offset) = match reply_type {
+        PacketType::FileAccept => {
+            let accept = FileAccept::from_payload(&response)
+                .map_err(|e| map_transfer_error("Read offer reply error", e))?;
+            (accept.file_id, accept.offset)
+        }
+        PacketType::FileResume => {
+            let resume = FileResume::from_payload(&response)
+                .map_err(|e| map_transfer_error("Read offer reply error", e))?;
+            (resume.file_id, resume.offset)
+        }
+        PacketType::FileReject => {
+            let reject = FileReject::from_payload(&response)
+                .map_err(|e| map_transfer_error("Read offer reply error", e))?;
+            if reject.whole_transfer {
+                return Err(SocketCommandError::TransferRejected(reject.reason));
+            }
+            return Err(SocketCommandError::FileSkipped(reject.reason));
+        }
+        other => {
+            return Err(map_transfer_error(
+                "Offer error",
+                format!("unexpected reply {:?}", other),
+            ));
+        }
     };
 
-    let header_bytes =
-        serde_json::to_vec(&header).map_err(|e| map_transfer_error("Serialize header error", e))?;
+    if replied_id != offer.id || offset > offer.size {
+        log::warn!(
+            "Ignoring invalid resume reply for {} (id: {}, offset: {})",
+            offer.id,
+            replied_id,
+            offset
+        );
+        return Ok(0);
+    }
 
-    connection
-        .send_packet(PacketType::FileOffer, |w| {
-            w.reserve(header_bytes.len());
-            w.write_bytes(&header_bytes);
-        })
+    Ok(offset)
+}
+
+/// Sends FileFinish and waits for the receiver to confirm it kept the data. Returns the
+/// ids of the bundle entries it did not keep; a single file it did not keep comes back as
+/// [`SocketCommandError::FileDiscarded`].
+async fn send_file_finish(
+    connection: &Arc<Connection>,
+    finish: &FileFinish,
+) -> Result<Vec<String>, SocketCommandError> {
+    let (reply_type, response) = connection
+        .request_message_with_timeout(finish, FINISH_REPLY_TIMEOUT)
         .await
-        .map_err(|e| map_transfer_error("Send header error", e))?;
+        .map_err(|e| match e.downcast_ref::<SocketError>() {
+            Some(SocketError::RequestTimedOut { .. }) => map_transfer_error(
+                "Finish error",
+                "timed out waiting for the receiver to confirm",
+            ),
+            _ => map_transfer_error("Send finish error", e),
+        })?;
+
+    match reply_type {
+        PacketType::FileAck => {
+            let ack = FileAck::from_payload(&response)
+                .map_err(|e| map_transfer_error("Read finish reply error", e))?;
+            Ok(ack.failed)
+        }
+        PacketType::FileReject => {
+            let reject = FileReject::from_payload(&response)
+                .map_err(|e| map_transfer_error("Read finish reply error", e))?;
+            if reject.whole_transfer {
+                return Err(SocketCommandError::TransferRejected(reject.reason));
+            }
+            Err(SocketCommandError::FileDiscarded(reject.reason))
+        }
+        other => Err(map_transfer_error(
+            "Finish error",
+            format!("unexpected reply {:?}", other),
+        )),
+    }
+}
 
-    Ok(())
+async fn send_file_pause(connection: &Arc<Connection>, file_id: &str) {
+    if connection.is_closing() {
+        return;
+    }
+
+    let pause = FilePause {
+        file_id: file_id.to_string(),
+    };
+    if let Err(e) = connection.send_message(&pause).await {
+        log::warn!("Failed to send FilePause for {}: {}", file_id, e);
+    }
+}
+
+/// Reads the first `resume_offset` bytes, which the receiver already has, and returns the
+/// checksum state over them. The checksum covers the whole file, so that part is hashed
+/// here rather than skipped with a seek.
+async fn hash_resumed_prefix(
+    file: &mut File,
+    resume_offset: u64,
+    buffer: &mut [u8],
+) -> Result<Sha256, SocketCommandError> {
+    let mut hasher = Sha256::new();
+    let mut remaining = resume_offset;
+    while remaining > 0 {
+        let want = buffer.len().min(remaining as usize);
+        let n = file
+            .read(&mut buffer[..want])
+            .await
+            .map_err(|e| map_transfer_error("Read error", e))?;
+        if n == 0 {
+            return Err(map_transfer_error(
+                "Resume error",
+                "file is shorter than the receiver's resume offset",
+            ));
+        }
+        hasher.update(&buffer[..n]);
+        remaining -= n as u64;
+    }
+
+    Ok(hasher)
 }
 
 async fn transfer_single_file(
     connection: &Arc<Connection>,
     context: &SendTransferContext,
-    path_str: &str,
+    summary: &BatchSummary,
+    entry: &SendEntry,
     buffer: &mut [u8],
-    total_bytes_sent: &mut u64,
+    total_bytes_sent: &AtomicU64,
 ) -> Result<(), SocketCommandError> {
-    let file_id = format!("{}:{}", context.transfer_id, Uuid::new_v4());
+    let path_str = entry.path.as_str();
+    let file_id = stable_file_id(&context.transfer_id, path_str);
     let path = Path::new(path_str);
     let file_name = file_name_from_path(path);
 
@@ -219,11 +430,56 @@ async fn transfer_single_file(
     let mut last_progress_emitted: u64 = 0;
     let mut file_sent_bytes: u64 = 0;
 
+    if let Some(folder) = &entry.folder {
+        context.folders.insert(file_id.clone(), folder.clone());
+    }
     context.emit_started(&file_id, path_str, &file_name, total_size);
 
-    send_file_offer(connection, &file_id, &file_name, total_size).await?;
+    let offer = FileOffer {
+        id: file_id.clone(),
+        name: file_name.clone(),
+        size: total_size,
+        file_count: Some(summary.file_count),
+        total_bytes: Some(summary.total_bytes),
+        sender_name: context.source_device_name.clone(),
+        relative_path: entry.relative_path.clone(),
+        folder_name: entry.folder_name(),
+        folder_total_bytes: entry.folder_total_bytes(),
+        bundle: None,
+    };
+
+    let resume_offset = match send_file_offer(connection, &offer).await {
+        Ok(offset) => offset,
+        Err(SocketCommandError::FileSkipped(reason)) => {
+            log::info!(
+                "Receiver skipped {} (id: {}): {}",
+                file_name,
+                file_id,
+                reason
+            );
+            context.emit_failed(&file_id, path_str, &file_name, total_size, reason);
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
 
     log::info!("Sent offer for {} (id: {})", file_name, file_id);
+
+    let mut hasher = hash_resumed_prefix(&mut file, resume_offset, buffer).await?;
+
+    if resume_offset > 0 {
+        file_sent_bytes = resume_offset;
+        last_progress_emitted = resume_offset;
+
+        log::info!(
+            "Resuming {} from offset {} of {} bytes",
+            file_name,
+            resume_offset,
+            total_size
+        );
+        context.emit_processing(&file_id, path_str, &file_name, total_size, resume_offset);
+    }
+
     log::info!(
         "Starting chunk transfer for {} (size: {} bytes, id: {})",
         file_name,
@@ -231,26 +487,31 @@ async fn transfer_single_file(
         file_id
     );
 
+    // Media and archives are already compressed; trying again only burns CPU.
+    let mut compressor = match get_file_type(&file_name, false) {
+        "image" | "video" | "audio" | "archive" => ChunkCompressor::disabled(),
+        _ => ChunkCompressor::new(ChunkCodec::negotiate(connection.capabilities())),
+    };
+
     loop {
-        let n = file
-            .read(buffer)
-            .await
-            .map_err(|e| map_transfer_error("Read error", e))?;
+        let n = match file.read(buffer).await {
+            Ok(n) => n,
+            Err(e) => {
+                send_file_pause(connection, &file_id).await;
+                return Err(map_transfer_error("Read error", e));
+            }
+        };
 
         if n == 0 {
             log::info!("EOF reached for {}", file_name);
             break;
         }
 
-        connection
-            .send_packet(PacketType::FileChunk, |w| {
-                w.write_string(&file_id);
-                w.write_bytes(&buffer[..n]);
-            })
-            .await
-            .map_err(|e| map_transfer_error("Send chunk error", e))?;
+        hasher.update(&buffer[..n]);
+
+        send_data_chunk(connection, &mut compressor, &file_id, &buffer[..n]).await?;
 
-        *total_bytes_sent += n as u64;
+        total_bytes_sent.fetch_add(n as u64, Ordering::Relaxed);
         file_sent_bytes += n as u64;
 
         let sent_bytes = file_sent_bytes.min(total_size);
@@ -263,13 +524,31 @@ async fn transfer_single_file(
         }
     }
 
-    log::info!("Sending FileFinish for {}", file_name);
-    connection
-        .send_packet(PacketType::FileFinish, |w| {
-            w.write_string(&file_id);
-        })
-        .await
-        .map_err(|e| map_transfer_error("Send finish error", e))?;
+    let checksum = hasher.finalize();
+
+    log::info!(
+        "Sending FileFinish for {} (sha256: {})",
+        file_name,
+        hex::encode(checksum)
+    );
+    let finish = FileFinish {
+        file_id: file_id.clone(),
+        checksum: Some(checksum.into()),
+    };
+    match send_file_finish(connection, &finish).await {
+        Ok(_) => {}
+        Err(SocketCommandError::FileDiscarded(reason)) => {
+            log::warn!(
+                "Receiver discarded {} (id: {}): {}",
+                file_name,
+                file_id,
+                reason
+            );
+            context.emit_failed(&file_id, path_str, &file_name, total_size, reason);
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    }
 
     log::info!("File transfer complete for {} (id: {})", file_name, file_id);
     context.emit_completed(&file_id, path_str, &file_name, total_size);
@@ -277,27 +556,394 @@ async fn transfer_single_file(
     Ok(())
 }
 
+/// Walks every directory in `paths` and returns the files below it, in a stable order,
+/// alongside the plain files. Symlinks inside a folder are skipped so a link cycle or a
+/// link out of the folder cannot pull in unrelated files.
+async fn expand_send_paths(paths: Vec<String>) -> Result<Vec<SendEntry>, SocketCommandError> {
+    let mut entries = Vec::new();
+
+    for path_str in paths {
+        let root = PathBuf::from(&path_str);
+        let metadata = tokio::fs::metadata(&root)
+            .await
+            .map_err(|e| map_transfer_error("Metadata error", e))?;
+
+        if !metadata.is_dir() {
+            entries.push(SendEntry {
+                path: path_str,
+                relative_path: None,
+                size: metadata.len(),
+                folder: None,
+            });
+            continue;
+        }
+
+        let folder_name = file_name_from_path(&root);
+        let mut folder_files: Vec<(PathBuf, String, u64)> = Vec::new();
+        let mut pending_dirs = vec![(root, folder_name.clone())];
+
+        while let Some((dir, relative_dir)) = pending_dirs.pop() {
+            let mut read_dir = tokio::fs::read_dir(&dir)
+                .await
+                .map_err(|e| map_transfer_error("Read folder error", e))?;
+
+            while let Some(child) = read_dir
+                .next_entry()
+                .await
+                .map_err(|e| map_transfer_error("Read folder error", e))?
+            {
+                let child_type = child
+                    .file_type()
+                    .await
+                    .map_err(|e| map_transfer_error("Read folder error", e))?;
+                let child_relative =
+                    format!("{}/{}", relative_dir, child.file_name().to_string_lossy());
+
+                if child_type.is_dir() {
+                    pending_dirs.push((child.path(), child_relative));
+                } else if child_type.is_file() {
+                    let size = child
+                        .metadata()
+                        .await
+                        .map_err(|e| map_transfer_error("Metadata error", e))?
+                        .len();
+                    folder_files.push((child.path(), child_relative, size));
+                } else {
+                    log::info!("Skipping non-regular file {:?}", child.path());
+                }
+            }
+        }
+
+        folder_files.sort_by(|a, b| a.1.cmp(&b.1));
+        let folder_total: u64 = folder_files.iter().map(|(_, _, size)| size).sum();
+        let folder = Arc::new(FolderProgressTracker::new(folder_name, folder_total));
+
+        entries.extend(
+            folder_files
+                .into_iter()
+                .map(|(path, relative_path, size)| SendEntry {
+                    path: path.to_string_lossy().to_string(),
+                    relative_path: Some(relative_path),
+                    size,
+                    folder: Some(folder.clone()),
+                }),
+        );
+    }
+
+    Ok(entries)
+}
+
+/// Gathers files no larger than `small_file_size` into bundles, keeping everything else
+/// as individual offers. A `small_file_size` of 0 disables bundling.
+fn group_send_items(entries: Vec<SendEntry>, small_file_size: u64) -> Vec<SendItem> {
+    fn close_bundle(items: &mut Vec<SendItem>, mut bundle: Vec<SendEntry>) {
+        // A bundle of one gains nothing over a plain offer.
+        match bundle.len() {
+            0 => {}
+            1 => items.push(SendItem::File(bundle.remove(0))),
+            _ => items.push(SendItem::Bundle(bundle)),
+        }
+    }
+
+    let mut items = Vec::new();
+    let mut bundle: Vec<SendEntry> = Vec::new();
+    let mut bundle_bytes: u64 = 0;
+
+    for entry in entries {
+        if small_file_size == 0 || entry.size > small_file_size {
+            items.push(SendItem::File(entry));
+            continue;
+        }
+
+        if bundle.len() >= MAX_BUNDLE_ENTRIES || bundle_bytes + entry.size > MAX_BUNDLE_BYTES {
+            close_bundle(&mut items, std::mem::take(&mut bundle));
+            bundle_bytes = 0;
+        }
+        bundle_bytes += entry.size;
+        bundle.push(entry);
+    }
+    close_bundle(&mut items, bundle);
+
+    items
+}
+
+/// Sends `data` as a `FileChunkCompressed` when the compressor finds it worthwhile,
+/// otherwise as a plain `FileChunk`.
+async fn send_data_chunk(
+    connection: &Arc<Connection>,
+    compressor: &mut ChunkCompressor,
+    file_id: &str,
+    data: &[u8],
+) -> Result<(), SocketCommandError> {
+    let sent = match compressor.compress(data) {
+        Some((codec, compressed)) => {
+            let chunk = FileChunkCompressed {
+                file_id: file_id.to_string(),
+                codec: codec as u8,
+                raw_len: data.len() as u32,
+                data: compressed,
+            };
+            connection.send_message(&chunk).await
+        }
+        None => {
+            let chunk = FileChunk {
+                file_id: file_id.to_string(),
+                data: data.to_vec(),
+            };
+            connection.send_message(&chunk).await
+        }
+    };
+
+    sent.map(|_| ())
+        .map_err(|e| map_transfer_error("Send chunk error", e))
+}
+
+async fn send_bundle_chunk(
+    connection: &Arc<Connection>,
+    compressor: &mut ChunkCompressor,
+    bundle_id: &str,
+    pending: &mut Vec<u8>,
+    total_bytes_sent: &AtomicU64,
+) -> Result<(), SocketCommandError> {
+    send_data_chunk(connection, compressor, bundle_id, pending).await?;
+
+    total_bytes_sent.fetch_add(pending.len() as u64, Ordering::Relaxed);
+    pending.clear();
+    Ok(())
+}
+
+/// Streams a run of small files under one offer: each file's bytes followed by its
+/// SHA-256, packed back to back so many files share a single `FileChunk`. Only the
+/// final outcome of each file is emitted, which keeps history writes to one per file.
+async fn transfer_bundle(
+    connection: &Arc<Connection>,
+    context: &SendTransferContext,
+    summary: &BatchSummary,
+    bundle_index: usize,
+    entries: &[SendEntry],
+    buffer: &mut [u8],
+    total_bytes_sent: &AtomicU64,
+) -> Result<(), SocketCommandError> {
+    let bundle_id = format!("{}:bundle-{}", context.transfer_id, bundle_index);
+
+    let manifest: Vec<BundleEntry> = entries
+        .iter()
+        .map(|entry| {
+            let file_id = stable_file_id(&context.transfer_id, &entry.path);
+            if let Some(folder) = &entry.folder {
+                context.folders.insert(file_id.clone(), folder.clone());
+            }
+            BundleEntry {
+                id: file_id,
+                name: file_name_from_path(Path::new(&entry.path)),
+                size: entry.size,
+                relative_path: entry.relative_path.clone(),
+                folder_name: entry.folder_name(),
+                folder_total_bytes: entry.folder_total_bytes(),
+            }
+        })
+        .collect();
+
+    let offer = FileOffer {
+        id: bundle_id.clone(),
+        name: format!("{} files", manifest.len()),
+        size: manifest.iter().map(|item| item.size).sum(),
+        file_count: Some(summary.file_count),
+        total_bytes: Some(summary.total_bytes),
+        sender_name: context.source_device_name.clone(),
+        relative_path: None,
+        folder_name: None,
+        folder_total_bytes: None,
+        bundle: Some(manifest.clone()),
+    };
+
+    match send_file_offer(connection, &offer).await {
+        Ok(_) => {}
+        Err(SocketCommandError::FileSkipped(reason)) => {
+            log::info!("Receiver skipped bundle {}: {}", bundle_id, reason);
+            for (entry, item) in entries.iter().zip(&manifest) {
+                context.emit_failed(&item.id, &entry.path, &item.name, item.size, reason.clone());
+            }
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    }
+
+    log::info!(
+        "Sending bundle {} ({} files, {} bytes)",
+        bundle_id,
+        manifest.len(),
+        offer.size
+    );
+
+    let chunk_size = buffer.len();
+    let mut pending = Vec::with_capacity(chunk_size * 2);
+    let mut compressor = ChunkCompressor::new(ChunkCodec::negotiate(connection.capabilities()));
+
+    for (entry, item) in entries.iter().zip(&manifest) {
+        let mut file = File::open(&entry.path)
+            .await
+            .map_err(|e| map_transfer_error("Open error", e))?;
+        let mut hasher = Sha256::new();
+
+        // The receiver splits the stream by the sizes in the manifest, so exactly that
+        // many bytes must be sent even if the file changed since it was listed.
+        let mut remaining = item.size;
+        while remaining > 0 {
+            let want = buffer.len().min(remaining as usize);
+            let n = file
+                .read(&mut buffer[..want])
+                .await
+                .map_err(|e| map_transfer_error("Read error", e))?;
+            if n == 0 {
+                return Err(map_transfer_error(
+                    "Bundle error",
+                    format!("{} shrank while being sent", item.name),
+                ));
+            }
+            hasher.update(&buffer[..n]);
+            pending.extend_from_slice(&buffer[..n]);
+            remaining -= n as u64;
+
+            if pending.len() >= chunk_size {
+                send_bundle_chunk(
+                    connection,
+                    &mut compressor,
+                    &bundle_id,
+                    &mut pending,
+                    total_bytes_sent,
+                )
+                .await?;
+            }
+        }
+
+        pending.extend_from_slice(&hasher.finalize());
+    }
+
+    if !pending.is_empty() {
+        send_bundle_chunk(
+            connection,
+            &mut compressor,
+            &bundle_id,
+            &mut pending,
+            total_bytes_sent,
+        )
+        .await?;
+    }
+
+    // Each entry's checksum already travelled in the stream.
+    let finish = FileFinish {
+        file_id: bundle_id.clone(),
+        checksum: None,
+    };
+    let failed: HashSet<String> = match send_file_finish(connection, &finish).await {
+        Ok(failed) => failed.into_iter().collect(),
+        Err(SocketCommandError::FileDiscarded(reason)) => {
+            log::warn!("Receiver discarded bundle {}: {}", bundle_id, reason);
+            for (entry, item) in entries.iter().zip(&manifest) {
+                context.emit_failed(&item.id, &entry.path, &item.name, item.size, reason.clone());
+            }
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+
+    for (entry, item) in entries.iter().zip(&manifest) {
+        if failed.contains(&item.id) {
+            let reason = "receiver did not keep the file".to_string();
+            context.emit_failed(&item.id, &entry.path, &item.name, item.size, reason);
+        } else {
+            context.emit_completed(&item.id, &entry.path, &item.name, item.size);
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends every file below `file_paths` except those in `delivered`, which an earlier
+/// attempt of the same transfer already completed.
 async fn send_files_batch(
     connection: Arc<Connection>,
     context: SendTransferContext,
     file_paths: Vec<String>,
+    delivered: HashSet<String>,
     chunk_size: usize,
 ) -> Result<u64, SocketCommandError> {
-    let mut total_bytes: u64 = 0;
-    let mut buffer = vec![0u8; chunk_size];
-
-    for path_str in file_paths {
-        transfer_single_file(
-            &connection,
-            &context,
-            &path_str,
-            &mut buffer,
-            &mut total_bytes,
-        )
-        .await?;
+    let config = TransferConfig::global();
+    let mut entries = expand_send_paths(file_paths).await?;
+    entries.retain(|entry| !delivered.contains(&entry.path));
+    let summary = Arc::new(BatchSummary {
+        file_count: entries.len() as u32,
+        total_bytes: entries.iter().map(|entry| entry.size).sum(),
+    });
+    // Peers that cannot unpack bundles get every file as its own offer.
+    let bundle_small_file_size = match connection.peer_handshake() {
+        Some(peer) if peer.supports(CAP_FILE_BUNDLES) => config.bundle_small_file_size,
+        _ => 0,
+    };
+    let items = Arc::new(group_send_items(entries, bundle_small_file_size));
+    let next_item = Arc::new(AtomicUsize::new(0));
+    let total_bytes = Arc::new(AtomicU64::new(0));
+
+    // Each worker sends one item at a time from the shared queue. Their chunks interleave
+    // on the connection's chunk lane, and the FIFO `chunk_permits` semaphore hands out
+    // slots in arrival order, so no single file can starve the others.
+    let worker_count = config.max_concurrent_files.clamp(1, items.len().max(1));
+    let mut workers = JoinSet::new();
+
+    for _ in 0..worker_count {
+        let connection = connection.clone();
+        let context = context.clone();
+        let items = items.clone();
+        let summary = summary.clone();
+        let next_item = next_item.clone();
+        let total_bytes = total_bytes.clone();
+
+        workers.spawn(async move {
+            let mut buffer = vec![0u8; chunk_size];
+            loop {
+                let index = next_item.fetch_add(1, Ordering::Relaxed);
+                match items.get(index) {
+                    Some(SendItem::File(entry)) => {
+                        transfer_single_file(
+                            &connection,
+                            &context,
+                            &summary,
+                            entry,
+                            &mut buffer,
+                            &total_bytes,
+                        )
+                        .await?
+                    }
+                    Some(SendItem::Bundle(entries)) => {
+                        transfer_bundle(
+                            &connection,
+                            &context,
+                            &summary,
+                            index,
+                            entries,
+                            &mut buffer,
+                            &total_bytes,
+                        )
+                        .await?
+                    }
+                    None => return Ok(()),
+                }
+            }
+        });
+    }
+
+    while let Some(joined) = workers.join_next().await {
+        let result = joined
+            .map_err(|e| map_transfer_error("Worker error", e))
+            .and_then(|result| result);
+        if let Err(e) = result {
+            workers.abort_all();
+            return Err(e);
+        }
     }
 
-    Ok(total_bytes)
+    Ok(total_bytes.load(Ordering::Relaxed))
 }
 
 #[derive(Debug, Error, Serialize, Deserialize)]
@@ -313,6 +959,24 @@ pub enum SocketCommandError {
 
     #[error("Server error: {0}")]
     ServerError(String),
+
+    #[error("Transfer rejected: {0}")]
+    TransferRejected(String),
+
+    #[error("File skipped by receiver: {0}")]
+    FileSkipped(String),
+
+    #[error("File discarded by receiver: {0}")]
+    FileDiscarded(String),
+
+    #[error("Device {0} is not paired and no fingerprint was given")]
+    NotPaired(String),
+
+    #[error("No pairing is waiting for confirmation: {0}")]
+    PairingNotFound(String),
+
+    #[error("No interrupted transfer to resume: {0}")]
+    TransferNotFound(String),
 }
 
 #[derive(serde::Serialize, Clone)]
@@ -351,10 +1015,10 @@ fn parse_route_kind(route: &str) -> Result<RouteKind, SocketCommandError> {
     }
 }
 
-async fn load_receive_dir_from_store(app: &AppHandle) -> Option<PathBuf> {
+fn load_app_config_from_store(app: &AppHandle) -> Option<JsonValue> {
     let resolved = tauri_plugin_store::resolve_store_path(app, STORE_FILE_NAME).ok();
     if let Some(path) = resolved {
-        log::info!("Reading receive path from store: {:?}", path);
+        log::info!("Reading app config from store: {:?}", path);
     }
 
     let store = match app.store(STORE_FILE_NAME) {
@@ -369,15 +1033,15 @@ async fn load_receive_dir_from_store(app: &AppHandle) -> Option<PathBuf> {
         log::warn!("Failed to reload store {}: {}", STORE_FILE_NAME, e);
     }
 
-    let raw_app_config = match store.get("appConfig") {
-        Some(value) => value,
-        None => {
-            log::info!("Store key appConfig not found in {}", STORE_FILE_NAME);
-            return None;
-        }
-    };
+    let raw_app_config = store.get("appConfig");
+    if raw_app_config.is_none() {
+        log::info!("Store key appConfig not found in {}", STORE_FILE_NAME);
+    }
+    raw_app_config
+}
 
-    let file_location = raw_app_config
+fn receive_dir_from_config(app_config: &JsonValue) -> Option<PathBuf> {
+    let file_location = app_config
         .get("fileLocation")
         .and_then(JsonValue::as_str)
         .map(str::trim)
@@ -392,6 +1056,38 @@ async fn load_receive_dir_from_store(app: &AppHandle) -> Option<PathBuf> {
     }
 }
 
+fn collision_policy_from_config(app_config: &JsonValue) -> CollisionPolicy {
+    let raw_policy = app_config
+        .get("fileCollisionPolicy")
+        .and_then(JsonValue::as_str);
+
+    match raw_policy {
+        Some(value) => CollisionPolicy::parse(value).unwrap_or_else(|| {
+            log::warn!(
+                "Unknown appConfig.fileCollisionPolicy {:?}, using rename",
+                value
+            );
+            CollisionPolicy::default()
+        }),
+        None => CollisionPolicy::default(),
+    }
+}
+
+fn server_config_from_config(app_config: &JsonValue) -> ConnectionServerConfig {
+    let mut config = ConnectionServerConfig::default();
+
+    match app_config.get("maxPeers").and_then(JsonValue::as_u64) {
+        Some(max_peers) if max_peers > 0 => config.max_peers = max_peers as usize,
+        Some(_) => log::warn!(
+            "Ignoring appConfig.maxPeers of 0, using {}",
+            config.max_peers
+        ),
+        None => {}
+    }
+
+    config
+}
+
 // =============================================================================
 // Connection Commands (Unified)
 // =============================================================================
@@ -403,12 +1099,22 @@ pub async fn socket_client_connect_to(
     receiver_id: String,
     receiver_address: String,
     receiver_port: u16,
-    receiver_fingerprint: String,
+    receiver_fingerprint: Option<String>,
 ) -> Result<ClientConnectionResponse, SocketCommandError> {
     let manager = state.inner().clone();
 
     let address = format!("{}:{}", receiver_address, receiver_port);
 
+    // Paired devices are pinned to the fingerprint stored when they were confirmed.
+    let receiver_fingerprint = match receiver_fingerprint {
+        Some(fingerprint) => fingerprint,
+        None => GlobalState::get::<DeviceManager>()
+            .trust_store()
+            .find_by_device_id(&receiver_id)
+            .map(|device| device.fingerprint)
+            .ok_or_else(|| SocketCommandError::NotPaired(receiver_id.clone()))?,
+    };
+
     let config = SocketClientConfig::new(device_id, address)
         .with_fingerprint(receiver_fingerprint)
         .with_target_id(receiver_id);
@@ -486,8 +1192,6 @@ pub async fn socket_client_send_files(
     source_user_name: Option<String>,
     source_device_name: Option<String>,
 ) -> Result<ClientConnectionResponse, SocketCommandError> {
-    let chunk_size = TransferConfig::global().chunk_size;
-
     let manager = state.inner().clone();
 
     let local = parse_uuid(&device_id, "device_id")?;
@@ -505,29 +1209,113 @@ pub async fn socket_client_send_files(
         target_id
     );
 
+    let transfer_id = transfer_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+    persist_outgoing_transfer(OutgoingTransfer {
+        transfer_id: transfer_id.clone(),
+        source_device_id: device_id.clone(),
+        target_device_id: target_id.clone(),
+        file_paths: file_paths.clone(),
+        source_user_id: source_user_id.clone(),
+        source_user_name: source_user_name.clone(),
+        source_device_name: source_device_name.clone(),
+        created_at_ms: now_timestamp_ms(),
+    })
+    .await;
+
     let queued_count = file_paths.len();
     let context = SendTransferContext {
         app_handle: app.clone(),
-        transfer_id: transfer_id.unwrap_or_else(|| Uuid::new_v4().to_string()),
+        transfer_id,
         source_user_id,
         source_user_name,
         source_device_id: device_id.clone(),
         source_device_name,
         target_device_id: target_id.clone(),
+        folders: Arc::new(DashMap::new()),
+    };
+    spawn_send_batch(connection, context, file_paths, HashSet::new());
+
+    Ok(ClientConnectionResponse {
+        status: ConnectionStatus::Connected,
+        message: Some(format!("Queued {} files", queued_count)),
+    })
+}
+
+/// Offers an interrupted outgoing transfer again under its original id, so the receiver
+/// continues each partial file from its offset. Files that already arrived are skipped.
+/// The target must be connected again first, as for [`socket_client_send_files`].
+#[tauri::command]
+pub async fn socket_client_resume_transfer(
+    state: State<'_, Arc<SocketManager>>,
+    app: AppHandle,
+    transfer_id: String,
+) -> Result<ClientConnectionResponse, SocketCommandError> {
+    let transfer = load_outgoing_transfer(&transfer_id)
+        .await
+        .map_err(|e| SocketCommandError::ServerError(format!("{:#}", e)))?
+        .ok_or_else(|| SocketCommandError::TransferNotFound(transfer_id.clone()))?;
+
+    let local = parse_uuid(&transfer.source_device_id, "device_id")?;
+    let peer = parse_uuid(&transfer.target_device_id, "target_id")?;
+    let pair_key = LinkKey::direct(local, peer).pair_key();
+
+    let connection = state
+        .get_connection(&pair_key)
+        .ok_or_else(|| SocketCommandError::ConnectionFailed("Not connected to target".into()))?;
+
+    let delivered = reopen_outgoing_transfer(&transfer_id).await;
+    log::info!(
+        "Resuming transfer {} to {} ({} files already delivered)",
+        transfer_id,
+        transfer.target_device_id,
+        delivered.len()
+    );
+
+    let context = SendTransferContext {
+        app_handle: app,
+        transfer_id: transfer.transfer_id,
+        source_user_id: transfer.source_user_id,
+        source_user_name: transfer.source_user_name,
+        source_device_id: transfer.source_device_id,
+        source_device_name: transfer.source_device_name,
+        target_device_id: transfer.target_device_id,
+        folders: Arc::new(DashMap::new()),
     };
-    let connection = connection.clone();
-    let file_paths = file_paths.clone();
+    spawn_send_batch(connection, context, transfer.file_paths, delivered);
+
+    Ok(ClientConnectionResponse {
+        status: ConnectionStatus::Connected,
+        message: Some(format!("Resuming transfer {}", transfer_id)),
+    })
+}
+
+/// Sends the batch in the background. A finished batch forgets its file list; a failed
+/// one keeps it for [`socket_client_resume_transfer`].
+fn spawn_send_batch(
+    connection: Arc<Connection>,
+    context: SendTransferContext,
+    file_paths: Vec<String>,
+    delivered: HashSet<String>,
+) {
+    let chunk_size = TransferConfig::global().chunk_size;
 
     tokio::spawn(async move {
         connection.begin_send_batch();
-        let result =
-            send_files_batch(connection.clone(), context.clone(), file_paths, chunk_size).await;
+        let result = send_files_batch(
+            connection.clone(),
+            context.clone(),
+            file_paths,
+            delivered,
+            chunk_size,
+        )
+        .await;
 
         connection.end_send_batch_and_maybe_close().await;
 
         match result {
             Ok(total_bytes) => {
                 log::info!("Batch completed, sent {} bytes", total_bytes);
+                clear_outgoing_transfer(context.transfer_id.clone());
             }
             Err(err) => {
                 log::error!("Send batch failed: {}", err);
@@ -535,11 +1323,6 @@ pub async fn socket_client_send_files(
             }
         }
     });
-
-    Ok(ClientConnectionResponse {
-        status: ConnectionStatus::Connected,
-        message: Some(format!("Queued {} files", queued_count)),
-    })
 }
 
 // =============================================================================
@@ -550,14 +1333,22 @@ pub async fn socket_client_send_files(
 pub async fn socket_server_start(
     state: State<'_, Arc<SocketManager>>,
     app: AppHandle,
-    sender_fingerprint: String,
+    sender_fingerprint: Option<String>,
 ) -> Result<ServerStartResponse, SocketCommandError> {
     let manager = state.inner().clone();
 
     set_transfer_event_app_handle(app.clone());
 
-    let receive_dir = load_receive_dir_from_store(&app).await;
+    let app_config = load_app_config_from_store(&app);
+    let receive_dir = app_config.as_ref().and_then(receive_dir_from_config);
     set_receive_base_dir(receive_dir.clone()).await;
+    set_collision_policy(
+        app_config
+            .as_ref()
+            .map(collision_policy_from_config)
+            .unwrap_or_default(),
+    )
+    .await;
 
     if let Some(path) = receive_dir {
         log::info!("Using configured receive directory from store: {:?}", path);
@@ -575,8 +1366,13 @@ pub async fn socket_server_start(
         .ip
         .ipv4;
 
+    let server_config = app_config
+        .as_ref()
+        .map(server_config_from_config)
+        .unwrap_or_default();
+
     let port = manager
-        .start_server(sender_fingerprint)
+        .start_server(sender_fingerprint, server_config)
         .await
         .map_err(|e| SocketCommandError::ServerError(format!("{:#}", e)))?;
 
@@ -610,3 +1406,187 @@ pub async fn socket_server_has_active_connection(
 
     Ok(manager.has_active_server_connection())
 }
+
+// =============================================================================
+// Pairing Commands
+// =============================================================================
+
+#[tauri::command]
+pub async fn socket_pairing_listen(
+    state: State<'_, Arc<SocketManager>>,
+    app: AppHandle,
+) -> Result<ServerStartResponse, SocketCommandError> {
+    let manager = state.inner().clone();
+
+    let address = GlobalState::get::<DeviceManager>()
+        .info()
+        .map_err(|e| {
+            SocketCommandError::ServerError(format!("Failed to get device info: {:#}", e))
+        })?
+        .device_info
+        .ip
+        .ipv4;
+
+    let server_config = load_app_config_from_store(&app)
+        .as_ref()
+        .map(server_config_from_config)
+        .unwrap_or_default();
+
+    let port = manager
+        .start_pairing_server(server_config)
+        .await
+        .map_err(|e| SocketCommandError::ServerError(format!("{:#}", e)))?;
+
+    Ok(ServerStartResponse {
+        port,
+        address,
+        message: format!("Waiting for a device to pair on port {}", port),
+    })
+}
+
+#[tauri::command]
+pub async fn socket_pairing_connect(
+    state: State<'_, Arc<SocketManager>>,
+    device_id: String,
+    receiver_address: String,
+    receiver_port: u16,
+) -> Result<ClientConnectionResponse, SocketCommandError> {
+    let manager = state.inner().clone();
+
+    let address = format!("{}:{}", receiver_address, receiver_port);
+
+    manager
+        .pair_with(device_id, address)
+        .await
+        .map_err(|e| SocketCommandError::ConnectionFailed(format!("{:#}", e)))?;
+
+    Ok(ClientConnectionResponse {
+        status: ConnectionStatus::Connected,
+        message: Some("Compare the pairing code on both devices".to_string()),
+    })
+}
+
+#[tauri::command]
+pub async fn socket_pairing_confirm(
+    pairing_id: String,
+    accepted: bool,
+) -> Result<(), SocketCommandError> {
+    if GlobalState::get::<PairingService>().decide(&pairing_id, accepted) {
+        Ok(())
+    } else {
+        Err(SocketCommandError::PairingNotFound(pairing_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::socket::handlers::file::open_partial_file;
+    use crate::core::transfer_history::TransferResumeState;
+    use tokio::io::AsyncWriteExt;
+
+    /// A fresh directory under the system temp dir, removed again on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path =
+                std::env::temp_dir().join(format!("nekoshare-test-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn interrupted_transfer_resumes_from_the_receivers_checkpoint() {
+        let dir = TempDir::new();
+        let contents: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let source_path = dir.0.join("video.mp4");
+        std::fs::write(&source_path, &contents).unwrap();
+
+        // The first attempt checkpointed 100 000 bytes and wrote a little more before the
+        // connection dropped.
+        let part_path = dir.0.join("video.mp4.part");
+        std::fs::write(&part_path, &contents[..120_000]).unwrap();
+
+        let transfer_id = "transfer";
+        let file_id = stable_file_id(transfer_id, &source_path.to_string_lossy());
+        assert_eq!(
+            file_id,
+            stable_file_id(transfer_id, &source_path.to_string_lossy())
+        );
+        let resume = TransferResumeState {
+            peer_fingerprint: "sender".to_string(),
+            file_id: file_id.clone(),
+            file_path: part_path.to_string_lossy().to_string(),
+            file_name: "video.mp4".to_string(),
+            total_bytes: contents.len() as u64,
+            received_bytes: 100_000,
+            updated_at_ms: 0,
+        };
+        let offer = FileOffer {
+            id: file_id,
+            name: "video.mp4".to_string(),
+            size: contents.len() as u64,
+            file_count: Some(1),
+            total_bytes: Some(contents.len() as u64),
+            sender_name: None,
+            relative_path: None,
+            folder_name: None,
+            folder_total_bytes: None,
+            bundle: None,
+        };
+
+        // The receiver answers the re-offer with its checkpoint...
+        let (mut part, _, offset, mut received_hash) =
+            open_partial_file(&resume, &offer).await.unwrap();
+        assert_eq!(offset, 100_000);
+
+        // ...and the sender continues from there.
+        let mut source = File::open(&source_path).await.unwrap();
+        let mut buffer = vec![0u8; 16 * 1024];
+        let mut sent_hash = hash_resumed_prefix(&mut source, offset, &mut buffer)
+            .await
+            .unwrap();
+        let mut sent_bytes = 0;
+        loop {
+            let n = source.read(&mut buffer).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            sent_hash.update(&buffer[..n]);
+            received_hash.update(&buffer[..n]);
+            part.write_all(&buffer[..n]).await.unwrap();
+            sent_bytes += n;
+        }
+        part.flush().await.unwrap();
+
+        assert_eq!(sent_bytes, contents.len() - 100_000);
+        assert_eq!(std::fs::read(&part_path).unwrap(), contents);
+        let checksum = sent_hash.finalize();
+        assert_eq!(checksum, received_hash.finalize());
+        assert_eq!(checksum, Sha256::digest(&contents));
+    }
+
+    #[tokio::test]
+    async fn resume_offset_past_the_end_of_the_file_is_an_error() {
+        let dir = TempDir::new();
+        let source_path = dir.0.join("notes.txt");
+        std::fs::write(&source_path, b"short").unwrap();
+
+        let mut source = File::open(&source_path).await.unwrap();
+        let mut buffer = vec![0u8; 4];
+        let result = hash_resumed_prefix(&mut source, 100, &mut buffer).await;
+
+        assert!(matches!(
+            result,
+            Err(SocketCommandError::ConnectionFailed(_))
+        ));
+    }
+}
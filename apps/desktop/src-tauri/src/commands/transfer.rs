@@ -0,0 +1,32 @@
+use crate::core::socket::handlers::file::{resolve_transfer_offer, OfferDecision};
+
+#[tauri::command]
+pub async fn transfer_accept(transfer_id: String, peer_fingerprint: String) -> Result<(), String> {
+    if resolve_transfer_offer(&peer_fingerprint, &transfer_id, OfferDecision::Accept) {
+        Ok(())
+    } else {
+        Err(format!("no pending offer for transfer {}", transfer_id))
+    }
+}
+
+#[tauri::command]
+pub async fn transfer_reject(
+    transfer_id: String,
+    peer_fingerprint: String,
+    reason: Option<String>,
+) -> Result<(), String> {
+    let reason = reason
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| "Declined by receiver".to_string());
+
+    if resolve_transfer_offer(
+        &peer_fingerprint,
+        &transfer_id,
+        OfferDecision::Reject(reason),
+    ) {
+        Ok(())
+    } else {
+        Err(format!("no pending offer for transfer {}", transfer_id))
+    }
+}
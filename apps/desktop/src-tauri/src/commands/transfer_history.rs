@@ -1,4 +1,6 @@
-use crate::core::transfer_history::{TransferHistoryRecord, TransferHistoryService};
+use crate::core::transfer_history::{
+    OutgoingTransfer, TransferHistoryRecord, TransferHistoryService,
+};
 use crate::state::GlobalState;
 
 #[tauri::command]
@@ -29,3 +31,15 @@ pub async fn transfer_history_delete_transfer(transfer_id: String) -> Result<(),
         .map_err(|err| format!("transfer history delete-transfer task failed: {}", err))?
         .map_err(|err| format!("failed to delete transfer history transfer: {}", err))
 }
+
+/// The file list of an outgoing transfer that has not finished, for offering it again.
+#[tauri::command]
+pub async fn transfer_history_outgoing(
+    transfer_id: String,
+) -> Result<Option<OutgoingTransfer>, String> {
+    let service = GlobalState::get::<TransferHistoryService>();
+    tokio::task::spawn_blocking(move || service.load_outgoing_transfer(&transfer_id))
+        .await
+        .map_err(|err| format!("outgoing transfer load task failed: {}", err))?
+        .map_err(|err| format!("failed to read outgoing transfer: {}", err))
+}
@@ -17,6 +17,12 @@ pub enum DeviceError {
     CertificateGeneration(String),
     #[error("Certificates not found at path: {path}")]
     CertificatesNotFound { path: String },
+    #[error("Invalid certificate fingerprint: {0}")]
+    InvalidFingerprint(String),
+    #[error("Invalid key rotation statement: {0}")]
+    InvalidRotation(String),
+    #[error("Private key protection error: {0}")]
+    KeyProtection(String),
     #[error("Failed to create storage directory: {path}")]
     StorageDirectoryCreation { path: String, source: io::Error },
 }
@@ -42,6 +48,18 @@ impl DeviceError {
         Self::CertificatesNotFound { path: path.into() }
     }
 
+    pub fn invalid_fingerprint(fingerprint: impl Into<String>) -> Self {
+        Self::InvalidFingerprint(fingerprint.into())
+    }
+
+    pub fn invalid_rotation(reason: impl Into<String>) -> Self {
+        Self::InvalidRotation(reason.into())
+    }
+
+    pub fn key_protection(reason: impl Into<String>) -> Self {
+        Self::KeyProtection(reason.into())
+    }
+
     pub fn storage_dir(path: impl Into<String>, source: io::Error) -> Self {
         Self::StorageDirectoryCreation {
             path: path.into(),
@@ -82,6 +100,9 @@ impl From<DeviceError> for CommandError {
             DeviceError::KeyGeneration(_) => "KEY_GEN_ERROR",
             DeviceError::CertificateGeneration(_) => "CERT_GEN_ERROR",
             DeviceError::CertificatesNotFound { .. } => "CERTS_NOT_FOUND",
+            DeviceError::InvalidFingerprint(_) => "INVALID_FINGERPRINT",
+            DeviceError::InvalidRotation(_) => "INVALID_ROTATION",
+            DeviceError::KeyProtection(_) => "KEY_PROTECTION_ERROR",
             DeviceError::StorageDirectoryCreation { .. } => "STORAGE_DIR_ERROR",
         };
 
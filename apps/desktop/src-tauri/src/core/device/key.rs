@@ -1,18 +1,59 @@
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
+use chrono::{Datelike, Days, Duration as ChronoDuration, NaiveDate, NaiveTime, Utc};
 use directories::ProjectDirs;
 use log::{debug, info, warn};
-use rcgen::{CertificateParams, KeyPair};
+use rcgen::{CertificateParams, Issuer, KeyPair};
 use sha2::{Digest, Sha256};
 
 use super::error::{DeviceError, DeviceResult};
+use super::key_store::{self, KeyProtection};
+use super::rotation::RotationStatement;
+
+const CERT_VALIDITY_DAYS: i64 = 365;
+/// Certificates this close to expiring are rotated when the app starts.
+const RENEW_BEFORE_DAYS: i64 = 30;
+/// How long peers keep accepting a rotated-out key unless the caller picks another period.
+pub const DEFAULT_ROTATION_GRACE_HOURS: i64 = 7 * 24;
+/// The longest grace period peers grant a rotated-out key, whatever the statement asks for.
+pub const MAX_ROTATION_GRACE_HOURS: i64 = 30 * 24;
+
+const SEALED_KEY_FILE_NAME: &str = "key.der.enc";
+/// Plaintext key written by earlier versions, encrypted on first load.
+const LEGACY_KEY_FILE_NAME: &str = "key.der";
+const VALIDITY_FILE_NAME: &str = "validity.json";
+const ROTATION_FILE_NAME: &str = "rotation.json";
+/// Holds the replacement key while a rotation is being written, until it takes over.
+const NEXT_KEY_DIR: &str = "next";
+/// Moved from [`NEXT_KEY_DIR`] in this order, so a device key is only replaced once the
+/// rest of its successor is in place.
+const KEY_FILE_NAMES: [&str; 5] = [
+    "cert.pem",
+    "key.pem",
+    VALIDITY_FILE_NAME,
+    SEALED_KEY_FILE_NAME,
+    "cert.der",
+];
+
+pub fn default_rotation_grace() -> ChronoDuration {
+    ChronoDuration::hours(DEFAULT_ROTATION_GRACE_HOURS)
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CertificateValidity {
+    not_after_ms: i64,
+}
 
 #[derive(Debug, Clone)]
 pub struct KeyConfig {
     pub common_name: String,
     pub storage_dir: Option<PathBuf>,
+    pub protection: KeyProtection,
+    /// Also write `cert.pem` and `key.pem` for use with external tools.
+    pub write_pem: bool,
 }
 
 impl Default for KeyConfig {
@@ -20,6 +61,8 @@ impl Default for KeyConfig {
         Self {
             common_name: "Nekoshare".to_string(),
             storage_dir: None,
+            protection: KeyProtection::from_env(),
+            write_pem: false,
         }
     }
 }
@@ -74,6 +117,8 @@ impl KeyManager {
 
         if let Err(e) = fs::create_dir_all(&final_key_path) {
             warn!("Failed to create key directory {:?}: {}", final_key_path, e);
+        } else if let Err(e) = key_store::restrict_to_owner(&final_key_path) {
+            warn!("{:#}", e);
         }
 
         debug!("KeyManager initialized with path: {:?}", final_key_path);
@@ -84,96 +129,345 @@ impl KeyManager {
         })
     }
 
+    /// Directory holding the device's key material.
+    pub fn storage_dir(&self) -> &Path {
+        &self.base_path
+    }
+
     pub fn get_or_create(&self, host: String) -> DeviceResult<KeyDer> {
-        match self.load_certificates() {
+        if let Err(e) = self.recover_rotation() {
+            warn!("Failed to recover an interrupted key rotation: {:#}", e);
+        }
+        if let Some(statement) = self.pending_rotation() {
+            if statement.grace_elapsed(Utc::now().timestamp_millis()) {
+                if let Err(e) = self.retire_rotation() {
+                    warn!("Failed to retire key rotation: {:#}", e);
+                }
+            }
+        }
+
+        let key = match self.load_certificates(&self.base_path) {
             Ok((cert_der, key_der)) => {
                 info!("Loaded existing certificates from {:?}", self.base_path);
-                Ok(KeyDer::new(cert_der, key_der))
+                KeyDer::new(cert_der, key_der)
             }
-            Err(e) => {
+            Err(e)
+                if e.downcast_ref::<DeviceError>()
+                    .is_some_and(|e| matches!(e, DeviceError::CertificatesNotFound { .. })) =>
+            {
                 debug!("Could not load certificates: {}, generating new ones", e);
-                self.generate_certificates(host)
+                return self.generate_certificates(host);
+            }
+            // Anything else, such as a wrong passphrase, must not replace the existing key.
+            Err(e) => return Err(e),
+        };
+
+        // Renew ahead of expiry through a regular rotation so paired peers follow along.
+        let renew_at = Utc::now() + ChronoDuration::days(RENEW_BEFORE_DAYS);
+        let expiring = self
+            .certificate_expiry_ms()
+            .is_some_and(|not_after_ms| not_after_ms <= renew_at.timestamp_millis());
+        if expiring && self.pending_rotation().is_none() {
+            info!(
+                "Certificate expires within {} days, rotating",
+                RENEW_BEFORE_DAYS
+            );
+            match self.rotate_key(host, &key, default_rotation_grace()) {
+                Ok((_, next)) => return Ok(next),
+                Err(e) => warn!("Failed to rotate expiring certificate: {:#}", e),
             }
         }
+
+        Ok(key)
     }
 
-    fn load_certificates(&self) -> DeviceResult<(Vec<u8>, Vec<u8>)> {
-        let cert_der_path = self.base_path.join("cert.der");
-        let key_der_path = self.base_path.join("key.der");
+    fn load_certificates(&self, dir: &Path) -> DeviceResult<(Vec<u8>, Vec<u8>)> {
+        let cert_der_path = dir.join("cert.der");
+        let sealed_key_path = dir.join(SEALED_KEY_FILE_NAME);
+        let legacy_key_path = dir.join(LEGACY_KEY_FILE_NAME);
 
-        if !cert_der_path.exists() || !key_der_path.exists() {
-            return Err(DeviceError::certs_not_found(self.base_path.display().to_string()).into());
+        if !cert_der_path.exists() || !(sealed_key_path.exists() || legacy_key_path.exists()) {
+            return Err(DeviceError::certs_not_found(dir.display().to_string()).into());
         }
 
         let cert_der = fs::read(&cert_der_path)
             .with_context(|| format!("Failed to read certificate from {:?}", cert_der_path))?;
 
-        let key_der = fs::read(&key_der_path)
-            .with_context(|| format!("Failed to read key from {:?}", key_der_path))?;
-
-        debug!("Successfully loaded certificates from {:?}", self.base_path);
+        let key_der = if sealed_key_path.exists() {
+            let sealed = fs::read(&sealed_key_path)
+                .with_context(|| format!("Failed to read key from {:?}", sealed_key_path))?;
+            self.config
+                .protection
+                .open(&self.base_path, &sealed)
+                .with_context(|| format!("Failed to decrypt key from {:?}", sealed_key_path))?
+        } else {
+            self.migrate_plaintext_key(dir)?
+        };
+
+        debug!("Successfully loaded certificates from {:?}", dir);
         Ok((cert_der, key_der))
     }
 
+    /// Encrypts a key left in plaintext by an earlier version and removes the plaintext copies.
+    fn migrate_plaintext_key(&self, dir: &Path) -> DeviceResult<Vec<u8>> {
+        let legacy_key_path = dir.join(LEGACY_KEY_FILE_NAME);
+        let key_der = fs::read(&legacy_key_path)
+            .with_context(|| format!("Failed to read key from {:?}", legacy_key_path))?;
+
+        self.write_private_key(dir, &key_der)?;
+        key_store::remove_plaintext(&legacy_key_path);
+        let pem_key_path = dir.join("key.pem");
+        if self.config.write_pem {
+            if pem_key_path.exists() {
+                key_store::restrict_to_owner(&pem_key_path)?;
+            }
+        } else {
+            key_store::remove_plaintext(&pem_key_path);
+        }
+
+        info!("Encrypted the plaintext device key in {:?}", dir);
+        Ok(key_der)
+    }
+
+    fn write_private_key(&self, dir: &Path, key_der: &[u8]) -> DeviceResult<()> {
+        let sealed = self.config.protection.seal(&self.base_path, key_der)?;
+        let sealed_key_path = dir.join(SEALED_KEY_FILE_NAME);
+        key_store::write_owner_only(&sealed_key_path, &sealed)
+            .with_context(|| format!("Failed to write encrypted key to {:?}", sealed_key_path))
+    }
+
     pub fn generate_certificates(&self, host: String) -> DeviceResult<KeyDer> {
+        self.generate_certificates_in(&self.base_path, &host, None)
+    }
+
+    /// Writes a new key and certificate to `dir`. The certificate is self-signed, or signed
+    /// by `issuer` when rotating, recording which key it replaced.
+    fn generate_certificates_in(
+        &self,
+        dir: &Path,
+        host: &str,
+        issuer: Option<&KeyDer>,
+    ) -> DeviceResult<KeyDer> {
         info!("Generating new TLS certificates for host: {}", host);
 
-        if !self.base_path.exists() {
-            fs::create_dir_all(&self.base_path)
-                .map_err(|e| DeviceError::storage_dir(self.base_path.display().to_string(), e))?;
-            debug!("Created storage directory: {:?}", self.base_path);
+        if !dir.exists() {
+            fs::create_dir_all(dir)
+                .map_err(|e| DeviceError::storage_dir(dir.display().to_string(), e))?;
+            debug!("Created storage directory: {:?}", dir);
+        }
+        if let Err(e) = key_store::restrict_to_owner(dir) {
+            warn!("{:#}", e);
         }
 
-        let san_names = vec![host.clone(), self.config.common_name.clone()];
-        let params = CertificateParams::new(san_names.clone()).map_err(|e| {
+        let san_names = vec![host.to_string(), self.config.common_name.clone()];
+        let mut params = CertificateParams::new(san_names.clone()).map_err(|e| {
             DeviceError::cert_params(format!(
                 "Failed to create certificate params for {:?}: {}",
                 san_names, e
             ))
         })?;
 
+        let not_before = Utc::now().date_naive();
+        let not_after = not_before + Days::new(CERT_VALIDITY_DAYS as u64);
+        let cert_date = |date: NaiveDate| {
+            rcgen::date_time_ymd(date.year(), date.month() as u8, date.day() as u8)
+        };
+        params.not_before = cert_date(not_before);
+        params.not_after = cert_date(not_after);
+
         let key_pair = KeyPair::generate_for(&rcgen::PKCS_ED25519).map_err(|e| {
             DeviceError::key_gen(format!("Failed to generate Ed25519 key pair: {}", e))
         })?;
 
         debug!("Generated Ed25519 key pair");
 
-        let cert = params.self_signed(&key_pair).map_err(|e| {
-            DeviceError::cert_gen(format!("Failed to self-sign certificate: {}", e))
-        })?;
-
-        let pem_cert_path = self.base_path.join("cert.pem");
-        let pem_key_path = self.base_path.join("key.pem");
-
-        if let Err(e) = fs::write(&pem_cert_path, cert.pem()) {
-            warn!(
-                "Failed to write PEM certificate to {:?}: {}",
-                pem_cert_path, e
-            );
-        }
-        if let Err(e) = fs::write(&pem_key_path, key_pair.serialize_pem()) {
-            warn!("Failed to write PEM key to {:?}: {}", pem_key_path, e);
+        let cert = match issuer {
+            Some(issuer) => {
+                let issuer_key = KeyPair::try_from(issuer.key_der.as_slice()).map_err(|e| {
+                    DeviceError::key_gen(format!("Failed to load the retiring key: {}", e))
+                })?;
+                // Same default subject as the issuer's certificate, which was made here too.
+                let issuer = Issuer::new(CertificateParams::new(Vec::<String>::new())?, issuer_key);
+                params.signed_by(&key_pair, &issuer).map_err(|e| {
+                    DeviceError::cert_gen(format!("Failed to sign certificate: {}", e))
+                })?
+            }
+            None => params.self_signed(&key_pair).map_err(|e| {
+                DeviceError::cert_gen(format!("Failed to self-sign certificate: {}", e))
+            })?,
+        };
+
+        if self.config.write_pem {
+            let pem_cert_path = dir.join("cert.pem");
+            let pem_key_path = dir.join("key.pem");
+
+            if let Err(e) = fs::write(&pem_cert_path, cert.pem()) {
+                warn!(
+                    "Failed to write PEM certificate to {:?}: {}",
+                    pem_cert_path, e
+                );
+            }
+            if let Err(e) =
+                key_store::write_owner_only(&pem_key_path, key_pair.serialize_pem().as_bytes())
+            {
+                warn!("Failed to write PEM key to {:?}: {:#}", pem_key_path, e);
+            }
         }
 
         let cert_der = cert.der().to_vec();
         let key_der = key_pair.serialize_der().to_vec();
 
-        let der_cert_path = self.base_path.join("cert.der");
-        let der_key_path = self.base_path.join("key.der");
+        let der_cert_path = dir.join("cert.der");
 
         fs::write(&der_cert_path, &cert_der)
             .with_context(|| format!("Failed to write DER certificate to {:?}", der_cert_path))?;
 
-        fs::write(&der_key_path, &key_der)
-            .with_context(|| format!("Failed to write DER key to {:?}", der_key_path))?;
+        self.write_private_key(dir, &key_der)?;
+
+        let validity = CertificateValidity {
+            not_after_ms: not_after
+                .and_time(NaiveTime::MIN)
+                .and_utc()
+                .timestamp_millis(),
+        };
+        let validity_path = dir.join(VALIDITY_FILE_NAME);
+        fs::write(&validity_path, serde_json::to_vec_pretty(&validity)?).with_context(|| {
+            format!(
+                "Failed to write certificate validity to {:?}",
+                validity_path
+            )
+        })?;
 
         let key_der_result = KeyDer::new(cert_der, key_der);
 
         info!(
-            "Successfully generated and saved TLS certificates to {:?} (fingerprint: {})",
-            self.base_path, key_der_result.fingerprint
+            "Successfully generated and saved TLS certificates to {:?} (fingerprint: {}, expires {})",
+            dir, key_der_result.fingerprint, not_after
         );
 
         Ok(key_der_result)
     }
+
+    /// When the current certificate stops being valid. `None` for certificates created
+    /// before expiry was tracked, which rcgen made valid until the year 4096.
+    pub fn certificate_expiry_ms(&self) -> Option<i64> {
+        let raw = fs::read(self.base_path.join(VALIDITY_FILE_NAME)).ok()?;
+        serde_json::from_slice::<CertificateValidity>(&raw)
+            .map(|validity| validity.not_after_ms)
+            .ok()
+    }
+
+    /// The rotation started by [`Self::rotate_key`] whose grace period has not been retired.
+    pub fn pending_rotation(&self) -> Option<RotationStatement> {
+        let path = self.base_path.join(ROTATION_FILE_NAME);
+        let raw = fs::read(&path).ok()?;
+        serde_json::from_slice(&raw)
+            .map_err(|e| warn!("Ignoring malformed rotation statement in {:?}: {}", path, e))
+            .ok()
+    }
+
+    /// Replaces `current` with a new key, which is used from now on. Its certificate is
+    /// signed by `current` and the hand-over statement is kept for `grace`, during which
+    /// peers that still pin `current` keep accepting it and learn about the new one; see
+    /// [`Self::retire_rotation`].
+    pub fn rotate_key(
+        &self,
+        host: String,
+        current: &KeyDer,
+        grace: ChronoDuration,
+    ) -> DeviceResult<(RotationStatement, KeyDer)> {
+        let next_dir = self.base_path.join(NEXT_KEY_DIR);
+        let next = self.generate_certificates_in(&next_dir, &host, Some(current))?;
+
+        let issued_at = Utc::now();
+        let statement = RotationStatement::sign(
+            current,
+            &next,
+            issued_at.timestamp_millis(),
+            (issued_at + grace).timestamp_millis(),
+        )?;
+
+        let path = self.base_path.join(ROTATION_FILE_NAME);
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(&statement)?)
+            .with_context(|| format!("Failed to write rotation statement to {:?}", tmp_path))?;
+        fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Failed to replace rotation statement at {:?}", path))?;
+
+        // From here on a restart finishes the hand-over, see `recover_rotation`.
+        self.promote_next_key()?;
+
+        info!(
+            "Rotated device key {} -> {}, peers accept the old key until {}",
+            statement.old_fingerprint,
+            statement.new_fingerprint,
+            issued_at + grace
+        );
+
+        Ok((statement, next))
+    }
+
+    /// Forgets the rotation statement once its grace period is over; the old key is no
+    /// longer presented alongside the new one or announced to peers.
+    pub fn retire_rotation(&self) -> DeviceResult<()> {
+        let rotation_path = self.base_path.join(ROTATION_FILE_NAME);
+        fs::remove_file(&rotation_path)
+            .with_context(|| format!("Failed to remove {:?}", rotation_path))?;
+
+        info!("Key rotation grace period is over");
+        Ok(())
+    }
+
+    /// Finishes a rotation interrupted between writing its statement and moving the new
+    /// key in place, or drops a new key no statement was written for.
+    fn recover_rotation(&self) -> DeviceResult<()> {
+        let next_dir = self.base_path.join(NEXT_KEY_DIR);
+        if !next_dir.exists() {
+            return Ok(());
+        }
+
+        let fingerprint_in = |dir: &Path| {
+            fs::read(dir.join("cert.der"))
+                .ok()
+                .map(|cert_der| KeyDer::compute_fingerprint(&cert_der))
+        };
+        let rotating_to = self
+            .pending_rotation()
+            .map(|statement| statement.new_fingerprint);
+        let promoting = rotating_to.is_some()
+            && (fingerprint_in(&next_dir) == rotating_to
+                || fingerprint_in(&self.base_path) == rotating_to);
+
+        if promoting {
+            info!("Finishing an interrupted key rotation");
+            self.promote_next_key()
+        } else {
+            warn!("Discarding a replacement key that was never announced");
+            fs::remove_dir_all(&next_dir)
+                .with_context(|| format!("Failed to remove {:?}", next_dir))
+        }
+    }
+
+    /// Moves the key generated by [`Self::rotate_key`] over the device key.
+    fn promote_next_key(&self) -> DeviceResult<()> {
+        let next_dir = self.base_path.join(NEXT_KEY_DIR);
+
+        // Stale plaintext copies of the retiring key must not outlive it.
+        for name in ["key.pem", LEGACY_KEY_FILE_NAME] {
+            key_store::remove_plaintext(&self.base_path.join(name));
+        }
+        for name in KEY_FILE_NAMES {
+            let from = next_dir.join(name);
+            if from.exists() {
+                let to = self.base_path.join(name);
+                fs::rename(&from, &to)
+                    .with_context(|| format!("Failed to move {:?} to {:?}", from, to))?;
+            }
+        }
+
+        if let Err(e) = fs::remove_dir_all(&next_dir) {
+            warn!("Failed to remove {:?}: {}", next_dir, e);
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,211 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use log::{info, warn};
+use nkcrypto::{generate_key, Cipher, PasswordKdfParams, KEY_SIZE, PASSWORD_PARAMS_SIZE};
+
+use super::error::{DeviceError, DeviceResult};
+
+/// Set to encrypt the device key with a passphrase instead of the key file.
+pub const PASSPHRASE_ENV: &str = "NEKOSHARE_KEY_PASSPHRASE";
+const KEY_FILE_NAME: &str = "storage.key";
+
+const SEALED_MAGIC: &[u8; 5] = b"NKKEY";
+const SEALED_VERSION: u8 = 1;
+const KIND_KEY_FILE: u8 = 0;
+const KIND_PASSPHRASE: u8 = 1;
+
+/// Where the key that encrypts the device's private key comes from.
+#[derive(Clone)]
+pub enum KeyProtection {
+    /// Random key kept in a file readable only by the owner.
+    KeyFile(Option<PathBuf>),
+    /// Key derived from a passphrase with PBKDF2-HMAC-SHA256.
+    Passphrase(String),
+}
+
+impl KeyProtection {
+    pub fn from_env() -> Self {
+        match std::env::var(PASSPHRASE_ENV) {
+            Ok(passphrase) if !passphrase.is_empty() => Self::Passphrase(passphrase),
+            _ => Self::KeyFile(None),
+        }
+    }
+
+    /// Encrypts a PKCS#8 private key into the on-disk format.
+    pub fn seal(&self, storage_dir: &Path, key_der: &[u8]) -> DeviceResult<Vec<u8>> {
+        let mut sealed = Vec::with_capacity(key_der.len() + 64);
+        sealed.extend_from_slice(SEALED_MAGIC);
+        sealed.push(SEALED_VERSION);
+
+        let key = match self {
+            Self::KeyFile(path) => {
+                sealed.push(KIND_KEY_FILE);
+                load_or_create_key_file(&key_file_path(storage_dir, path.as_deref()))?
+            }
+            Self::Passphrase(passphrase) => {
+                let params = PasswordKdfParams::generate();
+                sealed.push(KIND_PASSPHRASE);
+                sealed.extend_from_slice(&params.to_bytes());
+                derive_key(passphrase, &params)?
+            }
+        };
+
+        let ciphertext = Cipher::new(&key)
+            .encrypt(key_der)
+            .map_err(|e| DeviceError::key_protection(format!("encryption failed: {}", e)))?;
+        sealed.extend_from_slice(&ciphertext);
+
+        Ok(sealed)
+    }
+
+    /// Decrypts what [`Self::seal`] wrote. The kind recorded in the file wins, so a key
+    /// sealed with a passphrase cannot be opened without one.
+    pub fn open(&self, storage_dir: &Path, sealed: &[u8]) -> DeviceResult<Vec<u8>> {
+        let header_len = SEALED_MAGIC.len() + 2;
+        if sealed.len() < header_len || &sealed[..SEALED_MAGIC.len()] != SEALED_MAGIC {
+            return Err(DeviceError::key_protection("not a sealed device key").into());
+        }
+        if sealed[SEALED_MAGIC.len()] != SEALED_VERSION {
+            return Err(DeviceError::key_protection(format!(
+                "unsupported sealed key version {}",
+                sealed[SEALED_MAGIC.len()]
+            ))
+            .into());
+        }
+
+        let kind = sealed[SEALED_MAGIC.len() + 1];
+        let rest = &sealed[header_len..];
+        let (key, ciphertext) = match (kind, self) {
+            (KIND_KEY_FILE, Self::KeyFile(path)) => {
+                let path = key_file_path(storage_dir, path.as_deref());
+                if !path.exists() {
+                    return Err(DeviceError::key_protection(format!(
+                        "key file {:?} is missing",
+                        path
+                    ))
+                    .into());
+                }
+                (load_or_create_key_file(&path)?, rest)
+            }
+            (KIND_PASSPHRASE, Self::Passphrase(passphrase)) => {
+                let params = PasswordKdfParams::from_bytes(rest)
+                    .map_err(|_| DeviceError::key_protection("sealed key is truncated"))?;
+                (
+                    derive_key(passphrase, &params)?,
+                    &rest[PASSWORD_PARAMS_SIZE..],
+                )
+            }
+            (KIND_PASSPHRASE, Self::KeyFile(_)) => {
+                return Err(DeviceError::key_protection(format!(
+                    "the device key is protected by a passphrase; set {}",
+                    PASSPHRASE_ENV
+                ))
+                .into());
+            }
+            (KIND_KEY_FILE, Self::Passphrase(_)) => {
+                return Err(DeviceError::key_protection(format!(
+                    "the device key is protected by a key file; unset {}",
+                    PASSPHRASE_ENV
+                ))
+                .into());
+            }
+            (other, _) => {
+                return Err(DeviceError::key_protection(format!(
+                    "unknown key protection kind {}",
+                    other
+                ))
+                .into());
+            }
+        };
+
+        Cipher::new(&key).decrypt(ciphertext).map_err(|_| {
+            DeviceError::key_protection("decryption failed, wrong passphrase or key file").into()
+        })
+    }
+}
+
+impl std::fmt::Debug for KeyProtection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::KeyFile(path) => f.debug_tuple("KeyFile").field(path).finish(),
+            Self::Passphrase(_) => f.write_str("Passphrase(..)"),
+        }
+    }
+}
+
+fn key_file_path(storage_dir: &Path, configured: Option<&Path>) -> PathBuf {
+    configured
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| storage_dir.join(KEY_FILE_NAME))
+}
+
+fn load_or_create_key_file(path: &Path) -> DeviceResult<[u8; KEY_SIZE]> {
+    if path.exists() {
+        let raw = fs::read(path).with_context(|| format!("Failed to read key file {:?}", path))?;
+        return raw.as_slice().try_into().map_err(|_| {
+            DeviceError::key_protection(format!("key file {:?} must hold {} bytes", path, KEY_SIZE))
+                .into()
+        });
+    }
+
+    let key = generate_key();
+    write_owner_only(path, &key)?;
+    info!("Created device key file at {:?}", path);
+    Ok(key)
+}
+
+fn derive_key(passphrase: &str, params: &PasswordKdfParams) -> DeviceResult<[u8; KEY_SIZE]> {
+    params
+        .derive_key(passphrase.as_bytes())
+        .map_err(|e| DeviceError::key_protection(format!("key derivation failed: {}", e)).into())
+}
+
+/// Writes through a temporary file and leaves the result readable only by the owner.
+pub fn write_owner_only(path: &Path, contents: &[u8]) -> DeviceResult<()> {
+    let tmp_path = path.with_extension("tmp");
+
+    let _ = fs::remove_file(&tmp_path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(&tmp_path)
+        .and_then(|mut file| file.write_all(contents))
+        .with_context(|| format!("Failed to write {:?}", tmp_path))?;
+    fs::rename(&tmp_path, path).with_context(|| format!("Failed to replace {:?}", path))?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn restrict_to_owner(path: &Path) -> DeviceResult<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = if path.is_dir() { 0o700 } else { 0o600 };
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to restrict permissions of {:?}", path))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn restrict_to_owner(path: &Path) -> DeviceResult<()> {
+    // Per-user app data directories are already private on the other desktop platforms.
+    let _ = path;
+    Ok(())
+}
+
+/// Removes a file holding key material, logging rather than failing if it cannot be.
+pub fn remove_plaintext(path: &Path) {
+    if path.exists() {
+        if let Err(e) = fs::remove_file(path) {
+            warn!("Failed to remove plaintext key {:?}: {}", path, e);
+        }
+    }
+}
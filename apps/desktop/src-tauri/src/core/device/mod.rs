@@ -1,11 +1,19 @@
 pub mod error;
 pub mod info;
 pub mod key;
+pub mod key_store;
+pub mod rotation;
+pub mod signature;
+pub mod trust;
 
 pub use error::{CommandError, DeviceResult};
 pub use key::{KeyDer, KeyManager};
+pub use rotation::RotationStatement;
+pub use trust::{TrustStore, TrustedDevice};
 
-use anyhow::Context;
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Context};
 
 use crate::core::device::info::{DeviceInfo, DeviceInfoManager};
 
@@ -24,7 +32,11 @@ pub struct DeviceInfoWithFingerprint {
 
 pub struct DeviceManager {
     device_info_manager: DeviceInfoManager,
-    key_der: KeyDer,
+    key_manager: KeyManager,
+    key_der: RwLock<KeyDer>,
+    /// Set during the grace period of a rotation, while peers may still pin the old key.
+    rotation: RwLock<Option<RotationStatement>>,
+    trust_store: Arc<TrustStore>,
 }
 
 impl DeviceManager {
@@ -36,29 +48,94 @@ impl DeviceManager {
         let key_der = key_manager
             .get_or_create(device_info.id.clone())
             .context("Failed to get or create TLS certificates")?;
+        let trust_store = TrustStore::open(key_manager.storage_dir())
+            .context("Failed to load trusted devices")?;
+        let rotation = key_manager.pending_rotation();
 
         Ok(Self {
             device_info_manager,
-            key_der,
+            key_manager,
+            key_der: RwLock::new(key_der),
+            rotation: RwLock::new(rotation),
+            trust_store: Arc::new(trust_store),
         })
     }
 
     pub fn info(&self) -> DeviceResult<DeviceInfoWithFingerprint> {
         Ok(DeviceInfoWithFingerprint {
             device_info: self.device_info_manager.info(),
-            fingerprint: self.key_der.fingerprint.clone(),
+            fingerprint: self.key()?.fingerprint,
         })
     }
 
+    /// The key presented to peers.
     pub fn key(&self) -> DeviceResult<KeyDer> {
-        Ok(self.key_der.clone())
+        self.key_der
+            .read()
+            .map(|key| key.clone())
+            .map_err(|_| anyhow!("Device key lock poisoned"))
+    }
+
+    /// Replaces the device key, which is presented from now on. Until `grace` has passed
+    /// the returned statement is sent to peers, so those that pinned the old key can
+    /// follow along.
+    pub fn rotate_key(&self, grace: chrono::Duration) -> DeviceResult<RotationStatement> {
+        let current = self.key()?;
+        let host = self.device_info_manager.info().id;
+        let (statement, next) = self.key_manager.rotate_key(host, &current, grace)?;
+
+        *self
+            .key_der
+            .write()
+            .map_err(|_| anyhow!("Device key lock poisoned"))? = next;
+        *self
+            .rotation
+            .write()
+            .map_err(|_| anyhow!("Key rotation lock poisoned"))? = Some(statement.clone());
+
+        Ok(statement)
+    }
+
+    /// The rotation whose grace period is still running, if any.
+    pub fn pending_rotation(&self) -> Option<RotationStatement> {
+        if let Err(e) = self.retire_rotation_if_due() {
+            log::warn!("Failed to retire key rotation: {:#}", e);
+        }
+
+        self.rotation
+            .read()
+            .ok()
+            .and_then(|rotation| rotation.clone())
+    }
+
+    fn retire_rotation_if_due(&self) -> DeviceResult<()> {
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let mut rotation = self
+            .rotation
+            .write()
+            .map_err(|_| anyhow!("Key rotation lock poisoned"))?;
+        if !rotation
+            .as_ref()
+            .is_some_and(|statement| statement.grace_elapsed(now_ms))
+        {
+            return Ok(());
+        }
+
+        *rotation = None;
+        self.key_manager.retire_rotation()
+    }
+
+    pub fn trust_store(&self) -> Arc<TrustStore> {
+        self.trust_store.clone()
     }
 
     pub fn info_with_key(&self) -> DeviceResult<DeviceInfoWithKey> {
+        let key = self.key()?;
+
         Ok(DeviceInfoWithKey {
             device_info: self.device_info_manager.info(),
-            key: self.key_der.clone(),
-            fingerprint: self.key_der.fingerprint.clone(),
+            fingerprint: key.fingerprint.clone(),
+            key,
         })
     }
 }
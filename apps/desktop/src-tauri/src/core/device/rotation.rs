@@ -0,0 +1,73 @@
+use super::error::{DeviceError, DeviceResult};
+use super::key::KeyDer;
+use super::signature;
+
+const ROTATION_STATEMENT_LABEL: &str = "nekoshare-key-rotation-v1";
+
+/// Announces that a device replaced its key. Signed with the key being retired, so a peer
+/// that pinned the old certificate can move its pin without pairing again.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RotationStatement {
+    pub old_fingerprint: String,
+    pub new_fingerprint: String,
+    /// Hex DER of the retired certificate, whose key made `signature`.
+    pub old_certificate: String,
+    pub issued_at_ms: i64,
+    /// Until then peers keep accepting the old certificate, capped by their own maximum.
+    pub grace_until_ms: i64,
+    pub signature: String,
+}
+
+impl RotationStatement {
+    pub fn sign(
+        old: &KeyDer,
+        new: &KeyDer,
+        issued_at_ms: i64,
+        grace_until_ms: i64,
+    ) -> DeviceResult<Self> {
+        let mut statement = Self {
+            old_fingerprint: old.fingerprint.clone(),
+            new_fingerprint: new.fingerprint.clone(),
+            old_certificate: hex::encode(&old.cert_der),
+            issued_at_ms,
+            grace_until_ms,
+            signature: String::new(),
+        };
+        let signature = signature::sign(old, statement.signed_message().as_bytes())?;
+        statement.signature = hex::encode(signature);
+
+        Ok(statement)
+    }
+
+    /// Checks that the statement was signed by the certificate it names as retired.
+    pub fn verify(&self) -> DeviceResult<()> {
+        let invalid = |reason: &str| DeviceError::invalid_rotation(reason);
+
+        let cert_der = hex::decode(&self.old_certificate)
+            .map_err(|_| invalid("old certificate is not hex"))?;
+        if KeyDer::compute_fingerprint(&cert_der) != self.old_fingerprint {
+            return Err(invalid("old certificate does not match the old fingerprint").into());
+        }
+        let signature =
+            hex::decode(&self.signature).map_err(|_| invalid("signature is not hex"))?;
+
+        signature::verify(&cert_der, self.signed_message().as_bytes(), &signature)
+            .map_err(|reason| invalid(&format!("old certificate: {}", reason)).into())
+    }
+
+    pub fn grace_elapsed(&self, now_ms: i64) -> bool {
+        now_ms >= self.grace_until_ms
+    }
+
+    fn signed_message(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n{}",
+            ROTATION_STATEMENT_LABEL,
+            self.old_fingerprint,
+            self.new_fingerprint,
+            self.issued_at_ms,
+            self.grace_until_ms
+        )
+    }
+}
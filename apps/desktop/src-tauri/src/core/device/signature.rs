@@ -0,0 +1,42 @@
+use rcgen::{KeyPair, SigningKey};
+use rustls::crypto::{aws_lc_rs, CryptoProvider};
+use rustls::pki_types::CertificateDer;
+use rustls::SignatureScheme;
+use webpki::EndEntityCert;
+
+use super::error::{DeviceError, DeviceResult};
+use super::key::KeyDer;
+
+/// Signs `message` with the device key. Device keys are Ed25519, see
+/// [`super::KeyManager::generate_certificates`].
+pub fn sign(key: &KeyDer, message: &[u8]) -> DeviceResult<Vec<u8>> {
+    let key_pair = KeyPair::try_from(key.key_der.as_slice())
+        .map_err(|e| DeviceError::key_gen(format!("Failed to load the device key: {}", e)))?;
+
+    key_pair
+        .sign(message)
+        .map_err(|e| DeviceError::key_gen(format!("Failed to sign: {}", e)).into())
+}
+
+/// Checks an Ed25519 `signature` over `message` against the key of the DER certificate
+/// `cert_der`. The error says what was wrong.
+pub fn verify(cert_der: &[u8], message: &[u8], signature: &[u8]) -> Result<(), &'static str> {
+    let cert_der = CertificateDer::from(cert_der);
+    let cert = EndEntityCert::try_from(&cert_der).map_err(|_| "malformed certificate")?;
+
+    let algorithms = CryptoProvider::get_default()
+        .map(|provider| provider.signature_verification_algorithms)
+        .unwrap_or_else(|| aws_lc_rs::default_provider().signature_verification_algorithms);
+    let verified = algorithms
+        .mapping
+        .iter()
+        .filter(|(scheme, _)| *scheme == SignatureScheme::ED25519)
+        .flat_map(|(_, algs)| algs.iter())
+        .any(|alg| cert.verify_signature(*alg, message, signature).is_ok());
+
+    if verified {
+        Ok(())
+    } else {
+        Err("signature does not verify against the certificate")
+    }
+}
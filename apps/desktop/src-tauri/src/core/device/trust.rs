@@ -0,0 +1,397 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use anyhow::Context;
+use log::{debug, info, warn};
+
+use super::error::{DeviceError, DeviceResult};
+use super::key::MAX_ROTATION_GRACE_HOURS;
+use super::rotation::RotationStatement;
+
+const TRUST_FILE_NAME: &str = "trusted_devices.json";
+
+/// A peer whose certificate fingerprint may connect without being supplied per transfer.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrustedDevice {
+    pub fingerprint: String,
+    #[serde(default)]
+    pub device_id: Option<String>,
+    #[serde(default)]
+    pub name: Option<String>,
+    pub added_at_ms: i64,
+    /// Set once the device rotated away from this key; the pin lapses at that time.
+    #[serde(default)]
+    pub expires_at_ms: Option<i64>,
+    /// The key this device rotated to, recorded with `expires_at_ms`. Later statements for
+    /// the same key must name the same successor.
+    #[serde(default)]
+    pub successor_fingerprint: Option<String>,
+}
+
+impl TrustedDevice {
+    fn is_current(&self, now_ms: i64) -> bool {
+        self.expires_at_ms
+            .map_or(true, |expires_at_ms| now_ms < expires_at_ms)
+    }
+
+    fn is_retiring(&self) -> bool {
+        self.expires_at_ms.is_some()
+    }
+}
+
+/// Lower-cases a hex SHA-256 certificate fingerprint, rejecting anything else.
+pub fn normalize_fingerprint(fingerprint: &str) -> Option<String> {
+    let fingerprint = fingerprint.trim();
+    if fingerprint.len() != 64 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    Some(fingerprint.to_ascii_lowercase())
+}
+
+/// The allowlist of trusted devices, kept as JSON next to the device's own key.
+#[derive(Debug)]
+pub struct TrustStore {
+    path: PathBuf,
+    devices: RwLock<Vec<TrustedDevice>>,
+}
+
+impl TrustStore {
+    pub fn open(storage_dir: &Path) -> DeviceResult<Self> {
+        let path = storage_dir.join(TRUST_FILE_NAME);
+
+        let devices = if path.exists() {
+            let raw = fs::read(&path)
+                .with_context(|| format!("Failed to read trusted devices from {:?}", path))?;
+            // An unreadable list trusts nobody rather than keeping the app from starting.
+            serde_json::from_slice(&raw).unwrap_or_else(|e| {
+                warn!("Ignoring malformed trusted devices in {:?}: {}", path, e);
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+
+        debug!("Loaded {} trusted devices from {:?}", devices.len(), path);
+
+        Ok(Self {
+            path,
+            devices: RwLock::new(devices),
+        })
+    }
+
+    pub fn list(&self) -> Vec<TrustedDevice> {
+        match self.devices.read() {
+            Ok(devices) => devices.clone(),
+            Err(e) => {
+                warn!("Trusted device list lock poisoned: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    pub fn is_trusted(&self, fingerprint: &str) -> bool {
+        let Some(fingerprint) = normalize_fingerprint(fingerprint) else {
+            return false;
+        };
+
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        self.devices
+            .read()
+            .map(|devices| {
+                devices
+                    .iter()
+                    .any(|d| d.fingerprint == fingerprint && d.is_current(now_ms))
+            })
+            .unwrap_or(false)
+    }
+
+    /// The most recently added entry recorded for `device_id`.
+    pub fn find_by_device_id(&self, device_id: &str) -> Option<TrustedDevice> {
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        self.devices.read().ok().and_then(|devices| {
+            devices
+                .iter()
+                .filter(|d| {
+                    d.is_current(now_ms)
+                        && d.device_id
+                            .as_deref()
+                            .is_some_and(|id| id.eq_ignore_ascii_case(device_id))
+                })
+                .max_by_key(|d| d.added_at_ms)
+                .cloned()
+        })
+    }
+
+    /// Adds `fingerprint`, or refreshes the name and device id of an existing entry.
+    pub fn add(
+        &self,
+        fingerprint: &str,
+        device_id: Option<String>,
+        name: Option<String>,
+    ) -> DeviceResult<TrustedDevice> {
+        let fingerprint = normalize_fingerprint(fingerprint)
+            .ok_or_else(|| DeviceError::invalid_fingerprint(fingerprint))?;
+
+        let mut devices = self
+            .devices
+            .write()
+            .map_err(|_| anyhow::anyhow!("Trusted device list lock poisoned"))?;
+
+        let mut updated = devices.clone();
+        let device = match updated.iter_mut().find(|d| d.fingerprint == fingerprint) {
+            Some(existing) => {
+                existing.device_id = device_id.or(existing.device_id.take());
+                existing.name = name.or(existing.name.take());
+                existing.expires_at_ms = None;
+                existing.successor_fingerprint = None;
+                existing.clone()
+            }
+            None => {
+                let device = TrustedDevice {
+                    fingerprint,
+                    device_id,
+                    name,
+                    added_at_ms: chrono::Utc::now().timestamp_millis(),
+                    expires_at_ms: None,
+                    successor_fingerprint: None,
+                };
+                updated.push(device.clone());
+                device
+            }
+        };
+
+        self.save(&updated)?;
+        *devices = updated;
+        info!("Trusted device {}", device.fingerprint);
+        Ok(device)
+    }
+
+    /// Moves the pin of a trusted device to the key named in its rotation statement, which
+    /// the caller has verified. The old key stays trusted until the statement's grace period
+    /// ends, but never longer than it already was nor more than [`MAX_ROTATION_GRACE_HOURS`]
+    /// from now: whoever holds the old key can sign statements, and a rotation is how a
+    /// leaked key gets retired. A key is only ever handed to one successor; a statement
+    /// naming another one is rejected. Returns `false` when the old key was not trusted.
+    pub fn apply_rotation(&self, statement: &RotationStatement) -> DeviceResult<bool> {
+        let old = normalize_fingerprint(&statement.old_fingerprint)
+            .ok_or_else(|| DeviceError::invalid_fingerprint(&statement.old_fingerprint))?;
+        let new = normalize_fingerprint(&statement.new_fingerprint)
+            .ok_or_else(|| DeviceError::invalid_fingerprint(&statement.new_fingerprint))?;
+
+        let mut devices = self
+            .devices
+            .write()
+            .map_err(|_| anyhow::anyhow!("Trusted device list lock poisoned"))?;
+
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let mut updated = devices.clone();
+        let Some(retiring) = updated
+            .iter_mut()
+            .find(|d| d.fingerprint == old && d.is_current(now_ms))
+        else {
+            return Ok(false);
+        };
+        if retiring.is_retiring() {
+            // Entries rotated before successors were recorded moved to the key trusted with them.
+            let recorded = retiring.successor_fingerprint.clone().or_else(|| {
+                devices
+                    .iter()
+                    .any(|d| d.fingerprint == new)
+                    .then(|| new.clone())
+            });
+            if recorded.as_deref() != Some(&new) {
+                return Err(DeviceError::invalid_rotation(format!(
+                    "{} already rotated to {}",
+                    old,
+                    recorded.as_deref().unwrap_or("<unknown>")
+                ))
+                .into());
+            }
+        }
+
+        let max_grace_until_ms = now_ms + MAX_ROTATION_GRACE_HOURS * 60 * 60 * 1000;
+        let expires_at_ms = statement
+            .grace_until_ms
+            .min(max_grace_until_ms)
+            .min(retiring.expires_at_ms.unwrap_or(i64::MAX));
+        // Peers repeat the statement on every connection until the grace period ends.
+        let already_applied = retiring.expires_at_ms == Some(expires_at_ms);
+        retiring.expires_at_ms = Some(expires_at_ms);
+        retiring.successor_fingerprint = Some(new.clone());
+        let (device_id, name) = (retiring.device_id.clone(), retiring.name.clone());
+        if already_applied && devices.iter().any(|d| d.fingerprint == new) {
+            return Ok(true);
+        }
+
+        match updated.iter_mut().find(|d| d.fingerprint == new) {
+            Some(existing) => existing.expires_at_ms = None,
+            None => updated.push(TrustedDevice {
+                fingerprint: new.clone(),
+                device_id,
+                name,
+                added_at_ms: now_ms,
+                expires_at_ms: None,
+                successor_fingerprint: None,
+            }),
+        }
+
+        self.save(&updated)?;
+        *devices = updated;
+        info!("Trusted device rotated its key {} -> {}", old, new);
+        Ok(true)
+    }
+
+    /// Removes `fingerprint`; returns whether it was trusted.
+    pub fn revoke(&self, fingerprint: &str) -> DeviceResult<bool> {
+        let fingerprint = normalize_fingerprint(fingerprint)
+            .ok_or_else(|| DeviceError::invalid_fingerprint(fingerprint))?;
+
+        let mut devices = self
+            .devices
+            .write()
+            .map_err(|_| anyhow::anyhow!("Trusted device list lock poisoned"))?;
+
+        let updated: Vec<TrustedDevice> = devices
+            .iter()
+            .filter(|d| d.fingerprint != fingerprint)
+            .cloned()
+            .collect();
+        if updated.len() == devices.len() {
+            return Ok(false);
+        }
+
+        self.save(&updated)?;
+        *devices = updated;
+        info!("Revoked trusted device {}", fingerprint);
+        Ok(true)
+    }
+
+    /// Writes through a temporary file so a crash never leaves a truncated list behind.
+    fn save(&self, devices: &[TrustedDevice]) -> DeviceResult<()> {
+        let json =
+            serde_json::to_vec_pretty(devices).context("Failed to serialize trusted devices")?;
+        let tmp_path = self.path.with_extension("json.tmp");
+
+        fs::write(&tmp_path, json)
+            .with_context(|| format!("Failed to write trusted devices to {:?}", tmp_path))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to replace trusted devices at {:?}", self.path))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+    const NEW: &str = "2222222222222222222222222222222222222222222222222222222222222222";
+    const OTHER: &str = "3333333333333333333333333333333333333333333333333333333333333333";
+    const HOUR_MS: i64 = 60 * 60 * 1000;
+
+    /// A trust store in a fresh directory, removed again on drop.
+    struct TempStore {
+        dir: PathBuf,
+        store: TrustStore,
+    }
+
+    impl TempStore {
+        fn trusting(fingerprint: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("nekoshare-test-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            let store = TrustStore::open(&dir).unwrap();
+            store.add(fingerprint, None, None).unwrap();
+            Self { dir, store }
+        }
+
+        fn entry(&self, fingerprint: &str) -> TrustedDevice {
+            self.store
+                .list()
+                .into_iter()
+                .find(|d| d.fingerprint == fingerprint)
+                .unwrap()
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn statement(new: &str, grace_until_ms: i64) -> RotationStatement {
+        RotationStatement {
+            old_fingerprint: OLD.to_string(),
+            new_fingerprint: new.to_string(),
+            old_certificate: String::new(),
+            issued_at_ms: chrono::Utc::now().timestamp_millis(),
+            grace_until_ms,
+            signature: String::new(),
+        }
+    }
+
+    #[test]
+    fn rotation_moves_the_pin_and_retires_the_old_key() {
+        let trust = TempStore::trusting(OLD);
+        let grace_until_ms = chrono::Utc::now().timestamp_millis() + HOUR_MS;
+
+        assert!(trust
+            .store
+            .apply_rotation(&statement(NEW, grace_until_ms))
+            .unwrap());
+
+        let old = trust.entry(OLD);
+        assert_eq!(old.expires_at_ms, Some(grace_until_ms));
+        assert_eq!(old.successor_fingerprint.as_deref(), Some(NEW));
+        assert!(trust.store.is_trusted(OLD));
+        assert!(trust.store.is_trusted(NEW));
+        assert_eq!(trust.entry(NEW).expires_at_ms, None);
+    }
+
+    #[test]
+    fn grace_period_is_capped_and_never_extended() {
+        let trust = TempStore::trusting(OLD);
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let max_grace_until_ms = now_ms + MAX_ROTATION_GRACE_HOURS * HOUR_MS;
+
+        trust
+            .store
+            .apply_rotation(&statement(NEW, i64::MAX))
+            .unwrap();
+        let capped = trust.entry(OLD).expires_at_ms.unwrap();
+        assert!(capped >= max_grace_until_ms && capped < max_grace_until_ms + HOUR_MS);
+
+        trust
+            .store
+            .apply_rotation(&statement(NEW, now_ms + HOUR_MS))
+            .unwrap();
+        assert_eq!(trust.entry(OLD).expires_at_ms, Some(now_ms + HOUR_MS));
+
+        trust
+            .store
+            .apply_rotation(&statement(NEW, now_ms + 2 * HOUR_MS))
+            .unwrap();
+        assert_eq!(trust.entry(OLD).expires_at_ms, Some(now_ms + HOUR_MS));
+    }
+
+    #[test]
+    fn retiring_key_cannot_be_handed_to_another_successor() {
+        let trust = TempStore::trusting(OLD);
+        let grace_until_ms = chrono::Utc::now().timestamp_millis() + HOUR_MS;
+        trust
+            .store
+            .apply_rotation(&statement(NEW, grace_until_ms))
+            .unwrap();
+
+        assert!(trust
+            .store
+            .apply_rotation(&statement(OTHER, grace_until_ms))
+            .is_err());
+        assert!(!trust.store.is_trusted(OTHER));
+        assert_eq!(trust.entry(OLD).successor_fingerprint.as_deref(), Some(NEW));
+    }
+}
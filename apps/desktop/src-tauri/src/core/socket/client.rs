@@ -1,7 +1,9 @@
+use nktcp::packets::{AuthLoginRequest, AuthLoginResponse, AuthTokenRevoke, Heartbeat};
+use nktcp::Packet;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::net::TcpStream;
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, RwLock};
 use tokio::time::{self, Duration};
 use tokio_rustls::rustls::pki_types::ServerName;
 use tokio_rustls::rustls::{self, ClientConfig};
@@ -15,9 +17,14 @@ use super::binary::BinaryWriter;
 use super::config::SocketClientConfig;
 use super::connection::{Connection, UserInfo};
 use super::error::{Context, SocketError, SocketResult};
-use super::protocol::PacketType;
+use super::handlers::sys::announce_key_rotation;
+use super::handshake::{local_handshake, perform_handshake};
+use super::protocol::{PacketType, Side};
 use super::router::PacketRouter;
 
+/// The server answers logins straight away; a silent one should not hold up startup.
+const AUTH_REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct SocketClient {
     config: SocketClientConfig,
     connection: RwLock<Option<Arc<Connection>>>,
@@ -136,7 +143,12 @@ impl SocketClient {
         };
 
         let conn_id = Uuid::new_v4().to_string();
-        let (connection, mut incoming_rx) = Connection::new(conn_id, socket_stream);
+        let (connection, mut incoming_rx) = Connection::new(conn_id, socket_stream, Side::Dialer);
+
+        if let Err(e) = client.handshake(&connection, &mut incoming_rx).await {
+            connection.close().await;
+            return Err(e.context(format!("handshake with {}", address)));
+        }
 
         *client.connection.write().await = Some(Arc::clone(&connection));
         client.is_running.store(true, Ordering::SeqCst);
@@ -171,6 +183,37 @@ impl SocketClient {
         Ok(())
     }
 
+    async fn handshake(
+        &self,
+        connection: &Connection,
+        incoming_rx: &mut mpsc::Receiver<(PacketType, i32, Vec<u8>)>,
+    ) -> SocketResult<()> {
+        let local = local_handshake(self.config.device_id.clone());
+        let peer = perform_handshake(
+            connection,
+            incoming_rx,
+            &local,
+            self.config.fingerprint.as_deref(),
+        )
+        .await?;
+
+        if let Some(target_id) = &self.config.target_id {
+            if !peer.device_id.eq_ignore_ascii_case(target_id) {
+                log::warn!(
+                    "Dialed device {}, but peer identified as {}",
+                    target_id,
+                    peer.device_id
+                );
+            }
+        }
+
+        if let Err(e) = announce_key_rotation(connection).await {
+            log::warn!("Failed to announce key rotation: {:#}", e);
+        }
+
+        Ok(())
+    }
+
     pub async fn disconnect(&self) {
         self.is_running.store(false, Ordering::SeqCst);
 
@@ -184,52 +227,44 @@ impl SocketClient {
     pub async fn authenticate(&self, token: &str) -> SocketResult<bool> {
         let conn = self.get_connection().await?;
 
-        let response = conn
-            .request(PacketType::AuthLoginRequest, |w| {
-                w.write_string(token);
-            })
+        let request = AuthLoginRequest {
+            token: token.to_string(),
+        };
+        let (_, response) = conn
+            .request_message_with_timeout(&request, AUTH_REPLY_TIMEOUT)
             .await
             .with_context(|| "sending authentication request")?;
 
-        let mut reader = super::binary::BinaryReader::new(&response);
-
-        let result = match reader.read_u8() {
-            Ok(1) => {
-                let data = reader.read_string().unwrap_or_default();
-                let user: UserInfo = match serde_json::from_str(&data) {
-                    Ok(u) => u,
-                    Err(e) => {
-                        log::error!("Failed to parse user info JSON: {}", e);
-                        UserInfo::default()
-                    }
-                };
-
-                let conn = self.connection.read().await.clone();
-                if let Some(conn) = conn {
-                    conn.set_authenticated(user.clone()).await;
-                }
+        let response = AuthLoginResponse::from_payload(&response)
+            .map_err(|e| SocketError::parse(format!("malformed AuthLoginResponse: {}", e)))?;
 
-                log::info!("Authenticated as user: {}", user.name);
-                Ok(true)
-            }
-            _ => {
-                let reason = reader
-                    .read_string()
-                    .unwrap_or_else(|_| "Unknown error".to_string());
-                log::error!("Authentication failed: {}", reason);
+        if !response.success {
+            log::error!("Authentication failed: {}", response.data);
+            return Err(SocketError::auth_failed(response.data).into());
+        }
 
-                Err(SocketError::auth_failed(reason).into())
+        let user: UserInfo = match serde_json::from_str(&response.data) {
+            Ok(u) => u,
+            Err(e) => {
+                log::error!("Failed to parse user info JSON: {}", e);
+                UserInfo::default()
             }
         };
 
-        result
+        let conn = self.connection.read().await.clone();
+        if let Some(conn) = conn {
+            conn.set_authenticated(user.clone()).await;
+        }
+
+        log::info!("Authenticated as user: {}", user.name);
+        Ok(true)
     }
 
     pub async fn revoke_token(&self, token: &str) -> SocketResult<()> {
         let conn = self.get_connection().await?;
 
-        conn.send_packet(PacketType::AuthTokenRevoke, |w| {
-            w.write_string(token);
+        conn.send_message(&AuthTokenRevoke {
+            token: token.to_string(),
         })
         .await
         .with_context(|| "sending revoke token packet")?;
@@ -269,25 +304,10 @@ impl SocketClient {
         super::register_all_handlers(&self.router).await;
 
         self.router
-            .register(
-                PacketType::SystemHandshake,
-                |_conn, payload, _req_id| async move {
-                    log::info!("Received handshake payload: {:?}", payload);
-
-                    Ok(())
-                },
-            )
-            .await;
-
-        self.router
-            .register(
-                PacketType::SystemHeartbeat,
-                |conn, _payload, _req_id| async move {
-                    conn.send_packet(PacketType::SystemHeartbeat, |_| {})
-                        .await?;
-                    Ok(())
-                },
-            )
+            .register_packet(|conn, Heartbeat, _req_id| async move {
+                conn.send_message(&Heartbeat).await?;
+                Ok(())
+            })
             .await;
     }
 
@@ -312,7 +332,7 @@ impl SocketClient {
 
             let conn = self.connection.read().await.clone();
             if let Some(conn) = conn {
-                if let Err(e) = conn.send_packet(PacketType::SystemHeartbeat, |_| {}).await {
+                if let Err(e) = conn.send_message(&Heartbeat).await {
                     log::warn!("Failed to send heartbeat: {:#}", e);
                     break;
                 }
@@ -0,0 +1,103 @@
+use super::error::{SocketError, SocketResult};
+use super::protocol::MAX_PAYLOAD_SIZE;
+
+/// Codec bits, advertised as capability flags in the `SystemHandshake`.
+pub const CODEC_ZSTD: u32 = 1 << 0;
+pub const SUPPORTED_CODECS: u32 = CODEC_ZSTD;
+
+const ZSTD_LEVEL: i32 = 3;
+/// A compressed chunk is only sent if it saves at least this share of the original.
+const MIN_SAVINGS_PERCENT: usize = 10;
+/// After this many chunks in a row fail to shrink, the rest of the file is sent as is.
+const MAX_CONSECUTIVE_MISSES: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ChunkCodec {
+    Zstd = 1,
+}
+
+impl ChunkCodec {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(ChunkCodec::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Picks the codec to use on a connection with the given negotiated capabilities.
+    pub fn negotiate(capabilities: u32) -> Option<Self> {
+        if capabilities & SUPPORTED_CODECS & CODEC_ZSTD != 0 {
+            Some(ChunkCodec::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Compresses the chunks of one stream, giving up once the data proves incompressible.
+pub struct ChunkCompressor {
+    codec: Option<ChunkCodec>,
+    misses: u32,
+}
+
+impl ChunkCompressor {
+    pub fn new(codec: Option<ChunkCodec>) -> Self {
+        Self { codec, misses: 0 }
+    }
+
+    pub fn disabled() -> Self {
+        Self::new(None)
+    }
+
+    /// Returns the compressed form of `data` when it is worth sending instead.
+    pub fn compress(&mut self, data: &[u8]) -> Option<(ChunkCodec, Vec<u8>)> {
+        let codec = self.codec?;
+        if data.is_empty() {
+            return None;
+        }
+
+        let compressed = match codec {
+            ChunkCodec::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).ok(),
+        };
+
+        match compressed {
+            Some(compressed)
+                if compressed.len() * 100 <= data.len() * (100 - MIN_SAVINGS_PERCENT) =>
+            {
+                self.misses = 0;
+                Some((codec, compressed))
+            }
+            _ => {
+                self.misses += 1;
+                if self.misses >= MAX_CONSECUTIVE_MISSES {
+                    log::debug!("Disabling chunk compression: data is not compressible");
+                    self.codec = None;
+                }
+                None
+            }
+        }
+    }
+}
+
+pub fn decompress_chunk(codec: ChunkCodec, data: &[u8], raw_len: usize) -> SocketResult<Vec<u8>> {
+    if raw_len > MAX_PAYLOAD_SIZE {
+        return Err(SocketError::PacketTooLarge(raw_len).into());
+    }
+
+    let decompressed = match codec {
+        ChunkCodec::Zstd => zstd::bulk::decompress(data, raw_len)
+            .map_err(|e| SocketError::parse(format!("zstd: {}", e)))?,
+    };
+
+    if decompressed.len() != raw_len {
+        return Err(SocketError::parse(format!(
+            "decompressed chunk is {} bytes, expected {}",
+            decompressed.len(),
+            raw_len
+        ))
+        .into());
+    }
+
+    Ok(decompressed)
+}
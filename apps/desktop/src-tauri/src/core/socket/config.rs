@@ -80,6 +80,8 @@ pub struct TransferConfig {
     pub outgoing_channel_size: usize,
     pub incoming_channel_size: usize,
     pub max_in_flight_chunks: usize,
+    pub max_concurrent_files: usize,
+    pub bundle_small_file_size: u64,
 }
 
 impl Default for TransferConfig {
@@ -102,6 +104,8 @@ impl TransferConfig {
             outgoing_channel_size: 64,
             incoming_channel_size: 64,
             max_in_flight_chunks: 8,
+            max_concurrent_files: 4,
+            bundle_small_file_size: 256 * 1024, // 256 KB
         }
     }
 
@@ -118,6 +122,8 @@ impl TransferConfig {
             outgoing_channel_size: 16,
             incoming_channel_size: 16,
             max_in_flight_chunks: 2,
+            max_concurrent_files: 1,
+            bundle_small_file_size: 64 * 1024, // 64 KB
         }
     }
 
@@ -134,6 +140,8 @@ impl TransferConfig {
             outgoing_channel_size: 128,
             incoming_channel_size: 128,
             max_in_flight_chunks: 32,
+            max_concurrent_files: 8,
+            bundle_small_file_size: 1024 * 1024, // 1 MB
         }
     }
 
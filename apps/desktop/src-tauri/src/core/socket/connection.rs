@@ -1,8 +1,10 @@
+use nkcrypto::X25519KeyPair;
+use nktcp::{decode_length, FrameHeader, Packet, ProtocolError};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
-use std::sync::{Arc, Mutex as StdMutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex, MutexGuard, PoisonError, RwLock as StdRwLock};
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter, WriteHalf};
 use tokio::sync::{mpsc, oneshot, Mutex as TokioMutex, OwnedSemaphorePermit, RwLock, Semaphore};
 use tokio::time::{self, Duration, Instant};
@@ -11,10 +13,16 @@ use crate::core::socket::stream::SocketStream;
 use crate::core::socket::TransferConfig;
 
 use super::binary::BinaryWriter;
+use super::e2e::{is_end_to_end, PayloadCipher};
 use super::error::{Context, SocketError, SocketResult};
-use super::protocol::{PacketType, HEADER_SIZE, MAX_FRAME_SIZE};
+use super::handshake::{agree_payload_cipher, PeerHandshake};
+use super::protocol::{PacketType, Side, HEADER_SIZE, MAX_FRAME_SIZE};
 
 pub type OnCloseCallback = Box<dyn Fn(String) + Send + Sync + 'static>;
+type Reply = SocketResult<(PacketType, Vec<u8>)>;
+
+/// How long a request waits for its reply when the caller does not pick a deadline.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConnectionState {
@@ -36,6 +44,246 @@ struct OutgoingPacket {
     permit: Option<OwnedSemaphorePermit>,
 }
 
+/// Our ephemeral key and the peer's handshake, whichever arrives first waits for the other.
+#[derive(Default)]
+struct SessionKeyExchange {
+    key_pair: Option<X25519KeyPair>,
+    peer_handshake: Option<Vec<u8>>,
+}
+
+struct PendingRequest {
+    packet_type: PacketType,
+    sent_at: Instant,
+    reply_tx: oneshot::Sender<Reply>,
+}
+
+#[derive(Default)]
+struct PendingRequests {
+    by_id: HashMap<i32, PendingRequest>,
+    /// Set once the read loop has stopped, after which no reply can arrive.
+    closed: bool,
+}
+
+/// Requests waiting for a reply, shared with their [`PendingReply`] and
+/// [`RequestCanceller`] handles, and counters of how requests ended.
+#[derive(Default)]
+struct RequestTracker {
+    pending: StdMutex<PendingRequests>,
+    sent: AtomicU64,
+    answered: AtomicU64,
+    timed_out: AtomicU64,
+    cancelled: AtomicU64,
+    disconnected: AtomicU64,
+}
+
+impl RequestTracker {
+    fn lock(&self) -> MutexGuard<'_, PendingRequests> {
+        // Every update is a single map operation, so a poisoned map is still consistent.
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn register(
+        &self,
+        request_id: i32,
+        packet_type: PacketType,
+    ) -> SocketResult<oneshot::Receiver<Reply>> {
+        let mut pending = self.lock();
+        if pending.closed {
+            return Err(SocketError::ConnectionClosed.into());
+        }
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        pending.by_id.insert(
+            request_id,
+            PendingRequest {
+                packet_type,
+                sent_at: Instant::now(),
+                reply_tx,
+            },
+        );
+        Ok(reply_rx)
+    }
+
+    fn remove(&self, request_id: i32) -> Option<PendingRequest> {
+        self.lock().by_id.remove(&request_id)
+    }
+
+    /// Hands a reply to the request waiting on `request_id`. The payload is given back
+    /// if nothing is waiting or the packet is not a reply that request accepts, so it
+    /// can be routed like any other.
+    fn answer(
+        &self,
+        request_id: i32,
+        packet_type: PacketType,
+        payload: Vec<u8>,
+    ) -> Option<Vec<u8>> {
+        let request = {
+            let mut pending = self.lock();
+            match pending.by_id.get(&request_id) {
+                Some(request) if packet_type.is_reply_to(request.packet_type) => {
+                    pending.by_id.remove(&request_id)
+                }
+                _ => None,
+            }
+        };
+        let Some(request) = request else {
+            return Some(payload);
+        };
+
+        match request.reply_tx.send(Ok((packet_type, payload))) {
+            Ok(()) => {
+                self.answered.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Err(reply) => reply.ok().map(|(_, payload)| payload),
+        }
+    }
+
+    fn cancel(&self, request_id: i32) -> bool {
+        let Some(request) = self.remove(request_id) else {
+            return false;
+        };
+
+        self.cancelled.fetch_add(1, Ordering::Relaxed);
+        let _ = request.reply_tx.send(Err(SocketError::RequestCancelled {
+            packet_type: request.packet_type,
+            request_id,
+        }
+        .into()));
+        true
+    }
+
+    /// Fails every outstanding request with [`SocketError::ConnectionClosed`] and refuses
+    /// new ones.
+    fn close(&self) {
+        let mut pending = self.lock();
+        pending.closed = true;
+        for (_, request) in pending.by_id.drain() {
+            self.disconnected.fetch_add(1, Ordering::Relaxed);
+            let _ = request
+                .reply_tx
+                .send(Err(SocketError::ConnectionClosed.into()));
+        }
+    }
+
+    fn metrics(&self) -> RequestMetrics {
+        let pending = self.lock();
+        RequestMetrics {
+            outstanding: pending.by_id.len(),
+            oldest_outstanding: pending
+                .by_id
+                .values()
+                .map(|request| request.sent_at.elapsed())
+                .max(),
+            sent: self.sent.load(Ordering::Relaxed),
+            answered: self.answered.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+            cancelled: self.cancelled.load(Ordering::Relaxed),
+            disconnected: self.disconnected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Request/reply counters for one connection, from [`Connection::request_metrics`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestMetrics {
+    /// Requests sent and still waiting for a reply.
+    pub outstanding: usize,
+    /// How long the oldest outstanding request has been waiting.
+    pub oldest_outstanding: Option<Duration>,
+    pub sent: u64,
+    pub answered: u64,
+    pub timed_out: u64,
+    pub cancelled: u64,
+    /// Requests that failed because the connection went away first.
+    pub disconnected: u64,
+}
+
+/// A request that has been sent and is waiting for its reply. Dropping it gives up on the
+/// reply and removes the pending entry, so a late reply is routed like any other packet.
+pub struct PendingReply {
+    request_id: i32,
+    packet_type: PacketType,
+    timeout: Duration,
+    deadline: Instant,
+    reply_rx: oneshot::Receiver<Reply>,
+    requests: Arc<RequestTracker>,
+}
+
+impl PendingReply {
+    pub fn request_id(&self) -> i32 {
+        self.request_id
+    }
+
+    /// A handle for cancelling this request from another task.
+    pub fn canceller(&self) -> RequestCanceller {
+        RequestCanceller {
+            request_id: self.request_id,
+            requests: Arc::clone(&self.requests),
+        }
+    }
+
+    /// Waits for the reply until the request's deadline. Fails with
+    /// [`SocketError::RequestTimedOut`] if the deadline passes first and with
+    /// [`SocketError::ConnectionClosed`] if the connection goes away first.
+    pub async fn reply(mut self) -> SocketResult<(PacketType, Vec<u8>)> {
+        match time::timeout_at(self.deadline, &mut self.reply_rx).await {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(_)) => Err(SocketError::ConnectionClosed.into()),
+            Err(_) => {
+                if self.requests.remove(self.request_id).is_none() {
+                    // Answered, cancelled or disconnected just as the deadline passed.
+                    if let Ok(reply) = self.reply_rx.try_recv() {
+                        return reply;
+                    }
+                }
+
+                self.requests.timed_out.fetch_add(1, Ordering::Relaxed);
+                log::warn!(
+                    "{:?} request {} got no reply within {:?} ({} still outstanding)",
+                    self.packet_type,
+                    self.request_id,
+                    self.timeout,
+                    self.requests.lock().by_id.len()
+                );
+                Err(SocketError::RequestTimedOut {
+                    packet_type: self.packet_type,
+                    request_id: self.request_id,
+                    timeout: self.timeout,
+                }
+                .into())
+            }
+        }
+    }
+}
+
+impl Drop for PendingReply {
+    fn drop(&mut self) {
+        if self.requests.remove(self.request_id).is_some() {
+            self.requests.cancelled.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Cancels a [`PendingReply`] from another task, e.g. when the user aborts a transfer
+/// whose offer is still unanswered. The waiter gets [`SocketError::RequestCancelled`].
+#[derive(Clone)]
+pub struct RequestCanceller {
+    request_id: i32,
+    requests: Arc<RequestTracker>,
+}
+
+impl RequestCanceller {
+    pub fn request_id(&self) -> i32 {
+        self.request_id
+    }
+
+    /// Returns `false` if the request had already been answered, timed out or cancelled.
+    pub fn cancel(&self) -> bool {
+        self.requests.cancel(self.request_id)
+    }
+}
+
 impl OutgoingPacket {
     fn new(data: Vec<u8>, permit: Option<OwnedSemaphorePermit>) -> Self {
         Self { data, permit }
@@ -55,31 +303,45 @@ pub struct Connection {
     writer: TokioMutex<Option<BufWriter<WriteHalf<SocketStream>>>>,
     user: RwLock<Option<UserInfo>>,
     request_id_counter: AtomicU32,
+    side: Side,
     closing: AtomicBool,
     closed: AtomicBool,
     active_send_batches: AtomicUsize,
+    /// Certificate fingerprint the peer authenticated with; `None` on plain TCP.
+    peer_fingerprint: Option<String>,
+    /// Set once the version/capability handshake has succeeded.
+    peer_handshake: StdRwLock<Option<PeerHandshake>>,
+    session_key_exchange: StdMutex<SessionKeyExchange>,
+    /// Set once an end-to-end key has been agreed; seals [`is_end_to_end`] payloads as the
+    /// write loop puts them on the wire and opens them as the read loop takes them off.
+    payload_cipher: StdRwLock<Option<Arc<PayloadCipher>>>,
     outgoing_control_tx: mpsc::Sender<OutgoingPacket>,
     outgoing_chunk_tx: mpsc::Sender<OutgoingPacket>,
     chunk_permits: Arc<Semaphore>,
-    pending_requests: StdMutex<HashMap<i32, oneshot::Sender<Vec<u8>>>>,
-    on_close: TokioMutex<Option<OnCloseCallback>>,
+    requests: Arc<RequestTracker>,
+    /// Run once when the connection closes, keyed by whoever registered them.
+    on_close: TokioMutex<HashMap<&'static str, OnCloseCallback>>,
 }
 
 impl Connection {
     #[inline]
     fn uses_chunk_lane(packet_type: PacketType) -> bool {
-        matches!(packet_type, PacketType::FileChunk | PacketType::FileFinish)
+        packet_type.is_data() || packet_type == PacketType::FileFinish
     }
 
+    /// `side` says whether we dialed the peer or accepted it, which decides the half of
+    /// the request id space our requests are numbered in and our end-to-end send key.
     pub fn new(
         id: String,
         stream: SocketStream,
+        side: Side,
     ) -> (Arc<Self>, mpsc::Receiver<(PacketType, i32, Vec<u8>)>) {
         let config = TransferConfig::global();
 
         if let Err(e) = stream.configure(&config) {
             log::warn!("Failed to configure TCP socket: {}", e);
         }
+        let peer_fingerprint = stream.peer_fingerprint();
 
         let (read_half, write_half) = tokio::io::split(stream);
         let (outgoing_control_tx, outgoing_control_rx) =
@@ -98,15 +360,20 @@ impl Connection {
                 write_half,
             ))),
             user: RwLock::new(None),
-            request_id_counter: AtomicU32::new(1),
+            request_id_counter: AtomicU32::new(side.first_request_id()),
+            side,
             closing: AtomicBool::new(false),
             closed: AtomicBool::new(false),
             active_send_batches: AtomicUsize::new(0),
+            peer_fingerprint,
+            peer_handshake: StdRwLock::new(None),
+            session_key_exchange: StdMutex::new(SessionKeyExchange::default()),
+            payload_cipher: StdRwLock::new(None),
             outgoing_control_tx,
             outgoing_chunk_tx,
             chunk_permits,
-            pending_requests: StdMutex::new(HashMap::new()),
-            on_close: TokioMutex::new(None),
+            requests: Arc::new(RequestTracker::default()),
+            on_close: TokioMutex::new(HashMap::new()),
         });
 
         let conn_clone = Arc::clone(&connection);
@@ -127,8 +394,14 @@ impl Connection {
 
         (connection, incoming_rx)
     }
-    pub async fn set_on_close(&self, callback: impl Fn(String) + Send + Sync + 'static) {
-        *self.on_close.lock().await = Some(Box::new(callback));
+    /// Registers `callback` to run when the connection closes, replacing any earlier
+    /// callback registered under the same `name`.
+    pub async fn set_on_close(
+        &self,
+        name: &'static str,
+        callback: impl Fn(String) + Send + Sync + 'static,
+    ) {
+        self.on_close.lock().await.insert(name, Box::new(callback));
     }
 
     pub fn id(&self) -> &str {
@@ -152,8 +425,111 @@ impl Connection {
         *self.state.write().await = ConnectionState::Authenticated;
     }
 
+    pub fn peer_fingerprint(&self) -> Option<&str> {
+        self.peer_fingerprint.as_deref()
+    }
+
+    pub fn peer_handshake(&self) -> Option<PeerHandshake> {
+        self.peer_handshake
+            .read()
+            .ok()
+            .and_then(|handshake| handshake.clone())
+    }
+
+    pub fn set_peer_handshake(&self, handshake: PeerHandshake) {
+        match self.peer_handshake.write() {
+            Ok(mut lock) => *lock = Some(handshake),
+            Err(e) => log::error!("Failed to store peer handshake (lock poisoned): {}", e),
+        }
+    }
+
+    /// Registers the ephemeral key sent in our handshake. If the peer's handshake already
+    /// arrived, the end-to-end key is agreed right away.
+    pub fn offer_session_key(&self, key_pair: X25519KeyPair) {
+        let Ok(mut exchange) = self.session_key_exchange.lock() else {
+            log::error!("Session key mutex poisoned on {}", self.id);
+            return;
+        };
+
+        match exchange.peer_handshake.take() {
+            Some(peer_handshake) => self.agree_session_key(&key_pair, &peer_handshake),
+            None => exchange.key_pair = Some(key_pair),
+        }
+    }
+
+    fn on_peer_handshake(&self, payload: &[u8]) {
+        let Ok(mut exchange) = self.session_key_exchange.lock() else {
+            log::error!("Session key mutex poisoned on {}", self.id);
+            return;
+        };
+
+        match exchange.key_pair.take() {
+            Some(key_pair) => self.agree_session_key(&key_pair, payload),
+            None => exchange.peer_handshake = Some(payload.to_vec()),
+        }
+    }
+
+    fn agree_session_key(&self, key_pair: &X25519KeyPair, peer_handshake: &[u8]) {
+        match agree_payload_cipher(key_pair, peer_handshake, self.side) {
+            Ok(Some(cipher)) => match self.payload_cipher.write() {
+                Ok(mut lock) => *lock = Some(Arc::new(cipher)),
+                Err(e) => log::error!("Failed to store payload cipher (lock poisoned): {}", e),
+            },
+            // The handshake refuses connections that end up without a key.
+            Ok(None) => log::warn!("Peer on {} offered no end-to-end key", self.id),
+            // The handshake itself reports malformed packets.
+            Err(e) => log::warn!("No end-to-end key agreed on {}: {:#}", self.id, e),
+        }
+    }
+
+    /// Whether payloads of [`is_end_to_end`] packets are sealed on this connection.
+    pub fn is_end_to_end(&self) -> bool {
+        self.payload_cipher
+            .read()
+            .map(|cipher| cipher.is_some())
+            .unwrap_or(false)
+    }
+
+    fn payload_cipher_for(&self, packet_type: PacketType) -> Option<Arc<PayloadCipher>> {
+        if !is_end_to_end(packet_type) {
+            return None;
+        }
+        self.payload_cipher
+            .read()
+            .ok()
+            .and_then(|cipher| cipher.clone())
+    }
+
+    /// Seals the payload of a queued frame if its type is end to end. Runs in the write loop,
+    /// so payloads from both lanes are sealed in the order the peer reads them.
+    fn seal_outgoing(&self, mut frame: Vec<u8>) -> SocketResult<Vec<u8>> {
+        let FrameHeader {
+            packet_type,
+            request_id,
+        } = FrameHeader::parse(&frame[4..])?;
+        let Some(cipher) = self.payload_cipher_for(packet_type) else {
+            return Ok(frame);
+        };
+
+        let payload_start = 4 + HEADER_SIZE;
+        let sealed = cipher.seal(packet_type, request_id, &frame[payload_start..])?;
+        frame.truncate(payload_start);
+        frame.extend_from_slice(&sealed);
+        let body_len = (frame.len() - 4) as u32;
+        frame[..4].copy_from_slice(&body_len.to_le_bytes());
+        Ok(frame)
+    }
+
+    /// Capability flags both sides support; 0 before the handshake has completed.
+    pub fn capabilities(&self) -> u32 {
+        self.peer_handshake()
+            .map(|handshake| handshake.capabilities)
+            .unwrap_or(0)
+    }
+
     pub fn next_request_id(&self) -> i32 {
-        self.request_id_counter.fetch_add(1, Ordering::SeqCst) as i32
+        self.request_id_counter
+            .fetch_add(Side::REQUEST_ID_STEP, Ordering::SeqCst) as i32
     }
 
     pub async fn send_packet<F>(
@@ -178,6 +554,24 @@ impl Connection {
     ) -> SocketResult<()>
     where
         F: FnOnce(&mut BinaryWriter),
+    {
+        self.try_send_packet_with_id(packet_type, request_id, |w| {
+            payload_writer(w);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Like [`Connection::send_packet_with_id`], for payload writers that can fail, such as
+    /// a field too long for its length prefix. Nothing is sent if the writer fails.
+    pub async fn try_send_packet_with_id<F>(
+        &self,
+        packet_type: PacketType,
+        request_id: i32,
+        payload_writer: F,
+    ) -> SocketResult<()>
+    where
+        F: FnOnce(&mut BinaryWriter) -> Result<(), ProtocolError>,
     {
         if self.closing.load(Ordering::SeqCst) {
             return Err(SocketError::ConnectionClosed.into());
@@ -185,7 +579,7 @@ impl Connection {
 
         let config = TransferConfig::global();
 
-        let permit = if packet_type == PacketType::FileChunk {
+        let permit = if packet_type.is_data() {
             Some(
                 self.chunk_permits
                     .clone()
@@ -197,7 +591,7 @@ impl Connection {
             None
         };
 
-        let initial_capacity = if packet_type == PacketType::FileChunk {
+        let initial_capacity = if packet_type.is_data() {
             config.chunk_size + 128
         } else {
             64
@@ -208,14 +602,15 @@ impl Connection {
         writer.write_u32(0);
         let body_start = writer.len();
 
-        writer.write_u8(packet_type as u8);
+        writer.write_u8(packet_type.as_u8());
         writer.write_i32(request_id);
-        payload_writer(&mut writer);
+        payload_writer(&mut writer)
+            .map_err(|e| SocketError::parse(format!("encoding {:?}: {}", packet_type, e)))?;
 
         let body_len = (writer.len() - body_start) as u32;
         writer.write_u32_at(0, body_len);
 
-        if packet_type != PacketType::SystemHeartbeat && packet_type != PacketType::FileChunk {
+        if packet_type != PacketType::SystemHeartbeat && !packet_type.is_data() {
             log::info!(
                 "Sending packet: {:?} (id: {}, len: {})",
                 packet_type,
@@ -237,6 +632,23 @@ impl Connection {
         Ok(())
     }
 
+    /// Sends a typed packet under a fresh request id.
+    pub async fn send_message<P: Packet>(&self, packet: &P) -> SocketResult<i32> {
+        let request_id = self.next_request_id();
+        self.send_message_with_id(request_id, packet).await?;
+        Ok(request_id)
+    }
+
+    /// Sends a typed packet under `request_id`, e.g. as the reply to a request.
+    pub async fn send_message_with_id<P: Packet>(
+        &self,
+        request_id: i32,
+        packet: &P,
+    ) -> SocketResult<()> {
+        self.try_send_packet_with_id(P::PACKET_TYPE, request_id, |w| packet.encode(w))
+            .await
+    }
+
     pub async fn send_raw(
         &self,
         packet_type: PacketType,
@@ -251,11 +663,11 @@ impl Connection {
 
         let mut writer = BinaryWriter::with_capacity(4 + body_len as usize);
         writer.write_u32(body_len);
-        writer.write_u8(packet_type as u8);
+        writer.write_u8(packet_type.as_u8());
         writer.write_i32(request_id);
         writer.write_bytes(payload);
 
-        let permit = if packet_type == PacketType::FileChunk {
+        let permit = if packet_type.is_data() {
             Some(
                 self.chunk_permits
                     .clone()
@@ -288,7 +700,8 @@ impl Connection {
 
         *self.state.write().await = ConnectionState::Closing;
 
-        if let Some(cb) = self.on_close.lock().await.take() {
+        let callbacks = std::mem::take(&mut *self.on_close.lock().await);
+        for cb in callbacks.into_values() {
             cb(self.id.clone());
         }
 
@@ -314,6 +727,7 @@ impl Connection {
             .await;
     }
 
+    /// Sends a request and waits up to [`DEFAULT_REQUEST_TIMEOUT`] for the reply.
     pub async fn request<F>(
         &self,
         packet_type: PacketType,
@@ -322,35 +736,101 @@ impl Connection {
     where
         F: FnOnce(&mut BinaryWriter),
     {
-        let request_id = self.next_request_id();
-        let (tx, rx) = oneshot::channel();
+        let (_, payload) = self.request_with_type(packet_type, payload_writer).await?;
+        Ok(payload)
+    }
 
-        {
-            let mut pending = self
-                .pending_requests
-                .lock()
-                .map_err(|e| SocketError::ServerError(format!("Request mutex poisoned: {}", e)))?;
+    /// Like [`Connection::request`], but also returns the packet type of the reply so
+    /// callers can tell apart responses such as `FileAccept` and `FileReject`.
+    pub async fn request_with_type<F>(
+        &self,
+        packet_type: PacketType,
+        payload_writer: F,
+    ) -> SocketResult<(PacketType, Vec<u8>)>
+    where
+        F: FnOnce(&mut BinaryWriter),
+    {
+        self.start_request(packet_type, DEFAULT_REQUEST_TIMEOUT, |w| {
+            payload_writer(w);
+            Ok(())
+        })
+        .await?
+        .reply()
+        .await
+    }
+
+    /// Sends a typed packet as a request and returns the reply's packet type and payload,
+    /// waiting up to [`DEFAULT_REQUEST_TIMEOUT`].
+    pub async fn request_message<P: Packet>(
+        &self,
+        packet: &P,
+    ) -> SocketResult<(PacketType, Vec<u8>)> {
+        self.request_message_with_timeout(packet, DEFAULT_REQUEST_TIMEOUT)
+            .await
+    }
+
+    /// Like [`Connection::request_message`], with the caller's own deadline.
+    pub async fn request_message_with_timeout<P: Packet>(
+        &self,
+        packet: &P,
+        timeout: Duration,
+    ) -> SocketResult<(PacketType, Vec<u8>)> {
+        self.start_request_message(packet, timeout)
+            .await?
+            .reply()
+            .await
+    }
 
-            pending.insert(request_id, tx);
+    /// Sends a typed packet as a request and returns without waiting, for callers that
+    /// need to cancel the request while it is outstanding.
+    pub async fn start_request_message<P: Packet>(
+        &self,
+        packet: &P,
+        timeout: Duration,
+    ) -> SocketResult<PendingReply> {
+        self.start_request(P::PACKET_TYPE, timeout, |w| packet.encode(w))
+            .await
+    }
+
+    async fn start_request<F>(
+        &self,
+        packet_type: PacketType,
+        timeout: Duration,
+        payload_writer: F,
+    ) -> SocketResult<PendingReply>
+    where
+        F: FnOnce(&mut BinaryWriter) -> Result<(), ProtocolError>,
+    {
+        if packet_type.reply_types().is_empty() {
+            return Err(SocketError::other(format!("{} expects no reply", packet_type)).into());
         }
 
+        let request_id = self.next_request_id();
+        let reply_rx = self.requests.register(request_id, packet_type)?;
+
         if let Err(e) = self
-            .send_packet_with_id(packet_type, request_id, payload_writer)
+            .try_send_packet_with_id(packet_type, request_id, payload_writer)
             .await
         {
-            let mut pending = self.pending_requests.lock().map_err(|e| {
-                SocketError::ServerError(format!("Request mutex poisoned during cleanup: {}", e))
-            })?;
-
-            pending.remove(&request_id);
+            self.requests.remove(request_id);
             return Err(e);
         }
 
-        match rx.await {
-            Ok(payload) => Ok(payload),
-            Err(_) => Err(SocketError::ConnectionClosed.into()),
-        }
+        self.requests.sent.fetch_add(1, Ordering::Relaxed);
+        Ok(PendingReply {
+            request_id,
+            packet_type,
+            timeout,
+            deadline: Instant::now() + timeout,
+            reply_rx,
+            requests: Arc::clone(&self.requests),
+        })
+    }
+
+    pub fn request_metrics(&self) -> RequestMetrics {
+        self.requests.metrics()
     }
+
     pub fn is_closing(&self) -> bool {
         self.closing.load(Ordering::SeqCst)
     }
@@ -392,15 +872,19 @@ impl Connection {
                 }
             }
 
-            let frame_len = u32::from_le_bytes(len_buf) as usize;
-            if frame_len > MAX_FRAME_SIZE {
-                log::warn!(
-                    "Frame too large from peer: {} bytes (max: {}), closing connection",
-                    frame_len,
-                    MAX_FRAME_SIZE
-                );
-                return Err(SocketError::PacketTooLarge(frame_len).into());
-            }
+            let frame_len = match decode_length(len_buf) {
+                Ok(frame_len) => frame_len,
+                Err(e) => {
+                    log::warn!(
+                        "{} from peer (max: {}), closing connection",
+                        e,
+                        MAX_FRAME_SIZE
+                    );
+                    return Err(
+                        SocketError::PacketTooLarge(u32::from_le_bytes(len_buf) as usize).into(),
+                    );
+                }
+            };
 
             let mut frame_buf = vec![0u8; frame_len];
             match reader.read_exact(&mut frame_buf).await {
@@ -411,64 +895,54 @@ impl Connection {
                 }
             }
 
-            if frame_len < HEADER_SIZE {
-                log::error!("Frame too short: {}", frame_len);
-                continue;
-            }
-
-            let packet_type = PacketType::from_u8(frame_buf[0]);
-            let request_id =
-                i32::from_le_bytes([frame_buf[1], frame_buf[2], frame_buf[3], frame_buf[4]]);
-            let mut payload_opt = Some(frame_buf[HEADER_SIZE..].to_vec());
-            let mut is_handled = false;
-
-            {
-                match conn.pending_requests.lock() {
-                    Ok(mut pending) => {
-                        if let Some(tx) = pending.remove(&request_id) {
-                            if let Some(payload_data) = payload_opt.take() {
-                                if tx.send(payload_data).is_ok() {
-                                    is_handled = true;
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("Pending requests mutex poisoned: {}", e);
-                        return Err(
-                            SocketError::ServerError("Internal state corrupted".into()).into()
-                        );
-                    }
+            let FrameHeader {
+                packet_type,
+                request_id,
+            } = match FrameHeader::parse(&frame_buf) {
+                Ok(header) => header,
+                Err(e) => {
+                    log::error!("{}", e);
+                    continue;
                 }
-            }
+            };
+            let payload = &frame_buf[HEADER_SIZE..];
 
-            if !is_handled {
-                if let Some(payload_data) = payload_opt {
-                    if packet_type != PacketType::SystemHeartbeat
-                        && packet_type != PacketType::FileChunk
-                    {
-                        log::info!("Received packet pushed to queue: {:?}", packet_type);
-                    }
+            if packet_type == PacketType::SystemHandshake {
+                conn.on_peer_handshake(payload);
+            }
 
-                    if incoming_tx
-                        .send((packet_type, request_id, payload_data))
-                        .await
-                        .is_err()
-                    {
-                        log::info!("Incoming channel closed");
+            let payload = match conn.payload_cipher_for(packet_type) {
+                Some(cipher) => match cipher.open(packet_type, request_id, payload) {
+                    Ok(opened) => opened,
+                    Err(e) => {
+                        log::warn!("Dropping connection {}: {:#}", conn.id, e);
                         break;
                     }
+                },
+                None => payload.to_vec(),
+            };
+            let Some(payload) = conn.requests.answer(request_id, packet_type, payload) else {
+                continue;
+            };
 
-                    tokio::task::yield_now().await;
-                }
+            if packet_type != PacketType::SystemHeartbeat && !packet_type.is_data() {
+                log::info!("Received packet pushed to queue: {:?}", packet_type);
             }
-        }
 
-        match conn.pending_requests.lock() {
-            Ok(mut pending) => pending.clear(),
-            Err(e) => log::error!("Failed to clear pending requests (mutex poisoned): {}", e),
+            if incoming_tx
+                .send((packet_type, request_id, payload))
+                .await
+                .is_err()
+            {
+                log::info!("Incoming channel closed");
+                break;
+            }
+
+            tokio::task::yield_now().await;
         }
 
+        conn.requests.close();
+
         conn.close().await;
         Ok(())
     }
@@ -543,11 +1017,18 @@ impl Connection {
                 continue;
             }
 
-            let data_len = packet.data.len();
+            let data = match conn.seal_outgoing(packet.data) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::error!("Dropping connection {}: {:#}", conn.id, e);
+                    break;
+                }
+            };
+            let data_len = data.len();
 
             let mut writer_guard = conn.writer.lock().await;
             if let Some(writer) = writer_guard.as_mut() {
-                if let Err(e) = writer.write_all(&packet.data).await {
+                if let Err(e) = writer.write_all(&data).await {
                     log::error!("Write error: {}", e);
                     break;
                 }
@@ -600,6 +1081,58 @@ impl std::fmt::Debug for Connection {
                 "active_send_batches",
                 &self.active_send_batches.load(Ordering::SeqCst),
             )
+            .field("requests", &self.request_metrics())
             .finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_request_with_a_colliding_id_is_not_taken_as_the_reply() {
+        let requests = RequestTracker::default();
+        let mut reply_rx = requests.register(1, PacketType::FileOffer).unwrap();
+
+        // The peer's own offer under the same id is routed on, and ours keeps waiting.
+        let returned = requests.answer(1, PacketType::FileOffer, vec![1]);
+        assert_eq!(returned, Some(vec![1]));
+        let returned = requests.answer(1, PacketType::SystemHeartbeat, vec![2]);
+        assert_eq!(returned, Some(vec![2]));
+        assert_eq!(requests.metrics().outstanding, 1);
+        assert!(reply_rx.try_recv().is_err());
+
+        assert_eq!(requests.answer(1, PacketType::FileAccept, vec![3]), None);
+        let (packet_type, payload) = reply_rx.try_recv().unwrap().unwrap();
+        assert_eq!(packet_type, PacketType::FileAccept);
+        assert_eq!(payload, vec![3]);
+        assert_eq!(requests.metrics().outstanding, 0);
+        assert_eq!(requests.metrics().answered, 1);
+    }
+
+    #[test]
+    fn error_packets_answer_any_request() {
+        let requests = RequestTracker::default();
+        let mut reply_rx = requests.register(3, PacketType::AuthLoginRequest).unwrap();
+
+        assert_eq!(
+            requests.answer(3, PacketType::ErrorPermission, vec![]),
+            None
+        );
+        let (packet_type, _) = reply_rx.try_recv().unwrap().unwrap();
+        assert_eq!(packet_type, PacketType::ErrorPermission);
+    }
+
+    #[test]
+    fn replies_to_unknown_ids_are_routed_on() {
+        let requests = RequestTracker::default();
+        let _reply_rx = requests.register(5, PacketType::FileOffer).unwrap();
+
+        assert_eq!(
+            requests.answer(7, PacketType::FileAccept, vec![9]),
+            Some(vec![9])
+        );
+        assert_eq!(requests.metrics().outstanding, 1);
+    }
+}
@@ -0,0 +1,180 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use nkcrypto::{Cipher, X25519KeyPair};
+
+use super::error::{SocketError, SocketResult};
+use super::protocol::{PacketType, Side};
+
+const SESSION_KEY_INFO: &[u8] = b"nekoshare-e2e-payloads-v2";
+
+/// Packets whose payload is sealed end to end once a session key is agreed. Their framing
+/// (length, type and request id) stays readable so a relay can still forward them.
+pub fn is_end_to_end(packet_type: PacketType) -> bool {
+    matches!(
+        packet_type,
+        PacketType::FileChunk
+            | PacketType::FileChunkCompressed
+            | PacketType::TextMessage
+            | PacketType::ClipboardCopy
+    )
+}
+
+/// Seals and opens the payloads of [`is_end_to_end`] packets with keys agreed from the
+/// ephemeral X25519 keys exchanged in the `SystemHandshake`.
+///
+/// Each direction has its own key, so a packet reflected back at its sender does not open,
+/// and each sealed payload is bound to its position in that direction's stream of sealed
+/// packets. The position is not sent: both ends count, so a relay that drops, repeats or
+/// reorders a payload makes the next one fail to open. This requires payloads to be sealed
+/// in the order they are written and opened in the order they are read.
+pub struct PayloadCipher {
+    send: Cipher,
+    receive: Cipher,
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl PayloadCipher {
+    /// `side` is our end of the connection, which picks the key for each direction.
+    pub fn agree(local: &X25519KeyPair, peer_public: &[u8], side: Side) -> SocketResult<Self> {
+        let key_for = |from: Side| {
+            local
+                .agree(peer_public, &direction_info(from))
+                .map(|key| Cipher::new(&key))
+                .map_err(|e| SocketError::end_to_end(format!("key agreement: {}", e)))
+        };
+
+        Ok(Self {
+            send: key_for(side)?,
+            receive: key_for(peer_side(side))?,
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+        })
+    }
+
+    pub fn seal(
+        &self,
+        packet_type: PacketType,
+        request_id: i32,
+        payload: &[u8],
+    ) -> SocketResult<Vec<u8>> {
+        let sequence = self.sent.fetch_add(1, Ordering::SeqCst);
+        self.send
+            .encrypt_with_aad(payload, &Self::aad(packet_type, request_id, sequence))
+            .map_err(|e| SocketError::end_to_end(format!("sealing {}: {}", packet_type, e)).into())
+    }
+
+    /// Opens the next payload from the peer. Fails for anything but the payload the peer
+    /// sealed next, under the same type and request id.
+    pub fn open(
+        &self,
+        packet_type: PacketType,
+        request_id: i32,
+        sealed: &[u8],
+    ) -> SocketResult<Vec<u8>> {
+        let sequence = self.received.load(Ordering::SeqCst);
+        let opened = self
+            .receive
+            .decrypt_with_aad(sealed, &Self::aad(packet_type, request_id, sequence))
+            .map_err(|e| SocketError::end_to_end(format!("opening {}: {}", packet_type, e)))?;
+        self.received.store(sequence + 1, Ordering::SeqCst);
+
+        Ok(opened)
+    }
+
+    /// Binds the payload to its header and its position in the stream, so a relay cannot
+    /// replay it, reorder it or pass it off under another type or id.
+    fn aad(packet_type: PacketType, request_id: i32, sequence: u64) -> [u8; 13] {
+        let mut aad = [0u8; 13];
+        aad[0] = packet_type.as_u8();
+        aad[1..5].copy_from_slice(&request_id.to_le_bytes());
+        aad[5..].copy_from_slice(&sequence.to_le_bytes());
+        aad
+    }
+}
+
+/// Key derivation info for the payloads sent by `from`.
+fn direction_info(from: Side) -> Vec<u8> {
+    let direction: &[u8] = match from {
+        Side::Dialer => b"dialer",
+        Side::Acceptor => b"acceptor",
+    };
+    [SESSION_KEY_INFO, b"\n", direction].concat()
+}
+
+fn peer_side(side: Side) -> Side {
+    match side {
+        Side::Dialer => Side::Acceptor,
+        Side::Acceptor => Side::Dialer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The dialer's and the acceptor's cipher for one connection.
+    fn session() -> (PayloadCipher, PayloadCipher) {
+        let a = X25519KeyPair::generate().unwrap();
+        let b = X25519KeyPair::generate().unwrap();
+        (
+            PayloadCipher::agree(&a, &b.public_key(), Side::Dialer).unwrap(),
+            PayloadCipher::agree(&b, &a.public_key(), Side::Acceptor).unwrap(),
+        )
+    }
+
+    #[test]
+    fn both_sides_agree_on_the_keys() {
+        let (a, b) = session();
+        let sealed = a.seal(PacketType::TextMessage, 7, b"hello").unwrap();
+
+        assert_ne!(&sealed[..], b"hello");
+        assert_eq!(
+            b.open(PacketType::TextMessage, 7, &sealed).unwrap(),
+            b"hello"
+        );
+
+        let reply = b.seal(PacketType::TextMessage, 8, b"hi").unwrap();
+        assert_eq!(a.open(PacketType::TextMessage, 8, &reply).unwrap(), b"hi");
+    }
+
+    #[test]
+    fn header_is_authenticated() {
+        let (a, b) = session();
+        let sealed = a.seal(PacketType::TextMessage, 7, b"hello").unwrap();
+
+        assert!(b.open(PacketType::ClipboardCopy, 7, &sealed).is_err());
+        assert!(b.open(PacketType::TextMessage, 8, &sealed).is_err());
+    }
+
+    #[test]
+    fn replayed_payload_is_rejected() {
+        let (a, b) = session();
+        let sealed = a.seal(PacketType::FileChunk, 7, b"chunk").unwrap();
+
+        b.open(PacketType::FileChunk, 7, &sealed).unwrap();
+        assert!(b.open(PacketType::FileChunk, 7, &sealed).is_err());
+    }
+
+    #[test]
+    fn reordered_or_dropped_payload_is_rejected() {
+        let (a, b) = session();
+        let first = a.seal(PacketType::FileChunk, 7, b"first").unwrap();
+        let second = a.seal(PacketType::FileChunk, 7, b"second").unwrap();
+
+        assert!(b.open(PacketType::FileChunk, 7, &second).is_err());
+        assert_eq!(b.open(PacketType::FileChunk, 7, &first).unwrap(), b"first");
+        assert_eq!(
+            b.open(PacketType::FileChunk, 7, &second).unwrap(),
+            b"second"
+        );
+    }
+
+    #[test]
+    fn reflected_payload_is_rejected() {
+        let (a, _b) = session();
+        let sealed = a.seal(PacketType::TextMessage, 7, b"hello").unwrap();
+
+        assert!(a.open(PacketType::TextMessage, 7, &sealed).is_err());
+    }
+}
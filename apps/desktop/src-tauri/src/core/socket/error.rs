@@ -1,6 +1,9 @@
 use std::io;
+use std::time::Duration;
 use thiserror::Error;
 
+use super::protocol::PacketType;
+
 pub use anyhow::{anyhow, Context, Result};
 
 pub type SocketResult<T> = anyhow::Result<T>;
@@ -17,6 +20,18 @@ pub enum SocketError {
     ConnectionNotFound(String),
     #[error("Connection timed out")]
     Timeout,
+    /// The peer did not answer a request before its deadline; the connection may still be up.
+    #[error("{packet_type} request {request_id} got no reply within {timeout:?}")]
+    RequestTimedOut {
+        packet_type: PacketType,
+        request_id: i32,
+        timeout: Duration,
+    },
+    #[error("{packet_type} request {request_id} was cancelled")]
+    RequestCancelled {
+        packet_type: PacketType,
+        request_id: i32,
+    },
     #[error("Parse error: {0}")]
     ParseError(String),
     #[error("Authentication failed: {0}")]
@@ -25,6 +40,14 @@ pub enum SocketError {
     NotConnected,
     #[error("Already connected")]
     AlreadyConnected,
+    #[error("Handshake failed: {0}")]
+    HandshakeFailed(String),
+    #[error("Incompatible peer: {0}")]
+    IncompatiblePeer(String),
+    #[error("Server is full: it accepts at most {0} peers")]
+    ServerFull(u32),
+    #[error("End-to-end encryption failed: {0}")]
+    EndToEnd(String),
     #[error("Invalid packet type: 0x{0:02X}")]
     InvalidPacketType(u8),
     #[error("Invalid UUID parsing: {0}")]
@@ -60,6 +83,14 @@ impl SocketError {
         Self::AuthenticationFailed(reason.into())
     }
 
+    pub fn handshake(reason: impl Into<String>) -> Self {
+        Self::HandshakeFailed(reason.into())
+    }
+
+    pub fn end_to_end(reason: impl Into<String>) -> Self {
+        Self::EndToEnd(reason.into())
+    }
+
     pub fn other(msg: impl Into<String>) -> Self {
         Self::Other(msg.into())
     }
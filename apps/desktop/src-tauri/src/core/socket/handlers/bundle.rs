@@ -0,0 +1,231 @@
+/// One file packed into a bundle. The bundle offer lists every entry up front; the
+/// chunk stream then carries each entry's bytes followed by its SHA-256, in order,
+/// with no further framing.
+pub use nktcp::packets::BundleEntry;
+
+pub const BUNDLE_CHECKSUM_LEN: usize = nktcp::packets::FILE_CHECKSUM_SIZE;
+
+pub enum BundleEvent<'a> {
+    /// The entry at this index begins; emitted even for empty entries.
+    Start(usize),
+    Data(&'a [u8]),
+    /// The entry at this index is complete; carries the checksum the sender computed.
+    End(usize, [u8; BUNDLE_CHECKSUM_LEN]),
+}
+
+enum DecodeState {
+    Start,
+    Data { remaining: u64 },
+    Checksum { collected: usize },
+}
+
+/// Splits a bundle stream back into per-entry events as chunks arrive. It does no I/O,
+/// so chunk boundaries can fall anywhere, including inside a checksum.
+pub struct BundleDecoder {
+    sizes: Vec<u64>,
+    index: usize,
+    state: DecodeState,
+    checksum: [u8; BUNDLE_CHECKSUM_LEN],
+}
+
+impl BundleDecoder {
+    pub fn new(sizes: Vec<u64>) -> Self {
+        Self {
+            sizes,
+            index: 0,
+            state: DecodeState::Start,
+            checksum: [0; BUNDLE_CHECKSUM_LEN],
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.index >= self.sizes.len()
+    }
+
+    /// Index of the entry being decoded, if one has started and not yet ended.
+    pub fn current_entry(&self) -> Option<usize> {
+        match self.state {
+            DecodeState::Start => None,
+            _ => Some(self.index),
+        }
+    }
+
+    /// Consumes bytes from the front of `input` and returns the next event, or `None`
+    /// once `input` is exhausted or every entry has ended.
+    pub fn next_event<'a>(&mut self, input: &mut &'a [u8]) -> Option<BundleEvent<'a>> {
+        loop {
+            match self.state {
+                DecodeState::Start => {
+                    let size = *self.sizes.get(self.index)?;
+                    self.state = DecodeState::Data { remaining: size };
+                    return Some(BundleEvent::Start(self.index));
+                }
+                DecodeState::Data { remaining: 0 } => {
+                    self.state = DecodeState::Checksum { collected: 0 };
+                }
+                DecodeState::Data { remaining } => {
+                    if input.is_empty() {
+                        return None;
+                    }
+                    let take = remaining.min(input.len() as u64) as usize;
+                    let (data, rest) = input.split_at(take);
+                    *input = rest;
+                    self.state = DecodeState::Data {
+                        remaining: remaining - take as u64,
+                    };
+                    return Some(BundleEvent::Data(data));
+                }
+                DecodeState::Checksum { collected } if collected == BUNDLE_CHECKSUM_LEN => {
+                    let index = self.index;
+                    self.index += 1;
+                    self.state = DecodeState::Start;
+                    return Some(BundleEvent::End(index, self.checksum));
+                }
+                DecodeState::Checksum { collected } => {
+                    if input.is_empty() {
+                        return None;
+                    }
+                    let take = (BUNDLE_CHECKSUM_LEN - collected).min(input.len());
+                    self.checksum[collected..collected + take].copy_from_slice(&input[..take]);
+                    *input = &input[take..];
+                    self.state = DecodeState::Checksum {
+                        collected: collected + take,
+                    };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Entry sizes, and a stream of each entry's bytes followed by its checksum.
+    fn bundle_stream(sizes: &[u64]) -> (Vec<Vec<u8>>, Vec<[u8; BUNDLE_CHECKSUM_LEN]>, Vec<u8>) {
+        let mut contents = Vec::new();
+        let mut checksums = Vec::new();
+        let mut stream = Vec::new();
+        for (index, size) in sizes.iter().enumerate() {
+            let data: Vec<u8> = (0..*size)
+                .map(|i| (i as u8).wrapping_add(index as u8))
+                .collect();
+            let checksum = [0xC0 | index as u8; BUNDLE_CHECKSUM_LEN];
+            stream.extend_from_slice(&data);
+            stream.extend_from_slice(&checksum);
+            contents.push(data);
+            checksums.push(checksum);
+        }
+        (contents, checksums, stream)
+    }
+
+    /// Feeds `pieces` through a decoder and rebuilds every entry from its events.
+    fn decode(
+        sizes: &[u64],
+        pieces: &[&[u8]],
+    ) -> (Vec<Vec<u8>>, Vec<[u8; BUNDLE_CHECKSUM_LEN]>, BundleDecoder) {
+        let mut decoder = BundleDecoder::new(sizes.to_vec());
+        let mut contents: Vec<Vec<u8>> = Vec::new();
+        let mut checksums = Vec::new();
+
+        for piece in pieces {
+            let mut input = *piece;
+            while let Some(event) = decoder.next_event(&mut input) {
+                match event {
+                    BundleEvent::Start(index) => {
+                        assert_eq!(index, contents.len(), "entries start in order");
+                        contents.push(Vec::new());
+                    }
+                    BundleEvent::Data(data) => {
+                        assert!(!data.is_empty());
+                        contents.last_mut().expect("data before start").extend(data);
+                    }
+                    BundleEvent::End(index, checksum) => {
+                        assert_eq!(index, checksums.len(), "entries end in order");
+                        assert_eq!(index + 1, contents.len(), "end follows its start");
+                        checksums.push(checksum);
+                    }
+                }
+            }
+            assert!(input.is_empty() || decoder.is_finished());
+        }
+
+        (contents, checksums, decoder)
+    }
+
+    const SIZES: [u64; 4] = [5, 0, BUNDLE_CHECKSUM_LEN as u64 + 3, 1];
+
+    #[test]
+    fn decodes_a_stream_in_one_piece() {
+        let (contents, checksums, stream) = bundle_stream(&SIZES);
+
+        let (decoded, decoded_checksums, decoder) = decode(&SIZES, &[&stream]);
+
+        assert_eq!(decoded, contents);
+        assert_eq!(decoded_checksums, checksums);
+        assert!(decoder.is_finished());
+        assert_eq!(decoder.current_entry(), None);
+    }
+
+    #[test]
+    fn decodes_a_stream_split_at_every_boundary() {
+        let (contents, checksums, stream) = bundle_stream(&SIZES);
+
+        for split in 0..=stream.len() {
+            let (head, tail) = stream.split_at(split);
+            let (decoded, decoded_checksums, decoder) = decode(&SIZES, &[head, tail]);
+
+            assert_eq!(decoded, contents, "split at {}", split);
+            assert_eq!(decoded_checksums, checksums, "split at {}", split);
+            assert!(decoder.is_finished(), "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn decodes_a_stream_fed_byte_by_byte() {
+        let (contents, checksums, stream) = bundle_stream(&SIZES);
+        let pieces: Vec<&[u8]> = stream.chunks(1).collect();
+
+        let (decoded, decoded_checksums, _) = decode(&SIZES, &pieces);
+
+        assert_eq!(decoded, contents);
+        assert_eq!(decoded_checksums, checksums);
+    }
+
+    #[test]
+    fn reports_the_entry_in_progress() {
+        let (_, _, stream) = bundle_stream(&SIZES);
+        // All of the first entry and half of its checksum.
+        let cut = SIZES[0] as usize + BUNDLE_CHECKSUM_LEN / 2;
+
+        let (decoded, checksums, decoder) = decode(&SIZES, &[&stream[..cut]]);
+
+        assert_eq!(decoded.len(), 1);
+        assert!(checksums.is_empty());
+        assert_eq!(decoder.current_entry(), Some(0));
+        assert!(!decoder.is_finished());
+    }
+
+    #[test]
+    fn leaves_bytes_past_the_last_entry_unread() {
+        let (_, _, mut stream) = bundle_stream(&SIZES);
+        stream.extend_from_slice(b"extra");
+
+        let mut decoder = BundleDecoder::new(SIZES.to_vec());
+        let mut input = stream.as_slice();
+        while decoder.next_event(&mut input).is_some() {}
+
+        assert!(decoder.is_finished());
+        assert_eq!(input, b"extra");
+    }
+
+    #[test]
+    fn empty_bundle_is_finished_at_once() {
+        let mut decoder = BundleDecoder::new(Vec::new());
+        let mut input: &[u8] = b"stray";
+
+        assert!(decoder.is_finished());
+        assert!(decoder.next_event(&mut input).is_none());
+        assert_eq!(input, b"stray");
+    }
+}
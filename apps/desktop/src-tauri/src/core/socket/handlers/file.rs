@@ -1,39 +1,136 @@
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use nktcp::packets::{
+    FileAccept, FileAck, FileChunk, FileChunkCompressed, FileFinish, FileOffer, FilePause,
+    FileReject, FileResume,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::sync::RwLock as StdRwLock;
+use std::sync::{Mutex as StdMutex, RwLock as StdRwLock};
 use tauri::{AppHandle, Emitter};
-use tokio::fs::File;
-use tokio::io::{AsyncWriteExt, BufWriter};
-use tokio::sync::{Mutex, RwLock};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter};
+use tokio::sync::{watch, Mutex, RwLock};
+use tokio::time::{self, Duration, Instant};
 
-use crate::core::socket::{BinaryReader, Connection, PacketRouter, PacketType, SocketResult};
+use super::bundle::{BundleDecoder, BundleEntry, BundleEvent, BUNDLE_CHECKSUM_LEN};
+use super::receive_path::{
+    create_dir_within, create_part_file, finalize_part_file, part_path_for, sanitize_file_name,
+    sanitize_relative_path, target_path_for, CollisionPolicy,
+};
+use crate::core::socket::compression::{decompress_chunk, ChunkCodec};
+use crate::core::socket::{Connection, PacketRouter, SocketResult};
 use crate::core::socket::{SocketError, TransferConfig};
 use crate::core::transfer_history::{
-    persist_transfer_progress_event, TransferProgressEventPayload,
+    clear_transfer_resume_state, load_transfer_resume_state, persist_transfer_progress_event,
+    persist_transfer_resume_state, FolderProgressTracker, TransferFolderProgress,
+    TransferProgressEventPayload, TransferResumeState,
 };
 use crate::state::GlobalState;
 
 struct TransferState {
     writer: Mutex<BufWriter<File>>,
+    hasher: StdMutex<Sha256>,
+    /// Where the file will live once verified; may still be renamed on collision.
     file_path: PathBuf,
+    /// Where bytes are written until FileFinish verifies them.
+    part_path: PathBuf,
     file_name: String,
     file_id: String,
     transfer_id: String,
+    /// The sender's certificate, which the resume state is kept under.
+    peer_fingerprint: String,
     expected_size: u64,
     received_size: AtomicU64,
     last_emitted_size: AtomicU64,
+    last_checkpoint_size: AtomicU64,
+    folder: Option<Arc<FolderProgressTracker>>,
+}
+
+impl TransferState {
+    fn resume_state(&self, received_bytes: u64) -> TransferResumeState {
+        TransferResumeState {
+            peer_fingerprint: self.peer_fingerprint.clone(),
+            file_id: self.file_id.clone(),
+            file_path: self.part_path.to_string_lossy().to_string(),
+            file_name: self.file_name.clone(),
+            total_bytes: self.expected_size,
+            received_bytes,
+            updated_at_ms: now_timestamp_ms(),
+        }
+    }
+
+    fn folder_progress(&self, received_bytes: u64) -> Option<TransferFolderProgress> {
+        self.folder
+            .as_ref()
+            .map(|folder| folder.record(&self.file_id, received_bytes))
+    }
 }
 
 type TransferMap = DashMap<(String, String), Arc<TransferState>>;
+type FolderMap = DashMap<(String, String), Arc<FolderProgressTracker>>;
+type BundleMap = DashMap<(String, String), Arc<Mutex<BundleState>>>;
+
+/// The bundle entry currently being written out.
+struct BundleFile {
+    writer: BufWriter<File>,
+    hasher: Sha256,
+    file_path: PathBuf,
+    part_path: PathBuf,
+    written: u64,
+}
+
+struct BundleState {
+    transfer_id: String,
+    base_dir: PathBuf,
+    entries: Vec<BundleEntry>,
+    decoder: BundleDecoder,
+    /// `None` while no entry is open or while a skipped entry's bytes are discarded.
+    current: Option<BundleFile>,
+    /// Ids of the entries verified and moved into place so far.
+    kept: HashSet<String>,
+}
 
 const RECEIVE_PROGRESS_EMIT_STEP: u64 = 1024 * 1024;
+const RESUME_CHECKPOINT_STEP: u64 = 16 * 1024 * 1024;
+const OFFER_DECISION_TIMEOUT: Duration = Duration::from_secs(120);
+const OFFER_DECISION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+// Names of this module's close callbacks on a connection.
+const TRANSFERS_ON_CLOSE: &str = "file_transfers";
+const OFFERS_ON_CLOSE: &str = "offer_decisions";
+
+#[derive(Debug, Clone)]
+pub enum OfferDecision {
+    Accept,
+    Reject(String),
+}
+
+/// Decision slot shared by every file offer of one transfer: the first offer prompts
+/// the user, later offers of the same transfer reuse the answer. A rejection is dropped
+/// as soon as it has been delivered; an acceptance once the connection that asked closes.
+struct PendingOffer {
+    decision_tx: watch::Sender<Option<OfferDecision>>,
+    conn_id: String,
+    created_at: Instant,
+}
+
+/// The sender's certificate fingerprint and the transfer id, so one peer can never
+/// answer or reuse another peer's offer.
+type OfferKey = (String, String);
 
 pub struct FileTransferService {
     active_transfers: TransferMap,
+    active_bundles: BundleMap,
+    pending_offers: DashMap<OfferKey, PendingOffer>,
+    /// Folder totals keyed by (transfer_id, folder name), shared by the folder's files.
+    receive_folders: FolderMap,
     receive_base_dir: RwLock<Option<PathBuf>>,
+    collision_policy: RwLock<CollisionPolicy>,
     event_app_handle: StdRwLock<Option<AppHandle>>,
 }
 
@@ -41,17 +138,48 @@ impl FileTransferService {
     pub fn new() -> Self {
         Self {
             active_transfers: DashMap::new(),
+            active_bundles: DashMap::new(),
+            pending_offers: DashMap::new(),
+            receive_folders: DashMap::new(),
             receive_base_dir: RwLock::new(None),
+            collision_policy: RwLock::new(CollisionPolicy::default()),
             event_app_handle: StdRwLock::new(None),
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferOfferEventPayload {
+    pub transfer_id: String,
+    pub peer_fingerprint: String,
+    pub connection_id: String,
+    pub file_name: String,
+    pub file_count: u32,
+    pub total_bytes: u64,
+    pub sender_name: Option<String>,
+    pub expires_at_ms: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferOfferResolvedEventPayload {
+    pub transfer_id: String,
+    pub peer_fingerprint: String,
+    pub accepted: bool,
+    pub reason: Option<String>,
+}
+
 pub async fn set_receive_base_dir(path: Option<PathBuf>) {
     let service = GlobalState::get::<FileTransferService>();
     *service.receive_base_dir.write().await = path;
 }
 
+pub async fn set_collision_policy(policy: CollisionPolicy) {
+    let service = GlobalState::get::<FileTransferService>();
+    *service.collision_policy.write().await = policy;
+}
+
 pub fn set_transfer_event_app_handle(app: AppHandle) {
     let service = GlobalState::get::<FileTransferService>();
     if let Ok(mut guard) = service.event_app_handle.write() {
@@ -75,6 +203,157 @@ fn parse_transfer_id(file_id: &str) -> String {
     }
 }
 
+/// Records the user's answer for a pending incoming transfer. Returns `false` when the
+/// peer with `peer_fingerprint` has no offer awaiting a decision for `transfer_id`.
+pub fn resolve_transfer_offer(
+    peer_fingerprint: &str,
+    transfer_id: &str,
+    decision: OfferDecision,
+) -> bool {
+    let service = GlobalState::get::<FileTransferService>();
+    let key = (peer_fingerprint.to_string(), transfer_id.to_string());
+    let resolved = match service.pending_offers.get(&key) {
+        Some(entry) => entry.decision_tx.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(decision.clone());
+            true
+        }),
+        None => false,
+    };
+
+    if resolved {
+        let (accepted, reason) = match decision {
+            OfferDecision::Accept => (true, None),
+            OfferDecision::Reject(reason) => (false, Some(reason)),
+        };
+
+        if let Ok(guard) = service.event_app_handle.read() {
+            if let Some(app) = guard.as_ref() {
+                let _ = app.emit(
+                    "transfer-offer-resolved",
+                    TransferOfferResolvedEventPayload {
+                        transfer_id: transfer_id.to_string(),
+                        peer_fingerprint: peer_fingerprint.to_string(),
+                        accepted,
+                        reason,
+                    },
+                );
+            }
+        }
+    }
+
+    resolved
+}
+
+async fn await_offer_decision(conn: &Connection, metadata: &FileOffer) -> OfferDecision {
+    let service = GlobalState::get::<FileTransferService>();
+    let transfer_id = parse_transfer_id(&metadata.id);
+    let Some(peer_fingerprint) = conn.peer_handshake().and_then(|peer| peer.fingerprint) else {
+        return OfferDecision::Reject("Sender is not authenticated".to_string());
+    };
+    let key = (peer_fingerprint.clone(), transfer_id.clone());
+
+    service
+        .pending_offers
+        .retain(|_, offer| offer.created_at.elapsed() < OFFER_DECISION_TTL);
+
+    let (mut decision_rx, is_new) = match service.pending_offers.entry(key.clone()) {
+        Entry::Occupied(entry) => (entry.get().decision_tx.subscribe(), false),
+        Entry::Vacant(entry) => {
+            let (decision_tx, decision_rx) = watch::channel(None);
+            entry.insert(PendingOffer {
+                decision_tx,
+                conn_id: conn.id().to_string(),
+                created_at: Instant::now(),
+            });
+            (decision_rx, true)
+        }
+    };
+
+    if is_new {
+        forget_offers_on_close(conn).await;
+
+        let app = service
+            .event_app_handle
+            .read()
+            .ok()
+            .and_then(|guard| guard.clone());
+
+        match app {
+            Some(app) => {
+                let event = TransferOfferEventPayload {
+                    transfer_id: transfer_id.clone(),
+                    peer_fingerprint: peer_fingerprint.clone(),
+                    connection_id: conn.id().to_string(),
+                    file_name: metadata.name.clone(),
+                    file_count: metadata.file_count.unwrap_or(1),
+                    total_bytes: metadata.total_bytes.unwrap_or(metadata.size),
+                    sender_name: metadata.sender_name.clone(),
+                    expires_at_ms: now_timestamp_ms() + OFFER_DECISION_TIMEOUT.as_millis() as i64,
+                };
+                let _ = app.emit("transfer-offer", event);
+            }
+            None => {
+                log::warn!("No UI available to confirm transfer {}", transfer_id);
+                let reason = "The receiver cannot confirm transfers right now".to_string();
+                resolve_transfer_offer(
+                    &peer_fingerprint,
+                    &transfer_id,
+                    OfferDecision::Reject(reason),
+                );
+            }
+        }
+    }
+
+    let outcome = time::timeout(
+        OFFER_DECISION_TIMEOUT,
+        decision_rx.wait_for(Option::is_some),
+    )
+    .await
+    .map(|result| result.map(|decision| decision.clone()));
+
+    let decision = match outcome {
+        Ok(Ok(Some(decision))) => decision,
+        Ok(Ok(None)) | Ok(Err(_)) => OfferDecision::Reject("Offer was withdrawn".to_string()),
+        Err(_) => {
+            let reason = "Timed out waiting for the receiver to respond".to_string();
+            resolve_transfer_offer(
+                &peer_fingerprint,
+                &transfer_id,
+                OfferDecision::Reject(reason.clone()),
+            );
+            OfferDecision::Reject(reason)
+        }
+    };
+
+    // The sender abandons the whole transfer on a rejection, so nothing asks again.
+    if let OfferDecision::Reject(_) = decision {
+        service.pending_offers.remove(&key);
+    }
+
+    decision
+}
+
+/// Drops the decisions on offers `conn` made once it closes, so a later connection asks
+/// the user again.
+async fn forget_offers_on_close(conn: &Connection) {
+    conn.set_on_close(OFFERS_ON_CLOSE, |conn_id| forget_offers(&conn_id))
+        .await;
+
+    // Closed before the callback was in place.
+    if conn.is_closing() {
+        forget_offers(conn.id());
+    }
+}
+
+fn forget_offers(conn_id: &str) {
+    GlobalState::get::<FileTransferService>()
+        .pending_offers
+        .retain(|_, offer| offer.conn_id != conn_id);
+}
+
 fn emit_transfer_progress(service: &FileTransferService, event: TransferProgressEventPayload) {
     persist_transfer_progress_event(event.clone());
 
@@ -85,25 +364,175 @@ fn emit_transfer_progress(service: &FileTransferService, event: TransferProgress
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct FileMetadata {
-    pub id: String,
-    pub name: String,
-    pub size: u64,
+/// Reopens the partial file recorded for an earlier attempt of the same `file_id`,
+/// truncated to the last checkpointed offset, and re-hashes the bytes already on disk
+/// so the final checksum still covers the whole file. Returns `None` when the partial
+/// file is missing or no longer matches the offer, in which case the transfer restarts.
+pub(crate) async fn open_partial_file(
+    resume: &TransferResumeState,
+    metadata: &FileOffer,
+) -> Option<(File, PathBuf, u64, Sha256)> {
+    if resume.total_bytes != metadata.size || resume.received_bytes > metadata.size {
+        return None;
+    }
+
+    let part_path = PathBuf::from(&resume.file_path);
+    let on_disk = tokio::fs::metadata(&part_path).await.ok()?;
+    if !on_disk.is_file() || on_disk.len() < resume.received_bytes {
+        return None;
+    }
+
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&part_path)
+        .await
+        .ok()?;
+    file.set_len(resume.received_bytes).await.ok()?;
+    file.seek(SeekFrom::Start(0)).await.ok()?;
+
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; TransferConfig::global().read_buffer_size];
+    let mut remaining = resume.received_bytes;
+    while remaining > 0 {
+        let want = buffer.len().min(remaining as usize);
+        let n = file.read(&mut buffer[..want]).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        hasher.update(&buffer[..n]);
+        remaining -= n as u64;
+    }
+
+    Some((file, part_path, resume.received_bytes, hasher))
+}
+
+async fn checkpoint_transfer(
+    state: &TransferState,
+    writer: &mut BufWriter<File>,
+) -> SocketResult<()> {
+    writer.flush().await?;
+
+    let received_size = state.received_size.load(Ordering::SeqCst);
+    state
+        .last_checkpoint_size
+        .store(received_size, Ordering::SeqCst);
+    persist_transfer_resume_state(state.resume_state(received_size)).await;
+
+    Ok(())
+}
+
+/// Releases whatever transfers `conn` still has open once it closes.
+async fn release_transfers_on_close(conn: &Connection) {
+    conn.set_on_close(TRANSFERS_ON_CLOSE, |conn_id| {
+        tokio::spawn(release_connection_transfers(conn_id));
+    })
+    .await;
+
+    // Closed before the callback was in place; releasing twice is harmless.
+    if conn.is_closing() {
+        tokio::spawn(release_connection_transfers(conn.id().to_string()));
+    }
+}
+
+/// Checkpoints and forgets the transfers a closed connection left unfinished, so their
+/// files are not held open and a re-offer resumes from what reached the disk. Bundles
+/// are not resumed; the file a bundle was writing is discarded.
+async fn release_connection_transfers(conn_id: String) {
+    let service = GlobalState::get::<FileTransferService>();
+
+    for key in keys_of_connection(&service.active_transfers, &conn_id) {
+        let Some((_, state)) = service.active_transfers.remove(&key) else {
+            continue;
+        };
+        let mut writer = state.writer.lock().await;
+        match checkpoint_transfer(&state, &mut writer).await {
+            Ok(()) => log::info!(
+                "Connection closed, keeping {:?} at {} bytes",
+                state.part_path,
+                state.received_size.load(Ordering::SeqCst)
+            ),
+            Err(e) => log::warn!("Failed to checkpoint {:?}: {:#}", state.part_path, e),
+        }
+    }
+
+    for key in keys_of_connection(&service.active_bundles, &conn_id) {
+        let Some((_, bundle)) = service.active_bundles.remove(&key) else {
+            continue;
+        };
+        let mut bundle = bundle.lock().await;
+        if let (Some(index), Some(file)) = (bundle.decoder.current_entry(), bundle.current.take()) {
+            let entry = bundle.entries[index].clone();
+            finish_bundle_entry(&service, &bundle.transfer_id, &entry, file, None).await;
+        }
+    }
+}
+
+fn keys_of_connection<V>(
+    map: &DashMap<(String, String), V>,
+    conn_id: &str,
+) -> Vec<(String, String)> {
+    map.iter()
+        .filter(|entry| entry.key().0 == conn_id)
+        .map(|entry| entry.key().clone())
+        .collect()
 }
 
 async fn handle_file_offer(
     conn: Arc<Connection>,
-    payload: Vec<u8>,
-    _req_id: i32,
+    mut metadata: FileOffer,
+    req_id: i32,
 ) -> SocketResult<()> {
-    let service = GlobalState::get::<FileTransferService>();
-    let config = TransferConfig::global();
-    let metadata: FileMetadata =
-        serde_json::from_slice(&payload).map_err(|e| SocketError::parse(e.to_string()))?;
+    // The name comes from the peer; it must never reach the filesystem or the UI unchecked.
+    let file_name = sanitize_file_name(&metadata.name);
+    if file_name != metadata.name {
+        log::warn!(
+            "Sanitized offered file name {:?} to {:?}",
+            metadata.name,
+            file_name
+        );
+    }
+    metadata.name = file_name;
 
-    log::info!("Starting transfer: {} ({})", metadata.name, metadata.size);
+    log::info!("Received offer: {} ({})", metadata.name, metadata.size);
 
+    // Waiting on the user must not stall this connection's packet loop.
+    tokio::spawn(async move {
+        let file_id = metadata.id.clone();
+        // The flag tells the sender whether to abandon the whole transfer or just this file.
+        let rejection = match await_offer_decision(&conn, &metadata).await {
+            OfferDecision::Accept => {
+                let accepted = if metadata.bundle.is_some() {
+                    accept_bundle_offer(&conn, metadata, req_id).await
+                } else {
+                    accept_file_offer(&conn, metadata, req_id).await
+                };
+                match accepted {
+                    Ok(None) => None,
+                    Ok(Some(reason)) => Some((reason, false)),
+                    Err(e) => Some((format!("{:#}", e), false)),
+                }
+            }
+            OfferDecision::Reject(reason) => Some((reason, true)),
+        };
+
+        if let Some((reason, whole_transfer)) = rejection {
+            log::info!("Rejecting offer {}: {}", file_id, reason);
+            let reject = FileReject {
+                file_id,
+                reason,
+                whole_transfer,
+            };
+            if let Err(e) = conn.send_message_with_id(req_id, &reject).await {
+                log::warn!("Failed to send FileReject for {}: {}", reject.file_id, e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn resolve_receive_base_dir(service: &FileTransferService) -> SocketResult<PathBuf> {
     let user_dirs = directories::UserDirs::new()
         .ok_or_else(|| SocketError::other("Failed to get user directories"))?;
     let default_download_dir = user_dirs
@@ -132,10 +561,121 @@ async fn handle_file_offer(
         })?;
     }
 
-    let file_path = base_dir.join(&metadata.name);
-    log::info!("Receive target path: {:?}", file_path);
+    Ok(base_dir)
+}
 
-    let file = File::create(&file_path).await?;
+/// Picks where a new incoming file should end up, creating its parent directories and
+/// its `.part` file. The inner `Err` carries the reason when the file must not be
+/// written at all.
+async fn reserve_target_path(
+    service: &FileTransferService,
+    base_dir: &Path,
+    file_name: &str,
+    relative_path: Option<&str>,
+) -> SocketResult<Result<(PathBuf, File), String>> {
+    let relative = match relative_path {
+        Some(relative_path) => match sanitize_relative_path(relative_path) {
+            Some(relative_path) => relative_path,
+            None => return Ok(Err(format!("invalid relative path {:?}", relative_path))),
+        },
+        None => PathBuf::from(file_name),
+    };
+
+    if let Some(relative_dir) = relative.parent() {
+        match create_dir_within(base_dir, relative_dir) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::InvalidInput => return Ok(Err(e.to_string())),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let requested_path = base_dir.join(relative);
+
+    let policy = *service.collision_policy.read().await;
+    if policy == CollisionPolicy::Skip && requested_path.exists() {
+        log::info!("Skipping {:?}: file already exists", requested_path);
+        return Ok(Err(format!("{} already exists", file_name)));
+    }
+
+    // Overwrite only replaces finished files; a name another transfer is still writing
+    // is never shared.
+    let keep_existing = policy != CollisionPolicy::Overwrite;
+    let (target_path, part_file) = create_part_file(&requested_path, keep_existing)?;
+
+    Ok(Ok((target_path, File::from_std(part_file))))
+}
+
+fn folder_tracker(
+    service: &FileTransferService,
+    transfer_id: &str,
+    folder_name: Option<&str>,
+    folder_total_bytes: Option<u64>,
+) -> Option<Arc<FolderProgressTracker>> {
+    let (folder_name, total_bytes) = (folder_name?, folder_total_bytes?);
+    let folder_name = sanitize_file_name(folder_name);
+    let tracker = service
+        .receive_folders
+        .entry((transfer_id.to_string(), folder_name.clone()))
+        .or_insert_with(|| Arc::new(FolderProgressTracker::new(folder_name, total_bytes)))
+        .clone();
+    Some(tracker)
+}
+
+/// Opens the receive file for an accepted offer and replies with `FileAccept` or
+/// `FileResume`. Returns `Ok(Some(reason))` when the collision policy skips the file.
+async fn accept_file_offer(
+    conn: &Connection,
+    metadata: FileOffer,
+    req_id: i32,
+) -> SocketResult<Option<String>> {
+    let service = GlobalState::get::<FileTransferService>();
+    let config = TransferConfig::global();
+
+    log::info!("Starting transfer: {} ({})", metadata.name, metadata.size);
+
+    // Resume state is only ever matched against the peer that left it behind.
+    let Some(peer_fingerprint) = conn.peer_handshake().and_then(|peer| peer.fingerprint) else {
+        return Ok(Some("sender is not authenticated".to_string()));
+    };
+
+    let base_dir = resolve_receive_base_dir(&service).await?;
+
+    // A re-offer after a dropped connection supersedes whatever the old session left behind.
+    service.active_transfers.retain(|(_, file_id), state| {
+        file_id != &metadata.id || state.peer_fingerprint != peer_fingerprint
+    });
+
+    let partial = match load_transfer_resume_state(&peer_fingerprint, &metadata.id).await {
+        Some(resume) => open_partial_file(&resume, &metadata).await,
+        None => None,
+    };
+
+    let (file, part_path, resume_offset, hasher) = match partial {
+        Some((file, part_path, offset, hasher)) => {
+            log::info!(
+                "Resuming {} at offset {} ({:?})",
+                metadata.name,
+                offset,
+                part_path
+            );
+            (file, part_path, offset, hasher)
+        }
+        None => {
+            let (target_path, file) = match reserve_target_path(
+                &service,
+                &base_dir,
+                &metadata.name,
+                metadata.relative_path.as_deref(),
+            )
+            .await?
+            {
+                Ok(reserved) => reserved,
+                Err(reason) => return Ok(Some(reason)),
+            };
+            log::info!("Receive target path: {:?}", target_path);
+            (file, part_path_for(&target_path), 0, Sha256::new())
+        }
+    };
+    let file_path = target_path_for(&part_path);
 
     if config.preallocate_files && metadata.size > 0 {
         if let Err(e) = file.set_len(metadata.size).await {
@@ -147,20 +687,56 @@ async fn handle_file_offer(
     let conn_id = conn.id().to_string();
     let transfer_id = parse_transfer_id(&metadata.id);
 
+    let folder = folder_tracker(
+        &service,
+        &transfer_id,
+        metadata.folder_name.as_deref(),
+        metadata.folder_total_bytes,
+    );
+
     let state = Arc::new(TransferState {
         writer: Mutex::new(writer),
+        hasher: StdMutex::new(hasher),
         file_path: file_path.clone(),
+        part_path,
         file_name: metadata.name.clone(),
         file_id: metadata.id.clone(),
         transfer_id: transfer_id.clone(),
+        peer_fingerprint,
         expected_size: metadata.size,
-        received_size: AtomicU64::new(0),
-        last_emitted_size: AtomicU64::new(0),
+        received_size: AtomicU64::new(resume_offset),
+        last_emitted_size: AtomicU64::new(resume_offset),
+        last_checkpoint_size: AtomicU64::new(resume_offset),
+        folder,
     });
 
+    persist_transfer_resume_state(state.resume_state(resume_offset)).await;
+    let folder_progress = state.folder_progress(resume_offset);
+
     service
         .active_transfers
         .insert((conn_id, metadata.id.clone()), state);
+    release_transfers_on_close(conn).await;
+
+    if resume_offset > 0 {
+        let resume = FileResume {
+            file_id: metadata.id.clone(),
+            offset: resume_offset,
+        };
+        conn.send_message_with_id(req_id, &resume).await?;
+    } else {
+        let accept = FileAccept {
+            file_id: metadata.id.clone(),
+            offset: 0,
+        };
+        conn.send_message_with_id(req_id, &accept).await?;
+    }
+
+    let progress_percent = if metadata.size == 0 {
+        0.0
+    } else {
+        ((resume_offset as f64 / metadata.size as f64) * 100.0).min(100.0)
+    };
 
     emit_transfer_progress(
         &service,
@@ -177,30 +753,295 @@ async fn handle_file_offer(
             same_account: None,
             target_device_id: String::new(),
             total_bytes: metadata.size,
-            sent_bytes: 0,
-            progress_percent: 0.0,
+            sent_bytes: resume_offset,
+            progress_percent,
             status: "processing".to_string(),
             error: None,
             timestamp_ms: now_timestamp_ms(),
+            folder: folder_progress,
         },
     );
 
+    Ok(None)
+}
+
+/// Registers an accepted bundle. Its files are created one at a time as their bytes
+/// arrive, so collisions are resolved per file exactly as for single offers.
+async fn accept_bundle_offer(
+    conn: &Connection,
+    metadata: FileOffer,
+    req_id: i32,
+) -> SocketResult<Option<String>> {
+    let service = GlobalState::get::<FileTransferService>();
+    let entries = metadata.bundle.unwrap_or_default();
+
+    log::info!(
+        "Starting bundle {}: {} files ({} bytes)",
+        metadata.id,
+        entries.len(),
+        metadata.size
+    );
+
+    let base_dir = resolve_receive_base_dir(&service).await?;
+    let state = BundleState {
+        transfer_id: parse_transfer_id(&metadata.id),
+        base_dir,
+        decoder: BundleDecoder::new(entries.iter().map(|entry| entry.size).collect()),
+        entries,
+        current: None,
+        kept: HashSet::new(),
+    };
+
+    service.active_bundles.insert(
+        (conn.id().to_string(), metadata.id.clone()),
+        Arc::new(Mutex::new(state)),
+    );
+    release_transfers_on_close(conn).await;
+
+    let accept = FileAccept {
+        file_id: metadata.id,
+        offset: 0,
+    };
+    conn.send_message_with_id(req_id, &accept).await?;
+
+    Ok(None)
+}
+
+async fn open_bundle_entry(
+    service: &FileTransferService,
+    base_dir: &Path,
+    entry: &BundleEntry,
+) -> SocketResult<Option<BundleFile>> {
+    let file_name = sanitize_file_name(&entry.name);
+    let (target_path, file) = match reserve_target_path(
+        service,
+        base_dir,
+        &file_name,
+        entry.relative_path.as_deref(),
+    )
+    .await?
+    {
+        Ok(reserved) => reserved,
+        Err(reason) => {
+            log::info!("Skipping bundled file {}: {}", file_name, reason);
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(BundleFile {
+        writer: BufWriter::new(file),
+        hasher: Sha256::new(),
+        part_path: part_path_for(&target_path),
+        file_path: target_path,
+        written: 0,
+    }))
+}
+
+/// Verifies and moves one bundled file into place, then records its outcome. A missing
+/// checksum means the bundle ended before the file was complete. Returns whether the
+/// file was kept.
+async fn finish_bundle_entry(
+    service: &FileTransferService,
+    transfer_id: &str,
+    entry: &BundleEntry,
+    mut file: BundleFile,
+    expected_checksum: Option<[u8; BUNDLE_CHECKSUM_LEN]>,
+) -> bool {
+    let config = TransferConfig::global();
+
+    let flushed = match file.writer.flush().await {
+        Ok(()) if config.sync_on_complete => file.writer.get_ref().sync_all().await,
+        other => other,
+    };
+    let actual_checksum = file.hasher.finalize();
+
+    let verification_error = match (flushed, expected_checksum) {
+        (Err(e), _) => Some(format!("write error: {}", e)),
+        (Ok(()), None) => Some(format!(
+            "bundle ended after {} of {} bytes",
+            file.written, entry.size
+        )),
+        (Ok(()), Some(expected)) if expected[..] != actual_checksum[..] => Some(format!(
+            "checksum mismatch: expected {}, got {}",
+            hex::encode(expected),
+            hex::encode(actual_checksum)
+        )),
+        (Ok(()), Some(_)) => None,
+    };
+    drop(file.writer);
+
+    let mut file_path = file.file_path.clone();
+    let error = match verification_error {
+        Some(error) => {
+            if let Err(e) = tokio::fs::remove_file(&file.part_path).await {
+                log::warn!("Failed to remove {:?}: {}", file.part_path, e);
+            }
+            Some(error)
+        }
+        None => match finalize_received_file(&file.part_path, &file.file_path).await {
+            Ok(final_path) => {
+                file_path = final_path;
+                None
+            }
+            Err(e) => Some(format!("{:#}", e)),
+        },
+    };
+
+    let kept = error.is_none();
+    let status = match &error {
+        Some(error) => {
+            log::error!("Bundled file failed {:?}: {}", file_path, error);
+            "failed"
+        }
+        None => "success",
+    };
+
+    let folder = folder_tracker(
+        service,
+        transfer_id,
+        entry.folder_name.as_deref(),
+        entry.folder_total_bytes,
+    );
+    let folder_progress = folder
+        .as_ref()
+        .map(|folder| folder.record(&entry.id, file.written));
+    if let Some(folder) = folder.as_ref().filter(|folder| folder.is_complete()) {
+        release_folder(service, folder);
+    }
+
+    // Only the outcome is recorded; per-chunk progress for files this small is noise.
+    emit_transfer_progress(
+        service,
+        TransferProgressEventPayload {
+            transfer_id: transfer_id.to_string(),
+            file_id: entry.id.clone(),
+            file_path: file_path.to_string_lossy().to_string(),
+            file_name: sanitize_file_name(&entry.name),
+            direction: "receive".to_string(),
+            source_user_id: None,
+            source_user_name: None,
+            source_device_id: None,
+            source_device_name: None,
+            same_account: None,
+            target_device_id: String::new(),
+            total_bytes: entry.size,
+            sent_bytes: file.written,
+            progress_percent: if entry.size == 0 {
+                100.0
+            } else {
+                ((file.written as f64 / entry.size as f64) * 100.0).min(100.0)
+            },
+            status: status.to_string(),
+            error,
+            timestamp_ms: now_timestamp_ms(),
+            folder: folder_progress,
+        },
+    );
+
+    kept
+}
+
+async fn write_bundle_chunk(
+    service: &FileTransferService,
+    bundle: &mut BundleState,
+    chunk: &[u8],
+) -> SocketResult<()> {
+    let mut input = chunk;
+
+    while let Some(event) = bundle.decoder.next_event(&mut input) {
+        match event {
+            BundleEvent::Start(index) => {
+                bundle.current =
+                    open_bundle_entry(service, &bundle.base_dir, &bundle.entries[index]).await?;
+            }
+            BundleEvent::Data(data) => {
+                if let Some(file) = bundle.current.as_mut() {
+                    file.writer.write_all(data).await?;
+                    file.hasher.update(data);
+                    file.written += data.len() as u64;
+                }
+            }
+            BundleEvent::End(index, checksum) => {
+                if let Some(file) = bundle.current.take() {
+                    let entry = &bundle.entries[index];
+                    if finish_bundle_entry(
+                        service,
+                        &bundle.transfer_id,
+                        entry,
+                        file,
+                        Some(checksum),
+                    )
+                    .await
+                    {
+                        bundle.kept.insert(entry.id.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    if !input.is_empty() {
+        return Err(SocketError::parse("bundle stream continues past its last entry").into());
+    }
+
     Ok(())
 }
 
+fn release_folder(service: &FileTransferService, folder: &Arc<FolderProgressTracker>) {
+    service
+        .receive_folders
+        .retain(|_, tracker| !Arc::ptr_eq(tracker, folder));
+}
+
+/// Moves a verified `.part` file into place. Another file may have claimed the name
+/// while this one was in flight, so unless the policy is to overwrite it lands on the
+/// next free name instead.
+async fn finalize_received_file(part_path: &Path, target_path: &Path) -> SocketResult<PathBuf> {
+    if part_path == target_path {
+        return Ok(target_path.to_path_buf());
+    }
+
+    let service = GlobalState::get::<FileTransferService>();
+    let overwrite = *service.collision_policy.read().await == CollisionPolicy::Overwrite;
+
+    finalize_part_file(part_path, target_path, overwrite).map_err(|e| {
+        SocketError::other(format!(
+            "Failed to move {:?} to {:?}: {}",
+            part_path, target_path, e
+        ))
+        .into()
+    })
+}
+
 async fn handle_file_chunk(
     conn: Arc<Connection>,
-    payload: Vec<u8>,
+    chunk: FileChunk,
     _req_id: i32,
 ) -> SocketResult<()> {
     let service = GlobalState::get::<FileTransferService>();
-    let config = TransferConfig::global();
+    write_file_chunk(&service, &conn, chunk.file_id, &chunk.data).await
+}
+
+async fn handle_file_chunk_compressed(
+    conn: Arc<Connection>,
+    chunk: FileChunkCompressed,
+    _req_id: i32,
+) -> SocketResult<()> {
+    let service = GlobalState::get::<FileTransferService>();
+
+    let codec = ChunkCodec::from_u8(chunk.codec)
+        .ok_or_else(|| SocketError::parse(format!("unknown chunk codec {}", chunk.codec)))?;
+
+    let data = decompress_chunk(codec, &chunk.data, chunk.raw_len as usize)?;
+    write_file_chunk(&service, &conn, chunk.file_id, &data).await
+}
 
-    let mut reader = BinaryReader::new(&payload);
-    let file_id = reader
-        .read_string()
-        .map_err(|e| SocketError::parse(e.to_string()))?;
-    let chunk = reader.remaining_bytes();
+async fn write_file_chunk(
+    service: &FileTransferService,
+    conn: &Connection,
+    file_id: String,
+    chunk: &[u8],
+) -> SocketResult<()> {
     let chunk_len = chunk.len() as u64;
     let conn_id = conn.id().to_string();
 
@@ -214,6 +1055,9 @@ async fn handle_file_chunk(
     if let Some(state) = state {
         let mut writer = state.writer.lock().await;
         writer.write_all(chunk).await?;
+        if let Ok(mut hasher) = state.hasher.lock() {
+            hasher.update(chunk);
+        }
 
         let current_size = state.received_size.fetch_add(chunk_len, Ordering::SeqCst) + chunk_len;
         let total_size = state.expected_size;
@@ -232,7 +1076,7 @@ async fn handle_file_chunk(
                 ((current_size as f64 / total_size as f64) * 100.0).min(100.0)
             };
             emit_transfer_progress(
-                &service,
+                service,
                 TransferProgressEventPayload {
                     transfer_id: state.transfer_id.clone(),
                     file_id: state.file_id.clone(),
@@ -251,42 +1095,33 @@ async fn handle_file_chunk(
                     status: "processing".to_string(),
                     error: None,
                     timestamp_ms: now_timestamp_ms(),
+                    folder: state.folder_progress(current_size),
                 },
             );
         }
 
+        if current_size < state.expected_size
+            && current_size.saturating_sub(state.last_checkpoint_size.load(Ordering::SeqCst))
+                >= RESUME_CHECKPOINT_STEP
+        {
+            checkpoint_transfer(&state, &mut writer).await?;
+        }
+
         if current_size >= state.expected_size {
+            // Success is only reported once FileFinish confirms the checksum.
             writer.flush().await?;
-            if config.sync_on_complete {
-                writer.get_ref().sync_all().await?;
-            }
-
-            log::info!("Transfer complete: {:?}", state.file_path);
-            emit_transfer_progress(
-                &service,
-                TransferProgressEventPayload {
-                    transfer_id: state.transfer_id.clone(),
-                    file_id: state.file_id.clone(),
-                    file_path: state.file_path.to_string_lossy().to_string(),
-                    file_name: state.file_name.clone(),
-                    direction: "receive".to_string(),
-                    source_user_id: None,
-                    source_user_name: None,
-                    source_device_id: None,
-                    source_device_name: None,
-                    same_account: None,
-                    target_device_id: String::new(),
-                    total_bytes: state.expected_size,
-                    sent_bytes: state.expected_size,
-                    progress_percent: 100.0,
-                    status: "success".to_string(),
-                    error: None,
-                    timestamp_ms: now_timestamp_ms(),
-                },
+            log::info!(
+                "All bytes received for {:?}, awaiting FileFinish",
+                state.file_path
             );
-
-            service.active_transfers.remove(&(conn_id, file_id));
         }
+    } else if let Some(bundle) = service
+        .active_bundles
+        .get(&(conn_id, file_id.clone()))
+        .map(|r| r.value().clone())
+    {
+        let mut bundle = bundle.lock().await;
+        write_bundle_chunk(service, &mut bundle, chunk).await?;
     } else {
         log::debug!("Received chunk for unknown transfer: {}", file_id);
     }
@@ -296,39 +1131,118 @@ async fn handle_file_chunk(
 
 async fn handle_file_finish(
     conn: Arc<Connection>,
-    payload: Vec<u8>,
-    _req_id: i32,
+    finish: FileFinish,
+    req_id: i32,
 ) -> SocketResult<()> {
     let service = GlobalState::get::<FileTransferService>();
     let config = TransferConfig::global();
-    let mut reader = BinaryReader::new(&payload);
-    let file_id = reader
-        .read_string()
-        .map_err(|e| SocketError::parse(e.to_string()))?;
+    let expected_checksum = finish.checksum;
 
-    let conn_id = conn.id().to_string();
-
-    if let Some((_, state)) = service.active_transfers.remove(&(conn_id, file_id)) {
-        let mut writer = state.writer.lock().await;
-        writer.flush().await?;
+    let key = (conn.id().to_string(), finish.file_id);
 
-        if config.sync_on_complete {
-            writer.get_ref().sync_all().await?;
+    if let Some((_, bundle)) = service.active_bundles.remove(&key) {
+        let mut bundle = bundle.lock().await;
+        if !bundle.decoder.is_finished() {
+            log::warn!("Bundle {} finished before all of its files arrived", key.1);
+            if let (Some(index), Some(file)) =
+                (bundle.decoder.current_entry(), bundle.current.take())
+            {
+                let entry = bundle.entries[index].clone();
+                finish_bundle_entry(&service, &bundle.transfer_id, &entry, file, None).await;
+            }
         }
-        log::info!("File finished manually: {:?}", state.file_path);
+
+        // Skipped, failed and missing entries alike were not kept.
+        let failed = bundle
+            .entries
+            .iter()
+            .filter(|entry| !bundle.kept.contains(&entry.id))
+            .map(|entry| entry.id.clone())
+            .collect();
+        return reply_to_finish(&conn, req_id, key.1, Ok(failed)).await;
+    }
+
+    if let Some((_, state)) = service.active_transfers.remove(&key) {
+        let mut writer = state.writer.lock().await;
+        let written = match writer.flush().await {
+            Ok(()) if config.sync_on_complete => writer.get_ref().sync_all().await,
+            other => other,
+        };
+        clear_transfer_resume_state(state.peer_fingerprint.clone(), state.file_id.clone());
 
         let received_size = state.received_size.load(Ordering::SeqCst);
+        let actual_checksum = match state.hasher.lock() {
+            Ok(mut hasher) => std::mem::take(&mut *hasher).finalize().to_vec(),
+            Err(e) => {
+                return Err(SocketError::other(format!("Checksum state poisoned: {}", e)).into())
+            }
+        };
+
+        let verification_error = if let Err(e) = written {
+            Some(format!("write error: {}", e))
+        } else if received_size != state.expected_size {
+            Some(format!(
+                "size mismatch: expected {} bytes, received {}",
+                state.expected_size, received_size
+            ))
+        } else {
+            match expected_checksum {
+                Some(expected) if expected[..] != actual_checksum[..] => Some(format!(
+                    "checksum mismatch: expected {}, got {}",
+                    hex::encode(expected),
+                    hex::encode(&actual_checksum)
+                )),
+                Some(_) => None,
+                // Every supported peer sends one; without it nothing vouches for the bytes.
+                None => Some("no checksum supplied".to_string()),
+            }
+        };
+
         let progress_percent = if state.expected_size == 0 {
             100.0
         } else {
             ((received_size as f64 / state.expected_size as f64) * 100.0).min(100.0)
         };
+
+        let mut file_path = state.file_path.clone();
+        let verification_error = match verification_error {
+            Some(error) => {
+                if let Err(e) = tokio::fs::remove_file(&state.part_path).await {
+                    log::warn!("Failed to remove {:?}: {}", state.part_path, e);
+                }
+                Some(error)
+            }
+            None => match finalize_received_file(&state.part_path, &state.file_path).await {
+                Ok(final_path) => {
+                    file_path = final_path;
+                    None
+                }
+                Err(e) => Some(format!("{:#}", e)),
+            },
+        };
+
+        let (status, error) = match verification_error {
+            Some(error) => {
+                log::error!("Transfer failed for {:?}: {}", file_path, error);
+                ("failed", Some(error))
+            }
+            None => {
+                log::info!("Transfer complete: {:?}", file_path);
+                ("success", None)
+            }
+        };
+
+        let folder_progress = state.folder_progress(received_size);
+        if let Some(folder) = state.folder.as_ref().filter(|folder| folder.is_complete()) {
+            release_folder(&service, folder);
+        }
+
         emit_transfer_progress(
             &service,
             TransferProgressEventPayload {
                 transfer_id: state.transfer_id.clone(),
                 file_id: state.file_id.clone(),
-                file_path: state.file_path.to_string_lossy().to_string(),
+                file_path: file_path.to_string_lossy().to_string(),
                 file_name: state.file_name.clone(),
                 direction: "receive".to_string(),
                 source_user_id: None,
@@ -340,32 +1254,75 @@ async fn handle_file_finish(
                 total_bytes: state.expected_size,
                 sent_bytes: received_size,
                 progress_percent,
-                status: "success".to_string(),
-                error: None,
+                status: status.to_string(),
+                error: error.clone(),
                 timestamp_ms: now_timestamp_ms(),
+                folder: folder_progress,
             },
         );
+
+        let outcome = match error {
+            Some(reason) => Err(reason),
+            None => Ok(Vec::new()),
+        };
+        return reply_to_finish(&conn, req_id, key.1, outcome).await;
     }
 
-    Ok(())
+    let reason = "no transfer in progress".to_string();
+    reply_to_finish(&conn, req_id, key.1, Err(reason)).await
 }
 
-pub async fn register_file_handlers(router: &PacketRouter) {
-    router
-        .register(PacketType::FileOffer, |conn, payload, req_id| {
-            Box::pin(handle_file_offer(conn, payload, req_id))
-        })
-        .await;
+/// Answers a `FileFinish`. The sender only counts the data as delivered once it gets the
+/// `FileAck`, which lists the bundle entries that were not kept; `Err` carries the reason
+/// a single file was not.
+async fn reply_to_finish(
+    conn: &Connection,
+    req_id: i32,
+    file_id: String,
+    outcome: Result<Vec<String>, String>,
+) -> SocketResult<()> {
+    match outcome {
+        Ok(failed) => {
+            conn.send_message_with_id(req_id, &FileAck { file_id, failed })
+                .await
+        }
+        Err(reason) => {
+            let reject = FileReject {
+                file_id,
+                reason,
+                whole_transfer: false,
+            };
+            conn.send_message_with_id(req_id, &reject).await
+        }
+    }
+}
 
-    router
-        .register(PacketType::FileChunk, |conn, payload, req_id| {
-            Box::pin(handle_file_chunk(conn, payload, req_id))
-        })
-        .await;
+async fn handle_file_pause(
+    conn: Arc<Connection>,
+    pause: FilePause,
+    _req_id: i32,
+) -> SocketResult<()> {
+    let service = GlobalState::get::<FileTransferService>();
+    let conn_id = conn.id().to_string();
 
-    router
-        .register(PacketType::FileFinish, |conn, payload, req_id| {
-            Box::pin(handle_file_finish(conn, payload, req_id))
-        })
-        .await;
+    if let Some((_, state)) = service.active_transfers.remove(&(conn_id, pause.file_id)) {
+        let mut writer = state.writer.lock().await;
+        checkpoint_transfer(&state, &mut writer).await?;
+
+        log::info!(
+            "Transfer paused by sender: {:?} at {} bytes",
+            state.file_path,
+            state.received_size.load(Ordering::SeqCst)
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn register_file_handlers(router: &PacketRouter) {
+    router.register_packet(handle_file_offer).await;
+    router.register_packet(handle_file_chunk).await;
+    router.register_packet(handle_file_chunk_compressed).await;
+    router.register_packet(handle_file_finish).await;
+    router.register_packet(handle_file_pause).await;
 }
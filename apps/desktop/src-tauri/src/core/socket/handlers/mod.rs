@@ -1,4 +1,6 @@
+pub mod bundle;
 pub mod file;
+pub mod receive_path;
 pub mod sys;
 
 pub use file::register_file_handlers;
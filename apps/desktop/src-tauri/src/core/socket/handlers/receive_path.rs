@@ -0,0 +1,566 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+const PART_SUFFIX: &str = ".part";
+const FALLBACK_FILE_NAME: &str = "file";
+const MAX_FILE_NAME_BYTES: usize = 255;
+const MAX_RENAME_ATTEMPTS: u32 = 10_000;
+
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// What to do when an incoming file would land on a name that already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionPolicy {
+    #[default]
+    Rename,
+    Overwrite,
+    Skip,
+}
+
+impl CollisionPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "rename" => Some(Self::Rename),
+            "overwrite" => Some(Self::Overwrite),
+            "skip" => Some(Self::Skip),
+            _ => None,
+        }
+    }
+}
+
+/// Reduces a peer-supplied name to a single safe path component: directory parts,
+/// control characters and characters Windows rejects are removed, reserved device
+/// names are prefixed, and the result is never empty, `.` or `..`.
+pub fn sanitize_file_name(name: &str) -> String {
+    let last_component = name.rsplit(['/', '\\']).next().unwrap_or_default();
+
+    let mut cleaned: String = last_component
+        .chars()
+        .map(|c| {
+            if c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    // Windows drops trailing dots and spaces, which would let "a.txt." alias "a.txt".
+    let trimmed_len = cleaned.trim_end_matches(['.', ' ']).len();
+    cleaned.truncate(trimmed_len);
+    let mut cleaned = cleaned.trim_start().to_string();
+
+    if cleaned.is_empty() {
+        return FALLBACK_FILE_NAME.to_string();
+    }
+
+    let stem = cleaned.split('.').next().unwrap_or_default();
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        cleaned.insert(0, '_');
+    }
+
+    truncate_file_name(cleaned)
+}
+
+/// Turns a peer-supplied `/`- or `\`-separated relative path into one that can only
+/// descend below the receive directory. Empty and `.` components are dropped; any `..`
+/// component rejects the whole path.
+pub fn sanitize_relative_path(relative_path: &str) -> Option<PathBuf> {
+    let mut sanitized = PathBuf::new();
+    for component in relative_path.split(['/', '\\']) {
+        match component.trim() {
+            "" | "." => continue,
+            ".." => return None,
+            _ => sanitized.push(sanitize_file_name(component)),
+        }
+    }
+
+    if sanitized.as_os_str().is_empty() {
+        None
+    } else {
+        Some(sanitized)
+    }
+}
+
+/// Checks that `dir` still resolves inside `base_dir` once symlinks are followed, so a
+/// pre-existing link in the receive directory cannot redirect a folder transfer.
+pub fn is_within_base_dir(base_dir: &Path, dir: &Path) -> bool {
+    match (base_dir.canonicalize(), dir.canonicalize()) {
+        (Ok(base), Ok(resolved)) => resolved.starts_with(base),
+        _ => false,
+    }
+}
+
+/// Creates `relative_dir` below `base_dir` one component at a time and returns it. Each
+/// component, new or pre-existing, must be a directory that resolves inside `base_dir`
+/// before the next one is created, so a symlink in the receive directory can never make
+/// us create directories elsewhere. Such a path fails with [`io::ErrorKind::InvalidInput`].
+pub fn create_dir_within(base_dir: &Path, relative_dir: &Path) -> io::Result<PathBuf> {
+    let mut dir = base_dir.to_path_buf();
+    for component in relative_dir.components() {
+        let Component::Normal(name) = component else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} is not a plain relative path", relative_dir),
+            ));
+        };
+        dir.push(name);
+
+        match fs::create_dir(&dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+
+        if !dir.is_dir() || !is_within_base_dir(base_dir, &dir) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} resolves outside the receive directory", dir),
+            ));
+        }
+    }
+
+    Ok(dir)
+}
+
+fn truncate_file_name(name: String) -> String {
+    if name.len() <= MAX_FILE_NAME_BYTES {
+        return name;
+    }
+
+    let (stem, extension) = split_extension(&name);
+    let budget = MAX_FILE_NAME_BYTES.saturating_sub(extension.len());
+    let mut end = budget.min(stem.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    format!("{}{}", &stem[..end], extension)
+}
+
+/// Splits `name` into stem and extension, keeping the dot with the extension.
+/// Leading-dot names such as `.bashrc` are treated as having no extension.
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(index) if index > 0 => name.split_at(index),
+        _ => (name, ""),
+    }
+}
+
+/// The temporary path an incoming file is written to until it has been verified.
+pub fn part_path_for(target: &Path) -> PathBuf {
+    let mut file_name = target
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_else(|| OsString::from(FALLBACK_FILE_NAME));
+    file_name.push(PART_SUFFIX);
+    target.with_file_name(file_name)
+}
+
+/// Inverse of [`part_path_for`]; paths without the suffix are returned unchanged.
+pub fn target_path_for(part: &Path) -> PathBuf {
+    match part.file_name().and_then(|name| name.to_str()) {
+        Some(name) if name.len() > PART_SUFFIX.len() && name.ends_with(PART_SUFFIX) => {
+            part.with_file_name(&name[..name.len() - PART_SUFFIX.len()])
+        }
+        _ => part.to_path_buf(),
+    }
+}
+
+/// `target` itself, then `name (1).ext`, `name (2).ext`, ... alongside it, and finally a
+/// name no one else can have picked.
+fn candidate_paths(target: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    let file_name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| FALLBACK_FILE_NAME.to_string());
+    let (stem, extension) = split_extension(&file_name);
+    let (stem, extension) = (stem.to_string(), extension.to_string());
+
+    let suffixes = (1..=MAX_RENAME_ATTEMPTS)
+        .map(|attempt| attempt.to_string())
+        .chain(std::iter::once_with(|| uuid::Uuid::new_v4().to_string()));
+
+    std::iter::once(target.to_path_buf()).chain(
+        suffixes.map(move |suffix| {
+            target.with_file_name(format!("{} ({}){}", stem, suffix, extension))
+        }),
+    )
+}
+
+/// Creates the `.part` file for `target`, or for the first free `name (n).ext` alongside
+/// it, and returns the chosen target with the open file. The part file is created with
+/// `create_new`, so two transfers of the same name can never end up writing one file.
+/// With `keep_existing`, names whose final file already exists are passed over as well.
+pub fn create_part_file(target: &Path, keep_existing: bool) -> io::Result<(PathBuf, File)> {
+    for candidate in candidate_paths(target) {
+        if keep_existing && candidate.exists() {
+            continue;
+        }
+
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(part_path_for(&candidate))
+        {
+            Ok(file) => return Ok((candidate, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("no free name for {:?}", target),
+    ))
+}
+
+/// Moves a finished `.part` file to `target` and returns where it ended up. Unless
+/// `overwrite` is set, an existing file is never replaced: the part is hard-linked to the
+/// first free `name (n).ext` and then unlinked, since a plain rename would clobber a file
+/// that appeared after the name was chosen.
+pub fn finalize_part_file(part: &Path, target: &Path, overwrite: bool) -> io::Result<PathBuf> {
+    if overwrite {
+        fs::rename(part, target)?;
+        return Ok(target.to_path_buf());
+    }
+
+    for candidate in candidate_paths(target) {
+        match link_or_claim(part, &candidate) {
+            Ok(()) => return Ok(candidate),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("no free name for {:?}", target),
+    ))
+}
+
+/// Moves `part` to `target` only if `target` does not exist yet.
+fn link_or_claim(part: &Path, target: &Path) -> io::Result<()> {
+    match fs::hard_link(part, target) {
+        Ok(()) => fs::remove_file(part),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(e),
+        // Filesystems without hard links (FAT, some network shares): claim the name with
+        // an empty file first, then replace our own placeholder.
+        Err(_) => {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(target)?;
+            fs::rename(part, target)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system temp dir, removed again on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let path =
+                std::env::temp_dir().join(format!("nekoshare-test-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn file_name_keeps_only_the_last_component() {
+        assert_eq!(sanitize_file_name("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_file_name("/etc/passwd"), "passwd");
+        assert_eq!(sanitize_file_name("..\\..\\Windows\\win.ini"), "win.ini");
+        assert_eq!(sanitize_file_name("C:\\Windows\\system.ini"), "system.ini");
+        assert_eq!(
+            sanitize_file_name("\\\\server\\share\\report.pdf"),
+            "report.pdf"
+        );
+        assert_eq!(sanitize_file_name("C:evil.txt"), "C_evil.txt");
+    }
+
+    #[test]
+    fn file_name_is_never_empty_or_a_dot_name() {
+        for name in ["", ".", "..", "...", "a/", "a/..", " . ", "\\"] {
+            assert_eq!(sanitize_file_name(name), FALLBACK_FILE_NAME, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn file_name_drops_characters_windows_rejects() {
+        assert_eq!(
+            sanitize_file_name("a<b>c:d\"e|f?g*h.txt"),
+            "a_b_c_d_e_f_g_h.txt"
+        );
+        assert_eq!(sanitize_file_name("tab\there\u{0}.txt"), "tab_here_.txt");
+        // Trailing dots and spaces would alias the name without them on Windows.
+        assert_eq!(sanitize_file_name("report.txt. . "), "report.txt");
+        assert_eq!(sanitize_file_name("  leading.txt"), "leading.txt");
+    }
+
+    #[test]
+    fn file_name_prefixes_reserved_device_names() {
+        assert_eq!(sanitize_file_name("CON"), "_CON");
+        assert_eq!(sanitize_file_name("nul.txt"), "_nul.txt");
+        assert_eq!(sanitize_file_name("Lpt1.tar.gz"), "_Lpt1.tar.gz");
+        assert_eq!(sanitize_file_name("COM10"), "COM10");
+        assert_eq!(sanitize_file_name("console.log"), "console.log");
+    }
+
+    #[test]
+    fn long_file_name_is_truncated_on_a_char_boundary() {
+        let name = format!("{}.txt", "é".repeat(200));
+        let sanitized = sanitize_file_name(&name);
+
+        assert!(sanitized.len() <= MAX_FILE_NAME_BYTES);
+        assert!(sanitized.ends_with("é.txt"));
+    }
+
+    #[test]
+    fn relative_path_only_descends() {
+        let cases = [
+            ("photos/2024/beach.jpg", Some("photos/2024/beach.jpg")),
+            ("photos\\2024\\beach.jpg", Some("photos/2024/beach.jpg")),
+            ("./photos/./beach.jpg", Some("photos/beach.jpg")),
+            ("/etc/passwd", Some("etc/passwd")),
+            ("C:\\Windows\\win.ini", Some("C_/Windows/win.ini")),
+            ("\\\\server\\share\\file", Some("server/share/file")),
+            ("con/aux.txt", Some("_con/_aux.txt")),
+            ("../secret", None),
+            ("photos/../../secret", None),
+            ("photos\\..\\..\\secret", None),
+            (" .. /secret", None),
+            ("", None),
+            ("//./", None),
+        ];
+
+        for (input, expected) in cases {
+            let expected = expected.map(|path| path.split('/').collect::<PathBuf>());
+            assert_eq!(sanitize_relative_path(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn sanitized_relative_path_has_only_plain_components() {
+        let path = sanitize_relative_path("/a/\\b/c:/d").unwrap();
+
+        assert!(path.is_relative());
+        assert!(path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_))));
+    }
+
+    #[test]
+    fn within_base_dir_follows_the_real_path() {
+        let base = TempDir::new();
+        fs::create_dir(base.0.join("inside")).unwrap();
+
+        assert!(is_within_base_dir(&base.0, &base.0.join("inside")));
+        assert!(is_within_base_dir(&base.0, &base.0.join("inside/..")));
+        assert!(!is_within_base_dir(&base.0, &base.0.join("..")));
+        assert!(!is_within_base_dir(&base.0, &base.0.join("missing")));
+    }
+
+    #[test]
+    fn create_dir_within_creates_each_component() {
+        let base = TempDir::new();
+
+        let dir = create_dir_within(&base.0, Path::new("a/b/c")).unwrap();
+
+        assert_eq!(dir, base.0.join("a/b/c"));
+        assert!(dir.is_dir());
+        // Existing directories are reused.
+        assert_eq!(
+            create_dir_within(&base.0, Path::new("a/b")).unwrap(),
+            base.0.join("a/b")
+        );
+    }
+
+    #[test]
+    fn create_dir_within_rejects_non_plain_components() {
+        let base = TempDir::new();
+
+        for relative in ["../escape", "a/../../escape"] {
+            let err = create_dir_within(&base.0, Path::new(relative)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{:?}", relative);
+        }
+        assert!(!base.0.parent().unwrap().join("escape").exists());
+    }
+
+    #[test]
+    fn create_dir_within_rejects_a_file_in_the_way() {
+        let base = TempDir::new();
+        fs::write(base.0.join("a"), b"").unwrap();
+
+        let err = create_dir_within(&base.0, Path::new("a/b")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn create_dir_within_does_not_follow_links_out_of_the_base_dir() {
+        let base = TempDir::new();
+        let outside = TempDir::new();
+        std::os::unix::fs::symlink(&outside.0, base.0.join("link")).unwrap();
+        fs::create_dir(base.0.join("real")).unwrap();
+        std::os::unix::fs::symlink(base.0.join("real"), base.0.join("inner")).unwrap();
+
+        let err = create_dir_within(&base.0, Path::new("link/a/b")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(file_names(&outside.0).is_empty());
+
+        // A link that stays inside the receive directory is fine.
+        create_dir_within(&base.0, Path::new("inner/a")).unwrap();
+        assert!(base.0.join("real/a").is_dir());
+    }
+
+    #[test]
+    fn part_and_target_paths_are_inverses() {
+        let target = Path::new("/downloads/report.pdf");
+        let part = part_path_for(target);
+
+        assert_eq!(part, Path::new("/downloads/report.pdf.part"));
+        assert_eq!(target_path_for(&part), target);
+        assert_eq!(target_path_for(target), target);
+        assert_eq!(
+            target_path_for(Path::new("/downloads/.part")),
+            Path::new("/downloads/.part")
+        );
+    }
+
+    #[test]
+    fn part_files_are_numbered_while_a_name_is_taken() {
+        let base = TempDir::new();
+        let target = base.0.join("report.pdf");
+
+        let names: Vec<PathBuf> = (0..3)
+            .map(|_| create_part_file(&target, true).unwrap().0)
+            .collect();
+
+        assert_eq!(
+            names,
+            [
+                base.0.join("report.pdf"),
+                base.0.join("report (1).pdf"),
+                base.0.join("report (2).pdf"),
+            ]
+        );
+        assert_eq!(
+            file_names(&base.0),
+            [
+                "report (1).pdf.part",
+                "report (2).pdf.part",
+                "report.pdf.part"
+            ]
+        );
+    }
+
+    #[test]
+    fn part_file_numbering_keeps_the_extension() {
+        let base = TempDir::new();
+        fs::write(base.0.join("archive.tar.gz"), b"").unwrap();
+        fs::write(base.0.join(".bashrc"), b"").unwrap();
+        fs::write(base.0.join("README"), b"").unwrap();
+
+        let renamed = |name: &str| create_part_file(&base.0.join(name), true).unwrap().0;
+
+        assert_eq!(renamed("archive.tar.gz"), base.0.join("archive.tar (1).gz"));
+        assert_eq!(renamed(".bashrc"), base.0.join(".bashrc (1)"));
+        assert_eq!(renamed("README"), base.0.join("README (1)"));
+    }
+
+    #[test]
+    fn existing_files_are_only_passed_over_when_kept() {
+        let base = TempDir::new();
+        let target = base.0.join("notes.txt");
+        fs::write(&target, b"old").unwrap();
+
+        assert_eq!(create_part_file(&target, false).unwrap().0, target);
+        assert_eq!(
+            create_part_file(&target, true).unwrap().0,
+            base.0.join("notes (1).txt")
+        );
+    }
+
+    #[test]
+    fn concurrent_receivers_never_share_a_part_file() {
+        let base = TempDir::new();
+        let target = base.0.join("same.bin");
+
+        let handles: Vec<_> = (0..16)
+            .map(|_| {
+                let target = target.clone();
+                std::thread::spawn(move || create_part_file(&target, true).unwrap().0)
+            })
+            .collect();
+        let mut names: Vec<PathBuf> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        names.sort();
+        names.dedup();
+
+        assert_eq!(names.len(), 16);
+        assert_eq!(file_names(&base.0).len(), 16);
+    }
+
+    #[test]
+    fn finalize_never_replaces_an_existing_file() {
+        let base = TempDir::new();
+        let target = base.0.join("photo.jpg");
+        let (_, mut part) = create_part_file(&target, true).unwrap();
+        io::Write::write_all(&mut part, b"new").unwrap();
+        drop(part);
+        // Another transfer finished under the same name in the meantime.
+        fs::write(&target, b"old").unwrap();
+
+        let final_path = finalize_part_file(&part_path_for(&target), &target, false).unwrap();
+
+        assert_eq!(final_path, base.0.join("photo (1).jpg"));
+        assert_eq!(fs::read(&target).unwrap(), b"old");
+        assert_eq!(fs::read(&final_path).unwrap(), b"new");
+        assert_eq!(file_names(&base.0), ["photo (1).jpg", "photo.jpg"]);
+    }
+
+    #[test]
+    fn finalize_overwrites_when_asked() {
+        let base = TempDir::new();
+        let target = base.0.join("photo.jpg");
+        fs::write(&target, b"old").unwrap();
+        let (_, mut part) = create_part_file(&target, false).unwrap();
+        io::Write::write_all(&mut part, b"new").unwrap();
+        drop(part);
+
+        let final_path = finalize_part_file(&part_path_for(&target), &target, true).unwrap();
+
+        assert_eq!(final_path, target);
+        assert_eq!(fs::read(&target).unwrap(), b"new");
+        assert_eq!(file_names(&base.0), ["photo.jpg"]);
+    }
+}
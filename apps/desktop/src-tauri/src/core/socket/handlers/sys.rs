@@ -1,16 +1,74 @@
+use nktcp::packets::{Heartbeat, KeyRotation};
 use std::sync::Arc;
 
-use crate::core::socket::{Connection, PacketRouter, PacketType, SocketResult};
+use crate::core::device::{DeviceManager, RotationStatement};
+use crate::core::socket::{Connection, PacketRouter, SocketError, SocketResult};
+use crate::state::GlobalState;
 
 async fn handle_heartbeat(conn: Arc<Connection>) -> SocketResult<()> {
-    let _ = conn.send_packet(PacketType::SystemHeartbeat, |_| {}).await;
+    let _ = conn.send_message(&Heartbeat).await;
+    Ok(())
+}
+
+/// Sends our pending key rotation, if any, so a peer that pinned the old key can move on.
+pub async fn announce_key_rotation(conn: &Connection) -> SocketResult<()> {
+    let Some(statement) = GlobalState::get::<DeviceManager>().pending_rotation() else {
+        return Ok(());
+    };
+    let statement = serde_json::to_string(&statement)
+        .map_err(|e| SocketError::parse(format!("Failed to serialize rotation: {}", e)))?;
+
+    conn.send_message(&KeyRotation { statement }).await?;
+    Ok(())
+}
+
+async fn handle_key_rotation(conn: Arc<Connection>, rotation: KeyRotation) -> SocketResult<()> {
+    let statement: RotationStatement = serde_json::from_str(&rotation.statement)
+        .map_err(|e| SocketError::parse(format!("Invalid rotation statement: {}", e)))?;
+
+    // Only the device rotating may announce it: the session was authenticated either with the
+    // key being retired or with its successor, when the new fingerprint was pinned directly.
+    let announced_by_peer = conn.peer_fingerprint().is_some_and(|fingerprint| {
+        fingerprint == statement.old_fingerprint || fingerprint == statement.new_fingerprint
+    });
+    if !announced_by_peer {
+        log::warn!(
+            "Ignoring rotation of {} announced over a session with {}",
+            statement.old_fingerprint,
+            conn.peer_fingerprint().unwrap_or("<no certificate>")
+        );
+        return Ok(());
+    }
+    statement
+        .verify()
+        .map_err(|e| SocketError::parse(format!("{:#}", e)))?;
+
+    let trust_store = GlobalState::get::<DeviceManager>().trust_store();
+    if trust_store
+        .apply_rotation(&statement)
+        .map_err(|e| SocketError::server(format!("{:#}", e)))?
+    {
+        log::info!(
+            "Peer {} rotated its key to {}",
+            conn.id(),
+            statement.new_fingerprint
+        );
+    } else {
+        log::debug!(
+            "Peer {} rotated an untrusted key, nothing to update",
+            conn.id()
+        );
+    }
+
     Ok(())
 }
 
 pub async fn register_system_handlers(router: &PacketRouter) {
     router
-        .register(PacketType::SystemHeartbeat, |conn, _payload, _req_id| {
-            handle_heartbeat(conn)
-        })
+        .register_packet(|conn, Heartbeat, _req_id| handle_heartbeat(conn))
+        .await;
+
+    router
+        .register_packet(|conn, rotation, _req_id| handle_key_rotation(conn, rotation))
         .await;
 }
@@ -0,0 +1,357 @@
+use nkcrypto::X25519KeyPair;
+use nktcp::packets::{Handshake, ServerFull, SessionKeyProof};
+use nktcp::Packet;
+use tokio::sync::mpsc;
+use tokio::time::{self, Duration};
+
+use crate::core::device::trust::normalize_fingerprint;
+use crate::core::device::{signature, DeviceManager, KeyDer};
+use crate::state::GlobalState;
+
+use super::compression::CODEC_ZSTD;
+use super::connection::Connection;
+use super::e2e::PayloadCipher;
+use super::error::{SocketError, SocketResult};
+use super::protocol::{PacketType, Side};
+
+/// Bumped whenever framing or packet layouts change incompatibly.
+pub const PROTOCOL_VERSION: u16 = 2;
+/// Oldest peer protocol this build can still talk to. Version 1 sent offers as JSON.
+pub const MIN_PROTOCOL_VERSION: u16 = 2;
+/// From this version on, a handshake without an authenticated session key fails rather
+/// than falling back to plaintext payloads.
+const E2E_REQUIRED_VERSION: u16 = 2;
+
+pub const CAP_ZSTD_CHUNKS: u32 = CODEC_ZSTD;
+pub const CAP_FILE_BUNDLES: u32 = 1 << 1;
+/// Chunk, text and clipboard payloads are sealed with a key agreed during the handshake.
+pub const CAP_E2E_PAYLOADS: u32 = 1 << 2;
+pub const LOCAL_CAPABILITIES: u32 = CAP_ZSTD_CHUNKS | CAP_FILE_BUNDLES | CAP_E2E_PAYLOADS;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const SESSION_KEY_PROOF_LABEL: &str = "nekoshare-session-key-v1";
+
+/// What each side announces in its `SystemHandshake` packet.
+pub use Handshake as HandshakeInfo;
+
+pub fn local_handshake(device_id: impl Into<String>) -> HandshakeInfo {
+    HandshakeInfo {
+        protocol_version: PROTOCOL_VERSION,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        device_id: device_id.into(),
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        capabilities: LOCAL_CAPABILITIES,
+        session_public_key: None,
+        session_key_proof: None,
+    }
+}
+
+fn parse_handshake(payload: &[u8]) -> SocketResult<HandshakeInfo> {
+    Ok(HandshakeInfo::from_payload(payload)?)
+}
+
+/// Agrees on the end-to-end payload key from the peer's `SystemHandshake` payload, if both
+/// sides offered one. Called from the read loop as soon as the packet arrives, so that
+/// sealed packets the peer sends right after its handshake are already readable.
+pub(super) fn agree_payload_cipher(
+    key_pair: &X25519KeyPair,
+    handshake_payload: &[u8],
+    side: Side,
+) -> SocketResult<Option<PayloadCipher>> {
+    let peer = parse_handshake(handshake_payload)?;
+    if LOCAL_CAPABILITIES & peer.capabilities & CAP_E2E_PAYLOADS == 0 {
+        return Ok(None);
+    }
+
+    match peer.session_public_key {
+        Some(peer_public) => PayloadCipher::agree(key_pair, &peer_public, side).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn session_key_message(device_id: &str, session_public_key: &[u8]) -> Vec<u8> {
+    let mut message = format!("{}\n{}\n", SESSION_KEY_PROOF_LABEL, device_id).into_bytes();
+    message.extend_from_slice(session_public_key);
+    message
+}
+
+/// Signs our ephemeral key with the device key, whose certificate the peer has pinned.
+fn prove_session_key(
+    identity: &KeyDer,
+    device_id: &str,
+    session_public_key: &[u8],
+) -> SocketResult<SessionKeyProof> {
+    let signature = signature::sign(
+        identity,
+        &session_key_message(device_id, session_public_key),
+    )
+    .map_err(|e| SocketError::handshake(format!("signing the session key: {:#}", e)))?;
+    let signature = signature.try_into().map_err(|signature: Vec<u8>| {
+        SocketError::handshake(format!(
+            "device key made a {}-byte signature, expected Ed25519",
+            signature.len()
+        ))
+    })?;
+
+    Ok(SessionKeyProof {
+        certificate: identity.cert_der.clone(),
+        signature,
+    })
+}
+
+/// Checks that the peer's ephemeral key is signed by the certificate with `peer_fingerprint`,
+/// so whoever forwards the handshake cannot replace it with its own, not even another
+/// device we trust.
+fn verify_session_key(peer: &HandshakeInfo, peer_fingerprint: &str) -> SocketResult<()> {
+    let session_public_key = peer
+        .session_public_key
+        .ok_or_else(|| SocketError::handshake("peer sent no session key"))?;
+    let proof = peer
+        .session_key_proof
+        .as_ref()
+        .ok_or_else(|| SocketError::handshake("peer did not sign its session key"))?;
+
+    let fingerprint = KeyDer::compute_fingerprint(&proof.certificate);
+    if fingerprint != peer_fingerprint {
+        return Err(SocketError::handshake(format!(
+            "session key is signed by {}, but the peer is {}",
+            fingerprint, peer_fingerprint
+        ))
+        .into());
+    }
+
+    signature::verify(
+        &proof.certificate,
+        &session_key_message(&peer.device_id, &session_public_key),
+        &proof.signature,
+    )
+    .map_err(|reason| SocketError::handshake(format!("session key: {}", reason)).into())
+}
+
+/// The certificate this connection's peer must sign its session key with: the one it
+/// presented over TLS, or the pinned one on a plain stream.
+fn expected_peer(
+    connection: &Connection,
+    pinned_fingerprint: Option<&str>,
+) -> SocketResult<String> {
+    let pinned_fingerprint = pinned_fingerprint.and_then(normalize_fingerprint);
+    match (connection.peer_fingerprint(), pinned_fingerprint) {
+        (Some(presented), Some(pinned)) if presented != pinned => Err(SocketError::handshake(
+            format!("peer presented {}, but {} is pinned", presented, pinned),
+        )
+        .into()),
+        (Some(presented), _) => Ok(presented.to_string()),
+        (None, Some(pinned)) => Ok(pinned),
+        (None, None) => {
+            Err(SocketError::handshake("no peer certificate to bind the session key to").into())
+        }
+    }
+}
+
+/// The outcome of a successful handshake, kept on the [`Connection`].
+#[derive(Debug, Clone)]
+pub struct PeerHandshake {
+    pub protocol_version: u16,
+    pub device_id: String,
+    pub app_version: String,
+    /// Capabilities both sides support.
+    pub capabilities: u32,
+    /// Whether end-to-end payload encryption is active on this connection.
+    pub end_to_end: bool,
+    /// Certificate the peer signed its session key with; `None` when the negotiated
+    /// protocol predates session key proofs.
+    pub fingerprint: Option<String>,
+}
+
+impl PeerHandshake {
+    pub fn supports(&self, capability: u32) -> bool {
+        self.capabilities & capability == capability
+    }
+}
+
+/// Checks that the two protocol ranges overlap and returns the version to speak.
+fn negotiate_version(local: &HandshakeInfo, peer: &HandshakeInfo) -> SocketResult<u16> {
+    if peer.protocol_version < local.min_protocol_version
+        || local.protocol_version < peer.min_protocol_version
+    {
+        return Err(SocketError::IncompatiblePeer(format!(
+            "peer {} (app {}) speaks protocol {} (min {}), this app speaks {} (min {})",
+            peer.device_id,
+            peer.app_version,
+            peer.protocol_version,
+            peer.min_protocol_version,
+            local.protocol_version,
+            local.min_protocol_version
+        ))
+        .into());
+    }
+
+    Ok(local.protocol_version.min(peer.protocol_version))
+}
+
+/// Sends our `SystemHandshake` and waits for the peer's, which must be the first packet
+/// it sends. Runs before the connection's packets are handed to a router; on success the
+/// negotiated result is stored on `connection`.
+///
+/// The peer must have signed its session key with the certificate it authenticated with
+/// over TLS, which must also be `pinned_fingerprint` when the caller pinned one.
+pub async fn perform_handshake(
+    connection: &Connection,
+    incoming_rx: &mut mpsc::Receiver<(PacketType, i32, Vec<u8>)>,
+    local: &HandshakeInfo,
+    pinned_fingerprint: Option<&str>,
+) -> SocketResult<PeerHandshake> {
+    let device_manager = GlobalState::get::<DeviceManager>();
+
+    let mut announced = local.clone();
+    if local.capabilities & CAP_E2E_PAYLOADS != 0 {
+        let identity = device_manager
+            .key()
+            .map_err(|e| SocketError::config(format!("Failed to get device key: {}", e)))?;
+        let key_pair = X25519KeyPair::generate()
+            .map_err(|e| SocketError::handshake(format!("session key: {}", e)))?;
+        announced.session_public_key = Some(key_pair.public_key());
+        announced.session_key_proof = Some(prove_session_key(
+            &identity,
+            &local.device_id,
+            &key_pair.public_key(),
+        )?);
+        connection.offer_session_key(key_pair);
+    }
+
+    connection.send_message(&announced).await?;
+
+    let (packet_type, _, payload) = time::timeout(HANDSHAKE_TIMEOUT, incoming_rx.recv())
+        .await
+        .map_err(|_| SocketError::handshake("timed out waiting for the peer's handshake"))?
+        .ok_or(SocketError::ConnectionClosed)?;
+
+    if packet_type == ServerFull::PACKET_TYPE {
+        let max_peers = ServerFull::from_payload(&payload)
+            .map(|full| full.max_peers)
+            .unwrap_or_default();
+        return Err(SocketError::ServerFull(max_peers).into());
+    }
+
+    if packet_type != PacketType::SystemHandshake {
+        return Err(SocketError::handshake(format!(
+            "expected SystemHandshake, peer sent {:?}",
+            packet_type
+        ))
+        .into());
+    }
+
+    let peer = parse_handshake(&payload)
+        .map_err(|e| SocketError::handshake(format!("malformed handshake: {:#}", e)))?;
+    let protocol_version = negotiate_version(local, &peer)?;
+
+    let mut fingerprint = None;
+    if protocol_version >= E2E_REQUIRED_VERSION {
+        if local.capabilities & peer.capabilities & CAP_E2E_PAYLOADS == 0 {
+            return Err(SocketError::handshake(format!(
+                "end-to-end payloads are required from protocol {} on",
+                E2E_REQUIRED_VERSION
+            ))
+            .into());
+        }
+
+        let peer_fingerprint = expected_peer(connection, pinned_fingerprint)?;
+        verify_session_key(&peer, &peer_fingerprint)?;
+        fingerprint = Some(peer_fingerprint);
+
+        // The key was agreed by the read loop before the packet got here.
+        if !connection.is_end_to_end() {
+            return Err(SocketError::handshake("no end-to-end key was agreed").into());
+        }
+    }
+
+    let negotiated = PeerHandshake {
+        protocol_version,
+        device_id: peer.device_id,
+        app_version: peer.app_version,
+        capabilities: local.capabilities & peer.capabilities,
+        end_to_end: connection.is_end_to_end(),
+        fingerprint,
+    };
+
+    log::info!(
+        "Handshake with {} complete: device {}, app {}, protocol {}, capabilities {:#x}, end-to-end {}",
+        connection.id(),
+        negotiated.device_id,
+        negotiated.app_version,
+        negotiated.protocol_version,
+        negotiated.capabilities,
+        negotiated.end_to_end
+    );
+
+    connection.set_peer_handshake(negotiated.clone());
+    Ok(negotiated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, KeyPair};
+
+    fn device_key() -> KeyDer {
+        let key_pair = KeyPair::generate_for(&rcgen::PKCS_ED25519).unwrap();
+        let cert = CertificateParams::new(vec!["device".to_string()])
+            .unwrap()
+            .self_signed(&key_pair)
+            .unwrap();
+        KeyDer::new(cert.der().to_vec(), key_pair.serialize_der())
+    }
+
+    fn signed_handshake(identity: &KeyDer) -> HandshakeInfo {
+        let session_public_key = X25519KeyPair::generate().unwrap().public_key();
+        let mut handshake = local_handshake("device-a");
+        handshake.session_public_key = Some(session_public_key);
+        handshake.session_key_proof =
+            Some(prove_session_key(identity, "device-a", &session_public_key).unwrap());
+        handshake
+    }
+
+    #[test]
+    fn session_key_signed_by_the_pinned_certificate_verifies() {
+        let identity = device_key();
+        let handshake = signed_handshake(&identity);
+
+        verify_session_key(&handshake, &identity.fingerprint).unwrap();
+    }
+
+    #[test]
+    fn swapped_session_key_is_rejected() {
+        let identity = device_key();
+        let mut handshake = signed_handshake(&identity);
+        handshake.session_public_key = Some(X25519KeyPair::generate().unwrap().public_key());
+
+        assert!(verify_session_key(&handshake, &identity.fingerprint).is_err());
+    }
+
+    #[test]
+    fn session_key_signed_by_another_certificate_is_rejected() {
+        let identity = device_key();
+        let relay = device_key();
+        let handshake = signed_handshake(&relay);
+
+        assert!(verify_session_key(&handshake, &identity.fingerprint).is_err());
+    }
+
+    #[test]
+    fn session_key_is_bound_to_the_device_id() {
+        let identity = device_key();
+        let mut handshake = signed_handshake(&identity);
+        handshake.device_id = "device-b".to_string();
+
+        assert!(verify_session_key(&handshake, &identity.fingerprint).is_err());
+    }
+
+    #[test]
+    fn unsigned_session_key_is_rejected() {
+        let identity = device_key();
+        let mut handshake = signed_handshake(&identity);
+        handshake.session_key_proof = None;
+
+        assert!(verify_session_key(&handshake, &identity.fingerprint).is_err());
+    }
+}
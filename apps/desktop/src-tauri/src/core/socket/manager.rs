@@ -4,8 +4,10 @@ use tokio::sync::mpsc;
 use uuid::Uuid;
 
 use crate::core::socket::{
+    handlers::sys::announce_key_rotation,
     ids::{LinkKey, PairKey},
-    Connection, ConnectionEvent, SocketClientConfig, SocketError, SocketResult, SocketServer,
+    pairing, AcceptMode, Connection, ConnectionEvent, ConnectionServerConfig, SocketClientConfig,
+    SocketError, SocketResult, SocketServer,
 };
 
 pub struct SocketManager {
@@ -66,8 +68,47 @@ impl SocketManager {
         Ok(connection)
     }
 
-    pub async fn start_server(self: &Arc<Self>, sender_fingerprint: String) -> SocketResult<u16> {
-        let server = SocketServer::with_events(sender_fingerprint, self.event_tx.clone());
+    /// Starts a server that accepts `sender_fingerprint` and every trusted device. If a
+    /// persistent server is already listening, the sender is let into that one instead and
+    /// its port is returned.
+    pub async fn start_server(
+        self: &Arc<Self>,
+        sender_fingerprint: Option<String>,
+        config: ConnectionServerConfig,
+    ) -> SocketResult<u16> {
+        self.servers
+            .retain(|_, server| server.is_running() || server.has_active_connection());
+
+        let running = self
+            .servers
+            .iter()
+            .map(|entry| entry.value().clone())
+            .find(|server| server.is_running() && server.accept_mode() == AcceptMode::Persistent);
+        if let Some(server) = running {
+            if let Some(ref fingerprint) = sender_fingerprint {
+                server.allow_fingerprint(fingerprint)?;
+            }
+            log::info!("Reusing server on port {} for new sender", server.port());
+            return Ok(server.port());
+        }
+
+        let server = SocketServer::with_events(sender_fingerprint, config, self.event_tx.clone());
+
+        let port = server.start().await?;
+        self.servers.insert(port, server);
+
+        Ok(port)
+    }
+
+    /// Listens for one device to pair with; the outcome arrives as `PairingFinished`.
+    pub async fn start_pairing_server(
+        self: &Arc<Self>,
+        mut config: ConnectionServerConfig,
+    ) -> SocketResult<u16> {
+        config.pairing = true;
+        config.accept_mode = AcceptMode::OneShot;
+
+        let server = SocketServer::with_events(None, config, self.event_tx.clone());
 
         let port = server.start().await?;
         self.servers.insert(port, server);
@@ -75,6 +116,25 @@ impl SocketManager {
         Ok(port)
     }
 
+    /// Dials a device that is listening for pairing. Returns once the two are connected; the
+    /// code comparison runs in the background.
+    pub async fn pair_with(
+        self: &Arc<Self>,
+        device_id: String,
+        address: String,
+    ) -> SocketResult<()> {
+        let (connection, incoming_rx) = pairing::dial(device_id, &address).await?;
+
+        let event_tx = self.event_tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = pairing::run_pairing(connection, incoming_rx, Some(event_tx)).await {
+                log::error!("Pairing with {} failed: {:#}", address, e);
+            }
+        });
+
+        Ok(())
+    }
+
     async fn register_connection(self: &Arc<Self>, pair_key: PairKey, connection: Arc<Connection>) {
         self.active_sessions.insert(pair_key, connection.clone());
 
@@ -82,7 +142,7 @@ impl SocketManager {
         let key = pair_key;
 
         connection
-            .set_on_close(move |_id| {
+            .set_on_close("session", move |_id| {
                 log::info!("Connection closed, removing session: {}", key);
                 sessions.remove(&key);
             })
@@ -125,6 +185,60 @@ impl SocketManager {
         stopped_count
     }
 
+    /// Closes client sessions and server connections to the device with `fingerprint`.
+    pub async fn disconnect_fingerprint(&self, fingerprint: &str) -> usize {
+        let sessions: Vec<Arc<Connection>> = self
+            .active_sessions
+            .iter()
+            .filter(|entry| entry.value().peer_fingerprint() == Some(fingerprint))
+            .map(|entry| entry.value().clone())
+            .collect();
+        let servers: Vec<Arc<SocketServer>> = self
+            .servers
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+
+        let mut closed = sessions.len();
+        for conn in sessions {
+            conn.close().await;
+        }
+        for server in servers {
+            closed += server.disconnect_fingerprint(fingerprint).await;
+        }
+
+        closed
+    }
+
+    /// Sends the pending key rotation over every open session, so connected peers move
+    /// their pin without waiting to reconnect. Returns how many peers were told.
+    pub async fn announce_key_rotation(&self) -> usize {
+        let mut connections: Vec<Arc<Connection>> = self
+            .active_sessions
+            .iter()
+            .filter(|entry| !entry.value().is_closing())
+            .map(|entry| entry.value().clone())
+            .collect();
+        let servers: Vec<Arc<SocketServer>> = self
+            .servers
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        for server in servers {
+            connections.extend(server.open_connections());
+        }
+
+        let mut announced = 0;
+        for conn in connections {
+            match announce_key_rotation(&conn).await {
+                Ok(()) => announced += 1,
+                Err(e) => log::warn!("Failed to announce key rotation to {}: {:#}", conn.id(), e),
+            }
+        }
+
+        announced
+    }
+
     pub async fn disconnect(&self, pair_key: &PairKey) -> SocketResult<()> {
         if let Some(conn) = self.active_sessions.get(pair_key) {
             conn.close().await;
@@ -1,11 +1,15 @@
 pub mod binary;
 pub mod client;
+pub mod compression;
 pub mod config;
 pub mod connection;
+pub mod e2e;
 pub mod error;
 pub mod handlers;
+pub mod handshake;
 pub mod ids;
 pub mod manager;
+pub mod pairing;
 pub mod protocol;
 pub mod router;
 pub mod server;
@@ -22,4 +26,4 @@ pub use ids::{LinkKey, PairKey, RouteKind};
 pub use manager::*;
 pub use protocol::PacketType;
 pub use router::PacketRouter;
-pub use server::{ConnectionEvent, ConnectionServerConfig, SocketServer};
+pub use server::{AcceptMode, ConnectionEvent, ConnectionServerConfig, SocketServer};
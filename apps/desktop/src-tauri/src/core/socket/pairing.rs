@@ -0,0 +1,388 @@
+use dashmap::DashMap;
+use nktcp::packets::{
+    PairCommit, PairConfirm, PairReveal, PAIRING_COMMITMENT_SIZE, PAIRING_NONCE_SIZE,
+};
+use nktcp::Packet;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, Duration};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+use uuid::Uuid;
+
+use crate::core::device::DeviceManager;
+use crate::core::socket::stream::SocketStream;
+use crate::core::socket::tls::load_pairing_certificates;
+use crate::state::GlobalState;
+
+use super::connection::Connection;
+use super::error::{Context, SocketError, SocketResult};
+use super::handshake::{local_handshake, perform_handshake};
+use super::protocol::{PacketType, Side};
+use super::server::ConnectionEvent;
+
+const PAIRING_COMMIT_LABEL: &[u8] = b"nekoshare-pairing-commit-v1";
+const PAIRING_CODE_LABEL: &[u8] = b"nekoshare-pairing-v2";
+const PAIRING_CODE_DIGITS: u32 = 6;
+const PAIRING_NONCE_TIMEOUT: Duration = Duration::from_secs(10);
+const PAIRING_DECISION_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// What a device with `fingerprint` sends before revealing `nonce`.
+pub fn pairing_commitment(
+    fingerprint: &str,
+    nonce: &[u8; PAIRING_NONCE_SIZE],
+) -> [u8; PAIRING_COMMITMENT_SIZE] {
+    Sha256::new()
+        .chain_update(PAIRING_COMMIT_LABEL)
+        .chain_update(fingerprint.to_ascii_lowercase().as_bytes())
+        .chain_update(nonce)
+        .finalize()
+        .into()
+}
+
+/// Derives the code both users compare on screen from both certificates and both nonces.
+/// Each side committed to its nonce before seeing the other's, so a device in between
+/// cannot grind certificates or nonces towards matching codes: its two codes agree only
+/// by a one in a million chance.
+pub fn pairing_code(
+    local_fingerprint: &str,
+    local_nonce: &[u8; PAIRING_NONCE_SIZE],
+    peer_fingerprint: &str,
+    peer_nonce: &[u8; PAIRING_NONCE_SIZE],
+) -> String {
+    let local = (local_fingerprint.to_ascii_lowercase(), local_nonce);
+    let peer = (peer_fingerprint.to_ascii_lowercase(), peer_nonce);
+    let (first, second) = if local <= peer {
+        (local, peer)
+    } else {
+        (peer, local)
+    };
+
+    let digest = Sha256::new()
+        .chain_update(PAIRING_CODE_LABEL)
+        .chain_update(first.0.as_bytes())
+        .chain_update(first.1)
+        .chain_update(second.0.as_bytes())
+        .chain_update(second.1)
+        .finalize();
+    let value = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+
+    format!(
+        "{:0width$}",
+        value % 10u32.pow(PAIRING_CODE_DIGITS),
+        width = PAIRING_CODE_DIGITS as usize
+    )
+}
+
+/// Pairings waiting for the local user to confirm that the codes match.
+pub struct PairingService {
+    pending: DashMap<String, oneshot::Sender<bool>>,
+}
+
+impl PairingService {
+    pub fn new() -> Self {
+        Self {
+            pending: DashMap::new(),
+        }
+    }
+
+    /// Records the user's answer. Returns `false` when `pairing_id` is not waiting for one.
+    pub fn decide(&self, pairing_id: &str, accepted: bool) -> bool {
+        match self.pending.remove(pairing_id) {
+            Some((_, decision_tx)) => decision_tx.send(accepted).is_ok(),
+            None => false,
+        }
+    }
+}
+
+impl Default for PairingService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Dials a device in pairing mode, accepting whatever certificate it presents.
+pub async fn dial(
+    device_id: String,
+    address: &str,
+) -> SocketResult<(Arc<Connection>, mpsc::Receiver<(PacketType, i32, Vec<u8>)>)> {
+    let tcp_stream = time::timeout(Duration::from_secs(10), TcpStream::connect(address))
+        .await
+        .map_err(|_| SocketError::Timeout)
+        .with_context(|| format!("connecting to {}", address))?
+        .with_context(|| format!("establishing TCP connection to {}", address))?;
+
+    let tls_config = load_pairing_certificates()
+        .map_err(|e| SocketError::config(format!("Failed to load pairing TLS config: {}", e)))?;
+    let domain = address.split(':').next().unwrap_or("localhost").to_string();
+    let dns_name = ServerName::try_from(domain)
+        .map_err(|_| SocketError::ConfigError("Invalid DNS name".into()))?;
+
+    let tls_stream = TlsConnector::from(Arc::new(tls_config))
+        .connect(dns_name, tcp_stream)
+        .await
+        .with_context(|| "TLS handshake failed")?;
+
+    let (connection, mut incoming_rx) = Connection::new(
+        Uuid::new_v4().to_string(),
+        SocketStream::Tls(tls_stream),
+        Side::Dialer,
+    );
+
+    let local = local_handshake(device_id);
+    // Pairing lets any certificate through; the confirmation code then vouches for it.
+    if let Err(e) = perform_handshake(
+        &connection,
+        &mut incoming_rx,
+        &local,
+        connection.peer_fingerprint(),
+    )
+    .await
+    {
+        connection.close().await;
+        return Err(e.context(format!("handshake with {}", address)));
+    }
+
+    Ok((connection, incoming_rx))
+}
+
+/// Shows the pairing code, exchanges both users' answers and trusts the peer when both
+/// confirmed. Both ends run this right after the protocol handshake; the connection is
+/// closed afterwards and later sessions are pinned to the stored fingerprint.
+pub async fn run_pairing(
+    connection: Arc<Connection>,
+    mut incoming_rx: mpsc::Receiver<(PacketType, i32, Vec<u8>)>,
+    event_tx: Option<mpsc::Sender<ConnectionEvent>>,
+) -> SocketResult<bool> {
+    let result = exchange_decisions(&connection, &mut incoming_rx, event_tx).await;
+    connection.close_after_flush().await;
+    result
+}
+
+/// Commits to a fresh nonce and reveals it only once the peer's commitment has arrived.
+/// Returns our nonce and the peer's, checked against its commitment.
+async fn exchange_nonces(
+    connection: &Connection,
+    incoming_rx: &mut mpsc::Receiver<(PacketType, i32, Vec<u8>)>,
+    local_fingerprint: &str,
+    peer_fingerprint: &str,
+) -> SocketResult<([u8; PAIRING_NONCE_SIZE], [u8; PAIRING_NONCE_SIZE])> {
+    let local_nonce: [u8; PAIRING_NONCE_SIZE] = nkcrypto::generate_key();
+    connection
+        .send_message(&PairCommit {
+            commitment: pairing_commitment(local_fingerprint, &local_nonce),
+        })
+        .await?;
+    let payload = next_pairing_packet(incoming_rx, PacketType::SystemPairCommit).await?;
+    let peer_commit = PairCommit::from_payload(&payload)
+        .map_err(|e| SocketError::parse(format!("malformed PairCommit: {}", e)))?;
+
+    connection
+        .send_message(&PairReveal { nonce: local_nonce })
+        .await?;
+    let payload = next_pairing_packet(incoming_rx, PacketType::SystemPairReveal).await?;
+    let peer_nonce = PairReveal::from_payload(&payload)
+        .map_err(|e| SocketError::parse(format!("malformed PairReveal: {}", e)))?
+        .nonce;
+
+    if pairing_commitment(peer_fingerprint, &peer_nonce) != peer_commit.commitment {
+        return Err(
+            SocketError::handshake("peer revealed a pairing nonce it did not commit to").into(),
+        );
+    }
+
+    Ok((local_nonce, peer_nonce))
+}
+
+/// Waits for the peer's next `packet_type`, skipping anything else it sends meanwhile.
+async fn next_pairing_packet(
+    incoming_rx: &mut mpsc::Receiver<(PacketType, i32, Vec<u8>)>,
+    packet_type: PacketType,
+) -> SocketResult<Vec<u8>> {
+    let receive = async {
+        while let Some((received, _, payload)) = incoming_rx.recv().await {
+            if received == packet_type {
+                return Ok(payload);
+            }
+            log::debug!("Ignoring {:?} while pairing", received);
+        }
+        Err(SocketError::ConnectionClosed.into())
+    };
+
+    time::timeout(PAIRING_NONCE_TIMEOUT, receive)
+        .await
+        .map_err(|_| SocketError::handshake(format!("timed out waiting for {}", packet_type)))?
+}
+
+async fn exchange_decisions(
+    connection: &Connection,
+    incoming_rx: &mut mpsc::Receiver<(PacketType, i32, Vec<u8>)>,
+    event_tx: Option<mpsc::Sender<ConnectionEvent>>,
+) -> SocketResult<bool> {
+    let peer_fingerprint = connection
+        .peer_fingerprint()
+        .ok_or_else(|| SocketError::handshake("peer presented no certificate"))?
+        .to_string();
+    let peer_device_id = connection
+        .peer_handshake()
+        .map(|peer| peer.device_id)
+        .ok_or_else(|| SocketError::handshake("pairing started before the handshake"))?;
+
+    let device_manager = GlobalState::get::<DeviceManager>();
+    let local_fingerprint = device_manager
+        .key()
+        .map_err(|e| SocketError::config(format!("Failed to get device key: {}", e)))?
+        .fingerprint;
+
+    let (local_nonce, peer_nonce) = exchange_nonces(
+        connection,
+        incoming_rx,
+        &local_fingerprint,
+        &peer_fingerprint,
+    )
+    .await?;
+
+    let pairing_id = Uuid::new_v4().to_string();
+    let code = pairing_code(
+        &local_fingerprint,
+        &local_nonce,
+        &peer_fingerprint,
+        &peer_nonce,
+    );
+    let service = GlobalState::get::<PairingService>();
+    let (decision_tx, mut decision_rx) = oneshot::channel();
+    service.pending.insert(pairing_id.clone(), decision_tx);
+
+    log::info!(
+        "Pairing {} with device {} ({}), waiting for confirmation",
+        pairing_id,
+        peer_device_id,
+        peer_fingerprint
+    );
+
+    if let Some(ref tx) = event_tx {
+        let _ = tx
+            .send(ConnectionEvent::PairingRequested {
+                pairing_id: pairing_id.clone(),
+                code,
+                device_id: peer_device_id.clone(),
+                fingerprint: peer_fingerprint.clone(),
+            })
+            .await;
+    }
+
+    let mut local_decision = None;
+    let mut peer_decision = None;
+    let deadline = time::sleep(PAIRING_DECISION_TIMEOUT);
+    tokio::pin!(deadline);
+
+    // Either side declining ends the pairing without waiting for the other.
+    while local_decision != Some(false)
+        && peer_decision != Some(false)
+        && (local_decision.is_none() || peer_decision.is_none())
+    {
+        tokio::select! {
+            _ = &mut deadline => {
+                log::warn!("Pairing {} timed out", pairing_id);
+                break;
+            }
+            decision = &mut decision_rx, if local_decision.is_none() => {
+                let accepted = decision.unwrap_or(false);
+                connection.send_message(&PairConfirm { accepted }).await?;
+                local_decision = Some(accepted);
+            }
+            packet = incoming_rx.recv(), if peer_decision.is_none() => match packet {
+                Some((PacketType::SystemPairConfirm, _, payload)) => {
+                    peer_decision = Some(
+                        PairConfirm::from_payload(&payload).is_ok_and(|confirm| confirm.accepted),
+                    );
+                }
+                Some((packet_type, _, _)) => {
+                    log::debug!("Ignoring {:?} while pairing", packet_type);
+                }
+                None => break,
+            },
+        }
+    }
+
+    service.pending.remove(&pairing_id);
+
+    let paired = local_decision == Some(true) && peer_decision == Some(true);
+    if paired {
+        device_manager
+            .trust_store()
+            .add(&peer_fingerprint, Some(peer_device_id), None)
+            .map_err(|e| SocketError::server(format!("Failed to store trusted device: {}", e)))?;
+    }
+
+    log::info!(
+        "Pairing {} finished: {}",
+        pairing_id,
+        if paired { "paired" } else { "not paired" }
+    );
+
+    if let Some(ref tx) = event_tx {
+        let _ = tx
+            .send(ConnectionEvent::PairingFinished {
+                pairing_id,
+                fingerprint: peer_fingerprint,
+                paired,
+            })
+            .await;
+    }
+
+    Ok(paired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const B: &str = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+    const NONCE_A: [u8; PAIRING_NONCE_SIZE] = [1; PAIRING_NONCE_SIZE];
+    const NONCE_B: [u8; PAIRING_NONCE_SIZE] = [2; PAIRING_NONCE_SIZE];
+
+    #[test]
+    fn code_matches_the_derivation() {
+        // SHA-256 over the label, then each fingerprint and its nonce in fingerprint order;
+        // the first four bytes big-endian, modulo 10^6.
+        assert_eq!(pairing_code(A, &NONCE_A, B, &NONCE_B), "618640");
+    }
+
+    #[test]
+    fn both_sides_derive_the_same_code() {
+        assert_eq!(
+            pairing_code(A, &NONCE_A, B, &NONCE_B),
+            pairing_code(B, &NONCE_B, A, &NONCE_A)
+        );
+        assert_eq!(
+            pairing_code(&A.to_ascii_uppercase(), &NONCE_A, B, &NONCE_B),
+            pairing_code(A, &NONCE_A, B, &NONCE_B)
+        );
+    }
+
+    #[test]
+    fn code_depends_on_both_nonces() {
+        let code = pairing_code(A, &NONCE_A, B, &NONCE_B);
+
+        assert_ne!(pairing_code(A, &[3; PAIRING_NONCE_SIZE], B, &NONCE_B), code);
+        assert_ne!(pairing_code(A, &NONCE_A, B, &[3; PAIRING_NONCE_SIZE]), code);
+        // Swapping which device contributed which nonce is a different pairing too.
+        assert_ne!(pairing_code(A, &NONCE_B, B, &NONCE_A), code);
+    }
+
+    #[test]
+    fn commitment_binds_the_nonce_and_the_device() {
+        let commitment = pairing_commitment(A, &NONCE_A);
+
+        assert_eq!(
+            pairing_commitment(&A.to_ascii_uppercase(), &NONCE_A),
+            commitment
+        );
+        assert_ne!(pairing_commitment(A, &NONCE_B), commitment);
+        assert_ne!(pairing_commitment(B, &NONCE_A), commitment);
+    }
+}
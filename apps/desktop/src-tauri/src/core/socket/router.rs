@@ -1,3 +1,4 @@
+use nktcp::Packet;
 use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
@@ -5,7 +6,7 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 
 use super::connection::Connection;
-use super::error::SocketResult;
+use super::error::{SocketError, SocketResult};
 use super::protocol::PacketType;
 
 pub type PacketHandler = Arc<
@@ -40,6 +41,27 @@ impl PacketRouter {
             .insert(packet_type, boxed_handler);
     }
 
+    /// Registers `handler` for `P::PACKET_TYPE`, decoding the payload before it is called.
+    /// A payload that fails to decode is reported as a parse error like any handler error.
+    pub async fn register_packet<P, F, Fut>(&self, handler: F)
+    where
+        P: Packet + Send + 'static,
+        F: Fn(Arc<Connection>, P, i32) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = SocketResult<()>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        self.register(P::PACKET_TYPE, move |conn, payload, req_id| {
+            let handler = handler.clone();
+            async move {
+                let packet = P::from_payload(&payload).map_err(|e| {
+                    SocketError::parse(format!("malformed {:?}: {}", P::PACKET_TYPE, e))
+                })?;
+                handler(conn, packet, req_id).await
+            }
+        })
+        .await;
+    }
+
     pub async fn set_default_handler<F, Fut>(&self, handler: F)
     where
         F: Fn(Arc<Connection>, Vec<u8>, i32) -> Fut + Send + Sync + 'static,
@@ -87,7 +109,7 @@ impl PacketRouter {
             return true;
         }
 
-        log::warn!("No handler registered for {:?}", packet_type);
+        log::warn!("No handler registered for {}", packet_type);
         false
     }
 
@@ -1,33 +1,50 @@
-use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use dashmap::DashMap;
+use nktcp::packets::ServerFull;
 use rustls::ServerConfig;
 use socket2::{Domain, Protocol, Socket, Type};
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
 use std::sync::Arc;
-use std::sync::Mutex;
 use std::time::Duration;
-use tokio::net::TcpListener;
-use tokio::sync::mpsc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch, Semaphore};
 use tokio::time;
 use tokio_rustls::TlsAcceptor;
 use uuid::Uuid;
 
 use crate::core::device::DeviceManager;
 use crate::core::socket::stream::SocketStream;
-use crate::core::socket::tls::FingerprintVerifier;
+use crate::core::socket::tls::{DeviceCertResolver, FingerprintVerifier};
 use crate::state::GlobalState;
 
 use super::binary::BinaryWriter;
 use super::connection::Connection;
 use super::error::{SocketError, SocketResult};
-use super::protocol::PacketType;
+use super::handlers::sys::announce_key_rotation;
+use super::handshake::{local_handshake, perform_handshake};
+use super::pairing;
+use super::protocol::{PacketType, Side};
 use super::router::PacketRouter;
 
+/// How long a server keeps its listening socket open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptMode {
+    /// Accept a single peer within `connection_timeout_secs`, then stop listening.
+    OneShot,
+    /// Keep accepting peers until [`SocketServer::stop`] is called.
+    Persistent,
+}
+
 #[derive(Debug, Clone)]
 pub struct ConnectionServerConfig {
     pub connection_timeout_secs: u64,
     pub idle_timeout_secs: u64,
     pub bind_address: String,
+    pub accept_mode: AcceptMode,
+    /// Peers beyond this many concurrent connections are answered with `ErrorServerFull`.
+    pub max_peers: usize,
+    /// Accept any certificate and run the pairing exchange instead of serving packets.
+    pub pairing: bool,
 }
 
 impl Default for ConnectionServerConfig {
@@ -36,6 +53,9 @@ impl Default for ConnectionServerConfig {
             connection_timeout_secs: 30,
             idle_timeout_secs: 300,
             bind_address: "0.0.0.0:0".to_string(),
+            accept_mode: AcceptMode::Persistent,
+            max_peers: 8,
+            pairing: false,
         }
     }
 }
@@ -55,15 +75,30 @@ pub enum ConnectionEvent {
         packet_type: PacketType,
         data: Vec<u8>,
     },
+    /// Both users should now compare `code` before confirming the pairing.
+    PairingRequested {
+        pairing_id: String,
+        code: String,
+        device_id: String,
+        fingerprint: String,
+    },
+    PairingFinished {
+        pairing_id: String,
+        fingerprint: String,
+        paired: bool,
+    },
 }
 
 pub struct SocketServer {
     config: ConnectionServerConfig,
     tls_acceptor: Option<TlsAcceptor>,
+    verifier: Option<Arc<FingerprintVerifier>>,
     router: Arc<PacketRouter>,
-    pub connection: Mutex<Option<Arc<Connection>>>,
+    connections: DashMap<String, Arc<Connection>>,
+    peer_slots: Arc<Semaphore>,
     is_running: AtomicBool,
     listening_port: AtomicU16,
+    shutdown_tx: watch::Sender<bool>,
     event_tx: Option<mpsc::Sender<ConnectionEvent>>,
     on_connection: Option<Arc<dyn Fn(Arc<Connection>, String) + Send + Sync>>,
 }
@@ -74,35 +109,47 @@ impl SocketServer {
     }
 
     pub fn with_config(expected_fingerprint: String, config: ConnectionServerConfig) -> Arc<Self> {
-        let tls_acceptor = Self::create_tls_acceptor(expected_fingerprint.clone()).ok();
-
-        Arc::new(Self {
-            config,
-            tls_acceptor,
-            router: Arc::new(PacketRouter::new()),
-            connection: Mutex::new(None),
-            is_running: AtomicBool::new(false),
-            listening_port: AtomicU16::new(0),
-            event_tx: None,
-            on_connection: None,
-        })
+        Arc::new(Self::build(Some(expected_fingerprint), config, None))
     }
 
+    /// Without `expected_fingerprint`, only trusted devices are let in (or, for a pairing
+    /// server, any device).
     pub fn with_events(
-        expected_fingerprint: String,
+        expected_fingerprint: Option<String>,
+        config: ConnectionServerConfig,
         event_tx: mpsc::Sender<ConnectionEvent>,
     ) -> Arc<Self> {
-        let tls_acceptor = Self::create_tls_acceptor(expected_fingerprint.clone()).ok();
-        Arc::new(Self {
-            config: ConnectionServerConfig::default(),
+        Arc::new(Self::build(expected_fingerprint, config, Some(event_tx)))
+    }
+
+    fn build(
+        expected_fingerprint: Option<String>,
+        config: ConnectionServerConfig,
+        event_tx: Option<mpsc::Sender<ConnectionEvent>>,
+    ) -> Self {
+        let (tls_acceptor, verifier) =
+            match Self::create_tls_acceptor(expected_fingerprint, config.pairing) {
+                Ok((acceptor, verifier)) => (Some(acceptor), Some(verifier)),
+                Err(e) => {
+                    log::error!("Failed to set up TLS for server: {:#}", e);
+                    (None, None)
+                }
+            };
+        let (shutdown_tx, _) = watch::channel(false);
+
+        Self {
+            peer_slots: Arc::new(Semaphore::new(config.max_peers.max(1))),
+            config,
             tls_acceptor,
+            verifier,
             router: Arc::new(PacketRouter::new()),
-            connection: Mutex::new(None),
+            connections: DashMap::new(),
             is_running: AtomicBool::new(false),
             listening_port: AtomicU16::new(0),
-            event_tx: Some(event_tx),
+            shutdown_tx,
+            event_tx,
             on_connection: None,
-        })
+        }
     }
 
     pub fn set_connection_handler<F>(&mut self, handler: F)
@@ -116,24 +163,44 @@ impl SocketServer {
         self.listening_port.load(Ordering::SeqCst)
     }
 
-    fn create_tls_acceptor(fingerprint: String) -> SocketResult<TlsAcceptor> {
+    pub fn accept_mode(&self) -> AcceptMode {
+        self.config.accept_mode
+    }
+
+    fn create_tls_acceptor(
+        fingerprint: Option<String>,
+        pairing: bool,
+    ) -> SocketResult<(TlsAcceptor, Arc<FingerprintVerifier>)> {
         let device_manager = GlobalState::get::<DeviceManager>();
-        let key_info = device_manager
-            .key()
+        // Fail at startup rather than on the first handshake.
+        DeviceCertResolver::certified_key()
             .map_err(|e| SocketError::config(format!("Failed to get device key for TLS: {}", e)))?;
 
-        let cert_der = CertificateDer::from(key_info.cert_der);
-        let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_info.key_der));
-
-        let verifier = Arc::new(FingerprintVerifier::new(fingerprint)?);
+        let verifier = Arc::new(match fingerprint {
+            _ if pairing => FingerprintVerifier::for_pairing(),
+            Some(fingerprint) => FingerprintVerifier::new(fingerprint)?
+                .with_trust_store(device_manager.trust_store()),
+            None => FingerprintVerifier::trusted(device_manager.trust_store()),
+        });
 
         let server_config =
             ServerConfig::builder_with_protocol_versions(&[&rustls::version::TLS13])
-                .with_client_cert_verifier(verifier)
-                .with_single_cert(vec![cert_der], key_der)
-                .map_err(|e| SocketError::config(format!("TLS config failed: {}", e)))?;
+                .with_client_cert_verifier(verifier.clone())
+                .with_cert_resolver(Arc::new(DeviceCertResolver));
 
-        Ok(TlsAcceptor::from(Arc::new(server_config)))
+        Ok((TlsAcceptor::from(Arc::new(server_config)), verifier))
+    }
+
+    /// Lets a device with `fingerprint` connect to this server as well.
+    pub fn allow_fingerprint(&self, fingerprint: &str) -> SocketResult<()> {
+        let verifier = self
+            .verifier
+            .as_ref()
+            .ok_or_else(|| SocketError::config("Server has no TLS verifier"))?;
+        verifier
+            .allow(fingerprint)
+            .map_err(|e| SocketError::config(format!("Invalid fingerprint: {}", e)))?;
+        Ok(())
     }
 
     pub fn listening_port(&self) -> u16 {
@@ -145,13 +212,45 @@ impl SocketServer {
     }
 
     pub fn has_active_connection(&self) -> bool {
-        match self.connection.lock() {
-            Ok(lock) => lock
-                .as_ref()
-                .map(|conn| !conn.is_closing())
-                .unwrap_or(false),
-            Err(_) => false,
+        self.connections
+            .iter()
+            .any(|entry| !entry.value().is_closing())
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn connection(&self, conn_id: &str) -> Option<Arc<Connection>> {
+        self.connections
+            .get(conn_id)
+            .map(|entry| entry.value().clone())
+    }
+
+    /// Connections that are still open.
+    pub fn open_connections(&self) -> Vec<Arc<Connection>> {
+        self.connections
+            .iter()
+            .filter(|entry| !entry.value().is_closing())
+            .map(|entry| entry.value().clone())
+            .collect()
+    }
+
+    /// Closes every connection whose peer authenticated with `fingerprint`.
+    pub async fn disconnect_fingerprint(&self, fingerprint: &str) -> usize {
+        let matching: Vec<Arc<Connection>> = self
+            .connections
+            .iter()
+            .filter(|entry| entry.value().peer_fingerprint() == Some(fingerprint))
+            .map(|entry| entry.value().clone())
+            .collect();
+
+        for conn in &matching {
+            log::info!("Closing connection {} from revoked device", conn.id());
+            conn.close().await;
         }
+
+        matching.len()
     }
 
     pub async fn start(self: &Arc<Self>) -> SocketResult<u16> {
@@ -168,12 +267,18 @@ impl SocketServer {
 
         self.listening_port.store(port, Ordering::SeqCst);
         self.is_running.store(true, Ordering::SeqCst);
+        self.shutdown_tx.send_replace(false);
 
-        log::info!("Server listening on {}", local_addr);
+        log::info!(
+            "Server listening on {} ({:?}, max {} peers)",
+            local_addr,
+            self.config.accept_mode,
+            self.config.max_peers
+        );
 
         let server = Arc::clone(self);
         tokio::spawn(async move {
-            server.accept_one_shot(listener).await;
+            server.accept_loop(listener).await;
         });
 
         Ok(port)
@@ -195,77 +300,186 @@ impl SocketServer {
         socket.set_nonblocking(true)?;
         socket.set_reuse_address(true)?;
         socket.bind(&addr.into())?;
-        socket.listen(1)?;
+        socket.listen(self.config.max_peers.clamp(1, 128) as i32)?;
 
         Ok(TcpListener::from_std(socket.into())?)
     }
 
-    async fn accept_one_shot(self: Arc<Self>, listener: TcpListener) {
-        let timeout_duration = Duration::from_secs(self.config.connection_timeout_secs);
+    async fn accept_loop(self: Arc<Self>, listener: TcpListener) {
+        let mut shutdown_rx = self.shutdown_tx.subscribe();
+        let deadline = match self.config.accept_mode {
+            AcceptMode::OneShot => {
+                let timeout = Duration::from_secs(self.config.connection_timeout_secs);
+                log::info!(
+                    "Waiting for incoming connection... (timeout: {:?})",
+                    timeout
+                );
+                Some(time::Instant::now() + timeout)
+            }
+            AcceptMode::Persistent => None,
+        };
 
-        log::info!(
-            "Waiting for incoming connection... (timeout: {:?})",
-            timeout_duration
-        );
+        loop {
+            let accepted = tokio::select! {
+                _ = shutdown_rx.changed() => break,
+                _ = async {
+                    match deadline {
+                        Some(deadline) => time::sleep_until(deadline).await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    log::warn!("Connection timed out (No peer connected)");
+                    break;
+                }
+                accepted = listener.accept() => accepted,
+            };
+
+            let (stream, addr) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log::error!("Accept failed: {}", e);
+                    continue;
+                }
+            };
+
+            log::info!("Accepted TCP connection from {}", addr);
+            let slot = self.peer_slots.clone().try_acquire_owned().ok();
+            let server = Arc::clone(&self);
+            tokio::spawn(async move {
+                let Some(socket_stream) = server.secure_stream(stream, addr).await else {
+                    return;
+                };
+
+                match slot {
+                    Some(slot) => {
+                        if let Err(e) = server.prepare_to_run(socket_stream, addr).await {
+                            log::error!("Connection handling failed: {:#}", e);
+                        }
+                        drop(slot);
+                    }
+                    None => server.reject_full(socket_stream, addr).await,
+                }
+            });
 
-        let accept_result = time::timeout(timeout_duration, listener.accept()).await;
+            if self.config.accept_mode == AcceptMode::OneShot {
+                break;
+            }
+        }
 
         self.listening_port.store(0, Ordering::SeqCst);
         self.is_running.store(false, Ordering::SeqCst);
+        log::info!("Server stopped accepting connections");
+    }
 
-        match accept_result {
-            Ok(Ok((stream, addr))) => {
-                log::info!("Accepted TCP connection from {}", addr);
-                let server = self.clone();
-                tokio::spawn(async move {
-                    // Handle handshake
-                    let socket_stream = if let Some(ref tls) = server.tls_acceptor {
-                        match time::timeout(Duration::from_secs(5), tls.accept(stream)).await {
-                            Ok(Ok(tls_stream)) => {
-                                log::info!("TLS Handshake successful with {}", addr);
-                                SocketStream::ServerTls(tls_stream)
-                            }
-                            Ok(Err(e)) => {
-                                log::error!("TLS Handshake failed: {}", e);
-                                return;
-                            }
-                            Err(_) => {
-                                log::error!("TLS Handshake timed out with {}", addr);
-                                return;
-                            }
-                        }
-                    } else {
-                        SocketStream::Plain(stream)
-                    };
+    async fn secure_stream(&self, stream: TcpStream, addr: SocketAddr) -> Option<SocketStream> {
+        let Some(ref tls) = self.tls_acceptor else {
+            return Some(SocketStream::Plain(stream));
+        };
 
-                    if let Err(e) = server.prepare_to_run(socket_stream).await {
-                        log::error!("Connection handling failed: {}", e);
-                    }
-                });
+        match time::timeout(Duration::from_secs(5), tls.accept(stream)).await {
+            Ok(Ok(tls_stream)) => {
+                log::info!("TLS Handshake successful with {}", addr);
+                Some(SocketStream::ServerTls(tls_stream))
+            }
+            Ok(Err(e)) => {
+                log::error!("TLS Handshake failed: {}", e);
+                None
+            }
+            Err(_) => {
+                log::error!("TLS Handshake timed out with {}", addr);
+                None
             }
-            Ok(Err(e)) => log::error!("Accept failed: {}", e),
-            Err(_) => log::warn!("Connection timed out (No peer connected)"),
         }
     }
 
-    async fn prepare_to_run(self: &Arc<Self>, socket_stream: SocketStream) -> SocketResult<()> {
-        let conn_id = Uuid::new_v4().to_string();
-        let (connection, incoming_rx) = Connection::new(conn_id.clone(), socket_stream);
+    /// Tells a peer that arrived while every slot is taken to try again later.
+    async fn reject_full(&self, socket_stream: SocketStream, addr: SocketAddr) {
+        log::warn!(
+            "Rejecting {}: already serving {} peers",
+            addr,
+            self.config.max_peers
+        );
+
+        let (connection, _incoming_rx) =
+            Connection::new(Uuid::new_v4().to_string(), socket_stream, Side::Acceptor);
+        let full = ServerFull {
+            max_peers: self.config.max_peers as u32,
+        };
+        if let Err(e) = connection.send_message(&full).await {
+            log::warn!("Failed to send ErrorServerFull to {}: {:#}", addr, e);
+        }
+        connection.close_after_flush().await;
+    }
 
+    async fn prepare_to_run(
+        self: &Arc<Self>,
+        socket_stream: SocketStream,
+        addr: SocketAddr,
+    ) -> SocketResult<()> {
+        let conn_id = Uuid::new_v4().to_string();
+        let (connection, mut incoming_rx) =
+            Connection::new(conn_id.clone(), socket_stream, Side::Acceptor);
+
+        let device_id = GlobalState::get::<DeviceManager>()
+            .info()
+            .map(|info| info.device_info.id)
+            .map_err(|e| SocketError::config(format!("Failed to get device info: {}", e)))?;
+        let local = local_handshake(device_id);
+
+        // The TLS verifier already checked this certificate against the pin or trust store.
+        if let Err(e) = perform_handshake(
+            &connection,
+            &mut incoming_rx,
+            &local,
+            connection.peer_fingerprint(),
+        )
+        .await
         {
-            match self.connection.lock() {
-                Ok(mut lock) => *lock = Some(connection.clone()),
-                Err(e) => log::error!("Failed to lock connection mutex: {}", e),
-            }
+            connection.close().await;
+            return Err(e);
+        }
+
+        if self.config.pairing {
+            pairing::run_pairing(connection, incoming_rx, self.event_tx.clone()).await?;
+            return Ok(());
+        }
+
+        if let Err(e) = announce_key_rotation(&connection).await {
+            log::warn!("Failed to announce key rotation to {}: {:#}", addr, e);
         }
 
-        log::info!("Starting packet processing loop for {}", conn_id);
+        self.connections.insert(conn_id.clone(), connection.clone());
+        log::info!(
+            "Peer {} authenticated as {}",
+            addr,
+            connection.peer_fingerprint().unwrap_or("<no certificate>")
+        );
+
+        if let Some(ref tx) = self.event_tx {
+            let _ = tx
+                .send(ConnectionEvent::Connected {
+                    id: conn_id.clone(),
+                    address: addr.to_string(),
+                })
+                .await;
+        }
+        if let Some(ref handler) = self.on_connection {
+            handler(connection.clone(), addr.to_string());
+        }
+
+        log::info!(
+            "Starting packet processing loop for {} ({}/{} peers)",
+            conn_id,
+            self.connections.len(),
+            self.config.max_peers
+        );
 
         let router = self.router.clone();
         let event_tx = self.event_tx.clone();
         let config = self.config.clone();
 
         Self::run_connection_loop(connection, incoming_rx, router, event_tx, config).await;
+        self.connections.remove(&conn_id);
 
         Ok(())
     }
@@ -295,7 +509,7 @@ impl SocketServer {
                         Some((packet_type, request_id, payload)) => {
                             idle_sleep.as_mut().reset(time::Instant::now() + idle_timeout);
 
-                            let should_notify_ui = !matches!(packet_type, PacketType::FileChunk);
+                            let should_notify_ui = !packet_type.is_data();
                             let display_data = if should_notify_ui {
                                 if payload.len() > 1024 {
                                     Vec::new()
@@ -352,53 +566,37 @@ impl SocketServer {
         }
     }
 
-    fn shutdown_internal(&self) {
-        self.is_running.store(false, Ordering::SeqCst);
-        match self.connection.lock() {
-            Ok(mut lock) => *lock = None,
-            Err(e) => {
-                log::error!("Failed to clear connection state: {}", e);
-            }
-        }
-    }
-
+    /// Stops accepting peers and closes every connection once its queued packets are out.
     pub async fn stop(&self) {
         log::info!("Stopping server and closing connections...");
         self.is_running.store(false, Ordering::SeqCst);
+        self.shutdown_tx.send_replace(true);
 
-        let connection_to_close = {
-            match self.connection.lock() {
-                Ok(mut lock) => lock.take(),
-                Err(e) => {
-                    log::error!("Failed to acquire lock for shutdown: {}", e);
-                    None
-                }
-            }
-        };
+        let connections: Vec<Arc<Connection>> = self
+            .connections
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
 
-        if let Some(conn) = connection_to_close {
+        for conn in connections {
             log::debug!("Closing active connection: {}", conn.id());
-            conn.close().await;
+            conn.close_after_flush().await;
         }
 
         self.listening_port.store(0, Ordering::SeqCst);
     }
 
-    pub async fn send(
+    pub async fn send_to(
         &self,
+        conn_id: &str,
         packet_type: PacketType,
         data_writer: impl FnOnce(&mut BinaryWriter),
     ) -> SocketResult<()> {
-        let connection_guard = self
-            .connection
-            .lock()
-            .map_err(|_| SocketError::server("Connection lock poisoned"))?;
-
-        if let Some(conn) = connection_guard.as_ref() {
-            conn.send_packet(packet_type, data_writer).await?;
-            Ok(())
-        } else {
-            Err(SocketError::NotConnected.into())
-        }
+        let conn = self
+            .connection(conn_id)
+            .ok_or_else(|| SocketError::ConnectionNotFound(conn_id.to_string()))?;
+
+        conn.send_packet(packet_type, data_writer).await?;
+        Ok(())
     }
 }
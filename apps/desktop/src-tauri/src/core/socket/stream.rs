@@ -5,6 +5,8 @@ use tokio::net::TcpStream;
 use tokio_rustls::client::TlsStream as ClientTlsStream;
 use tokio_rustls::server::TlsStream as ServerTlsStream;
 
+use crate::core::device::KeyDer;
+
 use super::config::TransferConfig;
 
 pub fn configure_tcp_socket(stream: &TcpStream, config: &TransferConfig) -> std::io::Result<()> {
@@ -31,6 +33,19 @@ impl SocketStream {
         }
     }
 
+    /// SHA-256 fingerprint of the certificate the peer presented, if this is a TLS stream.
+    pub fn peer_fingerprint(&self) -> Option<String> {
+        let certificates = match self {
+            SocketStream::Plain(_) => None,
+            SocketStream::Tls(s) => s.get_ref().1.peer_certificates(),
+            SocketStream::ServerTls(s) => s.get_ref().1.peer_certificates(),
+        }?;
+
+        certificates
+            .first()
+            .map(|cert| KeyDer::compute_fingerprint(cert.as_ref()))
+    }
+
     pub fn configure(&self, config: &TransferConfig) -> std::io::Result<()> {
         if let Some(tcp) = self.get_tcp_ref() {
             configure_tcp_socket(tcp, config)?;
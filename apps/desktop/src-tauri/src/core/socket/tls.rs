@@ -1,14 +1,22 @@
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
 
 use anyhow::Context;
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::ResolvesClientCert;
+use rustls::crypto::{
+    aws_lc_rs, verify_tls12_signature, verify_tls13_signature, CryptoProvider,
+    WebPkiSupportedAlgorithms,
+};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
 use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
 use rustls::{ClientConfig, DigitallySignedStruct, Error, SignatureScheme};
 
 use sha2::{Digest, Sha256};
 
-use crate::core::device::DeviceManager;
+use crate::core::device::{trust, DeviceManager, TrustStore};
 use crate::state::GlobalState;
 
 const SUPPORTED_SCHEMES: &[SignatureScheme] = &[
@@ -25,32 +33,135 @@ const SUPPORTED_SCHEMES: &[SignatureScheme] = &[
 
 #[derive(Debug, Clone)]
 pub struct FingerprintVerifier {
-    pub expected_fingerprint: String,
+    expected_fingerprints: Arc<RwLock<HashSet<String>>>,
+    /// When set, any device on this allowlist is accepted as well. It is consulted on every
+    /// handshake, so revocations and key rotations apply to running servers. A rotated key is
+    /// accepted once the peer's rotation statement has moved its pin here; being issued by a
+    /// trusted certificate is not enough, since any trusted device can issue certificates.
+    trust_store: Option<Arc<TrustStore>>,
+    /// Pairing mode: any certificate is let through so its fingerprint can be confirmed by
+    /// the users afterwards. Handshake signatures are still checked.
+    accept_unpinned: bool,
+    algorithms: WebPkiSupportedAlgorithms,
 }
 
 impl FingerprintVerifier {
     pub fn new(expected_fingerprint: String) -> Result<Self, Error> {
-        if expected_fingerprint.len() != 64
-            || !expected_fingerprint.chars().all(|c| c.is_ascii_hexdigit())
-        {
-            return Err(Error::General("invalid SHA-256 fingerprint format".into()));
-        }
+        let expected_fingerprint = normalize_fingerprint(&expected_fingerprint)?;
 
         Ok(Self {
-            expected_fingerprint: expected_fingerprint.to_ascii_lowercase(),
+            expected_fingerprints: Arc::new(RwLock::new(HashSet::from([expected_fingerprint]))),
+            trust_store: None,
+            accept_unpinned: false,
+            algorithms: signature_algorithms(),
         })
     }
 
+    /// Accepts only the devices on `trust_store`, until more are let in with [`Self::allow`].
+    pub fn trusted(trust_store: Arc<TrustStore>) -> Self {
+        Self {
+            expected_fingerprints: Arc::new(RwLock::new(HashSet::new())),
+            trust_store: Some(trust_store),
+            accept_unpinned: false,
+            algorithms: signature_algorithms(),
+        }
+    }
+
+    /// Accepts any certificate that proves possession of its key, for a first pairing.
+    pub fn for_pairing() -> Self {
+        Self {
+            expected_fingerprints: Arc::new(RwLock::new(HashSet::new())),
+            trust_store: None,
+            accept_unpinned: true,
+            algorithms: signature_algorithms(),
+        }
+    }
+
+    pub fn with_trust_store(mut self, trust_store: Arc<TrustStore>) -> Self {
+        self.trust_store = Some(trust_store);
+        self
+    }
+
+    /// Accepts `fingerprint` in addition to the ones already expected. Handshakes that are
+    /// already in progress or done are unaffected.
+    pub fn allow(&self, fingerprint: &str) -> Result<(), Error> {
+        let fingerprint = normalize_fingerprint(fingerprint)?;
+        self.expected_fingerprints
+            .write()
+            .map_err(|_| Error::General("fingerprint set lock poisoned".into()))?
+            .insert(fingerprint);
+        Ok(())
+    }
+
     #[inline]
     fn verify_fingerprint(&self, cert: &CertificateDer<'_>) -> Result<(), Error> {
-        let digest = Sha256::digest(cert.as_ref());
+        if self.accept_unpinned {
+            return Ok(());
+        }
 
-        if hex::encode(digest) == self.expected_fingerprint {
+        if self.is_pinned(&fingerprint_of(cert))? {
             Ok(())
         } else {
             Err(Error::General("certificate fingerprint mismatch".into()))
         }
     }
+
+    fn is_pinned(&self, digest: &str) -> Result<bool, Error> {
+        let expected = self
+            .expected_fingerprints
+            .read()
+            .map_err(|_| Error::General("fingerprint set lock poisoned".into()))?
+            .contains(digest);
+        let trusted = || {
+            self.trust_store
+                .as_ref()
+                .is_some_and(|store| store.is_trusted(digest))
+        };
+
+        Ok(expected || trusted())
+    }
+
+    /// Runs before the handshake signature itself is checked: the signature must use one of
+    /// our schemes and come from the pinned certificate, so a peer that replays a trusted
+    /// certificate without holding its private key fails.
+    fn check_signature(
+        &self,
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<(), Error> {
+        if !self.supported_schemes().contains(&dss.scheme) {
+            return Err(Error::General(format!(
+                "unsupported signature scheme {:?}",
+                dss.scheme
+            )));
+        }
+
+        self.verify_fingerprint(cert)
+    }
+
+    fn supported_schemes(&self) -> Vec<SignatureScheme> {
+        let available = self.algorithms.supported_schemes();
+        SUPPORTED_SCHEMES
+            .iter()
+            .copied()
+            .filter(|scheme| available.contains(scheme))
+            .collect()
+    }
+}
+
+fn signature_algorithms() -> WebPkiSupportedAlgorithms {
+    CryptoProvider::get_default()
+        .map(|provider| provider.signature_verification_algorithms)
+        .unwrap_or_else(|| aws_lc_rs::default_provider().signature_verification_algorithms)
+}
+
+fn fingerprint_of(cert: &CertificateDer<'_>) -> String {
+    hex::encode(Sha256::digest(cert.as_ref()))
+}
+
+fn normalize_fingerprint(fingerprint: &str) -> Result<String, Error> {
+    trust::normalize_fingerprint(fingerprint)
+        .ok_or_else(|| Error::General("invalid SHA-256 fingerprint format".into()))
 }
 
 impl ServerCertVerifier for FingerprintVerifier {
@@ -68,24 +179,26 @@ impl ServerCertVerifier for FingerprintVerifier {
 
     fn verify_tls12_signature(
         &self,
-        _message: &[u8],
-        _cert: &CertificateDer<'_>,
-        _dss: &DigitallySignedStruct,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        Ok(HandshakeSignatureValid::assertion())
+        self.check_signature(cert, dss)?;
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
     }
 
     fn verify_tls13_signature(
         &self,
-        _message: &[u8],
-        _cert: &CertificateDer<'_>,
-        _dss: &DigitallySignedStruct,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        Ok(HandshakeSignatureValid::assertion())
+        self.check_signature(cert, dss)?;
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
     }
 
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-        SUPPORTED_SCHEMES.to_vec()
+        self.supported_schemes()
     }
 }
 
@@ -106,40 +219,407 @@ impl ClientCertVerifier for FingerprintVerifier {
 
     fn verify_tls12_signature(
         &self,
-        _message: &[u8],
-        _cert: &CertificateDer<'_>,
-        _dss: &DigitallySignedStruct,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        Ok(HandshakeSignatureValid::assertion())
+        self.check_signature(cert, dss)?;
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
     }
 
     fn verify_tls13_signature(
         &self,
-        _message: &[u8],
-        _cert: &CertificateDer<'_>,
-        _dss: &DigitallySignedStruct,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
-        Ok(HandshakeSignatureValid::assertion())
+        self.check_signature(cert, dss)?;
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
     }
 
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-        SUPPORTED_SCHEMES.to_vec()
+        self.supported_schemes()
     }
 }
 
 pub fn load_certificates(fingerprint: String) -> Result<ClientConfig, Box<dyn std::error::Error>> {
-    let device_manager = GlobalState::get::<DeviceManager>();
-    let key_info = device_manager.key().context("Failed to get device key")?;
+    client_config(FingerprintVerifier::new(fingerprint)?)
+}
 
-    let client_certs = vec![CertificateDer::from(key_info.cert_der)];
-    let client_key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_info.key_der));
+/// Client config for pairing with a device whose fingerprint is not known yet.
+pub fn load_pairing_certificates() -> Result<ClientConfig, Box<dyn std::error::Error>> {
+    client_config(FingerprintVerifier::for_pairing())
+}
 
-    let verifier = Arc::new(FingerprintVerifier::new(fingerprint)?);
+fn client_config(
+    verifier: FingerprintVerifier,
+) -> Result<ClientConfig, Box<dyn std::error::Error>> {
+    // Fail early rather than on the first handshake.
+    DeviceCertResolver::certified_key().context("Failed to get device key")?;
 
     let config = ClientConfig::builder()
         .dangerous()
-        .with_custom_certificate_verifier(verifier)
-        .with_client_auth_cert(client_certs, client_key)?;
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_client_cert_resolver(Arc::new(DeviceCertResolver));
 
     Ok(config)
 }
+
+/// Presents the current device key on every handshake, so a rotation takes effect for
+/// new connections without restarting servers or clients.
+#[derive(Debug)]
+pub struct DeviceCertResolver;
+
+impl DeviceCertResolver {
+    pub fn certified_key() -> anyhow::Result<Arc<CertifiedKey>> {
+        let key = GlobalState::get::<DeviceManager>().key()?;
+        let cert = CertificateDer::from(key.cert_der);
+
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.key_der));
+        let signing_key =
+            aws_lc_rs::sign::any_supported_type(&key).context("Unsupported device key type")?;
+        Ok(Arc::new(CertifiedKey::new(vec![cert], signing_key)))
+    }
+
+    fn resolve_device_key() -> Option<Arc<CertifiedKey>> {
+        Self::certified_key()
+            .map_err(|e| log::error!("Failed to load the device key for TLS: {:#}", e))
+            .ok()
+    }
+}
+
+impl ResolvesServerCert for DeviceCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Self::resolve_device_key()
+    }
+}
+
+impl ResolvesClientCert for DeviceCertResolver {
+    fn resolve(
+        &self,
+        _root_hint_subjects: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        Self::resolve_device_key()
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::device::{KeyDer, RotationStatement};
+    use rcgen::{CertificateParams, Issuer, KeyPair, PKCS_ED25519};
+    use rustls::{ClientConnection, Connection, ServerConfig, ServerConnection};
+
+    const SERVER_NAME: &str = "nekoshare.test";
+
+    struct Identity {
+        cert: CertificateDer<'static>,
+        key: PrivateKeyDer<'static>,
+        fingerprint: String,
+    }
+
+    fn identity() -> Identity {
+        let key_pair = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+        let cert = CertificateParams::new(vec![SERVER_NAME.to_string()])
+            .unwrap()
+            .self_signed(&key_pair)
+            .unwrap();
+        let cert = cert.der().clone();
+
+        Identity {
+            fingerprint: KeyDer::compute_fingerprint(&cert),
+            cert,
+            key: PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der())),
+        }
+    }
+
+    /// The successor of `retired` after a key rotation, issued by the retired key.
+    fn rotated(retired: &Identity) -> Identity {
+        let issuer_key = KeyPair::try_from(retired.key.secret_der()).unwrap();
+        let issuer = Issuer::new(
+            CertificateParams::new(Vec::<String>::new()).unwrap(),
+            issuer_key,
+        );
+        let key_pair = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+        let cert = CertificateParams::new(vec![SERVER_NAME.to_string()])
+            .unwrap()
+            .signed_by(&key_pair, &issuer)
+            .unwrap();
+        let cert = cert.der().clone();
+
+        Identity {
+            fingerprint: KeyDer::compute_fingerprint(&cert),
+            cert,
+            key: PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der())),
+        }
+    }
+
+    /// Presents `cert` but signs with `key`, which need not belong to it; this is what a
+    /// peer that copied a trusted certificate would do.
+    #[derive(Debug)]
+    struct Presented(Arc<CertifiedKey>);
+
+    impl Presented {
+        fn new(cert: &Identity, key: &Identity) -> Arc<Self> {
+            let signing_key = aws_lc_rs::sign::any_supported_type(&key.key).unwrap();
+            Arc::new(Self(Arc::new(CertifiedKey::new(
+                vec![cert.cert.clone()],
+                signing_key,
+            ))))
+        }
+
+        /// Presents `cert` followed by `issuer`, as a device does during a key rotation.
+        fn with_issuer(cert: &Identity, issuer: &Identity) -> Arc<Self> {
+            let signing_key = aws_lc_rs::sign::any_supported_type(&cert.key).unwrap();
+            Arc::new(Self(Arc::new(CertifiedKey::new(
+                vec![cert.cert.clone(), issuer.cert.clone()],
+                signing_key,
+            ))))
+        }
+    }
+
+    impl ResolvesServerCert for Presented {
+        fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+            Some(self.0.clone())
+        }
+    }
+
+    impl ResolvesClientCert for Presented {
+        fn resolve(
+            &self,
+            _root_hint_subjects: &[&[u8]],
+            _sigschemes: &[SignatureScheme],
+        ) -> Option<Arc<CertifiedKey>> {
+            Some(self.0.clone())
+        }
+
+        fn has_certs(&self) -> bool {
+            true
+        }
+    }
+
+    fn client_config(pinned: &Identity, presented: Arc<Presented>) -> ClientConfig {
+        ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(
+                FingerprintVerifier::new(pinned.fingerprint.clone()).unwrap(),
+            ))
+            .with_client_cert_resolver(presented)
+    }
+
+    fn server_config(expected: &Identity, presented: Arc<Presented>) -> ServerConfig {
+        ServerConfig::builder_with_protocol_versions(&[&rustls::version::TLS13])
+            .with_client_cert_verifier(Arc::new(
+                FingerprintVerifier::new(expected.fingerprint.clone()).unwrap(),
+            ))
+            .with_cert_resolver(presented)
+    }
+
+    fn transfer(from: &mut Connection, to: &mut Connection) -> Result<(), Error> {
+        let mut buffer = Vec::new();
+        while from.wants_write() {
+            from.write_tls(&mut buffer).unwrap();
+        }
+
+        let mut pending = &buffer[..];
+        while !pending.is_empty() {
+            to.read_tls(&mut pending).unwrap();
+        }
+        to.process_new_packets()?;
+        Ok(())
+    }
+
+    fn handshake(client: ClientConfig, server: ServerConfig) -> Result<(), Error> {
+        let name = ServerName::try_from(SERVER_NAME).unwrap();
+        let mut client = Connection::from(ClientConnection::new(Arc::new(client), name)?);
+        let mut server = Connection::from(ServerConnection::new(Arc::new(server))?);
+
+        for _ in 0..8 {
+            if !client.is_handshaking() && !server.is_handshaking() {
+                return Ok(());
+            }
+            transfer(&mut client, &mut server)?;
+            transfer(&mut server, &mut client)?;
+        }
+
+        Err(Error::General("handshake did not complete".into()))
+    }
+
+    #[test]
+    fn genuine_peers_complete_handshake() {
+        let server = identity();
+        let client = identity();
+
+        handshake(
+            client_config(&server, Presented::new(&client, &client)),
+            server_config(&client, Presented::new(&server, &server)),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn server_replaying_pinned_certificate_is_rejected() {
+        let server = identity();
+        let client = identity();
+        let attacker = identity();
+
+        let result = handshake(
+            client_config(&server, Presented::new(&client, &client)),
+            server_config(&client, Presented::new(&server, &attacker)),
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn client_replaying_trusted_certificate_is_rejected() {
+        let server = identity();
+        let client = identity();
+        let attacker = identity();
+
+        let result = handshake(
+            client_config(&server, Presented::new(&client, &attacker)),
+            server_config(&client, Presented::new(&server, &server)),
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn unpinned_certificate_is_rejected() {
+        let server = identity();
+        let client = identity();
+        let attacker = identity();
+
+        let result = handshake(
+            client_config(&server, Presented::new(&client, &client)),
+            server_config(&client, Presented::new(&attacker, &attacker)),
+        );
+
+        assert!(result.is_err());
+    }
+
+    /// A trust store in a fresh directory, removed again on drop.
+    struct TempTrustStore {
+        dir: std::path::PathBuf,
+        store: Arc<TrustStore>,
+    }
+
+    impl TempTrustStore {
+        fn trusting(identities: &[&Identity]) -> Self {
+            let dir = std::env::temp_dir().join(format!("nekoshare-test-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let store = Arc::new(TrustStore::open(&dir).unwrap());
+            for identity in identities {
+                store.add(&identity.fingerprint, None, None).unwrap();
+            }
+
+            Self { dir, store }
+        }
+
+        fn record_rotation(&self, retired: &Identity, successor: &Identity) {
+            let now_ms = chrono::Utc::now().timestamp_millis();
+            let statement = RotationStatement {
+                old_fingerprint: retired.fingerprint.clone(),
+                new_fingerprint: successor.fingerprint.clone(),
+                old_certificate: hex::encode(&retired.cert),
+                issued_at_ms: now_ms,
+                grace_until_ms: now_ms + 60 * 60 * 1000,
+                signature: String::new(),
+            };
+            assert!(self.store.apply_rotation(&statement).unwrap());
+        }
+    }
+
+    impl Drop for TempTrustStore {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn trusting_client_config(
+        verifier: &Arc<FingerprintVerifier>,
+        presented: Arc<Presented>,
+    ) -> ClientConfig {
+        ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(verifier.clone())
+            .with_client_cert_resolver(presented)
+    }
+
+    #[test]
+    fn rotated_key_is_rejected_until_its_rotation_is_recorded() {
+        let retired = identity();
+        let server = rotated(&retired);
+        let client = identity();
+        let trust = TempTrustStore::trusting(&[&retired]);
+        let verifier = Arc::new(FingerprintVerifier::trusted(trust.store.clone()));
+
+        let result = handshake(
+            trusting_client_config(&verifier, Presented::new(&client, &client)),
+            server_config(&client, Presented::with_issuer(&server, &retired)),
+        );
+        assert!(result.is_err());
+
+        trust.record_rotation(&retired, &server);
+        handshake(
+            trusting_client_config(&verifier, Presented::new(&client, &client)),
+            server_config(&client, Presented::new(&server, &server)),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn certificate_issued_by_trusted_device_is_rejected() {
+        let trusted = identity();
+        let minted = rotated(&trusted);
+        let client = identity();
+        let trust = TempTrustStore::trusting(&[&trusted]);
+        let verifier = Arc::new(FingerprintVerifier::trusted(trust.store.clone()));
+
+        let result = handshake(
+            trusting_client_config(&verifier, Presented::new(&client, &client)),
+            server_config(&client, Presented::with_issuer(&minted, &trusted)),
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn revoked_successor_is_rejected_by_running_verifier() {
+        let retired = identity();
+        let server = rotated(&retired);
+        let client = identity();
+        let trust = TempTrustStore::trusting(&[&retired]);
+        trust.record_rotation(&retired, &server);
+        let verifier = Arc::new(FingerprintVerifier::trusted(trust.store.clone()));
+
+        handshake(
+            trusting_client_config(&verifier, Presented::new(&client, &client)),
+            server_config(&client, Presented::new(&server, &server)),
+        )
+        .unwrap();
+
+        assert!(trust.store.revoke(&server.fingerprint).unwrap());
+        let result = handshake(
+            trusting_client_config(&verifier, Presented::new(&client, &client)),
+            server_config(&client, Presented::with_issuer(&server, &retired)),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn signature_check_rejects_certificate_other_than_pinned() {
+        let pinned = identity();
+        let other = identity();
+        let verifier = FingerprintVerifier::new(pinned.fingerprint.clone()).unwrap();
+
+        assert!(verifier.verify_fingerprint(&pinned.cert).is_ok());
+        assert!(verifier.verify_fingerprint(&other.cert).is_err());
+    }
+}
@@ -1,8 +1,11 @@
 use anyhow::{Context, Result};
-use rusqlite::{params, Connection};
+use dashmap::DashMap;
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fs;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use crate::state::GlobalState;
 
@@ -30,6 +33,65 @@ pub struct TransferProgressEventPayload {
     pub status: String,
     pub error: Option<String>,
     pub timestamp_ms: i64,
+    /// Aggregate progress of the folder this file belongs to, if it was sent as part of one.
+    #[serde(default)]
+    pub folder: Option<TransferFolderProgress>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferFolderProgress {
+    pub name: String,
+    pub total_bytes: u64,
+    pub transferred_bytes: u64,
+}
+
+/// Sums per-file progress into a folder total. Each file only ever moves its own
+/// count forward, so repeated or out-of-order events cannot double count.
+pub struct FolderProgressTracker {
+    name: String,
+    total_bytes: u64,
+    transferred_bytes: AtomicU64,
+    file_bytes: DashMap<String, u64>,
+}
+
+impl FolderProgressTracker {
+    pub fn new(name: String, total_bytes: u64) -> Self {
+        Self {
+            name,
+            total_bytes,
+            transferred_bytes: AtomicU64::new(0),
+            file_bytes: DashMap::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    pub fn record(&self, file_id: &str, file_bytes: u64) -> TransferFolderProgress {
+        let mut entry = self.file_bytes.entry(file_id.to_string()).or_insert(0);
+        if file_bytes > *entry {
+            self.transferred_bytes
+                .fetch_add(file_bytes - *entry, Ordering::SeqCst);
+            *entry = file_bytes;
+        }
+        drop(entry);
+
+        TransferFolderProgress {
+            name: self.name.clone(),
+            total_bytes: self.total_bytes,
+            transferred_bytes: self.transferred_bytes.load(Ordering::SeqCst),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.transferred_bytes.load(Ordering::SeqCst) >= self.total_bytes
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -55,13 +117,45 @@ pub struct TransferHistoryRecord {
     pub updated_at_ms: i64,
 }
 
+/// A partially received file, keyed by the sender's certificate fingerprint and the file id
+/// so a re-offer only resumes a partial file the same peer sent.
+#[derive(Debug, Clone)]
+pub struct TransferResumeState {
+    pub peer_fingerprint: String,
+    pub file_id: String,
+    pub file_path: String,
+    pub file_name: String,
+    pub total_bytes: u64,
+    pub received_bytes: u64,
+    pub updated_at_ms: i64,
+}
+
+/// What the sender needs to offer a transfer again under the same id, so the receiver can
+/// match each file against the partial copy it kept.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OutgoingTransfer {
+    pub transfer_id: String,
+    pub source_device_id: String,
+    pub target_device_id: String,
+    /// The paths as the user picked them; folders are walked again on resume.
+    pub file_paths: Vec<String>,
+    pub source_user_id: Option<String>,
+    pub source_user_name: Option<String>,
+    pub source_device_name: Option<String>,
+    pub created_at_ms: i64,
+}
+
 pub struct TransferHistoryService {
     db_path: PathBuf,
 }
 
 impl TransferHistoryService {
     pub fn new() -> Result<Self> {
-        let db_path = resolve_db_path()?;
+        Self::open(resolve_db_path()?)
+    }
+
+    pub fn open(db_path: PathBuf) -> Result<Self> {
         let db_parent = db_path.parent().context("transfer db parent missing")?;
         fs::create_dir_all(db_parent)
             .with_context(|| format!("failed to create transfer db dir {:?}", db_parent))?;
@@ -140,15 +234,238 @@ impl TransferHistoryService {
             "DELETE FROM transfer_history WHERE file_id = ?1",
             params![file_id],
         )?;
+        conn.execute(
+            "DELETE FROM transfer_resume WHERE file_id = ?1",
+            params![file_id],
+        )?;
         Ok(())
     }
 
     pub fn delete_by_transfer_id(&self, transfer_id: &str) -> Result<()> {
         let conn = self.open_connection()?;
+        conn.execute(
+            r#"
+            DELETE FROM transfer_resume
+            WHERE file_id IN (SELECT file_id FROM transfer_history WHERE transfer_id = ?1)
+            "#,
+            params![transfer_id],
+        )?;
         conn.execute(
             "DELETE FROM transfer_history WHERE transfer_id = ?1",
             params![transfer_id],
         )?;
+        conn.execute(
+            "DELETE FROM transfer_outgoing WHERE transfer_id = ?1",
+            params![transfer_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn load_resume_state(
+        &self,
+        peer_fingerprint: &str,
+        file_id: &str,
+    ) -> Result<Option<TransferResumeState>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT
+                peer_fingerprint,
+                file_id,
+                file_path,
+                file_name,
+                total_bytes,
+                received_bytes,
+                updated_at_ms
+            FROM transfer_resume
+            WHERE peer_fingerprint = ?1 AND file_id = ?2
+            "#,
+        )?;
+
+        let state = stmt
+            .query_row(params![peer_fingerprint, file_id], |row| {
+                Ok(TransferResumeState {
+                    peer_fingerprint: row.get(0)?,
+                    file_id: row.get(1)?,
+                    file_path: row.get(2)?,
+                    file_name: row.get(3)?,
+                    total_bytes: row.get(4)?,
+                    received_bytes: row.get(5)?,
+                    updated_at_ms: row.get(6)?,
+                })
+            })
+            .optional()?;
+
+        Ok(state)
+    }
+
+    pub fn save_resume_state(&self, state: &TransferResumeState) -> Result<()> {
+        let conn = self.open_connection()?;
+        conn.execute(
+            r#"
+            INSERT INTO transfer_resume (
+                peer_fingerprint,
+                file_id,
+                file_path,
+                file_name,
+                total_bytes,
+                received_bytes,
+                updated_at_ms
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            ON CONFLICT(peer_fingerprint, file_id) DO UPDATE SET
+                file_path = excluded.file_path,
+                file_name = excluded.file_name,
+                total_bytes = excluded.total_bytes,
+                received_bytes = excluded.received_bytes,
+                updated_at_ms = excluded.updated_at_ms
+            "#,
+            params![
+                state.peer_fingerprint,
+                state.file_id,
+                state.file_path,
+                state.file_name,
+                state.total_bytes,
+                state.received_bytes,
+                state.updated_at_ms
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_resume_state(&self, peer_fingerprint: &str, file_id: &str) -> Result<()> {
+        let conn = self.open_connection()?;
+        conn.execute(
+            "DELETE FROM transfer_resume WHERE peer_fingerprint = ?1 AND file_id = ?2",
+            params![peer_fingerprint, file_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn save_outgoing_transfer(&self, transfer: &OutgoingTransfer) -> Result<()> {
+        let file_paths = serde_json::to_string(&transfer.file_paths)?;
+        let conn = self.open_connection()?;
+        conn.execute(
+            r#"
+            INSERT INTO transfer_outgoing (
+                transfer_id,
+                source_device_id,
+                target_device_id,
+                file_paths,
+                source_user_id,
+                source_user_name,
+                source_device_name,
+                created_at_ms
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            ON CONFLICT(transfer_id) DO UPDATE SET
+                source_device_id = excluded.source_device_id,
+                target_device_id = excluded.target_device_id,
+                file_paths = excluded.file_paths,
+                source_user_id = excluded.source_user_id,
+                source_user_name = excluded.source_user_name,
+                source_device_name = excluded.source_device_name
+            "#,
+            params![
+                transfer.transfer_id,
+                transfer.source_device_id,
+                transfer.target_device_id,
+                file_paths,
+                transfer.source_user_id,
+                transfer.source_user_name,
+                transfer.source_device_name,
+                transfer.created_at_ms
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn load_outgoing_transfer(&self, transfer_id: &str) -> Result<Option<OutgoingTransfer>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT
+                transfer_id,
+                source_device_id,
+                target_device_id,
+                file_paths,
+                source_user_id,
+                source_user_name,
+                source_device_name,
+                created_at_ms
+            FROM transfer_outgoing
+            WHERE transfer_id = ?1
+            "#,
+        )?;
+
+        let row = stmt
+            .query_row(params![transfer_id], |row| {
+                Ok((
+                    OutgoingTransfer {
+                        transfer_id: row.get(0)?,
+                        source_device_id: row.get(1)?,
+                        target_device_id: row.get(2)?,
+                        file_paths: Vec::new(),
+                        source_user_id: row.get(4)?,
+                        source_user_name: row.get(5)?,
+                        source_device_name: row.get(6)?,
+                        created_at_ms: row.get(7)?,
+                    },
+                    row.get::<_, String>(3)?,
+                ))
+            })
+            .optional()?;
+
+        let Some((mut transfer, file_paths)) = row else {
+            return Ok(None);
+        };
+        transfer.file_paths = serde_json::from_str(&file_paths)
+            .with_context(|| format!("malformed file list for transfer {}", transfer_id))?;
+        Ok(Some(transfer))
+    }
+
+    pub fn delete_outgoing_transfer(&self, transfer_id: &str) -> Result<()> {
+        let conn = self.open_connection()?;
+        conn.execute(
+            "DELETE FROM transfer_outgoing WHERE transfer_id = ?1",
+            params![transfer_id],
+        )?;
+        Ok(())
+    }
+
+    /// Paths of the files in `transfer_id` the receiver confirmed it kept.
+    pub fn completed_send_paths(&self, transfer_id: &str) -> Result<HashSet<String>> {
+        let conn = self.open_connection()?;
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT file_path
+            FROM transfer_history
+            WHERE transfer_id = ?1 AND direction = 'send' AND status = 'success'
+            "#,
+        )?;
+
+        let rows = stmt.query_map(params![transfer_id], |row| row.get::<_, String>(0))?;
+
+        let mut paths = HashSet::new();
+        for row in rows {
+            paths.insert(row?);
+        }
+
+        Ok(paths)
+    }
+
+    /// Moves the files of `transfer_id` that failed to send back to processing, since
+    /// later progress events would otherwise not replace the failure.
+    pub fn reopen_failed_sends(&self, transfer_id: &str) -> Result<()> {
+        let conn = self.open_connection()?;
+        conn.execute(
+            r#"
+            UPDATE transfer_history
+            SET
+                status = 'processing',
+                error = NULL
+            WHERE transfer_id = ?1 AND direction = 'send' AND status = 'failed'
+            "#,
+            params![transfer_id],
+        )?;
         Ok(())
     }
 
@@ -304,6 +621,28 @@ impl TransferHistoryService {
             CREATE INDEX IF NOT EXISTS idx_transfer_history_transfer_id
             ON transfer_history(transfer_id);
 
+            CREATE TABLE IF NOT EXISTS transfer_resume (
+                peer_fingerprint TEXT NOT NULL,
+                file_id TEXT NOT NULL,
+                file_path TEXT NOT NULL,
+                file_name TEXT NOT NULL,
+                total_bytes INTEGER NOT NULL DEFAULT 0,
+                received_bytes INTEGER NOT NULL DEFAULT 0,
+                updated_at_ms INTEGER NOT NULL,
+                PRIMARY KEY (peer_fingerprint, file_id)
+            );
+
+            CREATE TABLE IF NOT EXISTS transfer_outgoing (
+                transfer_id TEXT PRIMARY KEY,
+                source_device_id TEXT NOT NULL,
+                target_device_id TEXT NOT NULL,
+                file_paths TEXT NOT NULL,
+                source_user_id TEXT NULL,
+                source_user_name TEXT NULL,
+                source_device_name TEXT NULL,
+                created_at_ms INTEGER NOT NULL
+            );
+
             UPDATE transfer_history
             SET
                 progress_percent = 100.0,
@@ -368,6 +707,134 @@ pub fn persist_transfer_progress_event(event: TransferProgressEventPayload) {
     });
 }
 
+pub async fn load_transfer_resume_state(
+    peer_fingerprint: &str,
+    file_id: &str,
+) -> Option<TransferResumeState> {
+    let service = GlobalState::get::<TransferHistoryService>();
+    let (peer_fingerprint, file_id) = (peer_fingerprint.to_string(), file_id.to_string());
+    let read_result =
+        tokio::task::spawn_blocking(move || service.load_resume_state(&peer_fingerprint, &file_id))
+            .await;
+
+    match read_result {
+        Ok(Ok(state)) => state,
+        Ok(Err(err)) => {
+            log::warn!("failed to load transfer resume state: {}", err);
+            None
+        }
+        Err(join_err) => {
+            log::warn!("transfer resume state load task failed: {}", join_err);
+            None
+        }
+    }
+}
+
+pub async fn persist_transfer_resume_state(state: TransferResumeState) {
+    let service = GlobalState::get::<TransferHistoryService>();
+    let write_result = tokio::task::spawn_blocking(move || service.save_resume_state(&state)).await;
+
+    match write_result {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => {
+            log::warn!("failed to persist transfer resume state: {}", err);
+        }
+        Err(join_err) => {
+            log::warn!(
+                "transfer resume state persistence task failed: {}",
+                join_err
+            );
+        }
+    }
+}
+
+pub fn clear_transfer_resume_state(peer_fingerprint: String, file_id: String) {
+    let service = GlobalState::get::<TransferHistoryService>();
+    tauri::async_runtime::spawn(async move {
+        let delete_result = tokio::task::spawn_blocking(move || {
+            service.delete_resume_state(&peer_fingerprint, &file_id)
+        })
+        .await;
+
+        match delete_result {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                log::warn!("failed to clear transfer resume state: {}", err);
+            }
+            Err(join_err) => {
+                log::warn!("transfer resume state cleanup task failed: {}", join_err);
+            }
+        }
+    });
+}
+
+pub async fn persist_outgoing_transfer(transfer: OutgoingTransfer) {
+    let service = GlobalState::get::<TransferHistoryService>();
+    let write_result =
+        tokio::task::spawn_blocking(move || service.save_outgoing_transfer(&transfer)).await;
+
+    match write_result {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => {
+            log::warn!("failed to persist outgoing transfer: {}", err);
+        }
+        Err(join_err) => {
+            log::warn!("outgoing transfer persistence task failed: {}", join_err);
+        }
+    }
+}
+
+pub async fn load_outgoing_transfer(transfer_id: &str) -> Result<Option<OutgoingTransfer>> {
+    let service = GlobalState::get::<TransferHistoryService>();
+    let transfer_id = transfer_id.to_string();
+    tokio::task::spawn_blocking(move || service.load_outgoing_transfer(&transfer_id))
+        .await
+        .context("outgoing transfer load task failed")?
+}
+
+/// Prepares `transfer_id` to be sent again and returns the paths it already delivered.
+/// When the history cannot be read every file is offered again and the receiver decides.
+pub async fn reopen_outgoing_transfer(transfer_id: &str) -> HashSet<String> {
+    let service = GlobalState::get::<TransferHistoryService>();
+    let transfer_id = transfer_id.to_string();
+    let read_result = tokio::task::spawn_blocking(move || {
+        service.reopen_failed_sends(&transfer_id)?;
+        service.completed_send_paths(&transfer_id)
+    })
+    .await;
+
+    match read_result {
+        Ok(Ok(paths)) => paths,
+        Ok(Err(err)) => {
+            log::warn!("failed to reopen outgoing transfer: {}", err);
+            HashSet::new()
+        }
+        Err(join_err) => {
+            log::warn!("outgoing transfer reopen task failed: {}", join_err);
+            HashSet::new()
+        }
+    }
+}
+
+pub fn clear_outgoing_transfer(transfer_id: String) {
+    let service = GlobalState::get::<TransferHistoryService>();
+    tauri::async_runtime::spawn(async move {
+        let delete_result =
+            tokio::task::spawn_blocking(move || service.delete_outgoing_transfer(&transfer_id))
+                .await;
+
+        match delete_result {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                log::warn!("failed to clear outgoing transfer: {}", err);
+            }
+            Err(join_err) => {
+                log::warn!("outgoing transfer cleanup task failed: {}", join_err);
+            }
+        }
+    });
+}
+
 fn resolve_db_path() -> Result<PathBuf> {
     if let Some(local_app_data) = std::env::var_os("LOCALAPPDATA") {
         let mut path = PathBuf::from(local_app_data);
@@ -398,3 +865,147 @@ fn clamp_progress(value: f64) -> f64 {
     }
     value.clamp(0.0, 100.0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database in a fresh directory under the system temp dir, removed again on drop.
+    struct TempDb {
+        dir: PathBuf,
+        service: TransferHistoryService,
+    }
+
+    impl TempDb {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("nekoshare-test-{}", uuid::Uuid::new_v4()));
+            let service = TransferHistoryService::open(dir.join(DB_FILE_NAME)).unwrap();
+            Self { dir, service }
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn outgoing(transfer_id: &str) -> OutgoingTransfer {
+        OutgoingTransfer {
+            transfer_id: transfer_id.to_string(),
+            source_device_id: "source".to_string(),
+            target_device_id: "target".to_string(),
+            file_paths: vec!["/photos".to_string(), "/notes.txt".to_string()],
+            source_user_id: Some("user".to_string()),
+            source_user_name: None,
+            source_device_name: Some("laptop".to_string()),
+            created_at_ms: 1,
+        }
+    }
+
+    fn sent(transfer_id: &str, file_path: &str, status: &str) -> TransferProgressEventPayload {
+        TransferProgressEventPayload {
+            transfer_id: transfer_id.to_string(),
+            file_id: format!("{}:{}", transfer_id, file_path),
+            file_path: file_path.to_string(),
+            file_name: file_path.to_string(),
+            direction: "send".to_string(),
+            source_user_id: None,
+            source_user_name: None,
+            source_device_id: None,
+            source_device_name: None,
+            same_account: None,
+            target_device_id: "target".to_string(),
+            total_bytes: 10,
+            sent_bytes: 5,
+            progress_percent: 50.0,
+            status: status.to_string(),
+            error: (status == "failed").then(|| "connection lost".to_string()),
+            timestamp_ms: 1,
+            folder: None,
+        }
+    }
+
+    fn partial(peer_fingerprint: &str, received_bytes: u64) -> TransferResumeState {
+        TransferResumeState {
+            peer_fingerprint: peer_fingerprint.to_string(),
+            file_id: "t1:file".to_string(),
+            file_path: format!("/downloads/{}.part", peer_fingerprint),
+            file_name: "file".to_string(),
+            total_bytes: 100,
+            received_bytes,
+            updated_at_ms: 1,
+        }
+    }
+
+    #[test]
+    fn resume_state_is_kept_per_peer() {
+        let db = TempDb::new();
+        db.service.save_resume_state(&partial("alice", 40)).unwrap();
+        db.service
+            .save_resume_state(&partial("mallory", 90))
+            .unwrap();
+
+        let resume = db.service.load_resume_state("alice", "t1:file").unwrap();
+        assert_eq!(resume.map(|state| state.received_bytes), Some(40));
+        assert!(db
+            .service
+            .load_resume_state("bob", "t1:file")
+            .unwrap()
+            .is_none());
+
+        db.service
+            .delete_resume_state("mallory", "t1:file")
+            .unwrap();
+        assert!(db
+            .service
+            .load_resume_state("alice", "t1:file")
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn outgoing_transfer_is_kept_until_its_history_is_deleted() {
+        let db = TempDb::new();
+        let transfer = outgoing("t1");
+
+        db.service.save_outgoing_transfer(&transfer).unwrap();
+        assert_eq!(
+            db.service.load_outgoing_transfer("t1").unwrap(),
+            Some(transfer)
+        );
+        assert_eq!(db.service.load_outgoing_transfer("t2").unwrap(), None);
+
+        db.service.delete_by_transfer_id("t1").unwrap();
+        assert_eq!(db.service.load_outgoing_transfer("t1").unwrap(), None);
+    }
+
+    #[test]
+    fn reopened_transfer_skips_delivered_files_and_retries_failed_ones() {
+        let db = TempDb::new();
+        for event in [
+            sent("t1", "/a", "success"),
+            sent("t1", "/b", "failed"),
+            sent("t1", "/c", "processing"),
+            sent("t2", "/d", "success"),
+        ] {
+            db.service.upsert_progress_event(&event).unwrap();
+        }
+
+        assert_eq!(
+            db.service.completed_send_paths("t1").unwrap(),
+            HashSet::from(["/a".to_string()])
+        );
+
+        db.service.reopen_failed_sends("t1").unwrap();
+        let retried = db
+            .service
+            .list_records(None)
+            .unwrap()
+            .into_iter()
+            .find(|record| record.file_path == "/b")
+            .unwrap();
+        assert_eq!(retried.status, "processing");
+        assert_eq!(retried.error, None);
+    }
+}
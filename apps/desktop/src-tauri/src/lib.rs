@@ -13,6 +13,7 @@ use core::socket::{ConnectionEvent, SocketManager};
 
 use crate::core::device::DeviceManager;
 use crate::core::socket::handlers::file::FileTransferService;
+use crate::core::socket::pairing::PairingService;
 use crate::core::transfer_history::TransferHistoryService;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -33,6 +34,11 @@ pub fn run() {
             commands::device::ns_get_device_info,
             commands::device::ns_get_device_info_with_key,
             commands::device::ns_get_key,
+            commands::device::ns_rotate_device_key,
+            commands::device::ns_get_key_rotation,
+            commands::device::ns_list_trusted_devices,
+            commands::device::ns_trust_device,
+            commands::device::ns_revoke_trusted_device,
             // File System
             commands::file::read_files_in_dir,
             commands::file::read_files_ready_to_use,
@@ -40,19 +46,28 @@ pub fn run() {
             // Search
             commands::search::search_items,
             commands::search::search_items_paginated,
+            // Transfer
+            commands::transfer::transfer_accept,
+            commands::transfer::transfer_reject,
             // Transfer History
             commands::transfer_history::transfer_history_list,
             commands::transfer_history::transfer_history_delete,
             commands::transfer_history::transfer_history_delete_transfer,
+            commands::transfer_history::transfer_history_outgoing,
             // Socket Client
             commands::socket::socket_client_connect_to,
             commands::socket::socket_client_disconnect_from,
             commands::socket::socket_client_is_connected,
             commands::socket::socket_client_send_files,
+            commands::socket::socket_client_resume_transfer,
             // Socket Server
             commands::socket::socket_server_start,
             commands::socket::socket_server_stop,
             commands::socket::socket_server_has_active_connection,
+            // Pairing
+            commands::socket::socket_pairing_listen,
+            commands::socket::socket_pairing_connect,
+            commands::socket::socket_pairing_confirm,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -64,6 +79,7 @@ fn setup_app(app: &mut App) -> Result<(), Box<dyn std::error::Error>> {
     GlobalState::new()
         .register(DeviceManager::new().expect("Failed to initialize DeviceManager"))
         .register(FileTransferService::new())
+        .register(PairingService::new())
         .register(transfer_history_service)
         .init();
 
@@ -85,6 +101,36 @@ fn setup_app(app: &mut App) -> Result<(), Box<dyn std::error::Error>> {
                 ConnectionEvent::Disconnected { id, .. } => {
                     let _ = app_handle.emit("socket-disconnected", serde_json::json!({ "id": id }));
                 }
+                ConnectionEvent::PairingRequested {
+                    pairing_id,
+                    code,
+                    device_id,
+                    fingerprint,
+                } => {
+                    let _ = app_handle.emit(
+                        "pairing-code",
+                        serde_json::json!({
+                            "pairingId": pairing_id,
+                            "code": code,
+                            "deviceId": device_id,
+                            "fingerprint": fingerprint,
+                        }),
+                    );
+                }
+                ConnectionEvent::PairingFinished {
+                    pairing_id,
+                    fingerprint,
+                    paired,
+                } => {
+                    let _ = app_handle.emit(
+                        "pairing-finished",
+                        serde_json::json!({
+                            "pairingId": pairing_id,
+                            "fingerprint": fingerprint,
+                            "paired": paired,
+                        }),
+                    );
+                }
                 _ => {}
             }
         }
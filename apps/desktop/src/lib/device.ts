@@ -18,6 +18,55 @@ export async function getDeviceInfo(): Promise<LocalDeviceInfo> {
   };
 }
 
+export type TrustedDevice = {
+  fingerprint: string;
+  deviceId: string | null;
+  name: string | null;
+  addedAtMs: number;
+};
+
+export function listTrustedDevices(): Promise<TrustedDevice[]> {
+  return invoke<TrustedDevice[]>("ns_list_trusted_devices");
+}
+
+export function trustDevice(
+  fingerprint: string,
+  deviceId?: string,
+  name?: string,
+): Promise<TrustedDevice> {
+  return invoke<TrustedDevice>("ns_trust_device", {
+    fingerprint,
+    deviceId: deviceId ?? null,
+    name: name ?? null,
+  });
+}
+
+export function revokeTrustedDevice(fingerprint: string): Promise<boolean> {
+  return invoke<boolean>("ns_revoke_trusted_device", { fingerprint });
+}
+
+export type KeyRotation = {
+  oldFingerprint: string;
+  newFingerprint: string;
+  issuedAtMs: number;
+  graceUntilMs: number;
+};
+
+/**
+ * Replaces the device key, which is used right away. Paired devices keep
+ * accepting the old key for `graceHours` (a week by default) while they pick
+ * up the new one.
+ */
+export function rotateDeviceKey(graceHours?: number): Promise<KeyRotation> {
+  return invoke<KeyRotation>("ns_rotate_device_key", {
+    graceHours: graceHours ?? null,
+  });
+}
+
+export function getKeyRotation(): Promise<KeyRotation | null> {
+  return invoke<KeyRotation | null>("ns_get_key_rotation");
+}
+
 function toRegistrationPayload(
   deviceInfo: LocalDeviceInfo,
 ): ApiDeviceRegistrationPayload {
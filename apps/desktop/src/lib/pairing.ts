@@ -0,0 +1,50 @@
+import { invoke } from "@tauri-apps/api/core";
+
+export type PairingCodeEvent = {
+  pairingId: string;
+  code: string;
+  deviceId: string;
+  fingerprint: string;
+};
+
+export type PairingFinishedEvent = {
+  pairingId: string;
+  fingerprint: string;
+  paired: boolean;
+};
+
+export type PairingListener = {
+  address: string;
+  port: number;
+  message: string;
+};
+
+/** Waits for one device on the LAN to dial in and pair. */
+export function startPairingListener(): Promise<PairingListener> {
+  return invoke<PairingListener>("socket_pairing_listen");
+}
+
+/** Dials a device that is waiting in {@link startPairingListener}. */
+export function pairWithDevice(
+  deviceId: string,
+  address: string,
+  port: number,
+): Promise<void> {
+  return invoke("socket_pairing_connect", {
+    deviceId,
+    receiverAddress: address,
+    receiverPort: port,
+  });
+}
+
+export function confirmPairing(
+  pairingId: string,
+  accepted: boolean,
+): Promise<void> {
+  return invoke("socket_pairing_confirm", { pairingId, accepted });
+}
+
+/** Shows the code as two groups of three digits, e.g. "123 456". */
+export function formatPairingCode(code: string): string {
+  return code.length === 6 ? `${code.slice(0, 3)} ${code.slice(3)}` : code;
+}
@@ -2,8 +2,12 @@ import { dirname } from "@tauri-apps/api/path";
 import { stat } from "@tauri-apps/plugin-fs";
 import { create } from "zustand";
 
+export type FileCollisionPolicy = "rename" | "overwrite" | "skip";
+
 export interface AppConfig {
   fileLocation: string | null;
+  fileCollisionPolicy?: FileCollisionPolicy;
+  maxPeers?: number;
 }
 
 export type ConfigStatus = "loading" | "ready" | "needs-setup";
@@ -121,7 +125,7 @@ export const useDesktopStore = create<DesktopStore>((set, get) => ({
       throw new Error("Invalid path: directory does not exist");
     }
 
-    const newConfig: AppConfig = { fileLocation: path };
+    const newConfig: AppConfig = { ...get().config, fileLocation: path };
     await storage.set(CONFIG_KEY, newConfig);
 
     const parentPath = await getParentPath(path);
@@ -2,6 +2,12 @@ import { create } from "zustand";
 
 export type TransferStatus = "processing" | "success" | "failed";
 
+export interface TransferFolderProgress {
+  name: string;
+  totalBytes: number;
+  transferredBytes: number;
+}
+
 export interface TransferProgressEvent {
   transferId: string;
   fileId: string;
@@ -20,6 +26,7 @@ export interface TransferProgressEvent {
   status: TransferStatus;
   error?: string | null;
   timestampMs: number;
+  folder?: TransferFolderProgress | null;
 }
 
 export interface TransferRecord {
@@ -27,6 +27,17 @@ interface TransferHistoryRecordDto {
   updatedAtMs: number;
 }
 
+export interface OutgoingTransfer {
+  transferId: string;
+  sourceDeviceId: string;
+  targetDeviceId: string;
+  filePaths: string[];
+  sourceUserId: string | null;
+  sourceUserName: string | null;
+  sourceDeviceName: string | null;
+  createdAtMs: number;
+}
+
 function toTransferRecord(row: TransferHistoryRecordDto): TransferRecord {
   return {
     transferId: row.transferId,
@@ -72,6 +83,18 @@ export async function deleteTransferHistoryByTransferId(
   await invoke("transfer_history_delete_transfer", { transferId });
 }
 
+export async function getOutgoingTransfer(
+  transferId: string,
+): Promise<OutgoingTransfer | null> {
+  return invoke<OutgoingTransfer | null>("transfer_history_outgoing", {
+    transferId,
+  });
+}
+
+export async function resumeTransfer(transferId: string): Promise<void> {
+  await invoke("socket_client_resume_transfer", { transferId });
+}
+
 export function eventToTransferRecord(event: TransferProgressEvent): TransferRecord {
   return {
     transferId: event.transferId,
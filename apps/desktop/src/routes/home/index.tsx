@@ -2,6 +2,7 @@
 
 import { createFileRoute, useNavigate } from "@tanstack/react-router";
 import { invoke } from "@tauri-apps/api/core";
+import { emit } from "@tauri-apps/api/event";
 import { revealItemInDir } from "@tauri-apps/plugin-opener";
 
 import {
@@ -251,6 +252,16 @@ function RouteComponent() {
           }
         }
       }}
+      onItemResume={(id) => {
+        const file = historyData.find(
+          (item) => generateStableId(item.stableKey ?? item.path) === id,
+        );
+        const transferId = file?.transfer?.transferId;
+
+        if (transferId) {
+          void emit("transfer-resume-requested", { transferId });
+        }
+      }}
       onItemRemove={async (id, scope = "history") => {
         const file = historyData.find(
           (item) => generateStableId(item.stableKey ?? item.path) === id,
@@ -21,6 +21,7 @@ import {
   DropOverlayProvider,
   DropOverlayUI,
   type FileEntry,
+  formatFileSize,
   type GlobalOptions,
   transformDevices,
   transformFriends,
@@ -42,11 +43,18 @@ import { SetupApplicationUI } from "@/components/setup";
 import { useNSDesktop } from "@/context/NSDesktopContext";
 import { useTauriFileDrop } from "@/hooks/use-tauri-file-drop";
 import { getCachedSession } from "@/lib/auth";
+import {
+  confirmPairing,
+  formatPairingCode,
+  type PairingCodeEvent,
+  type PairingFinishedEvent,
+} from "@/lib/pairing";
 import {
   type TransferProgressEvent,
   useTransferStore,
 } from "@/lib/store/transfers";
 import { parseDropZoneId } from "@/lib/transfer";
+import { getOutgoingTransfer, resumeTransfer } from "@/lib/transfer-history";
 
 export const Route = createFileRoute("/home")({
   async beforeLoad() {
@@ -73,6 +81,23 @@ export const Route = createFileRoute("/home")({
   component: RouteComponent,
 });
 
+async function buildOfferFiles(files: string[]) {
+  return Promise.all(
+    files.map(async (filePath) => {
+      const fileStat = await stat(filePath);
+      const fileName = filePath.split(/[\\/]/).pop() || filePath;
+      const extension = fileName.includes(".")
+        ? fileName.split(".").pop() || ""
+        : "";
+      return {
+        fileName,
+        extension,
+        size: fileStat.size,
+      };
+    }),
+  );
+}
+
 interface HomeContentProps {
   isReady: boolean;
   titlebarHelperActions: {
@@ -158,6 +183,7 @@ function RouteComponent() {
   );
   const clearOldTransfers = useTransferStore((state) => state.clearOld);
   const pendingTransfers = useRef<Map<string, string[]>>(new Map());
+  const resumingTransfers = useRef<Set<string>>(new Set());
   const pendingTransferEvents = useRef<Map<string, TransferProgressEvent>>(
     new Map(),
   );
@@ -220,29 +246,15 @@ function RouteComponent() {
         sameAccount,
       });
 
-      const hasActiveDirect = await invoke<boolean>(
-        "socket_server_has_active_connection",
-      );
-
-      let address = "0.0.0.0";
-      let port = 0;
-      let reuse = false;
-
-      if (!hasActiveDirect) {
-        // Automatically accept all incoming file offers for now
-        const a = await invoke<{
-          address: string;
-          port: number;
-          message?: string;
-        }>("socket_server_start", {
-          senderFingerprint: senderDeviceFingerprint,
-        });
-
-        address = a.address;
-        port = a.port;
-      } else {
-        reuse = true;
-      }
+      // The server keeps listening across transfers; starting it again only
+      // lets this sender's fingerprint in and returns the port already in use.
+      const { address, port } = await invoke<{
+        address: string;
+        port: number;
+        message?: string;
+      }>("socket_server_start", {
+        senderFingerprint: senderDeviceFingerprint,
+      });
 
       const acceptPayload = {
         transferId: transferId,
@@ -251,21 +263,17 @@ function RouteComponent() {
         receiverFingerprint: currentDevice.fingerprint,
         address,
         port,
-        reuse,
+        reuse: false,
       };
 
       send(PacketType.FILE_ACCEPT, (w) => {
         w.writeString(JSON.stringify(acceptPayload));
       });
 
-      if (!reuse) {
-        console.log("[FILE_OFFER] Listening for incoming connection on:", {
-          address,
-          port,
-        });
-      } else {
-        console.log("[FILE_OFFER] Reusing existing direct connection");
-      }
+      console.log("[FILE_OFFER] Listening for incoming connection on:", {
+        address,
+        port,
+      });
     },
     [PacketType.FILE_ACCEPT]: async (message) => {
       if (message.status === "error") {
@@ -297,6 +305,14 @@ function RouteComponent() {
         route: "direct",
       });
 
+      if (resumingTransfers.current.delete(transferId)) {
+        console.log(`[FILE_ACCEPT] Resuming transfer ${transferId}`);
+
+        await resumeTransfer(transferId);
+        toast.success("Transfer resumed!");
+        return;
+      }
+
       const filesToSend = pendingTransfers.current.get(transferId);
 
       if (filesToSend) {
@@ -415,6 +431,209 @@ function RouteComponent() {
     };
   }, [flushTransferEvents, clearOldTransfers]);
 
+  useEffect(() => {
+    const unlisteners: Array<() => void> = [];
+    let active = true;
+
+    const setup = async () => {
+      const unlistenOffer = await listen<{
+        transferId: string;
+        peerFingerprint: string;
+        fileName: string;
+        fileCount: number;
+        totalBytes: number;
+        senderName: string | null;
+        expiresAtMs: number;
+      }>("transfer-offer", (event) => {
+        const offer = event.payload;
+        const files =
+          offer.fileCount === 1 ? offer.fileName : `${offer.fileCount} files`;
+
+        toast(`Incoming: ${files}, ${formatFileSize(offer.totalBytes)}`, {
+          id: `transfer-offer:${offer.peerFingerprint}:${offer.transferId}`,
+          description: offer.senderName ? `From ${offer.senderName}` : undefined,
+          duration: Math.max(offer.expiresAtMs - Date.now(), 0),
+          action: {
+            label: "Accept",
+            onClick: () => {
+              invoke("transfer_accept", {
+                transferId: offer.transferId,
+                peerFingerprint: offer.peerFingerprint,
+              }).catch((error) =>
+                console.error("Failed to accept transfer:", error),
+              );
+            },
+          },
+          cancel: {
+            label: "Decline",
+            onClick: () => {
+              invoke("transfer_reject", {
+                transferId: offer.transferId,
+                peerFingerprint: offer.peerFingerprint,
+              }).catch((error) =>
+                console.error("Failed to reject transfer:", error),
+              );
+            },
+          },
+        });
+      });
+
+      const unlistenResolved = await listen<{
+        transferId: string;
+        peerFingerprint: string;
+      }>("transfer-offer-resolved", (event) => {
+        const { peerFingerprint, transferId } = event.payload;
+        toast.dismiss(`transfer-offer:${peerFingerprint}:${transferId}`);
+      });
+
+      const unlistenPairing = await listen<PairingCodeEvent>(
+        "pairing-code",
+        (event) => {
+          const pairing = event.payload;
+
+          toast(`Pairing code: ${formatPairingCode(pairing.code)}`, {
+            id: `pairing:${pairing.pairingId}`,
+            description:
+              "Confirm only if the other device shows the same code.",
+            duration: Infinity,
+            action: {
+              label: "Codes match",
+              onClick: () => {
+                confirmPairing(pairing.pairingId, true).catch((error) =>
+                  console.error("Failed to confirm pairing:", error),
+                );
+              },
+            },
+            cancel: {
+              label: "Reject",
+              onClick: () => {
+                confirmPairing(pairing.pairingId, false).catch((error) =>
+                  console.error("Failed to reject pairing:", error),
+                );
+              },
+            },
+          });
+        },
+      );
+
+      const unlistenPairingFinished = await listen<PairingFinishedEvent>(
+        "pairing-finished",
+        (event) => {
+          toast.dismiss(`pairing:${event.payload.pairingId}`);
+          if (event.payload.paired) {
+            toast.success("Device paired");
+          } else {
+            toast.info("Pairing cancelled");
+          }
+        },
+      );
+
+      if (!active) {
+        unlistenOffer();
+        unlistenResolved();
+        unlistenPairing();
+        unlistenPairingFinished();
+        return;
+      }
+
+      unlisteners.push(
+        unlistenOffer,
+        unlistenResolved,
+        unlistenPairing,
+        unlistenPairingFinished,
+      );
+    };
+
+    setup();
+
+    return () => {
+      active = false;
+      for (const unlisten of unlisteners) {
+        unlisten();
+      }
+    };
+  }, [toast]);
+
+  const handleResumeTransfer = useCallback(
+    async (transferId: string) => {
+      const transfer = await getOutgoingTransfer(transferId);
+      if (!transfer) {
+        toast.error("This transfer can no longer be resumed");
+        return;
+      }
+
+      const device = devices.find((d) => d.id === transfer.targetDeviceId);
+      if (!device) {
+        toast.error("Device not found");
+        return;
+      }
+
+      if (device.status !== "online") {
+        toast.error(`${device.name} is offline`);
+        return;
+      }
+
+      let filesPayload: Awaited<ReturnType<typeof buildOfferFiles>>;
+      try {
+        filesPayload = await buildOfferFiles(transfer.filePaths);
+      } catch (error) {
+        console.error("[Transfer] Failed to stat files for resume:", error);
+        toast.error("Some files of this transfer are no longer available");
+        return;
+      }
+
+      // The re-offer keeps the original transfer id so the receiver can match it
+      // against the partial files it kept from the interrupted attempt.
+      resumingTransfers.current.add(transferId);
+
+      const offerPayload = {
+        transferId,
+        fromDeviceId: transfer.sourceDeviceId,
+        toDeviceId: device.id,
+        files: filesPayload,
+      };
+
+      send(PacketType.FILE_OFFER, (w) => {
+        w.writeString(JSON.stringify(offerPayload));
+      });
+
+      toast.info(`Resuming transfer to ${device.name}...`);
+    },
+    [devices, send, toast],
+  );
+
+  useEffect(() => {
+    let unlistenFn: null | (() => void) = null;
+    let active = true;
+
+    const setup = async () => {
+      const unlisten = await listen<{ transferId: string }>(
+        "transfer-resume-requested",
+        (event) => {
+          handleResumeTransfer(event.payload.transferId).catch((error) =>
+            console.error("Failed to resume transfer:", error),
+          );
+        },
+      );
+
+      if (!active) {
+        unlisten();
+        return;
+      }
+
+      unlistenFn = unlisten;
+    };
+
+    setup();
+
+    return () => {
+      active = false;
+      if (unlistenFn) {
+        unlistenFn();
+      }
+    };
+  }, [handleResumeTransfer]);
+
   const handleProcessFiles = useCallback(
     async (paths: string[]): Promise<FileEntry[]> => {
       const promises = paths.map(async (path) => {
@@ -463,20 +682,7 @@ function RouteComponent() {
             toast.error("Session is missing device information");
             return;
           }
-          const filesPayload = await Promise.all(
-            files.map(async (filePath) => {
-              const fileStat = await stat(filePath);
-              const fileName = filePath.split(/[\\/]/).pop() || filePath;
-              const extension = fileName.includes(".")
-                ? fileName.split(".").pop() || ""
-                : "";
-              return {
-                fileName,
-                extension,
-                size: fileStat.size,
-              };
-            }),
-          );
+          const filesPayload = await buildOfferFiles(files);
 
           const seedTimestamp = Date.now();
           files.forEach((filePath, index) => {
@@ -73,6 +73,7 @@ interface VirtualRowProps {
 	isSelected: boolean;
 	onItemClick: (id: number) => void;
 	onItemReveal: (id: number) => void;
+	onItemResume?: (id: number) => void;
 	handleCopyFilename: (id: number) => void;
 	handleItemDelete: (id: number) => void;
 }
@@ -84,9 +85,12 @@ const VirtualRow = memo(
 		isSelected,
 		onItemClick,
 		onItemReveal,
+		onItemResume,
 		handleCopyFilename,
 		handleItemDelete,
 	}: VirtualRowProps) {
+		const canResume = onItemResume && row.original.direction === "send" && row.original.status === "failed";
+
 		return (
 			<ContextMenu>
 				<ContextMenuTrigger asChild>
@@ -137,6 +141,12 @@ const VirtualRow = memo(
 						<LuFolderInput className="mr-2 h-4 w-4" />
 						เปิดตำแหน่งไฟล์
 					</ContextMenuItem>
+					{canResume && (
+						<ContextMenuItem onSelect={() => onItemResume?.(row.original.id)}>
+							<LuRefreshCcw className="mr-2 h-4 w-4" />
+							ส่งต่อจากจุดที่ค้าง
+						</ContextMenuItem>
+					)}
 					<ContextMenuSeparator />
 					<ContextMenuItem variant="destructive" onSelect={() => handleItemDelete(row.original.id)}>
 						<LuTrash2 className="mr-2 h-4 w-4" />
@@ -158,6 +168,7 @@ const VirtualRow = memo(
 export function HomeUI({
 	onItemClick,
 	onItemReveal,
+	onItemResume,
 	onItemRemove,
 	onRefresh,
 	data,
@@ -208,6 +219,13 @@ export function HomeUI({
 		[onItemReveal],
 	);
 
+	const handleItemResume = useCallback(
+		(id: number) => {
+			onItemResume?.(id);
+		},
+		[onItemResume],
+	);
+
 	const handleItemDelete = useCallback(
 		(id: number) => {
 			const targetItem = items.find((item) => item.id === id);
@@ -450,6 +468,7 @@ export function HomeUI({
 											isSelected={row.getIsSelected()}
 											onItemClick={handleItemClick}
 											onItemReveal={handleItemReveal}
+											onItemResume={onItemResume ? handleItemResume : undefined}
 											handleCopyFilename={handleCopyFilename}
 											handleItemDelete={handleItemDelete}
 										/>
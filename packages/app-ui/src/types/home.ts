@@ -51,6 +51,8 @@ export interface HomeProps {
 	onItemClick: (id: number) => void;
 	onItemReveal: (id: number) => void;
 	onItemRemove: (id: number, scope?: DeleteScope) => Promise<void>;
+	/** Re-offers an interrupted outgoing transfer; the receiver continues from its partial files. */
+	onItemResume?: (id: number) => void;
 	onBulkDelete: (ids: number[]) => void;
 	onRefresh?: () => Promise<void> | void;
 	data: FileData[];
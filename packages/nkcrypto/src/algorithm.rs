@@ -0,0 +1,44 @@
+use crate::error::CryptoError;
+
+/// The AEAD a [`crate::Cipher`] seals with. Its id is the first byte of every ciphertext,
+/// so blobs sealed with any of them can be opened with the same key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[repr(u8)]
+pub enum Algorithm {
+    #[default]
+    Aes256Gcm = 1,
+    /// Faster than AES-GCM on CPUs without AES instructions.
+    ChaCha20Poly1305 = 2,
+    /// 192-bit nonces, so random nonces stay safe for any number of messages under one key.
+    XChaCha20Poly1305 = 3,
+}
+
+impl Algorithm {
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_id(id: u8) -> Result<Self, CryptoError> {
+        match id {
+            1 => Ok(Self::Aes256Gcm),
+            2 => Ok(Self::ChaCha20Poly1305),
+            3 => Ok(Self::XChaCha20Poly1305),
+            _ => Err(CryptoError::UnsupportedAlgorithm(id)),
+        }
+    }
+
+    pub fn nonce_len(self) -> usize {
+        match self {
+            Self::Aes256Gcm | Self::ChaCha20Poly1305 => 12,
+            Self::XChaCha20Poly1305 => 24,
+        }
+    }
+}
+
+impl TryFrom<u8> for Algorithm {
+    type Error = CryptoError;
+
+    fn try_from(id: u8) -> Result<Self, Self::Error> {
+        Self::from_id(id)
+    }
+}
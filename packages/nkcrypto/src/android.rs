@@ -1,10 +1,13 @@
 use std::time::Instant;
 
 use jni::objects::{JByteArray, JClass};
+use jni::sys::jint;
 use jni::{EnvUnowned, jni_str};
 use jni_fn::jni_fn;
 
 use crate::cipher::{generate_key, Cipher};
+use crate::exchange::X25519KeyPair;
+use crate::kdf::{derive_password_key, generate_salt, hkdf_sha256};
 
 #[jni_fn("com.thiratt.nekoshare.core.jni.NkCrypto")]
 pub fn generateKey<'caller>(
@@ -102,3 +105,265 @@ pub fn decrypt<'caller>(
 
     outcome.resolve::<jni::errors::ThrowRuntimeExAndDefault>()
 }
+
+#[jni_fn("com.thiratt.nekoshare.core.jni.NkCrypto")]
+pub fn encryptWithAad<'caller>(
+    mut unowned_env: EnvUnowned<'caller>,
+    _class: JClass<'caller>,
+    key: JByteArray<'caller>,
+    plaintext: JByteArray<'caller>,
+    aad: JByteArray<'caller>,
+) -> JByteArray<'caller> {
+    let outcome = unowned_env.with_env(|mut env| -> Result<_, jni::errors::Error> {
+        let key_bytes = env.convert_byte_array(&key)?;
+        let plaintext_bytes = env.convert_byte_array(&plaintext)?;
+        let aad_bytes = env.convert_byte_array(&aad)?;
+
+        let key_arr: [u8; 32] = match key_bytes.try_into() {
+            Ok(arr) => arr,
+            Err(_) => {
+                let class = jni_str!("java/lang/IllegalArgumentException");
+                let msg = jni_str!("key must be exactly 32 bytes");
+                env.throw_new(class, msg)?;
+                return Err(jni::errors::Error::JavaException);
+            }
+        };
+
+        let cipher = Cipher::new(&key_arr);
+        let encrypted = match cipher.encrypt_with_aad(&plaintext_bytes, &aad_bytes) {
+            Ok(data) => data,
+            Err(_e) => {
+                let class = jni_str!("java/lang/RuntimeException");
+                let msg = jni_str!("Cannot encrypt data: Unknown error");
+                env.throw_new(class, msg)?;
+                return Err(jni::errors::Error::JavaException);
+            }
+        };
+
+        let result = env.new_byte_array(encrypted.len())?;
+        result.set_region(&mut env, 0, bytemuck::cast_slice(&encrypted))?;
+        Ok(result)
+    });
+
+    outcome.resolve::<jni::errors::ThrowRuntimeExAndDefault>()
+}
+
+#[jni_fn("com.thiratt.nekoshare.core.jni.NkCrypto")]
+pub fn decryptWithAad<'caller>(
+    mut unowned_env: EnvUnowned<'caller>,
+    _class: JClass<'caller>,
+    key: JByteArray<'caller>,
+    data: JByteArray<'caller>,
+    aad: JByteArray<'caller>,
+) -> JByteArray<'caller> {
+    let outcome = unowned_env.with_env(|mut env| -> Result<_, jni::errors::Error> {
+        let key_bytes = env.convert_byte_array(&key)?;
+        let data_bytes = env.convert_byte_array(&data)?;
+        let aad_bytes = env.convert_byte_array(&aad)?;
+
+        let key_arr: [u8; 32] = match key_bytes.try_into() {
+            Ok(arr) => arr,
+            Err(_) => {
+                let class = jni_str!("java/lang/IllegalArgumentException");
+                let msg = jni_str!("key must be exactly 32 bytes");
+                env.throw_new(class, msg)?;
+                return Err(jni::errors::Error::JavaException);
+            }
+        };
+
+        let cipher = Cipher::new(&key_arr);
+        let decrypted = match cipher.decrypt_with_aad(&data_bytes, &aad_bytes) {
+            Ok(data) => data,
+            Err(_e) => {
+                let class = jni_str!("java/lang/RuntimeException");
+                let msg = jni_str!("Cannot decrypt data: Unknown error");
+                env.throw_new(class, msg)?;
+                return Err(jni::errors::Error::JavaException);
+            }
+        };
+
+        let result = env.new_byte_array(decrypted.len())?;
+        result.set_region(&mut env, 0, bytemuck::cast_slice(&decrypted))?;
+        Ok(result)
+    });
+
+    outcome.resolve::<jni::errors::ThrowRuntimeExAndDefault>()
+}
+
+#[jni_fn("com.thiratt.nekoshare.core.jni.NkCrypto")]
+pub fn hkdfSha256<'caller>(
+    mut unowned_env: EnvUnowned<'caller>,
+    _class: JClass<'caller>,
+    ikm: JByteArray<'caller>,
+    salt: JByteArray<'caller>,
+    info: JByteArray<'caller>,
+    length: jint,
+) -> JByteArray<'caller> {
+    let outcome = unowned_env.with_env(|mut env| -> Result<_, jni::errors::Error> {
+        let ikm_bytes = env.convert_byte_array(&ikm)?;
+        let salt_bytes = env.convert_byte_array(&salt)?;
+        let info_bytes = env.convert_byte_array(&info)?;
+
+        let mut okm = vec![0u8; length.max(0) as usize];
+        if length < 0 || hkdf_sha256(&ikm_bytes, &salt_bytes, &info_bytes, &mut okm).is_err() {
+            let class = jni_str!("java/lang/IllegalArgumentException");
+            let msg = jni_str!("length must be between 0 and 8160 bytes");
+            env.throw_new(class, msg)?;
+            return Err(jni::errors::Error::JavaException);
+        }
+
+        let result = env.new_byte_array(okm.len())?;
+        result.set_region(&mut env, 0, bytemuck::cast_slice(&okm))?;
+        Ok(result)
+    });
+
+    outcome.resolve::<jni::errors::ThrowRuntimeExAndDefault>()
+}
+
+#[jni_fn("com.thiratt.nekoshare.core.jni.NkCrypto")]
+pub fn generateSalt<'caller>(
+    mut unowned_env: EnvUnowned<'caller>,
+    _class: JClass<'caller>,
+) -> JByteArray<'caller> {
+    let outcome = unowned_env.with_env(|mut env| -> Result<_, jni::errors::Error> {
+        let salt = generate_salt();
+
+        let result = env.new_byte_array(salt.len())?;
+        result.set_region(&mut env, 0, bytemuck::cast_slice(&salt))?;
+        Ok(result)
+    });
+
+    outcome.resolve::<jni::errors::ThrowRuntimeExAndDefault>()
+}
+
+#[jni_fn("com.thiratt.nekoshare.core.jni.NkCrypto")]
+pub fn derivePasswordKey<'caller>(
+    mut unowned_env: EnvUnowned<'caller>,
+    _class: JClass<'caller>,
+    password: JByteArray<'caller>,
+    salt: JByteArray<'caller>,
+    iterations: jint,
+) -> JByteArray<'caller> {
+    let outcome = unowned_env.with_env(|mut env| -> Result<_, jni::errors::Error> {
+        let password_bytes = env.convert_byte_array(&password)?;
+        let salt_bytes = env.convert_byte_array(&salt)?;
+
+        if iterations <= 0 {
+            let class = jni_str!("java/lang/IllegalArgumentException");
+            let msg = jni_str!("iterations must be positive");
+            env.throw_new(class, msg)?;
+            return Err(jni::errors::Error::JavaException);
+        }
+
+        let key = match derive_password_key(&password_bytes, &salt_bytes, iterations as u32) {
+            Ok(key) => key,
+            Err(_e) => {
+                let class = jni_str!("java/lang/IllegalArgumentException");
+                let msg = jni_str!("salt must be at least 16 bytes");
+                env.throw_new(class, msg)?;
+                return Err(jni::errors::Error::JavaException);
+            }
+        };
+
+        let result = env.new_byte_array(key.len())?;
+        result.set_region(&mut env, 0, bytemuck::cast_slice(&key))?;
+        Ok(result)
+    });
+
+    outcome.resolve::<jni::errors::ThrowRuntimeExAndDefault>()
+}
+
+#[jni_fn("com.thiratt.nekoshare.core.jni.NkCrypto")]
+pub fn x25519GenerateSecret<'caller>(
+    mut unowned_env: EnvUnowned<'caller>,
+    _class: JClass<'caller>,
+) -> JByteArray<'caller> {
+    let outcome = unowned_env.with_env(|mut env| -> Result<_, jni::errors::Error> {
+        let secret = match X25519KeyPair::generate() {
+            Ok(pair) => pair.secret_bytes(),
+            Err(_e) => {
+                let class = jni_str!("java/lang/RuntimeException");
+                let msg = jni_str!("Cannot generate key pair: Unknown error");
+                env.throw_new(class, msg)?;
+                return Err(jni::errors::Error::JavaException);
+            }
+        };
+
+        let result = env.new_byte_array(secret.len())?;
+        result.set_region(&mut env, 0, bytemuck::cast_slice(&secret))?;
+        Ok(result)
+    });
+
+    outcome.resolve::<jni::errors::ThrowRuntimeExAndDefault>()
+}
+
+#[jni_fn("com.thiratt.nekoshare.core.jni.NkCrypto")]
+pub fn x25519PublicKey<'caller>(
+    mut unowned_env: EnvUnowned<'caller>,
+    _class: JClass<'caller>,
+    secret: JByteArray<'caller>,
+) -> JByteArray<'caller> {
+    let outcome = unowned_env.with_env(|mut env| -> Result<_, jni::errors::Error> {
+        let secret_bytes = env.convert_byte_array(&secret)?;
+
+        let secret_arr: [u8; 32] = match secret_bytes.try_into() {
+            Ok(arr) => arr,
+            Err(_) => {
+                let class = jni_str!("java/lang/IllegalArgumentException");
+                let msg = jni_str!("secret must be exactly 32 bytes");
+                env.throw_new(class, msg)?;
+                return Err(jni::errors::Error::JavaException);
+            }
+        };
+
+        let public = X25519KeyPair::from_secret_bytes(secret_arr).public_key();
+
+        let result = env.new_byte_array(public.len())?;
+        result.set_region(&mut env, 0, bytemuck::cast_slice(&public))?;
+        Ok(result)
+    });
+
+    outcome.resolve::<jni::errors::ThrowRuntimeExAndDefault>()
+}
+
+#[jni_fn("com.thiratt.nekoshare.core.jni.NkCrypto")]
+pub fn x25519Agree<'caller>(
+    mut unowned_env: EnvUnowned<'caller>,
+    _class: JClass<'caller>,
+    secret: JByteArray<'caller>,
+    peer_public: JByteArray<'caller>,
+    info: JByteArray<'caller>,
+) -> JByteArray<'caller> {
+    let outcome = unowned_env.with_env(|mut env| -> Result<_, jni::errors::Error> {
+        let secret_bytes = env.convert_byte_array(&secret)?;
+        let peer_public_bytes = env.convert_byte_array(&peer_public)?;
+        let info_bytes = env.convert_byte_array(&info)?;
+
+        let secret_arr: [u8; 32] = match secret_bytes.try_into() {
+            Ok(arr) => arr,
+            Err(_) => {
+                let class = jni_str!("java/lang/IllegalArgumentException");
+                let msg = jni_str!("secret must be exactly 32 bytes");
+                env.throw_new(class, msg)?;
+                return Err(jni::errors::Error::JavaException);
+            }
+        };
+
+        let pair = X25519KeyPair::from_secret_bytes(secret_arr);
+        let key = match pair.agree(&peer_public_bytes, &info_bytes) {
+            Ok(key) => key,
+            Err(_e) => {
+                let class = jni_str!("java/lang/IllegalArgumentException");
+                let msg = jni_str!("invalid peer public key");
+                env.throw_new(class, msg)?;
+                return Err(jni::errors::Error::JavaException);
+            }
+        };
+
+        let result = env.new_byte_array(key.len())?;
+        result.set_region(&mut env, 0, bytemuck::cast_slice(&key))?;
+        Ok(result)
+    });
+
+    outcome.resolve::<jni::errors::ThrowRuntimeExAndDefault>()
+}
@@ -0,0 +1,131 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::cipher::KEY_SIZE;
+use crate::error::CryptoError;
+use crate::stream::{StreamDecryptor, StreamEncryptor};
+
+/// Async counterpart of [`crate::EncryptWriter`]. Shutting the writer down writes the
+/// final chunk, so the stream is only complete once `shutdown` has returned.
+pub struct AsyncEncryptWriter<W> {
+    inner: W,
+    encryptor: StreamEncryptor,
+    out: Vec<u8>,
+    out_pos: usize,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncEncryptWriter<W> {
+    pub fn new(inner: W, key: &[u8; KEY_SIZE]) -> Result<Self, CryptoError> {
+        Ok(Self {
+            inner,
+            encryptor: StreamEncryptor::new(key)?,
+            out: Vec::new(),
+            out_pos: 0,
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Writes out sealed chunks still waiting for the underlying writer.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.out_pos < self.out.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.out[self.out_pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.out_pos += n;
+        }
+
+        self.out.clear();
+        self.out_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncEncryptWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+
+        let n = this.encryptor.buffer(data);
+        if this.encryptor.chunk_ready() {
+            this.encryptor.seal_chunk(&mut this.out)?;
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        if !this.encryptor.is_finished() {
+            this.encryptor.finish(&mut this.out)?;
+            ready!(this.poll_drain(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Async counterpart of [`crate::DecryptReader`], with the same error reporting.
+pub struct AsyncDecryptReader<R> {
+    inner: R,
+    decryptor: StreamDecryptor,
+}
+
+impl<R: AsyncRead + Unpin> AsyncDecryptReader<R> {
+    pub fn new(inner: R, key: &[u8; KEY_SIZE]) -> Self {
+        Self {
+            inner,
+            decryptor: StreamDecryptor::new(key),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncDecryptReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let plaintext = this.decryptor.plaintext();
+            if !plaintext.is_empty() {
+                let n = plaintext.len().min(buf.remaining());
+                buf.put_slice(&plaintext[..n]);
+                this.decryptor.consume(n);
+                return Poll::Ready(Ok(()));
+            }
+            if this.decryptor.is_finished() || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut input = ReadBuf::new(this.decryptor.input_buf());
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut input))?;
+            let n = input.filled().len();
+            this.decryptor.advance_input(n)?;
+        }
+    }
+}
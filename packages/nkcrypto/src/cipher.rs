@@ -1,71 +1,185 @@
-use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use chacha20poly1305::aead::AeadInPlace;
+use chacha20poly1305::{KeyInit, XChaCha20Poly1305, XNonce};
+use ring::aead::{
+    Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, CHACHA20_POLY1305, NONCE_LEN,
+};
+use ring::error::Unspecified;
 use ring::rand::{SecureRandom, SystemRandom};
 
+use crate::algorithm::Algorithm;
 use crate::error::CryptoError;
 
 pub const KEY_SIZE: usize = 32;
 pub const NONCE_SIZE: usize = NONCE_LEN;
-const TAG_SIZE: usize = 16;
-const MIN_CIPHERTEXT_LEN: usize = NONCE_SIZE + TAG_SIZE;
+pub(crate) const TAG_SIZE: usize = 16;
+/// Ciphertexts written before the algorithm byte: AES-256-GCM `nonce | ciphertext | tag`.
+const LEGACY_MIN_CIPHERTEXT_LEN: usize = NONCE_SIZE + TAG_SIZE;
+const MAX_NONCE_SIZE: usize = 24;
+
+enum AeadKey {
+    Ring(Box<LessSafeKey>),
+    XChaCha(XChaCha20Poly1305),
+}
+
+impl AeadKey {
+    fn new(algorithm: Algorithm, key: &[u8; KEY_SIZE]) -> Self {
+        let ring_key = |alg| {
+            let unbound = UnboundKey::new(alg, key).expect("invalid key size");
+            Self::Ring(Box::new(LessSafeKey::new(unbound)))
+        };
+
+        match algorithm {
+            Algorithm::Aes256Gcm => ring_key(&AES_256_GCM),
+            Algorithm::ChaCha20Poly1305 => ring_key(&CHACHA20_POLY1305),
+            Algorithm::XChaCha20Poly1305 => Self::XChaCha(XChaCha20Poly1305::new(key.into())),
+        }
+    }
+
+    fn seal(&self, nonce: &[u8], aad: &[u8], buf: &mut Vec<u8>) -> Result<(), Unspecified> {
+        match self {
+            Self::Ring(key) => key.seal_in_place_append_tag(
+                Nonce::try_assume_unique_for_key(nonce)?,
+                Aad::from(aad),
+                buf,
+            ),
+            Self::XChaCha(key) => key
+                .encrypt_in_place(XNonce::from_slice(nonce), aad, buf)
+                .map_err(|_| Unspecified),
+        }
+    }
+
+    fn open(&self, nonce: &[u8], aad: &[u8], buf: &mut Vec<u8>) -> Result<(), Unspecified> {
+        match self {
+            Self::Ring(key) => {
+                let len = key
+                    .open_in_place(
+                        Nonce::try_assume_unique_for_key(nonce)?,
+                        Aad::from(aad),
+                        buf,
+                    )?
+                    .len();
+                buf.truncate(len);
+                Ok(())
+            }
+            Self::XChaCha(key) => key
+                .decrypt_in_place(XNonce::from_slice(nonce), aad, buf)
+                .map_err(|_| Unspecified),
+        }
+    }
+}
 
+/// Seals with one [`Algorithm`] and opens ciphertexts from any of them, as well as those
+/// written before the algorithm byte was added.
+///
+/// Format: `algorithm id (1 byte) | nonce | ciphertext | tag`, the nonce being as long as
+/// the algorithm requires.
 pub struct Cipher {
-    inner: LessSafeKey,
+    algorithm: Algorithm,
+    key: [u8; KEY_SIZE],
+    inner: AeadKey,
     rng: SystemRandom,
 }
 
 impl Cipher {
     pub fn new(key: &[u8; KEY_SIZE]) -> Self {
-        let unbound = UnboundKey::new(&AES_256_GCM, key).expect("invalid key size");
+        Self::with_algorithm(key, Algorithm::default())
+    }
+
+    pub fn with_algorithm(key: &[u8; KEY_SIZE], algorithm: Algorithm) -> Self {
         Self {
-            inner: LessSafeKey::new(unbound),
+            algorithm,
+            key: *key,
+            inner: AeadKey::new(algorithm, key),
             rng: SystemRandom::new(),
         }
     }
 
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
     pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, CryptoError> {
-        let nonce_bytes = self.generate_nonce()?;
-        let nonce = Nonce::assume_unique_for_key(nonce_bytes);
+        self.encrypt_with_aad(plaintext, &[])
+    }
+
+    /// Like [`Cipher::encrypt`], but also authenticates `aad`. The same bytes must be passed
+    /// to [`Cipher::decrypt_with_aad`], which ties the ciphertext to that context (for
+    /// example a transfer id and file name) without storing them in it.
+    pub fn encrypt_with_aad(
+        &self,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let nonce_len = self.algorithm.nonce_len();
+        let mut nonce_bytes = [0u8; MAX_NONCE_SIZE];
+        let nonce_bytes = &mut nonce_bytes[..nonce_len];
+        self.rng.fill(nonce_bytes).map_err(|_| CryptoError::NonceFailed)?;
 
         let mut buf = plaintext.to_vec();
         self.inner
-            .seal_in_place_append_tag(nonce, Aad::empty(), &mut buf)
+            .seal(nonce_bytes, aad, &mut buf)
             .map_err(CryptoError::EncryptionFailed)?;
 
-        let mut output = Vec::with_capacity(NONCE_SIZE + buf.len());
-        output.extend_from_slice(&nonce_bytes);
+        let mut output = Vec::with_capacity(1 + nonce_len + buf.len());
+        output.push(self.algorithm.id());
+        output.extend_from_slice(nonce_bytes);
         output.extend_from_slice(&buf);
         Ok(output)
     }
 
     pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
-        if data.len() < MIN_CIPHERTEXT_LEN {
+        self.decrypt_with_aad(data, &[])
+    }
+
+    pub fn decrypt_with_aad(
+        &self,
+        data: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        if data.len() < LEGACY_MIN_CIPHERTEXT_LEN {
             return Err(CryptoError::CiphertextTooShort {
                 len: data.len(),
-                min: MIN_CIPHERTEXT_LEN,
+                min: LEGACY_MIN_CIPHERTEXT_LEN,
             });
         }
 
-        let (nonce_bytes, ciphertext) = data.split_at(NONCE_SIZE);
-        let nonce = Nonce::try_assume_unique_for_key(nonce_bytes)
-            .map_err(CryptoError::DecryptionFailed)?;
-
-        let mut buf = ciphertext.to_vec();
-        let plaintext = self.inner
-            .open_in_place(nonce, Aad::empty(), &mut buf)
-            .map_err(CryptoError::DecryptionFailed)?;
-
-        Ok(plaintext.to_vec())
+        // A legacy blob starts with a random nonce, so its first byte may look like an
+        // algorithm id. Fall back to the legacy layout whenever the tagged one fails.
+        let tagged = Algorithm::from_id(data[0]).and_then(|algorithm| {
+            let min = 1 + algorithm.nonce_len() + TAG_SIZE;
+            if data.len() < min {
+                return Err(CryptoError::CiphertextTooShort {
+                    len: data.len(),
+                    min,
+                });
+            }
+
+            let (nonce, ciphertext) = data[1..].split_at(algorithm.nonce_len());
+            self.open_with(algorithm, nonce, ciphertext, aad)
+        });
+
+        match tagged {
+            Ok(plaintext) => Ok(plaintext),
+            Err(err) => {
+                let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+                self.open_with(Algorithm::Aes256Gcm, nonce, ciphertext, aad)
+                    .map_err(|_| err)
+            }
+        }
     }
 
+    /// Seals without the algorithm byte under a nonce the caller guarantees is unique.
+    /// Only defined for the algorithms with 96-bit nonces.
     pub fn encrypt_with_nonce(
         &self,
         nonce_bytes: &[u8; NONCE_SIZE],
         plaintext: &[u8],
     ) -> Result<Vec<u8>, CryptoError> {
-        let nonce = Nonce::assume_unique_for_key(*nonce_bytes);
+        self.check_nonce_size()?;
+
         let mut buf = plaintext.to_vec();
         self.inner
-            .seal_in_place_append_tag(nonce, Aad::empty(), &mut buf)
+            .seal(nonce_bytes, &[], &mut buf)
             .map_err(CryptoError::EncryptionFailed)?;
         Ok(buf)
     }
@@ -75,6 +189,7 @@ impl Cipher {
         nonce_bytes: &[u8; NONCE_SIZE],
         ciphertext: &[u8],
     ) -> Result<Vec<u8>, CryptoError> {
+        self.check_nonce_size()?;
         if ciphertext.len() < TAG_SIZE {
             return Err(CryptoError::CiphertextTooShort {
                 len: ciphertext.len(),
@@ -82,18 +197,40 @@ impl Cipher {
             });
         }
 
-        let nonce = Nonce::assume_unique_for_key(*nonce_bytes);
         let mut buf = ciphertext.to_vec();
-        let plaintext = self.inner
-            .open_in_place(nonce, Aad::empty(), &mut buf)
+        self.inner
+            .open(nonce_bytes, &[], &mut buf)
+            .map_err(CryptoError::DecryptionFailed)?;
+        Ok(buf)
+    }
+
+    fn open_with(
+        &self,
+        algorithm: Algorithm,
+        nonce: &[u8],
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let other;
+        let key = if algorithm == self.algorithm {
+            &self.inner
+        } else {
+            other = AeadKey::new(algorithm, &self.key);
+            &other
+        };
+
+        let mut buf = ciphertext.to_vec();
+        key.open(nonce, aad, &mut buf)
             .map_err(CryptoError::DecryptionFailed)?;
-        Ok(plaintext.to_vec())
+        Ok(buf)
     }
 
-    fn generate_nonce(&self) -> Result<[u8; NONCE_SIZE], CryptoError> {
-        let mut nonce = [0u8; NONCE_SIZE];
-        self.rng.fill(&mut nonce).map_err(|_| CryptoError::NonceFailed)?;
-        Ok(nonce)
+    fn check_nonce_size(&self) -> Result<(), CryptoError> {
+        if self.algorithm.nonce_len() == NONCE_SIZE {
+            Ok(())
+        } else {
+            Err(CryptoError::UnsupportedAlgorithm(self.algorithm.id()))
+        }
     }
 }
 
@@ -102,4 +239,4 @@ pub fn generate_key() -> [u8; KEY_SIZE] {
     let mut key = [0u8; KEY_SIZE];
     rng.fill(&mut key).expect("failed to generate key");
     key
-}
\ No newline at end of file
+}
@@ -1,4 +1,5 @@
 use std::fmt;
+use std::io;
 
 #[derive(Debug)]
 pub enum CryptoError {
@@ -6,6 +7,13 @@ pub enum CryptoError {
     DecryptionFailed(ring::error::Unspecified),
     NonceFailed,
     CiphertextTooShort { len: usize, min: usize },
+    StreamTruncated,
+    StreamTooLong,
+    UnsupportedAlgorithm(u8),
+    SaltTooShort { len: usize, min: usize },
+    InvalidLength { len: usize, expected: usize },
+    KeyDerivationFailed,
+    KeyAgreementFailed,
 }
 
 impl fmt::Display for CryptoError {
@@ -17,8 +25,32 @@ impl fmt::Display for CryptoError {
             CryptoError::CiphertextTooShort { len, min } => {
                 write!(f, "ciphertext too short: {len} bytes, minimum {min} required")
             }
+            CryptoError::StreamTruncated => write!(f, "stream ended before its final chunk"),
+            CryptoError::StreamTooLong => write!(f, "stream exceeds the maximum number of chunks"),
+            CryptoError::UnsupportedAlgorithm(id) => write!(f, "unsupported algorithm id {id}"),
+            CryptoError::SaltTooShort { len, min } => {
+                write!(f, "salt too short: {len} bytes, minimum {min} required")
+            }
+            CryptoError::InvalidLength { len, expected } => {
+                write!(f, "invalid length: {len} bytes, expected {expected}")
+            }
+            CryptoError::KeyDerivationFailed => write!(f, "key derivation failed"),
+            CryptoError::KeyAgreementFailed => write!(f, "key agreement failed"),
         }
     }
 }
 
-impl std::error::Error for CryptoError {}
\ No newline at end of file
+impl std::error::Error for CryptoError {}
+
+impl From<CryptoError> for io::Error {
+    fn from(err: CryptoError) -> Self {
+        let kind = match err {
+            CryptoError::DecryptionFailed(_)
+            | CryptoError::CiphertextTooShort { .. }
+            | CryptoError::StreamTruncated
+            | CryptoError::UnsupportedAlgorithm(_) => io::ErrorKind::InvalidData,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
\ No newline at end of file
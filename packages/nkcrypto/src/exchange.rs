@@ -0,0 +1,73 @@
+use ring::rand::{SecureRandom, SystemRandom};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::cipher::KEY_SIZE;
+use crate::error::CryptoError;
+use crate::kdf::derive_subkey;
+
+pub const X25519_KEY_SIZE: usize = 32;
+
+/// An X25519 key pair for agreeing on a [`crate::Cipher`] key with another device.
+///
+/// Generate a fresh pair per exchange; the secret half can be exported so that callers
+/// across the JNI boundary can hold on to it between sending their public key and
+/// receiving the peer's.
+pub struct X25519KeyPair {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl X25519KeyPair {
+    pub fn generate() -> Result<Self, CryptoError> {
+        let mut secret = [0u8; X25519_KEY_SIZE];
+        SystemRandom::new()
+            .fill(&mut secret)
+            .map_err(|_| CryptoError::KeyAgreementFailed)?;
+        Ok(Self::from_secret_bytes(secret))
+    }
+
+    pub fn from_secret_bytes(secret: [u8; X25519_KEY_SIZE]) -> Self {
+        let secret = StaticSecret::from(secret);
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    pub fn secret_bytes(&self) -> [u8; X25519_KEY_SIZE] {
+        self.secret.to_bytes()
+    }
+
+    pub fn public_key(&self) -> [u8; X25519_KEY_SIZE] {
+        self.public.to_bytes()
+    }
+
+    /// Agrees on a key with the owner of `peer_public`. The shared secret is run through
+    /// HKDF-SHA256 salted with both public keys, so both sides get the same key for the
+    /// same `info` and the raw Diffie-Hellman output is never used directly.
+    pub fn agree(&self, peer_public: &[u8], info: &[u8]) -> Result<[u8; KEY_SIZE], CryptoError> {
+        let peer_public: [u8; X25519_KEY_SIZE] =
+            peer_public
+                .try_into()
+                .map_err(|_| CryptoError::InvalidLength {
+                    len: peer_public.len(),
+                    expected: X25519_KEY_SIZE,
+                })?;
+
+        let shared = self.secret.diffie_hellman(&PublicKey::from(peer_public));
+        // Low-order peer keys force a known shared secret.
+        if !shared.was_contributory() {
+            return Err(CryptoError::KeyAgreementFailed);
+        }
+
+        let own_public = self.public_key();
+        let (first, second) = if own_public <= peer_public {
+            (own_public, peer_public)
+        } else {
+            (peer_public, own_public)
+        };
+        let mut salt = [0u8; 2 * X25519_KEY_SIZE];
+        salt[..X25519_KEY_SIZE].copy_from_slice(&first);
+        salt[X25519_KEY_SIZE..].copy_from_slice(&second);
+
+        derive_subkey(shared.as_bytes(), &salt, info)
+    }
+}
@@ -0,0 +1,131 @@
+use std::num::NonZeroU32;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{hkdf, pbkdf2};
+
+use crate::cipher::KEY_SIZE;
+use crate::error::CryptoError;
+
+pub const SALT_SIZE: usize = 16;
+/// OWASP's 2023 recommendation for PBKDF2-HMAC-SHA256.
+pub const PBKDF2_DEFAULT_ITERATIONS: u32 = 600_000;
+/// Length of [`PasswordKdfParams::to_bytes`].
+pub const PASSWORD_PARAMS_SIZE: usize = SALT_SIZE + 4;
+const HKDF_MAX_OUTPUT_LEN: usize = 255 * 32;
+
+struct OutputLen(usize);
+
+impl hkdf::KeyType for OutputLen {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+/// Fills `out` with HKDF-SHA256 output keyed by `ikm`. Different `info` strings give
+/// independent sub-keys from the same input key.
+pub fn hkdf_sha256(
+    ikm: &[u8],
+    salt: &[u8],
+    info: &[u8],
+    out: &mut [u8],
+) -> Result<(), CryptoError> {
+    if out.len() > HKDF_MAX_OUTPUT_LEN {
+        return Err(CryptoError::KeyDerivationFailed);
+    }
+
+    let info = [info];
+    hkdf::Salt::new(hkdf::HKDF_SHA256, salt)
+        .extract(ikm)
+        .expand(&info, OutputLen(out.len()))
+        .and_then(|okm| okm.fill(out))
+        .map_err(|_| CryptoError::KeyDerivationFailed)
+}
+
+/// Derives a [`crate::Cipher`] key from `ikm` with HKDF-SHA256.
+pub fn derive_subkey(ikm: &[u8], salt: &[u8], info: &[u8]) -> Result<[u8; KEY_SIZE], CryptoError> {
+    let mut key = [0u8; KEY_SIZE];
+    hkdf_sha256(ikm, salt, info, &mut key)?;
+    Ok(key)
+}
+
+pub fn generate_salt() -> [u8; SALT_SIZE] {
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; SALT_SIZE];
+    rng.fill(&mut salt).expect("failed to generate salt");
+    salt
+}
+
+/// Derives a [`crate::Cipher`] key from a password with PBKDF2-HMAC-SHA256.
+pub fn derive_password_key(
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+) -> Result<[u8; KEY_SIZE], CryptoError> {
+    if salt.len() < SALT_SIZE {
+        return Err(CryptoError::SaltTooShort {
+            len: salt.len(),
+            min: SALT_SIZE,
+        });
+    }
+    let iterations = NonZeroU32::new(iterations).ok_or(CryptoError::KeyDerivationFailed)?;
+
+    let mut key = [0u8; KEY_SIZE];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        salt,
+        password,
+        &mut key,
+    );
+    Ok(key)
+}
+
+/// The salt and work factor to store next to data encrypted under a password key, so the
+/// same key can be derived again later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordKdfParams {
+    pub salt: [u8; SALT_SIZE],
+    pub iterations: u32,
+}
+
+impl PasswordKdfParams {
+    /// A fresh random salt with the default iteration count.
+    pub fn generate() -> Self {
+        Self {
+            salt: generate_salt(),
+            iterations: PBKDF2_DEFAULT_ITERATIONS,
+        }
+    }
+
+    pub fn derive_key(&self, password: &[u8]) -> Result<[u8; KEY_SIZE], CryptoError> {
+        derive_password_key(password, &self.salt, self.iterations)
+    }
+
+    /// `salt | iterations (u32, big endian)`.
+    pub fn to_bytes(&self) -> [u8; PASSWORD_PARAMS_SIZE] {
+        let mut bytes = [0u8; PASSWORD_PARAMS_SIZE];
+        bytes[..SALT_SIZE].copy_from_slice(&self.salt);
+        bytes[SALT_SIZE..].copy_from_slice(&self.iterations.to_be_bytes());
+        bytes
+    }
+
+    /// Reads what [`Self::to_bytes`] wrote from the start of `bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CryptoError> {
+        if bytes.len() < PASSWORD_PARAMS_SIZE {
+            return Err(CryptoError::InvalidLength {
+                len: bytes.len(),
+                expected: PASSWORD_PARAMS_SIZE,
+            });
+        }
+
+        let mut salt = [0u8; SALT_SIZE];
+        salt.copy_from_slice(&bytes[..SALT_SIZE]);
+        let mut iterations = [0u8; 4];
+        iterations.copy_from_slice(&bytes[SALT_SIZE..PASSWORD_PARAMS_SIZE]);
+
+        Ok(Self {
+            salt,
+            iterations: u32::from_be_bytes(iterations),
+        })
+    }
+}
@@ -1,5 +1,12 @@
+mod algorithm;
 mod cipher;
 mod error;
+mod exchange;
+mod kdf;
+mod stream;
+
+#[cfg(feature = "tokio")]
+mod async_stream;
 
 #[cfg(target_os = "android")]
 mod android;
@@ -7,5 +14,15 @@ mod android;
 #[cfg(test)]
 mod tests;
 
+pub use algorithm::Algorithm;
 pub use cipher::{generate_key, Cipher, KEY_SIZE, NONCE_SIZE};
-pub use error::CryptoError;
\ No newline at end of file
+pub use error::CryptoError;
+pub use exchange::{X25519KeyPair, X25519_KEY_SIZE};
+pub use kdf::{
+    derive_password_key, derive_subkey, generate_salt, hkdf_sha256, PasswordKdfParams,
+    PASSWORD_PARAMS_SIZE, PBKDF2_DEFAULT_ITERATIONS, SALT_SIZE,
+};
+pub use stream::{DecryptReader, EncryptWriter, STREAM_CHUNK_SIZE, STREAM_HEADER_SIZE};
+
+#[cfg(feature = "tokio")]
+pub use async_stream::{AsyncDecryptReader, AsyncEncryptWriter};
\ No newline at end of file
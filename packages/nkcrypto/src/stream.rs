@@ -0,0 +1,369 @@
+//! Chunked encryption for payloads too large to hold in memory, following the STREAM
+//! construction: the plaintext is cut into fixed-size chunks sealed under a nonce made
+//! of a random per-stream prefix, the chunk counter and a final-chunk flag. Reordered,
+//! dropped or truncated chunks therefore fail to authenticate.
+//!
+//! Wire format: `prefix (7 bytes) | chunk 0 | chunk 1 | ... | final chunk`, where every
+//! chunk is its ciphertext followed by the tag and all but the final chunk carry exactly
+//! [`STREAM_CHUNK_SIZE`] bytes of plaintext.
+
+use std::io::{self, Read, Write};
+
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::cipher::{KEY_SIZE, NONCE_SIZE, TAG_SIZE};
+use crate::error::CryptoError;
+
+/// Plaintext bytes per chunk.
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+const NONCE_PREFIX_SIZE: usize = NONCE_SIZE - 5;
+/// Bytes written ahead of the first chunk.
+pub const STREAM_HEADER_SIZE: usize = NONCE_PREFIX_SIZE;
+const SEALED_CHUNK_SIZE: usize = STREAM_CHUNK_SIZE + TAG_SIZE;
+
+struct ChunkKey {
+    key: LessSafeKey,
+    prefix: [u8; NONCE_PREFIX_SIZE],
+    counter: u32,
+}
+
+impl ChunkKey {
+    fn new(key: &[u8; KEY_SIZE], prefix: [u8; NONCE_PREFIX_SIZE]) -> Self {
+        let unbound = UnboundKey::new(&AES_256_GCM, key).expect("invalid key size");
+        Self {
+            key: LessSafeKey::new(unbound),
+            prefix,
+            counter: 0,
+        }
+    }
+
+    fn nonce(&self, last: bool) -> Result<Nonce, CryptoError> {
+        // The final chunk may still use the last counter value, no other chunk may.
+        if !last && self.counter == u32::MAX {
+            return Err(CryptoError::StreamTooLong);
+        }
+
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce[..NONCE_PREFIX_SIZE].copy_from_slice(&self.prefix);
+        nonce[NONCE_PREFIX_SIZE..NONCE_SIZE - 1].copy_from_slice(&self.counter.to_be_bytes());
+        nonce[NONCE_SIZE - 1] = last as u8;
+        Ok(Nonce::assume_unique_for_key(nonce))
+    }
+
+    fn seal(&mut self, buf: &mut Vec<u8>, last: bool) -> Result<(), CryptoError> {
+        let nonce = self.nonce(last)?;
+        self.key
+            .seal_in_place_append_tag(nonce, Aad::empty(), buf)
+            .map_err(CryptoError::EncryptionFailed)?;
+        self.counter = self.counter.wrapping_add(1);
+        Ok(())
+    }
+
+    fn open(&mut self, buf: &mut Vec<u8>, last: bool) -> Result<(), CryptoError> {
+        if buf.len() < TAG_SIZE {
+            return Err(CryptoError::CiphertextTooShort {
+                len: buf.len(),
+                min: TAG_SIZE,
+            });
+        }
+
+        let nonce = self.nonce(last)?;
+        let len = self
+            .key
+            .open_in_place(nonce, Aad::empty(), buf)
+            .map_err(CryptoError::DecryptionFailed)?
+            .len();
+        buf.truncate(len);
+        self.counter = self.counter.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Opens the chunk the input ended on. If it only authenticates as a non-final chunk,
+    /// the stream was cut at a chunk boundary, which is reported as truncation.
+    fn open_last(&mut self, buf: &mut Vec<u8>) -> Result<(), CryptoError> {
+        let sealed = buf.clone();
+        match self.open(buf, true) {
+            Err(CryptoError::DecryptionFailed(e)) => {
+                let mut sealed = sealed;
+                if self.open(&mut sealed, false).is_ok() {
+                    Err(CryptoError::StreamTruncated)
+                } else {
+                    Err(CryptoError::DecryptionFailed(e))
+                }
+            }
+            result => result,
+        }
+    }
+}
+
+/// Buffers plaintext into chunks and seals them; shared by the blocking and async writers.
+pub(crate) struct StreamEncryptor {
+    chunk_key: ChunkKey,
+    header: Option<[u8; NONCE_PREFIX_SIZE]>,
+    buf: Vec<u8>,
+    finished: bool,
+}
+
+impl StreamEncryptor {
+    pub(crate) fn new(key: &[u8; KEY_SIZE]) -> Result<Self, CryptoError> {
+        let mut prefix = [0u8; NONCE_PREFIX_SIZE];
+        SystemRandom::new()
+            .fill(&mut prefix)
+            .map_err(|_| CryptoError::NonceFailed)?;
+
+        Ok(Self {
+            chunk_key: ChunkKey::new(key, prefix),
+            header: Some(prefix),
+            buf: Vec::with_capacity(SEALED_CHUNK_SIZE + 1),
+            finished: false,
+        })
+    }
+
+    /// Takes as much of `data` as fits in the current chunk. One byte past a full chunk is
+    /// held back so that a chunk is only sealed once it is known not to be the last.
+    pub(crate) fn buffer(&mut self, data: &[u8]) -> usize {
+        let n = data.len().min(STREAM_CHUNK_SIZE + 1 - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        n
+    }
+
+    pub(crate) fn chunk_ready(&self) -> bool {
+        self.buf.len() > STREAM_CHUNK_SIZE
+    }
+
+    /// Seals the full chunk waiting in the buffer and appends it to `out`.
+    pub(crate) fn seal_chunk(&mut self, out: &mut Vec<u8>) -> Result<(), CryptoError> {
+        let carry = self.buf.pop();
+        self.seal_into(out, false)?;
+        self.buf.extend(carry);
+        Ok(())
+    }
+
+    /// Seals whatever is buffered as the final chunk. Nothing may be written afterwards.
+    pub(crate) fn finish(&mut self, out: &mut Vec<u8>) -> Result<(), CryptoError> {
+        if self.finished {
+            return Ok(());
+        }
+        if self.chunk_ready() {
+            self.seal_chunk(out)?;
+        }
+        self.seal_into(out, true)?;
+        self.finished = true;
+        Ok(())
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn seal_into(&mut self, out: &mut Vec<u8>, last: bool) -> Result<(), CryptoError> {
+        if let Some(header) = self.header.take() {
+            out.extend_from_slice(&header);
+        }
+        self.chunk_key.seal(&mut self.buf, last)?;
+        out.extend_from_slice(&self.buf);
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+/// Collects sealed chunks and opens them; shared by the blocking and async readers.
+pub(crate) struct StreamDecryptor {
+    chunk_key: ChunkKey,
+    header_read: bool,
+    input: Vec<u8>,
+    filled: usize,
+    plain: Vec<u8>,
+    pos: usize,
+    finished: bool,
+}
+
+impl StreamDecryptor {
+    pub(crate) fn new(key: &[u8; KEY_SIZE]) -> Self {
+        Self {
+            chunk_key: ChunkKey::new(key, [0u8; NONCE_PREFIX_SIZE]),
+            header_read: false,
+            input: Vec::with_capacity(SEALED_CHUNK_SIZE + 1),
+            filled: 0,
+            plain: Vec::with_capacity(SEALED_CHUNK_SIZE + 1),
+            pos: 0,
+            finished: false,
+        }
+    }
+
+    /// Where the next bytes from the underlying reader go. Reads past the next chunk
+    /// boundary by one byte, which is how a non-final chunk is told apart from the last.
+    pub(crate) fn input_buf(&mut self) -> &mut [u8] {
+        let target = if self.header_read {
+            SEALED_CHUNK_SIZE + 1
+        } else {
+            STREAM_HEADER_SIZE
+        };
+        self.input.resize(target, 0);
+        &mut self.input[self.filled..]
+    }
+
+    /// Records that `n` bytes were read into [`Self::input_buf`] and opens the chunk they
+    /// complete, if any. `n == 0` means the underlying reader is exhausted.
+    pub(crate) fn advance_input(&mut self, n: usize) -> Result<(), CryptoError> {
+        self.filled += n;
+        let eof = n == 0;
+
+        if !self.header_read {
+            if self.filled == STREAM_HEADER_SIZE {
+                self.chunk_key
+                    .prefix
+                    .copy_from_slice(&self.input[..STREAM_HEADER_SIZE]);
+                self.header_read = true;
+                self.filled = 0;
+            } else if eof {
+                return Err(CryptoError::StreamTruncated);
+            }
+            return Ok(());
+        }
+
+        if self.filled == SEALED_CHUNK_SIZE + 1 {
+            let carry = self.input[SEALED_CHUNK_SIZE];
+            self.input.truncate(SEALED_CHUNK_SIZE);
+            self.chunk_key.open(&mut self.input, false)?;
+            self.take_plaintext();
+            self.input.push(carry);
+            self.filled = 1;
+        } else if eof {
+            // Every stream ends with a final chunk of at least a tag.
+            if self.filled == 0 {
+                return Err(CryptoError::StreamTruncated);
+            }
+            self.input.truncate(self.filled);
+            self.chunk_key.open_last(&mut self.input)?;
+            self.take_plaintext();
+            self.filled = 0;
+            self.finished = true;
+        }
+
+        Ok(())
+    }
+
+    /// Decrypted bytes not yet handed out.
+    pub(crate) fn plaintext(&self) -> &[u8] {
+        &self.plain[self.pos..]
+    }
+
+    pub(crate) fn consume(&mut self, n: usize) {
+        self.pos = (self.pos + n).min(self.plain.len());
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn take_plaintext(&mut self) {
+        std::mem::swap(&mut self.input, &mut self.plain);
+        self.input.clear();
+        self.pos = 0;
+    }
+}
+
+/// Encrypts everything written to it into `inner`.
+///
+/// [`EncryptWriter::finish`] must be called to write the final chunk; a stream dropped
+/// without it is rejected by the reader as truncated. `flush` only flushes what has been
+/// sealed so far, since sealing a partial chunk would end the stream.
+pub struct EncryptWriter<W: Write> {
+    inner: W,
+    encryptor: StreamEncryptor,
+    out: Vec<u8>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    pub fn new(inner: W, key: &[u8; KEY_SIZE]) -> Result<Self, CryptoError> {
+        Ok(Self {
+            inner,
+            encryptor: StreamEncryptor::new(key)?,
+            out: Vec::with_capacity(STREAM_HEADER_SIZE + SEALED_CHUNK_SIZE),
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Writes the final chunk and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.encryptor.finish(&mut self.out)?;
+        self.inner.write_all(&self.out)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_out(&mut self) -> io::Result<()> {
+        let result = self.inner.write_all(&self.out);
+        self.out.clear();
+        result
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = self.encryptor.buffer(data);
+        if self.encryptor.chunk_ready() {
+            self.encryptor.seal_chunk(&mut self.out)?;
+            self.write_out()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts a stream written by [`EncryptWriter`].
+///
+/// Plaintext is only returned once its chunk has authenticated. A stream that was cut
+/// short fails with [`CryptoError::StreamTruncated`], any other tampering, including
+/// reordered chunks, with [`CryptoError::DecryptionFailed`]; both arrive as
+/// [`io::ErrorKind::InvalidData`] errors wrapping the `CryptoError`.
+pub struct DecryptReader<R: Read> {
+    inner: R,
+    decryptor: StreamDecryptor,
+}
+
+impl<R: Read> DecryptReader<R> {
+    pub fn new(inner: R, key: &[u8; KEY_SIZE]) -> Self {
+        Self {
+            inner,
+            decryptor: StreamDecryptor::new(key),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let plaintext = self.decryptor.plaintext();
+            if !plaintext.is_empty() {
+                let n = plaintext.len().min(buf.len());
+                buf[..n].copy_from_slice(&plaintext[..n]);
+                self.decryptor.consume(n);
+                return Ok(n);
+            }
+            if self.decryptor.is_finished() || buf.is_empty() {
+                return Ok(0);
+            }
+
+            let n = match self.inner.read(self.decryptor.input_buf()) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.decryptor.advance_input(n)?;
+        }
+    }
+}
@@ -1,4 +1,10 @@
-use crate::{generate_key, Cipher, CryptoError, NONCE_SIZE};
+use std::io::{self, Read, Write};
+
+use crate::{
+    derive_password_key, derive_subkey, generate_key, generate_salt, hkdf_sha256, Algorithm,
+    Cipher, CryptoError, DecryptReader, EncryptWriter, PasswordKdfParams, X25519KeyPair,
+    NONCE_SIZE, PBKDF2_DEFAULT_ITERATIONS, SALT_SIZE, STREAM_CHUNK_SIZE, STREAM_HEADER_SIZE,
+};
 
 #[test]
 fn encrypt_decrypt_round_trip() {
@@ -68,7 +74,7 @@ fn encrypt_empty_plaintext() {
     let cipher = Cipher::new(&key);
 
     let encrypted = cipher.encrypt(b"").unwrap();
-    assert_eq!(encrypted.len(), NONCE_SIZE + 16);
+    assert_eq!(encrypted.len(), 1 + NONCE_SIZE + 16);
 
     let decrypted = cipher.decrypt(&encrypted).unwrap();
     assert!(decrypted.is_empty());
@@ -99,6 +105,283 @@ fn encrypt_decrypt_with_explicit_nonce() {
     assert_eq!(plaintext.to_vec(), decrypted);
 }
 
+#[test]
+fn encrypt_decrypt_with_aad() {
+    let key = generate_key();
+    let cipher = Cipher::new(&key);
+
+    let encrypted = cipher
+        .encrypt_with_aad(b"file contents", b"transfer-1/report.pdf")
+        .unwrap();
+    let decrypted = cipher
+        .decrypt_with_aad(&encrypted, b"transfer-1/report.pdf")
+        .unwrap();
+
+    assert_eq!(decrypted, b"file contents");
+}
+
+#[test]
+fn decrypt_with_other_aad_fails() {
+    let key = generate_key();
+    let cipher = Cipher::new(&key);
+
+    let encrypted = cipher
+        .encrypt_with_aad(b"file contents", b"transfer-1/report.pdf")
+        .unwrap();
+
+    assert!(cipher.decrypt_with_aad(&encrypted, b"transfer-2/report.pdf").is_err());
+    assert!(cipher.decrypt(&encrypted).is_err());
+}
+
+#[test]
+fn empty_aad_matches_plain_encrypt() {
+    let key = generate_key();
+    let cipher = Cipher::new(&key);
+
+    let encrypted = cipher.encrypt(b"no context").unwrap();
+    assert_eq!(cipher.decrypt_with_aad(&encrypted, b"").unwrap(), b"no context");
+}
+
+const ALGORITHMS: [Algorithm; 3] = [
+    Algorithm::Aes256Gcm,
+    Algorithm::ChaCha20Poly1305,
+    Algorithm::XChaCha20Poly1305,
+];
+
+#[test]
+fn every_algorithm_round_trips_and_tags_its_output() {
+    let key = generate_key();
+
+    for algorithm in ALGORITHMS {
+        let cipher = Cipher::with_algorithm(&key, algorithm);
+        let encrypted = cipher.encrypt_with_aad(b"payload", b"context").unwrap();
+
+        assert_eq!(encrypted[0], algorithm.id());
+        assert_eq!(encrypted.len(), 1 + algorithm.nonce_len() + b"payload".len() + 16);
+        assert_eq!(
+            cipher.decrypt_with_aad(&encrypted, b"context").unwrap(),
+            b"payload",
+            "{algorithm:?}"
+        );
+        assert!(cipher.decrypt_with_aad(&encrypted, b"other").is_err());
+    }
+}
+
+#[test]
+fn decrypt_follows_the_algorithm_byte() {
+    let key = generate_key();
+    let reader = Cipher::new(&key);
+
+    for algorithm in ALGORITHMS {
+        let encrypted = Cipher::with_algorithm(&key, algorithm)
+            .encrypt(b"written elsewhere")
+            .unwrap();
+        assert_eq!(reader.decrypt(&encrypted).unwrap(), b"written elsewhere");
+    }
+}
+
+#[test]
+fn decrypt_accepts_ciphertexts_without_algorithm_byte() {
+    let key = generate_key();
+    let cipher = Cipher::new(&key);
+
+    // Older blobs are `nonce | ciphertext | tag` under AES-256-GCM. Their first byte is
+    // random, so include nonces that start like a valid algorithm id.
+    for first in [0u8, 1, 2, 3, 0xFF] {
+        let mut nonce = [7u8; NONCE_SIZE];
+        nonce[0] = first;
+
+        let mut legacy = nonce.to_vec();
+        legacy.extend(cipher.encrypt_with_nonce(&nonce, b"old blob").unwrap());
+
+        assert_eq!(cipher.decrypt(&legacy).unwrap(), b"old blob", "first byte {first}");
+    }
+}
+
+#[test]
+fn decrypt_checks_the_legacy_minimum_length() {
+    let key = generate_key();
+    let cipher = Cipher::new(&key);
+
+    let result = cipher.decrypt(&[0u8; NONCE_SIZE + 15]);
+    assert!(matches!(
+        result,
+        Err(CryptoError::CiphertextTooShort { len: 27, min: 28 })
+    ));
+
+    // Exactly `nonce | tag`: an empty legacy plaintext, one byte short of any tagged layout.
+    let nonce = [9u8; NONCE_SIZE];
+    let mut legacy = nonce.to_vec();
+    legacy.extend(cipher.encrypt_with_nonce(&nonce, b"").unwrap());
+    assert_eq!(legacy.len(), 28);
+    assert!(cipher.decrypt(&legacy).unwrap().is_empty());
+
+    legacy[NONCE_SIZE] ^= 0xFF;
+    assert!(cipher.decrypt(&legacy).is_err());
+}
+
+#[test]
+fn decrypt_unknown_algorithm_fails() {
+    let key = generate_key();
+    let cipher = Cipher::new(&key);
+
+    let mut encrypted = cipher.encrypt(b"data").unwrap();
+    encrypted[0] = 0x7F;
+
+    assert!(matches!(
+        cipher.decrypt(&encrypted),
+        Err(CryptoError::UnsupportedAlgorithm(0x7F))
+    ));
+    assert!(matches!(
+        Algorithm::try_from(0x7F),
+        Err(CryptoError::UnsupportedAlgorithm(0x7F))
+    ));
+}
+
+#[test]
+fn explicit_nonce_requires_96_bit_nonce_algorithm() {
+    let key = generate_key();
+    let nonce = [1u8; NONCE_SIZE];
+
+    let chacha = Cipher::with_algorithm(&key, Algorithm::ChaCha20Poly1305);
+    let ciphertext = chacha.encrypt_with_nonce(&nonce, b"data").unwrap();
+    assert_eq!(chacha.decrypt_with_nonce(&nonce, &ciphertext).unwrap(), b"data");
+
+    let xchacha = Cipher::with_algorithm(&key, Algorithm::XChaCha20Poly1305);
+    assert!(matches!(
+        xchacha.encrypt_with_nonce(&nonce, b"data"),
+        Err(CryptoError::UnsupportedAlgorithm(3))
+    ));
+}
+
+fn unhex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn hkdf_matches_rfc5869_test_case_1() {
+    let ikm = [0x0bu8; 22];
+    let salt = unhex("000102030405060708090a0b0c");
+    let info = unhex("f0f1f2f3f4f5f6f7f8f9");
+
+    let mut okm = [0u8; 42];
+    hkdf_sha256(&ikm, &salt, &info, &mut okm).unwrap();
+
+    assert_eq!(
+        okm.to_vec(),
+        unhex(
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865"
+        )
+    );
+}
+
+#[test]
+fn hkdf_subkeys_depend_on_info() {
+    let ikm = generate_key();
+
+    let a = derive_subkey(&ikm, b"salt", b"file-encryption").unwrap();
+    let b = derive_subkey(&ikm, b"salt", b"metadata").unwrap();
+
+    assert_ne!(a, b);
+    assert_eq!(a, derive_subkey(&ikm, b"salt", b"file-encryption").unwrap());
+}
+
+#[test]
+fn hkdf_rejects_oversized_output() {
+    let mut out = vec![0u8; 255 * 32 + 1];
+    let result = hkdf_sha256(b"ikm", b"salt", b"info", &mut out);
+
+    assert!(matches!(result, Err(CryptoError::KeyDerivationFailed)));
+}
+
+#[test]
+fn password_key_is_deterministic_per_salt() {
+    let salt = generate_salt();
+    let other_salt = generate_salt();
+
+    let key = derive_password_key(b"correct horse", &salt, 1_000).unwrap();
+
+    assert_eq!(key, derive_password_key(b"correct horse", &salt, 1_000).unwrap());
+    assert_ne!(key, derive_password_key(b"correct horse", &other_salt, 1_000).unwrap());
+    assert_ne!(key, derive_password_key(b"battery staple", &salt, 1_000).unwrap());
+    assert_ne!(key, derive_password_key(b"correct horse", &salt, 1_001).unwrap());
+}
+
+#[test]
+fn password_key_rejects_weak_parameters() {
+    assert!(matches!(
+        derive_password_key(b"password", b"salt", 1_000),
+        Err(CryptoError::SaltTooShort { len: 4, min: SALT_SIZE })
+    ));
+    assert!(matches!(
+        derive_password_key(b"password", &generate_salt(), 0),
+        Err(CryptoError::KeyDerivationFailed)
+    ));
+}
+
+#[test]
+fn password_params_round_trip() {
+    let params = PasswordKdfParams::generate();
+    assert_eq!(params.iterations, PBKDF2_DEFAULT_ITERATIONS);
+
+    let mut stored = params.to_bytes().to_vec();
+    stored.extend_from_slice(b"ciphertext follows");
+    assert_eq!(PasswordKdfParams::from_bytes(&stored).unwrap(), params);
+
+    assert!(matches!(
+        PasswordKdfParams::from_bytes(&stored[..SALT_SIZE]),
+        Err(CryptoError::InvalidLength { .. })
+    ));
+}
+
+#[test]
+fn x25519_public_key_matches_rfc7748() {
+    let secret: [u8; 32] = unhex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a")
+        .try_into()
+        .unwrap();
+    let pair = X25519KeyPair::from_secret_bytes(secret);
+
+    assert_eq!(
+        pair.public_key().to_vec(),
+        unhex("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a")
+    );
+    assert_eq!(pair.secret_bytes(), secret);
+}
+
+#[test]
+fn x25519_both_sides_agree_on_a_cipher_key() {
+    let alice = X25519KeyPair::generate().unwrap();
+    let bob = X25519KeyPair::generate().unwrap();
+
+    let alice_key = alice.agree(&bob.public_key(), b"transfer").unwrap();
+    let bob_key = bob.agree(&alice.public_key(), b"transfer").unwrap();
+    assert_eq!(alice_key, bob_key);
+    assert_ne!(alice_key, alice.agree(&bob.public_key(), b"other").unwrap());
+
+    let sealed = Cipher::new(&alice_key).encrypt(b"hello bob").unwrap();
+    assert_eq!(Cipher::new(&bob_key).decrypt(&sealed).unwrap(), b"hello bob");
+
+    let eve = X25519KeyPair::generate().unwrap();
+    assert_ne!(alice_key, eve.agree(&bob.public_key(), b"transfer").unwrap());
+}
+
+#[test]
+fn x25519_rejects_bad_peer_keys() {
+    let pair = X25519KeyPair::generate().unwrap();
+
+    assert!(matches!(
+        pair.agree(&[0u8; 32], b"info"),
+        Err(CryptoError::KeyAgreementFailed)
+    ));
+    assert!(matches!(
+        pair.agree(&[9u8; 31], b"info"),
+        Err(CryptoError::InvalidLength { len: 31, expected: 32 })
+    ));
+}
+
 #[test]
 fn error_display_is_meaningful() {
     let err = CryptoError::CiphertextTooShort { len: 5, min: 28 };
@@ -106,3 +389,198 @@ fn error_display_is_meaningful() {
     assert!(msg.contains("5"));
     assert!(msg.contains("28"));
 }
+
+const SEALED_CHUNK: usize = STREAM_CHUNK_SIZE + 16;
+
+fn stream_plaintext(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+fn encrypt_stream(key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+    let mut writer = EncryptWriter::new(Vec::new(), key).unwrap();
+    // Uneven writes so chunk boundaries never line up with them.
+    for part in plaintext.chunks(7919) {
+        writer.write_all(part).unwrap();
+    }
+    writer.finish().unwrap()
+}
+
+fn decrypt_stream(key: &[u8; 32], sealed: &[u8]) -> io::Result<Vec<u8>> {
+    let mut plaintext = Vec::new();
+    DecryptReader::new(sealed, key).read_to_end(&mut plaintext)?;
+    Ok(plaintext)
+}
+
+fn stream_error(err: &io::Error) -> &CryptoError {
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    err.get_ref()
+        .and_then(|inner| inner.downcast_ref::<CryptoError>())
+        .expect("io error should wrap a CryptoError")
+}
+
+#[test]
+fn stream_round_trip() {
+    let key = generate_key();
+
+    for len in [
+        0,
+        1,
+        STREAM_CHUNK_SIZE - 1,
+        STREAM_CHUNK_SIZE,
+        STREAM_CHUNK_SIZE + 1,
+        3 * STREAM_CHUNK_SIZE + 17,
+    ] {
+        let plaintext = stream_plaintext(len);
+        let sealed = encrypt_stream(&key, &plaintext);
+
+        let chunks = len.div_ceil(STREAM_CHUNK_SIZE).max(1);
+        assert_eq!(sealed.len(), STREAM_HEADER_SIZE + len + chunks * 16, "len {len}");
+        assert_eq!(decrypt_stream(&key, &sealed).unwrap(), plaintext, "len {len}");
+    }
+}
+
+#[test]
+fn stream_with_wrong_key_fails() {
+    let sealed = encrypt_stream(&generate_key(), b"secret");
+    let err = decrypt_stream(&generate_key(), &sealed).unwrap_err();
+
+    assert!(matches!(stream_error(&err), CryptoError::DecryptionFailed(_)));
+}
+
+#[test]
+fn stream_truncated_at_chunk_boundary_fails() {
+    let key = generate_key();
+    let sealed = encrypt_stream(&key, &stream_plaintext(3 * STREAM_CHUNK_SIZE + 5));
+
+    for chunks in 1..=3 {
+        let cut = &sealed[..STREAM_HEADER_SIZE + chunks * SEALED_CHUNK];
+        let err = decrypt_stream(&key, cut).unwrap_err();
+        assert!(
+            matches!(stream_error(&err), CryptoError::StreamTruncated),
+            "cut after {chunks} chunks"
+        );
+    }
+}
+
+#[test]
+fn stream_truncated_mid_chunk_fails() {
+    let key = generate_key();
+    let sealed = encrypt_stream(&key, &stream_plaintext(2 * STREAM_CHUNK_SIZE + 5));
+
+    for cut in [sealed.len() - 1, STREAM_HEADER_SIZE + SEALED_CHUNK + 100, 3] {
+        assert!(decrypt_stream(&key, &sealed[..cut]).is_err(), "cut at {cut}");
+    }
+    let err = decrypt_stream(&key, &sealed[..STREAM_HEADER_SIZE]).unwrap_err();
+    assert!(matches!(stream_error(&err), CryptoError::StreamTruncated));
+}
+
+#[test]
+fn stream_without_finish_is_truncated() {
+    let key = generate_key();
+    let mut writer = EncryptWriter::new(Vec::new(), &key).unwrap();
+    writer.write_all(&stream_plaintext(2 * STREAM_CHUNK_SIZE)).unwrap();
+    let sealed = writer.get_ref().clone();
+
+    let err = decrypt_stream(&key, &sealed).unwrap_err();
+    assert!(matches!(stream_error(&err), CryptoError::StreamTruncated));
+}
+
+#[test]
+fn stream_reordered_chunks_fail() {
+    let key = generate_key();
+    let sealed = encrypt_stream(&key, &stream_plaintext(3 * STREAM_CHUNK_SIZE + 5));
+
+    let first = STREAM_HEADER_SIZE..STREAM_HEADER_SIZE + SEALED_CHUNK;
+    let second = first.end..first.end + SEALED_CHUNK;
+    let mut swapped = sealed[..first.start].to_vec();
+    swapped.extend_from_slice(&sealed[second.clone()]);
+    swapped.extend_from_slice(&sealed[first]);
+    swapped.extend_from_slice(&sealed[second.end..]);
+
+    let err = decrypt_stream(&key, &swapped).unwrap_err();
+    assert!(matches!(stream_error(&err), CryptoError::DecryptionFailed(_)));
+}
+
+#[test]
+fn stream_dropped_chunk_fails() {
+    let key = generate_key();
+    let sealed = encrypt_stream(&key, &stream_plaintext(3 * STREAM_CHUNK_SIZE + 5));
+
+    let mut dropped = sealed[..STREAM_HEADER_SIZE].to_vec();
+    dropped.extend_from_slice(&sealed[STREAM_HEADER_SIZE + SEALED_CHUNK..]);
+
+    let err = decrypt_stream(&key, &dropped).unwrap_err();
+    assert!(matches!(stream_error(&err), CryptoError::DecryptionFailed(_)));
+}
+
+#[test]
+fn stream_tampered_chunk_fails_before_its_plaintext_is_returned() {
+    let key = generate_key();
+    let plaintext = stream_plaintext(2 * STREAM_CHUNK_SIZE + 5);
+    let mut sealed = encrypt_stream(&key, &plaintext);
+    sealed[STREAM_HEADER_SIZE + SEALED_CHUNK + 10] ^= 0x01;
+
+    let mut reader = DecryptReader::new(sealed.as_slice(), &key);
+    let mut first = vec![0u8; STREAM_CHUNK_SIZE];
+    reader.read_exact(&mut first).unwrap();
+    assert_eq!(first, plaintext[..STREAM_CHUNK_SIZE]);
+
+    let err = reader.read(&mut [0u8; 16]).unwrap_err();
+    assert!(matches!(stream_error(&err), CryptoError::DecryptionFailed(_)));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_stream_round_trip() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{AsyncDecryptReader, AsyncEncryptWriter};
+
+    let key = generate_key();
+    let plaintext = stream_plaintext(2 * STREAM_CHUNK_SIZE + 123);
+
+    let (client, server) = tokio::io::duplex(4096);
+    let writer_task = {
+        let plaintext = plaintext.clone();
+        tokio::spawn(async move {
+            let mut writer = AsyncEncryptWriter::new(client, &key).unwrap();
+            for part in plaintext.chunks(7919) {
+                writer.write_all(part).await.unwrap();
+            }
+            writer.shutdown().await.unwrap();
+        })
+    };
+
+    let mut decrypted = Vec::new();
+    AsyncDecryptReader::new(server, &key)
+        .read_to_end(&mut decrypted)
+        .await
+        .unwrap();
+    writer_task.await.unwrap();
+
+    assert_eq!(decrypted, plaintext);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_stream_matches_blocking_format() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{AsyncDecryptReader, AsyncEncryptWriter};
+
+    let key = generate_key();
+    let plaintext = stream_plaintext(STREAM_CHUNK_SIZE + 1);
+
+    let mut writer = AsyncEncryptWriter::new(Vec::new(), &key).unwrap();
+    writer.write_all(&plaintext).await.unwrap();
+    writer.shutdown().await.unwrap();
+    let sealed = writer.into_inner();
+    assert_eq!(decrypt_stream(&key, &sealed).unwrap(), plaintext);
+
+    let cut = &sealed[..STREAM_HEADER_SIZE + SEALED_CHUNK];
+    let err = AsyncDecryptReader::new(cut, &key)
+        .read_to_end(&mut Vec::new())
+        .await
+        .unwrap_err();
+    assert!(matches!(stream_error(&err), CryptoError::StreamTruncated));
+}
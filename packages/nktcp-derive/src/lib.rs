@@ -0,0 +1,229 @@
+//! `#[derive(Encode, Decode)]` for `nktcp` packet structs.
+//!
+//! Fields are written in declaration order with their own `Encode`/`Decode` impls. Two
+//! field attributes cover the layouts that predate the derive:
+//!
+//! - `#[nktcp(rest)]` on a `Vec<u8>`: the raw bytes up to the end of the payload.
+//! - `#[nktcp(trailing)]` on an `Option<T>`: present only if bytes remain, so it can be
+//!   appended to an existing packet without breaking older peers.
+//!
+//! `#[nktcp(packet = Name)]` on the struct also implements `Packet` for
+//! `PacketType::Name`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Fields, Ident, Index, parse_macro_input};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FieldKind {
+    Plain,
+    Rest,
+    Trailing,
+}
+
+struct Field {
+    member: TokenStream2,
+    binding: Ident,
+    kind: FieldKind,
+}
+
+#[proc_macro_derive(Encode, attributes(nktcp))]
+pub fn derive_encode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_encode(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(Decode, attributes(nktcp))]
+pub fn derive_decode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_decode(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_encode(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let fields = struct_fields(input)?;
+
+    let writes = fields.iter().map(|field| {
+        let member = &field.member;
+        match field.kind {
+            FieldKind::Plain => quote! { ::nktcp::Encode::encode(&self.#member, writer)?; },
+            FieldKind::Rest => quote! { writer.write_bytes(&self.#member); },
+            FieldKind::Trailing => quote! {
+                if let ::core::option::Option::Some(value) = &self.#member {
+                    ::nktcp::Encode::encode(value, writer)?;
+                }
+            },
+        }
+    });
+
+    let packet = packet_impl(input)?;
+
+    Ok(quote! {
+        impl #impl_generics ::nktcp::Encode for #name #ty_generics #where_clause {
+            fn encode(
+                &self,
+                writer: &mut ::nktcp::BinaryWriter,
+            ) -> ::core::result::Result<(), ::nktcp::ProtocolError> {
+                #(#writes)*
+                ::core::result::Result::Ok(())
+            }
+        }
+
+        #packet
+    })
+}
+
+fn expand_decode(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let fields = struct_fields(input)?;
+
+    let reads = fields.iter().map(|field| {
+        let binding = &field.binding;
+        match field.kind {
+            FieldKind::Plain => quote! { let #binding = ::nktcp::Decode::decode(reader)?; },
+            FieldKind::Rest => quote! { let #binding = reader.read_remaining().to_vec(); },
+            FieldKind::Trailing => quote! {
+                let #binding = if reader.is_empty() {
+                    ::core::option::Option::None
+                } else {
+                    ::core::option::Option::Some(::nktcp::Decode::decode(reader)?)
+                };
+            },
+        }
+    });
+
+    let construct = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(_) => {
+                let inits = fields.iter().map(|field| {
+                    let member = &field.member;
+                    let binding = &field.binding;
+                    quote! { #member: #binding }
+                });
+                quote! { Self { #(#inits),* } }
+            }
+            Fields::Unnamed(_) => {
+                let bindings = fields.iter().map(|field| &field.binding);
+                quote! { Self(#(#bindings),*) }
+            }
+            Fields::Unit => quote! { Self },
+        },
+        _ => unreachable!("struct_fields rejects everything but structs"),
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::nktcp::Decode for #name #ty_generics #where_clause {
+            fn decode(
+                reader: &mut ::nktcp::BinaryReader<'_>,
+            ) -> ::core::result::Result<Self, ::nktcp::ProtocolError> {
+                #(#reads)*
+                ::core::result::Result::Ok(#construct)
+            }
+        }
+    })
+}
+
+fn struct_fields(input: &DeriveInput) -> syn::Result<Vec<Field>> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "Encode and Decode can only be derived for structs",
+        ));
+    };
+
+    let mut fields = Vec::new();
+    for (index, field) in data.fields.iter().enumerate() {
+        let (member, binding) = match &field.ident {
+            Some(ident) => (quote! { #ident }, format_ident!("field_{}", ident)),
+            None => {
+                let index = Index::from(index);
+                (quote! { #index }, format_ident!("field_{}", index.index))
+            }
+        };
+        fields.push(Field {
+            member,
+            binding,
+            kind: field_kind(field)?,
+        });
+    }
+
+    let rest = fields.iter().position(|f| f.kind == FieldKind::Rest);
+    if rest.is_some_and(|position| position + 1 != fields.len()) {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "#[nktcp(rest)] must be on the last field",
+        ));
+    }
+    let trailing = fields.iter().position(|f| f.kind == FieldKind::Trailing);
+    if trailing.is_some_and(|position| {
+        fields[position..]
+            .iter()
+            .any(|f| f.kind != FieldKind::Trailing)
+    }) {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "#[nktcp(trailing)] fields must come after all other fields",
+        ));
+    }
+
+    Ok(fields)
+}
+
+fn field_kind(field: &syn::Field) -> syn::Result<FieldKind> {
+    let mut kind = FieldKind::Plain;
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("nktcp"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rest") {
+                kind = FieldKind::Rest;
+                Ok(())
+            } else if meta.path.is_ident("trailing") {
+                kind = FieldKind::Trailing;
+                Ok(())
+            } else {
+                Err(meta.error("expected `rest` or `trailing`"))
+            }
+        })?;
+    }
+    Ok(kind)
+}
+
+fn packet_impl(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let mut packet_type: Option<Ident> = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("nktcp"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("packet") {
+                packet_type = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `packet = PacketTypeVariant`"))
+            }
+        })?;
+    }
+
+    let Some(packet_type) = packet_type else {
+        return Ok(TokenStream2::new());
+    };
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::nktcp::Packet for #name #ty_generics #where_clause {
+            const PACKET_TYPE: ::nktcp::PacketType = ::nktcp::PacketType::#packet_type;
+        }
+    })
+}
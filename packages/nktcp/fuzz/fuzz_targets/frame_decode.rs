@@ -0,0 +1,31 @@
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use nktcp::{Frame, FrameCodec, FrameHeader, LENGTH_PREFIX_SIZE, decode_length};
+use tokio_util::codec::Decoder;
+
+// A single buffer as it would arrive from a peer, through every entry point the apps use.
+fuzz_target!(|data: &[u8]| {
+    let decoded = Frame::decode(data);
+
+    if let Ok(Some((frame, len))) = &decoded {
+        assert_eq!(frame.to_bytes().unwrap(), data[..*len]);
+    }
+
+    // The desktop read loop: length prefix first, then the header of the body.
+    if let Some(prefix) = data.first_chunk::<LENGTH_PREFIX_SIZE>()
+        && let Ok(body_len) = decode_length(*prefix)
+    {
+        let body = &data[LENGTH_PREFIX_SIZE..];
+        let _ = FrameHeader::parse(&body[..body_len.min(body.len())]);
+    }
+
+    let mut buf = BytesMut::from(data);
+    let from_codec = FrameCodec::new().decode(&mut buf);
+    match (&decoded, from_codec) {
+        (Ok(Some((frame, _))), Ok(Some(codec_frame))) => assert_eq!(*frame, codec_frame),
+        (Ok(None), Ok(None)) | (Err(_), Err(_)) => {}
+        (decoded, from_codec) => panic!("decode {decoded:?} but codec {from_codec:?}"),
+    }
+});
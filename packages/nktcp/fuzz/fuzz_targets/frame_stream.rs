@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nktcp::{FrameDecoder, ProtocolError};
+
+// The stream as the Android client sees it: arbitrary bytes pushed in arbitrary pieces.
+// The first byte picks the piece length.
+fuzz_target!(|data: &[u8]| {
+    let Some((&piece_len, stream)) = data.split_first() else {
+        return;
+    };
+
+    let mut decoder = FrameDecoder::new();
+    for piece in stream.chunks(piece_len.max(1) as usize) {
+        decoder.push(piece);
+        loop {
+            match decoder.next_frame() {
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                // Consumed already, so decoding goes on.
+                Err(ProtocolError::FrameTooShort(_)) => {}
+                // Left in the buffer; a real reader drops the connection here.
+                Err(_) => return,
+            }
+        }
+    }
+    assert!(decoder.buffered_len() <= stream.len());
+});
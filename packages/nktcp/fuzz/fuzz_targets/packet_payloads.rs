@@ -0,0 +1,42 @@
+#![no_main]
+
+use std::fmt::Debug;
+
+use libfuzzer_sys::fuzz_target;
+use nktcp::Packet;
+use nktcp::packets::{
+    AuthLoginRequest, AuthLoginResponse, AuthTokenRevoke, FileAccept, FileAck, FileChunk,
+    FileChunkCompressed, FileFinish, FileOffer, FilePause, FileReject, FileResume, Handshake,
+    Heartbeat, KeyRotation, PairCommit, PairConfirm, PairReveal, ServerFull,
+};
+
+/// Decoding may be lenient, but whatever it accepts must survive a round trip.
+fn check<P: Packet + PartialEq + Debug>(payload: &[u8]) {
+    if let Ok(packet) = P::from_payload(payload) {
+        let reencoded = packet.to_payload().expect("decoded packet must encode");
+        assert_eq!(P::from_payload(&reencoded).unwrap(), packet);
+    }
+}
+
+// Every payload decoder, fed the same bytes.
+fuzz_target!(|data: &[u8]| {
+    check::<Handshake>(data);
+    check::<Heartbeat>(data);
+    check::<PairCommit>(data);
+    check::<PairReveal>(data);
+    check::<PairConfirm>(data);
+    check::<KeyRotation>(data);
+    check::<AuthLoginRequest>(data);
+    check::<AuthLoginResponse>(data);
+    check::<AuthTokenRevoke>(data);
+    check::<FileOffer>(data);
+    check::<FileAccept>(data);
+    check::<FileResume>(data);
+    check::<FileReject>(data);
+    check::<FilePause>(data);
+    check::<FileFinish>(data);
+    check::<FileAck>(data);
+    check::<FileChunk>(data);
+    check::<FileChunkCompressed>(data);
+    check::<ServerFull>(data);
+});
@@ -0,0 +1,131 @@
+use jni::objects::{JByteArray, JClass};
+use jni::sys::jint;
+use jni::{EnvUnowned, jni_str};
+use jni_fn::jni_fn;
+
+use crate::binary::BinaryWriter;
+use crate::frame::{Frame, FrameHeader, LENGTH_PREFIX_SIZE, decode_length};
+use crate::packet::PacketType;
+
+#[jni_fn("com.thiratt.nekoshare.core.jni.NkTcp")]
+pub fn encodeFrame<'caller>(
+    mut unowned_env: EnvUnowned<'caller>,
+    _class: JClass<'caller>,
+    packet_type: jint,
+    request_id: jint,
+    payload: JByteArray<'caller>,
+) -> JByteArray<'caller> {
+    let outcome = unowned_env.with_env(|mut env| -> Result<_, jni::errors::Error> {
+        let payload_bytes = env.convert_byte_array(&payload)?;
+
+        let packet_type = match u8::try_from(packet_type) {
+            Ok(value) => PacketType::from_u8(value),
+            Err(_) => {
+                let class = jni_str!("java/lang/IllegalArgumentException");
+                let msg = jni_str!("packet type must fit in one byte");
+                env.throw_new(class, msg)?;
+                return Err(jni::errors::Error::JavaException);
+            }
+        };
+
+        let mut writer = BinaryWriter::new();
+        if Frame::new(packet_type, request_id, payload_bytes)
+            .encode(&mut writer)
+            .is_err()
+        {
+            let class = jni_str!("java/lang/IllegalArgumentException");
+            let msg = jni_str!("payload exceeds the maximum frame size");
+            env.throw_new(class, msg)?;
+            return Err(jni::errors::Error::JavaException);
+        }
+
+        let frame = writer.into_bytes();
+        let result = env.new_byte_array(frame.len())?;
+        result.set_region(&mut env, 0, bytemuck::cast_slice(&frame))?;
+        Ok(result)
+    });
+
+    outcome.resolve::<jni::errors::ThrowRuntimeExAndDefault>()
+}
+
+/// Returns the body length announced by a 4-byte length prefix.
+#[jni_fn("com.thiratt.nekoshare.core.jni.NkTcp")]
+pub fn decodeFrameLength<'caller>(
+    mut unowned_env: EnvUnowned<'caller>,
+    _class: JClass<'caller>,
+    prefix: JByteArray<'caller>,
+) -> jint {
+    let outcome = unowned_env.with_env(|mut env| -> Result<_, jni::errors::Error> {
+        let prefix_bytes = env.convert_byte_array(&prefix)?;
+
+        let prefix: [u8; LENGTH_PREFIX_SIZE] = match prefix_bytes.try_into() {
+            Ok(arr) => arr,
+            Err(_) => {
+                let class = jni_str!("java/lang/IllegalArgumentException");
+                let msg = jni_str!("length prefix must be exactly 4 bytes");
+                env.throw_new(class, msg)?;
+                return Err(jni::errors::Error::JavaException);
+            }
+        };
+
+        match decode_length(prefix) {
+            Ok(len) => Ok(len as jint),
+            Err(_e) => {
+                let class = jni_str!("java/io/IOException");
+                let msg = jni_str!("frame exceeds the maximum frame size");
+                env.throw_new(class, msg)?;
+                Err(jni::errors::Error::JavaException)
+            }
+        }
+    });
+
+    outcome.resolve::<jni::errors::ThrowRuntimeExAndDefault>()
+}
+
+/// Returns the packet type byte of a frame body (everything after the length prefix).
+#[jni_fn("com.thiratt.nekoshare.core.jni.NkTcp")]
+pub fn framePacketType<'caller>(
+    mut unowned_env: EnvUnowned<'caller>,
+    _class: JClass<'caller>,
+    body: JByteArray<'caller>,
+) -> jint {
+    let outcome = unowned_env.with_env(|mut env| -> Result<_, jni::errors::Error> {
+        let body_bytes = env.convert_byte_array(&body)?;
+
+        match FrameHeader::parse(&body_bytes) {
+            Ok(header) => Ok(header.packet_type.as_u8() as jint),
+            Err(_e) => {
+                let class = jni_str!("java/io/IOException");
+                let msg = jni_str!("frame too short to hold a header");
+                env.throw_new(class, msg)?;
+                Err(jni::errors::Error::JavaException)
+            }
+        }
+    });
+
+    outcome.resolve::<jni::errors::ThrowRuntimeExAndDefault>()
+}
+
+/// Returns the request id of a frame body (everything after the length prefix).
+#[jni_fn("com.thiratt.nekoshare.core.jni.NkTcp")]
+pub fn frameRequestId<'caller>(
+    mut unowned_env: EnvUnowned<'caller>,
+    _class: JClass<'caller>,
+    body: JByteArray<'caller>,
+) -> jint {
+    let outcome = unowned_env.with_env(|mut env| -> Result<_, jni::errors::Error> {
+        let body_bytes = env.convert_byte_array(&body)?;
+
+        match FrameHeader::parse(&body_bytes) {
+            Ok(header) => Ok(header.request_id),
+            Err(_e) => {
+                let class = jni_str!("java/io/IOException");
+                let msg = jni_str!("frame too short to hold a header");
+                env.throw_new(class, msg)?;
+                Err(jni::errors::Error::JavaException)
+            }
+        }
+    });
+
+    outcome.resolve::<jni::errors::ThrowRuntimeExAndDefault>()
+}
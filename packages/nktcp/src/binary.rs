@@ -0,0 +1,226 @@
+use crate::error::ProtocolError;
+
+#[derive(Debug, Default)]
+pub struct BinaryWriter {
+    buffer: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct BinaryReader<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+impl BinaryWriter {
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buffer: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i32(&mut self, value: i32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32_at(&mut self, offset: usize, value: u32) {
+        let bytes = value.to_le_bytes();
+        self.buffer[offset..offset + 4].copy_from_slice(&bytes);
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// # Panics
+    ///
+    /// If `value` is longer than `u16::MAX` bytes. Use [`Self::try_write_string`] for
+    /// strings that are not known to fit.
+    pub fn write_string(&mut self, value: &str) {
+        if let Err(err) = self.try_write_string(value) {
+            panic!("{err}");
+        }
+    }
+
+    /// Writes a `u16` byte length, then the UTF-8 bytes. Nothing is written on error.
+    pub fn try_write_string(&mut self, value: &str) -> Result<(), ProtocolError> {
+        let bytes = value.as_bytes();
+        let len = u16::try_from(bytes.len()).map_err(|_| ProtocolError::ValueTooLong {
+            len: bytes.len(),
+            max: u16::MAX as usize,
+        })?;
+
+        self.buffer.extend_from_slice(&len.to_le_bytes());
+        self.buffer.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// # Panics
+    ///
+    /// If `bytes` is longer than `u32::MAX`; see [`Self::try_write_bytes_with_length`].
+    pub fn write_bytes_with_length(&mut self, bytes: &[u8]) {
+        if let Err(err) = self.try_write_bytes_with_length(bytes) {
+            panic!("{err}");
+        }
+    }
+
+    /// Writes a `u32` byte length, then the bytes. Nothing is written on error.
+    pub fn try_write_bytes_with_length(&mut self, bytes: &[u8]) -> Result<(), ProtocolError> {
+        let len = u32::try_from(bytes.len()).map_err(|_| ProtocolError::ValueTooLong {
+            len: bytes.len(),
+            max: u32::MAX as usize,
+        })?;
+
+        self.buffer.extend_from_slice(&len.to_le_bytes());
+        self.buffer.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.buffer.push(if value { 1 } else { 0 });
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.buffer.truncate(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.buffer.reserve(additional);
+    }
+}
+
+impl<'a> BinaryReader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self { buffer, offset: 0 }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, ProtocolError> {
+        Ok(u8::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, ProtocolError> {
+        Ok(u16::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, ProtocolError> {
+        Ok(i32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, ProtocolError> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, ProtocolError> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_string(&mut self) -> Result<String, ProtocolError> {
+        let length = self.read_u16()? as usize;
+        let offset = self.offset;
+        let bytes = self.read_slice(length)?;
+        std::str::from_utf8(bytes)
+            .map(str::to_string)
+            .map_err(|_| ProtocolError::InvalidUtf8 { offset })
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], ProtocolError> {
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(self.read_slice(N)?);
+        Ok(bytes)
+    }
+
+    pub fn read_slice(&mut self, len: usize) -> Result<&'a [u8], ProtocolError> {
+        self.check_bounds(len)?;
+        let bytes = &self.buffer[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    pub fn read_bytes_with_length(&mut self) -> Result<Vec<u8>, ProtocolError> {
+        let length = self.read_u32()? as usize;
+        Ok(self.read_slice(length)?.to_vec())
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, ProtocolError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    /// Consumes and returns everything up to the end of the buffer.
+    pub fn read_remaining(&mut self) -> &'a [u8] {
+        let bytes = &self.buffer[self.offset.min(self.buffer.len())..];
+        self.offset = self.buffer.len();
+        bytes
+    }
+
+    pub fn remaining_bytes(&self) -> &'a [u8] {
+        &self.buffer[self.offset..]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offset >= self.buffer.len()
+    }
+
+    pub fn remaining_len(&self) -> usize {
+        self.buffer.len().saturating_sub(self.offset)
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn skip(&mut self, count: usize) -> Result<(), ProtocolError> {
+        self.read_slice(count).map(|_| ())
+    }
+
+    fn check_bounds(&self, needed: usize) -> Result<(), ProtocolError> {
+        let remaining = self.remaining_len();
+        if needed > remaining {
+            Err(ProtocolError::Underflow {
+                offset: self.offset,
+                needed,
+                remaining,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
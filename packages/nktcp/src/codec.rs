@@ -0,0 +1,53 @@
+use std::io;
+
+use bytes::{BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::error::ProtocolError;
+use crate::frame::{Frame, LENGTH_PREFIX_SIZE, complete_frame_len};
+use crate::packet::{HEADER_SIZE, MAX_PAYLOAD_SIZE};
+
+/// [`Frame`] codec for `tokio_util::codec::Framed` and friends.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameCodec;
+
+impl FrameCodec {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, io::Error> {
+        let Some(frame_len) = complete_frame_len(src)? else {
+            if let Some(prefix) = src.first_chunk::<LENGTH_PREFIX_SIZE>() {
+                let frame_len = LENGTH_PREFIX_SIZE + u32::from_le_bytes(*prefix) as usize;
+                src.reserve(frame_len - src.len());
+            }
+            return Ok(None);
+        };
+
+        let body = src.split_to(frame_len).split_off(LENGTH_PREFIX_SIZE);
+        Ok(Some(Frame::decode_body(&body)?))
+    }
+}
+
+impl Encoder<Frame> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<(), io::Error> {
+        if frame.payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(ProtocolError::FrameTooLarge(HEADER_SIZE + frame.payload.len()).into());
+        }
+
+        dst.reserve(LENGTH_PREFIX_SIZE + HEADER_SIZE + frame.payload.len());
+        dst.put_u32_le((HEADER_SIZE + frame.payload.len()) as u32);
+        dst.put_u8(frame.packet_type.as_u8());
+        dst.put_i32_le(frame.request_id);
+        dst.put_slice(&frame.payload);
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use crate::binary::{BinaryReader, BinaryWriter};
+use crate::error::ProtocolError;
+use crate::packet::PacketType;
+
+/// Writes a value in the wire format. Derive it with `#[derive(Encode)]`.
+pub trait Encode {
+    /// Fails if a field does not fit its length prefix, e.g. a string over 64 KiB. The
+    /// writer may then hold part of the value.
+    fn encode(&self, writer: &mut BinaryWriter) -> Result<(), ProtocolError>;
+}
+
+/// Reads a value written by [`Encode`]. Derive it with `#[derive(Decode)]`.
+pub trait Decode: Sized {
+    fn decode(reader: &mut BinaryReader<'_>) -> Result<Self, ProtocolError>;
+}
+
+/// A payload that travels in its own [`PacketType`]. Set it with
+/// `#[nktcp(packet = Variant)]` next to the derives.
+pub trait Packet: Encode + Decode {
+    const PACKET_TYPE: PacketType;
+
+    fn to_payload(&self) -> Result<Vec<u8>, ProtocolError> {
+        let mut writer = BinaryWriter::new();
+        self.encode(&mut writer)?;
+        Ok(writer.into_bytes())
+    }
+
+    /// Decodes a whole payload. Bytes left over are ignored, so peers may append fields.
+    fn from_payload(payload: &[u8]) -> Result<Self, ProtocolError> {
+        Self::decode(&mut BinaryReader::new(payload))
+    }
+}
+
+macro_rules! impl_primitive {
+    ($($ty:ty => $write:ident, $read:ident;)*) => {
+        $(
+            impl Encode for $ty {
+                fn encode(&self, writer: &mut BinaryWriter) -> Result<(), ProtocolError> {
+                    writer.$write(*self);
+                    Ok(())
+                }
+            }
+
+            impl Decode for $ty {
+                fn decode(reader: &mut BinaryReader<'_>) -> Result<Self, ProtocolError> {
+                    reader.$read()
+                }
+            }
+        )*
+    };
+}
+
+impl_primitive! {
+    u8 => write_u8, read_u8;
+    u16 => write_u16, read_u16;
+    u32 => write_u32, read_u32;
+    u64 => write_u64, read_u64;
+    i32 => write_i32, read_i32;
+    bool => write_bool, read_bool;
+}
+
+/// `u16` byte length, then UTF-8.
+impl Encode for String {
+    fn encode(&self, writer: &mut BinaryWriter) -> Result<(), ProtocolError> {
+        writer.try_write_string(self)
+    }
+}
+
+impl Decode for String {
+    fn decode(reader: &mut BinaryReader<'_>) -> Result<Self, ProtocolError> {
+        reader.read_string()
+    }
+}
+
+/// Fixed-size byte arrays are written as is.
+impl<const N: usize> Encode for [u8; N] {
+    fn encode(&self, writer: &mut BinaryWriter) -> Result<(), ProtocolError> {
+        writer.write_bytes(self);
+        Ok(())
+    }
+}
+
+impl<const N: usize> Decode for [u8; N] {
+    fn decode(reader: &mut BinaryReader<'_>) -> Result<Self, ProtocolError> {
+        reader.read_array()
+    }
+}
+
+/// `u8` presence flag, then the value if present.
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, writer: &mut BinaryWriter) -> Result<(), ProtocolError> {
+        match self {
+            Some(value) => {
+                writer.write_bool(true);
+                value.encode(writer)
+            }
+            None => {
+                writer.write_bool(false);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(reader: &mut BinaryReader<'_>) -> Result<Self, ProtocolError> {
+        if reader.read_bool()? {
+            Ok(Some(T::decode(reader)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// `u32` element count, then the elements.
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, writer: &mut BinaryWriter) -> Result<(), ProtocolError> {
+        let len = u32::try_from(self.len()).map_err(|_| ProtocolError::ValueTooLong {
+            len: self.len(),
+            max: u32::MAX as usize,
+        })?;
+        writer.write_u32(len);
+        for item in self {
+            item.encode(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(reader: &mut BinaryReader<'_>) -> Result<Self, ProtocolError> {
+        let len = reader.read_u32()? as usize;
+        // Caps the up-front allocation, so a bogus count cannot reserve more than the payload.
+        let mut items = Vec::with_capacity(len.min(reader.remaining_len()));
+        for _ in 0..len {
+            items.push(T::decode(reader)?);
+        }
+        Ok(items)
+    }
+}
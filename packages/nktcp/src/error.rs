@@ -0,0 +1,52 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    Underflow {
+        offset: usize,
+        needed: usize,
+        remaining: usize,
+    },
+    InvalidUtf8 {
+        offset: usize,
+    },
+    FrameTooShort(usize),
+    FrameTooLarge(usize),
+    /// A string or byte field longer than its length prefix can express.
+    ValueTooLong {
+        len: usize,
+        max: usize,
+    },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Underflow {
+                offset,
+                needed,
+                remaining,
+            } => write!(
+                f,
+                "buffer underflow at offset {offset}: need {needed} bytes, have {remaining} remaining"
+            ),
+            ProtocolError::InvalidUtf8 { offset } => {
+                write!(f, "invalid UTF-8 string at offset {offset}")
+            }
+            ProtocolError::FrameTooShort(len) => write!(f, "frame too short: {len} bytes"),
+            ProtocolError::FrameTooLarge(len) => write!(f, "frame too large: {len} bytes"),
+            ProtocolError::ValueTooLong { len, max } => {
+                write!(f, "value too long: {len} bytes (max {max})")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<ProtocolError> for io::Error {
+    fn from(err: ProtocolError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
@@ -0,0 +1,171 @@
+use crate::binary::BinaryWriter;
+use crate::error::ProtocolError;
+use crate::packet::{HEADER_SIZE, MAX_FRAME_SIZE, MAX_PAYLOAD_SIZE, PacketType};
+
+/// Size of the little-endian `u32` that precedes every frame body.
+pub const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// One packet on the wire: `body length (u32) | packet type (u8) | request id (i32) | payload`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub packet_type: PacketType,
+    pub request_id: i32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(packet_type: PacketType, request_id: i32, payload: Vec<u8>) -> Self {
+        Self {
+            packet_type,
+            request_id,
+            payload,
+        }
+    }
+
+    /// Appends the length-prefixed frame to `writer`.
+    pub fn encode(&self, writer: &mut BinaryWriter) -> Result<(), ProtocolError> {
+        if self.payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(ProtocolError::FrameTooLarge(
+                HEADER_SIZE + self.payload.len(),
+            ));
+        }
+
+        writer.reserve(LENGTH_PREFIX_SIZE + HEADER_SIZE + self.payload.len());
+        writer.write_u32((HEADER_SIZE + self.payload.len()) as u32);
+        write_header(writer, self.packet_type, self.request_id);
+        writer.write_bytes(&self.payload);
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ProtocolError> {
+        let mut writer = BinaryWriter::new();
+        self.encode(&mut writer)?;
+        Ok(writer.into_bytes())
+    }
+
+    /// Parses a frame body, i.e. everything after the length prefix.
+    pub fn decode_body(body: &[u8]) -> Result<Self, ProtocolError> {
+        let header = FrameHeader::parse(body)?;
+        Ok(Self::new(
+            header.packet_type,
+            header.request_id,
+            body[HEADER_SIZE..].to_vec(),
+        ))
+    }
+
+    /// Decodes the first complete frame in `buf`, returning it with the number of bytes it
+    /// took up. `Ok(None)` means more bytes are needed.
+    pub fn decode(buf: &[u8]) -> Result<Option<(Self, usize)>, ProtocolError> {
+        let Some(frame_len) = complete_frame_len(buf)? else {
+            return Ok(None);
+        };
+
+        let frame = Self::decode_body(&buf[LENGTH_PREFIX_SIZE..frame_len])?;
+        Ok(Some((frame, frame_len)))
+    }
+}
+
+/// The fixed part of a frame body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub packet_type: PacketType,
+    pub request_id: i32,
+}
+
+impl FrameHeader {
+    pub fn parse(body: &[u8]) -> Result<Self, ProtocolError> {
+        let Some(header) = body.first_chunk::<HEADER_SIZE>() else {
+            return Err(ProtocolError::FrameTooShort(body.len()));
+        };
+
+        Ok(Self {
+            packet_type: PacketType::from_u8(header[0]),
+            request_id: i32::from_le_bytes([header[1], header[2], header[3], header[4]]),
+        })
+    }
+}
+
+/// Which end of a connection a peer is. Each end numbers its requests in its own half of
+/// the id space, the dialer odd and the acceptor even, so a request from the peer never
+/// carries the id of one of ours that is still waiting for its reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Dialer,
+    Acceptor,
+}
+
+impl Side {
+    /// Gap between consecutive request ids of one side.
+    pub const REQUEST_ID_STEP: u32 = 2;
+
+    pub const fn first_request_id(self) -> u32 {
+        match self {
+            Side::Dialer => 1,
+            Side::Acceptor => 2,
+        }
+    }
+
+    /// Whether `request_id` lies in this side's half of the id space.
+    pub const fn owns_request_id(self, request_id: i32) -> bool {
+        request_id as u32 % Self::REQUEST_ID_STEP == self.first_request_id() % Self::REQUEST_ID_STEP
+    }
+}
+
+/// Reads the body length from a length prefix, rejecting frames larger than
+/// [`MAX_FRAME_SIZE`] before anything is allocated for them.
+pub fn decode_length(prefix: [u8; LENGTH_PREFIX_SIZE]) -> Result<usize, ProtocolError> {
+    let body_len = u32::from_le_bytes(prefix) as usize;
+    if body_len > MAX_FRAME_SIZE {
+        return Err(ProtocolError::FrameTooLarge(body_len));
+    }
+    Ok(body_len)
+}
+
+/// Length of the frame at the start of `buf` including its prefix, once all of it is there.
+pub(crate) fn complete_frame_len(buf: &[u8]) -> Result<Option<usize>, ProtocolError> {
+    let Some(prefix) = buf.first_chunk::<LENGTH_PREFIX_SIZE>() else {
+        return Ok(None);
+    };
+
+    let frame_len = LENGTH_PREFIX_SIZE + decode_length(*prefix)?;
+    Ok((buf.len() >= frame_len).then_some(frame_len))
+}
+
+/// Writes the packet type and request id that start every frame body.
+pub fn write_header(writer: &mut BinaryWriter, packet_type: PacketType, request_id: i32) {
+    writer.write_u8(packet_type.as_u8());
+    writer.write_i32(request_id);
+}
+
+/// Incremental decoder for callers that receive the stream in arbitrary pieces, such as
+/// the Android client feeding socket reads through JNI.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete frame, if one has been pushed in full. A frame too short
+    /// to hold a header is consumed before its error is returned, so decoding can go on.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, ProtocolError> {
+        let Some(frame_len) = complete_frame_len(&self.buffer)? else {
+            return Ok(None);
+        };
+
+        let result = Frame::decode_body(&self.buffer[LENGTH_PREFIX_SIZE..frame_len]);
+        self.buffer.drain(..frame_len);
+        result.map(Some)
+    }
+
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+}
@@ -1,3 +1,30 @@
-pub fn add(a: i32, b: i32) -> i32 {
-    a + b
-}
\ No newline at end of file
+// Lets the derives name `::nktcp` from inside this crate too.
+extern crate self as nktcp;
+
+mod binary;
+mod encoding;
+mod error;
+mod frame;
+mod packet;
+pub mod packets;
+
+#[cfg(feature = "tokio")]
+mod codec;
+
+#[cfg(target_os = "android")]
+mod android;
+
+#[cfg(test)]
+mod tests;
+
+pub use binary::{BinaryReader, BinaryWriter};
+pub use encoding::{Decode, Encode, Packet};
+pub use error::ProtocolError;
+pub use frame::{
+    Frame, FrameDecoder, FrameHeader, LENGTH_PREFIX_SIZE, Side, decode_length, write_header,
+};
+pub use nktcp_derive::{Decode, Encode};
+pub use packet::{HEADER_SIZE, MAX_FRAME_SIZE, MAX_PAYLOAD_SIZE, PacketType, TUNNEL_HEADER_SIZE};
+
+#[cfg(feature = "tokio")]
+pub use codec::FrameCodec;
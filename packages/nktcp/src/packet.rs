@@ -0,0 +1,218 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+pub const HEADER_SIZE: usize = 5;
+pub const TUNNEL_HEADER_SIZE: usize = 13;
+pub const MAX_PAYLOAD_SIZE: usize = 16 * 1024 * 1024;
+pub const MAX_FRAME_SIZE: usize = MAX_PAYLOAD_SIZE + HEADER_SIZE;
+
+/// Declares every packet type once, with its wire byte; the enum, both directions of
+/// the byte mapping and [`PacketType::KNOWN`] are generated from this table.
+macro_rules! packet_types {
+    ($($(#[$meta:meta])* $name:ident = $value:literal,)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum PacketType {
+            $($(#[$meta])* $name,)*
+            /// A byte with no entry in the table, e.g. a type added by a newer peer. Holds
+            /// the raw byte so it can be reported and sent on unchanged.
+            Unknown(u8),
+        }
+
+        impl PacketType {
+            /// Every type in the table, in wire order.
+            pub const KNOWN: &[PacketType] = &[$(PacketType::$name,)*];
+
+            pub const fn from_u8(value: u8) -> Self {
+                match value {
+                    $($value => PacketType::$name,)*
+                    other => PacketType::Unknown(other),
+                }
+            }
+
+            pub const fn as_u8(self) -> u8 {
+                match self {
+                    $(PacketType::$name => $value,)*
+                    PacketType::Unknown(value) => value,
+                }
+            }
+        }
+    };
+}
+
+packet_types! {
+    // ==========================================
+    // 0x00 - 0x0F: System & Connection (Layer 0)
+    // ==========================================
+    SystemHandshake = 0x00,
+    SystemHeartbeat = 0x01,
+    SystemKick = 0x02,
+    SystemVersion = 0x03,
+    SystemCapabilities = 0x04,
+    SystemPairConfirm = 0x05,
+    SystemKeyRotation = 0x06,
+    SystemPairCommit = 0x07,
+    SystemPairReveal = 0x08,
+
+    // ==========================================
+    // 0x10 - 0x1F: Authentication (Layer 1)
+    // ==========================================
+    AuthLoginRequest = 0x10,
+    AuthLoginResponse = 0x11,
+    AuthTokenRefresh = 0x12,
+    AuthTokenRevoke = 0x13,
+    AuthLogout = 0x14,
+
+    // ==========================================
+    // 0x20 - 0x2F: User & State
+    // ==========================================
+    UserGetProfile = 0x20,
+    UserUpdateProfile = 0x21,
+    UserUpdateDevice = 0x22,
+    UserStatusChange = 0x23,
+
+    // ==========================================
+    // 0x30 - 0x3F: Peer Discovery & Signaling
+    // ==========================================
+    PeerListRequest = 0x30,
+    PeerConnectRequest = 0x31,
+    PeerConnectResponse = 0x32,
+    PeerSocketReady = 0x33,
+    PeerConnectionInfo = 0x34,
+    PeerIncomingRequest = 0x35,
+    PeerSignalingData = 0x36,
+    PeerConnectionConfirm = 0x37,
+    PeerDisconnect = 0x38,
+    PeerDisconnected = 0x39,
+    Ack = 0x3A,
+
+    // ==========================================
+    // 0x40 - 0x4F: File Transfer (Control Plane)
+    // ==========================================
+    FileOffer = 0x40,
+    FileAccept = 0x41,
+    FileReject = 0x42,
+    FilePause = 0x43,
+    FileResume = 0x44,
+    FileAck = 0x45,
+    FileFinish = 0x46,
+
+    // ==========================================
+    // 0x50 - 0x5F: File Transfer (Data Plane)
+    // ==========================================
+    FileChunk = 0x50,
+    FileChunkCompressed = 0x51,
+
+    // ==========================================
+    // 0x60 - 0x6F: Clipboard & Text (Utility)
+    // ==========================================
+    TextMessage = 0x60,
+    ClipboardCopy = 0x61,
+
+    // ==========================================
+    // 0x70 - 0x7F: Input
+    // ==========================================
+    InputKeyDown = 0x70,
+    InputMouseMove = 0x71,
+
+    // ==========================================
+    // 0x90 - 0x9F: Device Management
+    // ==========================================
+    DeviceRename = 0x90,
+    DeviceDelete = 0x91,
+    DeviceUpdated = 0x92,
+    DeviceRemoved = 0x93,
+    DeviceAdded = 0x94,
+
+    // ==========================================
+    // 0xE0 - 0xEF: Debug & Metrics
+    // ==========================================
+    DebugLog = 0xE0,
+    DebugPerformance = 0xE1,
+
+    // ==========================================
+    // 0xF0 - 0xFF: Error & Termination
+    // ==========================================
+    ErrorGeneric = 0xF0,
+    ErrorPermission = 0xF1,
+    ErrorNotFound = 0xF2,
+    ErrorServerFull = 0xF3,
+}
+
+impl PacketType {
+    pub fn is_unknown(&self) -> bool {
+        matches!(self, PacketType::Unknown(_))
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.in_range(0xF0..=0xFF)
+    }
+
+    pub fn is_system(&self) -> bool {
+        self.in_range(0x00..=0x0F)
+    }
+
+    pub fn is_auth(&self) -> bool {
+        self.in_range(0x10..=0x1F)
+    }
+
+    pub fn is_peer(&self) -> bool {
+        self.in_range(0x30..=0x3F)
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.in_range(0x40..=0x5F)
+    }
+
+    pub fn is_data(&self) -> bool {
+        self.in_range(0x50..=0x5F)
+    }
+
+    /// Types that answer a request of this type under its request id; empty for types that
+    /// are not sent as requests.
+    pub fn reply_types(&self) -> &'static [PacketType] {
+        match self {
+            PacketType::AuthLoginRequest => &[PacketType::AuthLoginResponse],
+            PacketType::PeerConnectRequest => &[PacketType::PeerConnectResponse],
+            PacketType::FileOffer => &[
+                PacketType::FileAccept,
+                PacketType::FileResume,
+                PacketType::FileReject,
+            ],
+            PacketType::FileFinish => &[PacketType::FileAck, PacketType::FileReject],
+            _ => &[],
+        }
+    }
+
+    /// Whether a packet of this type can answer a `request`. Error packets answer any
+    /// request.
+    pub fn is_reply_to(&self, request: PacketType) -> bool {
+        let replies = request.reply_types();
+        !replies.is_empty() && (self.is_error() || replies.contains(self))
+    }
+
+    /// Unknown types belong to no range, whatever their byte.
+    fn in_range(&self, range: RangeInclusive<u8>) -> bool {
+        !self.is_unknown() && range.contains(&self.as_u8())
+    }
+}
+
+impl From<u8> for PacketType {
+    fn from(value: u8) -> Self {
+        PacketType::from_u8(value)
+    }
+}
+
+impl From<PacketType> for u8 {
+    fn from(value: PacketType) -> Self {
+        value.as_u8()
+    }
+}
+
+impl fmt::Display for PacketType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketType::Unknown(value) => write!(f, "Unknown({:#04x})", value),
+            known => write!(f, "{:?}", known),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Typed payloads for the packets the apps exchange. Each struct's fields are its wire
+//! layout, in order; see [`crate::Encode`] for how each field type is written.
+
+use crate::{Decode, Encode};
+
+/// Length of the ephemeral X25519 key carried in a [`Handshake`].
+pub const SESSION_PUBLIC_KEY_SIZE: usize = 32;
+/// Length of the Ed25519 signature in a [`SessionKeyProof`].
+pub const SESSION_KEY_SIGNATURE_SIZE: usize = 64;
+/// Length of the random nonce each side contributes to a pairing code.
+pub const PAIRING_NONCE_SIZE: usize = 32;
+/// Length of the SHA-256 in a [`PairCommit`].
+pub const PAIRING_COMMITMENT_SIZE: usize = 32;
+/// Length of the SHA-256 in a [`FileFinish`].
+pub const FILE_CHECKSUM_SIZE: usize = 32;
+
+// ==========================================
+// System & Connection
+// ==========================================
+
+/// The first packet each side sends on a new connection.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = SystemHandshake)]
+pub struct Handshake {
+    pub protocol_version: u16,
+    pub min_protocol_version: u16,
+    pub device_id: String,
+    pub app_version: String,
+    pub capabilities: u32,
+    /// Ephemeral key for the end-to-end session key. Appended after the capabilities, so
+    /// peers that predate it simply don't send one.
+    #[nktcp(trailing)]
+    pub session_public_key: Option<[u8; SESSION_PUBLIC_KEY_SIZE]>,
+    /// Ties `session_public_key` to the sender's device certificate. Only sent along with
+    /// a session key.
+    #[nktcp(trailing)]
+    pub session_key_proof: Option<SessionKeyProof>,
+}
+
+/// A device certificate and its key's signature over a handshake's session key, so a
+/// relay cannot swap the ephemeral key for its own.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct SessionKeyProof {
+    /// DER of the certificate the device presents over TLS.
+    pub certificate: Vec<u8>,
+    pub signature: [u8; SESSION_KEY_SIGNATURE_SIZE],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = SystemHeartbeat)]
+pub struct Heartbeat;
+
+/// Binds the sender to its pairing nonce before it has seen the peer's, so neither side
+/// can pick a nonce that steers the pairing code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = SystemPairCommit)]
+pub struct PairCommit {
+    pub commitment: [u8; PAIRING_COMMITMENT_SIZE],
+}
+
+/// The nonce a [`PairCommit`] committed to, sent once the peer's commitment arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = SystemPairReveal)]
+pub struct PairReveal {
+    pub nonce: [u8; PAIRING_NONCE_SIZE],
+}
+
+/// The local user's answer to a pairing request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = SystemPairConfirm)]
+pub struct PairConfirm {
+    pub accepted: bool,
+}
+
+/// A signed key rotation statement, serialized as JSON so its signature covers stable text.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = SystemKeyRotation)]
+pub struct KeyRotation {
+    pub statement: String,
+}
+
+// ==========================================
+// Authentication
+// ==========================================
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = AuthLoginRequest)]
+pub struct AuthLoginRequest {
+    pub token: String,
+}
+
+/// `data` holds the user as JSON on success and the reason otherwise.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = AuthLoginResponse)]
+pub struct AuthLoginResponse {
+    pub success: bool,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = AuthTokenRevoke)]
+pub struct AuthTokenRevoke {
+    pub token: String,
+}
+
+// ==========================================
+// File Transfer (Control Plane)
+// ==========================================
+
+/// One file packed into a bundle offer.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct BundleEntry {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+    pub relative_path: Option<String>,
+    pub folder_name: Option<String>,
+    pub folder_total_bytes: Option<u64>,
+}
+
+/// Asks the receiver to accept a file, or a bundle of small files when `bundle` is set.
+/// Answered with [`FileAccept`], [`FileResume`] or [`FileReject`] under the same request id.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = FileOffer)]
+pub struct FileOffer {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+    pub file_count: Option<u32>,
+    pub total_bytes: Option<u64>,
+    pub sender_name: Option<String>,
+    /// `/`-separated path below the receive directory for files sent as part of a folder.
+    pub relative_path: Option<String>,
+    pub folder_name: Option<String>,
+    pub folder_total_bytes: Option<u64>,
+    pub bundle: Option<Vec<BundleEntry>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = FileAccept)]
+pub struct FileAccept {
+    pub file_id: String,
+    /// Always 0; kept so accept and resume replies share a layout.
+    pub offset: u64,
+}
+
+/// Accepts an offer the receiver already holds part of.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = FileResume)]
+pub struct FileResume {
+    pub file_id: String,
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = FileReject)]
+pub struct FileReject {
+    pub file_id: String,
+    pub reason: String,
+    /// Whether the sender should abandon the whole batch rather than only this file.
+    pub whole_transfer: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = FilePause)]
+pub struct FilePause {
+    pub file_id: String,
+}
+
+/// Sent after the last chunk. Answered with [`FileAck`] once the receiver has verified
+/// and kept the data, or with [`FileReject`] carrying the reason it did not.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = FileFinish)]
+pub struct FileFinish {
+    pub file_id: String,
+    /// SHA-256 of the whole file, required for single files; bundles carry one per entry in
+    /// the stream instead.
+    #[nktcp(trailing)]
+    pub checksum: Option<[u8; FILE_CHECKSUM_SIZE]>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = FileAck)]
+pub struct FileAck {
+    pub file_id: String,
+    /// Ids of the bundle entries the receiver did not keep; empty for single files.
+    pub failed: Vec<String>,
+}
+
+// ==========================================
+// File Transfer (Data Plane)
+// ==========================================
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = FileChunk)]
+pub struct FileChunk {
+    pub file_id: String,
+    #[nktcp(rest)]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = FileChunkCompressed)]
+pub struct FileChunkCompressed {
+    pub file_id: String,
+    pub codec: u8,
+    /// Length of `data` once decompressed.
+    pub raw_len: u32,
+    #[nktcp(rest)]
+    pub data: Vec<u8>,
+}
+
+// ==========================================
+// Error & Termination
+// ==========================================
+
+/// Sent instead of a handshake when the server has no room left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+#[nktcp(packet = ErrorServerFull)]
+pub struct ServerFull {
+    pub max_peers: u32,
+}
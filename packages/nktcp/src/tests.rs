@@ -0,0 +1,564 @@
+use std::collections::HashSet;
+use std::fmt::Debug;
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use crate::packets::{
+    AuthLoginRequest, AuthLoginResponse, AuthTokenRevoke, BundleEntry, FileAccept, FileAck,
+    FileChunk, FileChunkCompressed, FileFinish, FileOffer, FilePause, FileReject, FileResume,
+    Handshake, Heartbeat, KeyRotation, PairCommit, PairConfirm, PairReveal, ServerFull,
+    SessionKeyProof,
+};
+use crate::{
+    BinaryReader, BinaryWriter, Decode, Encode, Frame, FrameDecoder, HEADER_SIZE,
+    LENGTH_PREFIX_SIZE, MAX_FRAME_SIZE, Packet, PacketType, ProtocolError, Side, decode_length,
+};
+
+fn handshake() -> Handshake {
+    Handshake {
+        protocol_version: 1,
+        min_protocol_version: 1,
+        device_id: "device-a".to_string(),
+        app_version: "0.1.0".to_string(),
+        capabilities: 0b111,
+        session_public_key: Some([7u8; 32]),
+        session_key_proof: Some(SessionKeyProof {
+            certificate: vec![1, 2, 3],
+            signature: [9u8; 64],
+        }),
+    }
+}
+
+#[test]
+fn frame_round_trip() {
+    let frame = Frame::new(PacketType::TextMessage, 42, b"hello".to_vec());
+    let bytes = frame.to_bytes().unwrap();
+
+    assert_eq!(bytes.len(), LENGTH_PREFIX_SIZE + HEADER_SIZE + 5);
+    assert_eq!(Frame::decode(&bytes).unwrap(), Some((frame, bytes.len())));
+}
+
+#[test]
+fn frame_layout_matches_the_wire_format() {
+    let bytes = Frame::new(PacketType::FileOffer, -1, vec![0xAA])
+        .to_bytes()
+        .unwrap();
+
+    assert_eq!(bytes, [6, 0, 0, 0, 0x40, 0xFF, 0xFF, 0xFF, 0xFF, 0xAA]);
+}
+
+#[test]
+fn decode_waits_for_the_whole_frame() {
+    let bytes = Frame::new(PacketType::Ack, 1, vec![1, 2, 3])
+        .to_bytes()
+        .unwrap();
+
+    for len in 0..bytes.len() {
+        assert_eq!(Frame::decode(&bytes[..len]).unwrap(), None);
+    }
+}
+
+#[test]
+fn oversized_length_prefix_is_rejected() {
+    let too_large = (MAX_FRAME_SIZE as u32 + 1).to_le_bytes();
+
+    assert_eq!(
+        decode_length(too_large),
+        Err(ProtocolError::FrameTooLarge(MAX_FRAME_SIZE + 1))
+    );
+    assert!(Frame::decode(&too_large).is_err());
+}
+
+#[test]
+fn frame_decoder_reassembles_split_frames() {
+    let first = Frame::new(PacketType::FileChunk, 1, vec![9; 100]);
+    let second = Frame::new(PacketType::FileFinish, 2, Vec::new());
+    let mut stream = first.to_bytes().unwrap();
+    stream.extend(second.to_bytes().unwrap());
+
+    let mut decoder = FrameDecoder::new();
+    let mut frames = Vec::new();
+    for piece in stream.chunks(7) {
+        decoder.push(piece);
+        while let Some(frame) = decoder.next_frame().unwrap() {
+            frames.push(frame);
+        }
+    }
+
+    assert_eq!(frames, [first, second]);
+    assert_eq!(decoder.buffered_len(), 0);
+}
+
+#[test]
+fn frame_decoder_skips_frames_without_a_header() {
+    let mut decoder = FrameDecoder::new();
+    decoder.push(&[2, 0, 0, 0, 1, 2]);
+    decoder.push(
+        &Frame::new(PacketType::Ack, 3, Vec::new())
+            .to_bytes()
+            .unwrap(),
+    );
+
+    assert_eq!(decoder.next_frame(), Err(ProtocolError::FrameTooShort(2)));
+    assert_eq!(
+        decoder.next_frame().unwrap(),
+        Some(Frame::new(PacketType::Ack, 3, Vec::new()))
+    );
+}
+
+#[test]
+fn every_byte_round_trips_through_packet_type() {
+    for byte in 0..=u8::MAX {
+        let packet_type = PacketType::from_u8(byte);
+        assert_eq!(packet_type.as_u8(), byte);
+        assert_eq!(u8::from(PacketType::from(byte)), byte);
+        assert_eq!(
+            packet_type.is_unknown(),
+            !PacketType::KNOWN.contains(&packet_type)
+        );
+    }
+}
+
+#[test]
+fn known_packet_types_have_distinct_bytes() {
+    let bytes: HashSet<u8> = PacketType::KNOWN.iter().map(|t| t.as_u8()).collect();
+    assert_eq!(bytes.len(), PacketType::KNOWN.len());
+
+    for &packet_type in PacketType::KNOWN {
+        assert_eq!(PacketType::from_u8(packet_type.as_u8()), packet_type);
+    }
+}
+
+#[test]
+fn auth_token_revoke_and_logout_are_distinct() {
+    assert_eq!(PacketType::from_u8(0x13), PacketType::AuthTokenRevoke);
+    assert_eq!(PacketType::from_u8(0x14), PacketType::AuthLogout);
+    assert_eq!(AuthTokenRevoke::PACKET_TYPE.as_u8(), 0x13);
+}
+
+#[test]
+fn unknown_packet_type_keeps_its_byte() {
+    let packet_type = PacketType::from_u8(0x7F);
+    assert_eq!(packet_type, PacketType::Unknown(0x7F));
+    assert_eq!(packet_type.to_string(), "Unknown(0x7f)");
+    assert!(!PacketType::Unknown(0xFF).is_error());
+    assert!(!PacketType::Unknown(0x50).is_data());
+
+    let bytes = Frame::new(packet_type, 1, vec![1]).to_bytes().unwrap();
+    assert_eq!(bytes[LENGTH_PREFIX_SIZE], 0x7F);
+    assert_eq!(
+        Frame::decode(&bytes).unwrap().unwrap().0.packet_type,
+        packet_type
+    );
+}
+
+#[test]
+fn requests_only_accept_their_own_replies() {
+    assert!(PacketType::FileAccept.is_reply_to(PacketType::FileOffer));
+    assert!(PacketType::FileReject.is_reply_to(PacketType::FileOffer));
+    assert!(PacketType::ErrorGeneric.is_reply_to(PacketType::FileOffer));
+    assert!(!PacketType::FileOffer.is_reply_to(PacketType::FileOffer));
+    assert!(!PacketType::AuthLoginResponse.is_reply_to(PacketType::FileOffer));
+    assert!(PacketType::FileAck.is_reply_to(PacketType::FileFinish));
+    assert!(PacketType::FileReject.is_reply_to(PacketType::FileFinish));
+    assert!(!PacketType::FileAck.is_reply_to(PacketType::FileOffer));
+    // Nothing answers a packet that is not a request, not even an error.
+    assert!(!PacketType::ErrorGeneric.is_reply_to(PacketType::TextMessage));
+}
+
+#[test]
+fn sides_number_requests_in_disjoint_halves() {
+    for side in [Side::Dialer, Side::Acceptor] {
+        let other = match side {
+            Side::Dialer => Side::Acceptor,
+            Side::Acceptor => Side::Dialer,
+        };
+        let mut id = side.first_request_id();
+        for _ in 0..1000 {
+            assert!(side.owns_request_id(id as i32));
+            assert!(!other.owns_request_id(id as i32));
+            id = id.wrapping_add(Side::REQUEST_ID_STEP);
+        }
+
+        // The counter keeps its parity when it wraps past u32::MAX.
+        let mut id = (u32::MAX - 3).wrapping_add(side.first_request_id());
+        for _ in 0..4 {
+            assert!(side.owns_request_id(id as i32), "{id}");
+            id = id.wrapping_add(Side::REQUEST_ID_STEP);
+        }
+    }
+}
+
+#[test]
+fn reader_reports_underflow() {
+    let mut reader = BinaryReader::new(&[1, 2, 3]);
+
+    assert_eq!(
+        reader.read_u32(),
+        Err(ProtocolError::Underflow {
+            offset: 0,
+            needed: 4,
+            remaining: 3
+        })
+    );
+    assert_eq!(reader.read_u16().unwrap(), 0x0201);
+}
+
+#[test]
+fn reader_rejects_invalid_utf8() {
+    let mut reader = BinaryReader::new(&[2, 0, 0xFF, 0xFE]);
+
+    assert_eq!(
+        reader.read_string(),
+        Err(ProtocolError::InvalidUtf8 { offset: 2 })
+    );
+}
+
+#[test]
+fn handshake_round_trip() {
+    let handshake = handshake();
+    let mut writer = BinaryWriter::new();
+    handshake.encode(&mut writer).unwrap();
+
+    let decoded = Handshake::decode(&mut BinaryReader::new(writer.as_bytes())).unwrap();
+    assert_eq!(decoded, handshake);
+}
+
+#[test]
+fn handshake_without_session_key_is_accepted() {
+    let handshake = Handshake {
+        session_public_key: None,
+        session_key_proof: None,
+        ..handshake()
+    };
+    let mut writer = BinaryWriter::new();
+    handshake.encode(&mut writer).unwrap();
+
+    let decoded = Handshake::decode(&mut BinaryReader::new(writer.as_bytes())).unwrap();
+    assert_eq!(decoded.session_public_key, None);
+    assert_eq!(decoded.session_key_proof, None);
+}
+
+#[test]
+fn handshake_without_session_key_proof_is_accepted() {
+    let handshake = Handshake {
+        session_key_proof: None,
+        ..handshake()
+    };
+
+    let decoded = Handshake::from_payload(&handshake.to_payload().unwrap()).unwrap();
+    assert_eq!(decoded, handshake);
+}
+
+#[test]
+fn handshake_layout_is_unchanged_by_the_derive() {
+    let payload = Handshake {
+        session_public_key: None,
+        session_key_proof: None,
+        ..handshake()
+    }
+    .to_payload()
+    .unwrap();
+
+    let mut expected = BinaryWriter::new();
+    expected.write_u16(1);
+    expected.write_u16(1);
+    expected.write_string("device-a");
+    expected.write_string("0.1.0");
+    expected.write_u32(0b111);
+    assert_eq!(payload, expected.into_bytes());
+}
+
+#[test]
+fn file_offer_round_trip() {
+    let offer = FileOffer {
+        id: "transfer:bundle-0".to_string(),
+        name: "2 files".to_string(),
+        size: 30,
+        file_count: Some(2),
+        total_bytes: Some(30),
+        sender_name: None,
+        relative_path: None,
+        folder_name: None,
+        folder_total_bytes: None,
+        bundle: Some(vec![
+            BundleEntry {
+                id: "a".to_string(),
+                name: "a.txt".to_string(),
+                size: 10,
+                relative_path: Some("docs/a.txt".to_string()),
+                folder_name: Some("docs".to_string()),
+                folder_total_bytes: Some(30),
+            },
+            BundleEntry {
+                id: "b".to_string(),
+                name: "b.txt".to_string(),
+                size: 20,
+                relative_path: None,
+                folder_name: None,
+                folder_total_bytes: None,
+            },
+        ]),
+    };
+
+    assert_eq!(FileOffer::PACKET_TYPE, PacketType::FileOffer);
+    assert_eq!(
+        FileOffer::from_payload(&offer.to_payload().unwrap()).unwrap(),
+        offer
+    );
+}
+
+#[test]
+fn trailing_field_is_optional() {
+    let without = FileFinish {
+        file_id: "f".to_string(),
+        checksum: None,
+    };
+    let with = FileFinish {
+        checksum: Some([3; 32]),
+        ..without.clone()
+    };
+
+    assert_eq!(without.to_payload().unwrap().len(), 3);
+    assert_eq!(with.to_payload().unwrap().len(), 3 + 32);
+    assert_eq!(
+        FileFinish::from_payload(&without.to_payload().unwrap()).unwrap(),
+        without
+    );
+    assert_eq!(
+        FileFinish::from_payload(&with.to_payload().unwrap()).unwrap(),
+        with
+    );
+}
+
+#[test]
+fn rest_field_takes_the_remaining_bytes() {
+    let chunk = FileChunk {
+        file_id: "f".to_string(),
+        data: vec![1, 2, 3, 4],
+    };
+    let payload = chunk.to_payload().unwrap();
+
+    assert_eq!(payload, [1, 0, b'f', 1, 2, 3, 4]);
+    assert_eq!(FileChunk::from_payload(&payload).unwrap(), chunk);
+}
+
+#[test]
+fn unit_packet_has_an_empty_payload() {
+    assert!(Heartbeat.to_payload().unwrap().is_empty());
+    assert_eq!(Heartbeat::from_payload(&[]).unwrap(), Heartbeat);
+}
+
+#[test]
+fn option_and_vec_layouts() {
+    let mut writer = BinaryWriter::new();
+    Some(7u16).encode(&mut writer).unwrap();
+    None::<u16>.encode(&mut writer).unwrap();
+    vec![1u8, 2].encode(&mut writer).unwrap();
+
+    assert_eq!(writer.as_bytes(), [1, 7, 0, 0, 2, 0, 0, 0, 1, 2]);
+
+    let mut reader = BinaryReader::new(writer.as_bytes());
+    assert_eq!(Option::<u16>::decode(&mut reader).unwrap(), Some(7));
+    assert_eq!(Option::<u16>::decode(&mut reader).unwrap(), None);
+    assert_eq!(Vec::<u8>::decode(&mut reader).unwrap(), [1, 2]);
+    assert!(reader.is_empty());
+}
+
+#[test]
+fn truncated_payload_is_an_error() {
+    let payload = FileChunk {
+        file_id: "file".to_string(),
+        data: Vec::new(),
+    }
+    .to_payload()
+    .unwrap();
+
+    assert!(matches!(
+        FileChunk::from_payload(&payload[..3]),
+        Err(ProtocolError::Underflow { .. })
+    ));
+}
+
+#[test]
+fn oversized_string_is_an_error() {
+    let long = "a".repeat(u16::MAX as usize + 1);
+    let mut writer = BinaryWriter::new();
+
+    assert_eq!(
+        writer.try_write_string(&long),
+        Err(ProtocolError::ValueTooLong {
+            len: u16::MAX as usize + 1,
+            max: u16::MAX as usize
+        })
+    );
+    assert!(writer.is_empty());
+
+    let pause = FilePause { file_id: long };
+    assert!(matches!(
+        pause.to_payload(),
+        Err(ProtocolError::ValueTooLong { .. })
+    ));
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Field {
+    U8(u8),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    U64(u64),
+    Bool(bool),
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+fn field() -> impl Strategy<Value = Field> {
+    prop_oneof![
+        any::<u8>().prop_map(Field::U8),
+        any::<u16>().prop_map(Field::U16),
+        any::<i32>().prop_map(Field::I32),
+        any::<u32>().prop_map(Field::U32),
+        any::<u64>().prop_map(Field::U64),
+        any::<bool>().prop_map(Field::Bool),
+        ".{0,64}".prop_map(Field::String),
+        vec(any::<u8>(), 0..64).prop_map(Field::Bytes),
+    ]
+}
+
+fn write_field(writer: &mut BinaryWriter, field: &Field) {
+    match field {
+        Field::U8(value) => writer.write_u8(*value),
+        Field::U16(value) => writer.write_u16(*value),
+        Field::I32(value) => writer.write_i32(*value),
+        Field::U32(value) => writer.write_u32(*value),
+        Field::U64(value) => writer.write_u64(*value),
+        Field::Bool(value) => writer.write_bool(*value),
+        Field::String(value) => writer.try_write_string(value).unwrap(),
+        Field::Bytes(value) => writer.try_write_bytes_with_length(value).unwrap(),
+    }
+}
+
+fn read_like(reader: &mut BinaryReader<'_>, field: &Field) -> Result<Field, ProtocolError> {
+    Ok(match field {
+        Field::U8(_) => Field::U8(reader.read_u8()?),
+        Field::U16(_) => Field::U16(reader.read_u16()?),
+        Field::I32(_) => Field::I32(reader.read_i32()?),
+        Field::U32(_) => Field::U32(reader.read_u32()?),
+        Field::U64(_) => Field::U64(reader.read_u64()?),
+        Field::Bool(_) => Field::Bool(reader.read_bool()?),
+        Field::String(_) => Field::String(reader.read_string()?),
+        Field::Bytes(_) => Field::Bytes(reader.read_bytes_with_length()?),
+    })
+}
+
+/// Decoding may be lenient, but whatever it accepts must survive a round trip.
+fn check_reencodes<P: Packet + PartialEq + Debug>(payload: &[u8]) -> Result<(), TestCaseError> {
+    if let Ok(packet) = P::from_payload(payload) {
+        let reencoded = packet.to_payload().unwrap();
+        prop_assert_eq!(P::from_payload(&reencoded).unwrap(), packet);
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn writer_and_reader_round_trip(fields in vec(field(), 0..32)) {
+        let mut writer = BinaryWriter::new();
+        for field in &fields {
+            write_field(&mut writer, field);
+        }
+
+        let mut reader = BinaryReader::new(writer.as_bytes());
+        for field in &fields {
+            prop_assert_eq!(&read_like(&mut reader, field).unwrap(), field);
+        }
+        prop_assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn reader_survives_arbitrary_input(
+        bytes in vec(any::<u8>(), 0..128),
+        fields in vec(field(), 0..32),
+    ) {
+        let mut reader = BinaryReader::new(&bytes);
+        for field in &fields {
+            let _ = read_like(&mut reader, field);
+        }
+        prop_assert!(reader.offset() <= bytes.len());
+    }
+
+    #[test]
+    fn frame_decoder_handles_any_split(
+        payloads in vec(vec(any::<u8>(), 0..64), 1..8),
+        piece_len in 1usize..32,
+    ) {
+        let frames: Vec<Frame> = payloads
+            .into_iter()
+            .enumerate()
+            .map(|(id, payload)| Frame::new(PacketType::FileChunk, id as i32, payload))
+            .collect();
+        let stream: Vec<u8> = frames.iter().flat_map(|frame| frame.to_bytes().unwrap()).collect();
+
+        let mut decoder = FrameDecoder::new();
+        let mut decoded = Vec::new();
+        for piece in stream.chunks(piece_len) {
+            decoder.push(piece);
+            while let Some(frame) = decoder.next_frame().unwrap() {
+                decoded.push(frame);
+            }
+        }
+
+        prop_assert_eq!(decoded, frames);
+        prop_assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn frame_decode_survives_arbitrary_input(bytes in vec(any::<u8>(), 0..64)) {
+        if let Ok(Some((frame, len))) = Frame::decode(&bytes) {
+            prop_assert_eq!(frame.to_bytes().unwrap(), &bytes[..len]);
+        }
+    }
+
+    #[test]
+    fn packet_decoders_survive_arbitrary_payloads(payload in vec(any::<u8>(), 0..128)) {
+        check_reencodes::<Handshake>(&payload)?;
+        check_reencodes::<Heartbeat>(&payload)?;
+        check_reencodes::<PairCommit>(&payload)?;
+        check_reencodes::<PairReveal>(&payload)?;
+        check_reencodes::<PairConfirm>(&payload)?;
+        check_reencodes::<KeyRotation>(&payload)?;
+        check_reencodes::<AuthLoginRequest>(&payload)?;
+        check_reencodes::<AuthLoginResponse>(&payload)?;
+        check_reencodes::<AuthTokenRevoke>(&payload)?;
+        check_reencodes::<FileOffer>(&payload)?;
+        check_reencodes::<FileAccept>(&payload)?;
+        check_reencodes::<FileResume>(&payload)?;
+        check_reencodes::<FileReject>(&payload)?;
+        check_reencodes::<FilePause>(&payload)?;
+        check_reencodes::<FileFinish>(&payload)?;
+        check_reencodes::<FileAck>(&payload)?;
+        check_reencodes::<FileChunk>(&payload)?;
+        check_reencodes::<FileChunkCompressed>(&payload)?;
+        check_reencodes::<ServerFull>(&payload)?;
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn codec_matches_frame_encoding() {
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use crate::FrameCodec;
+
+    let frame = Frame::new(PacketType::ClipboardCopy, 5, b"copied".to_vec());
+    let mut codec = FrameCodec::new();
+    let mut buf = BytesMut::new();
+    codec.encode(frame.clone(), &mut buf).unwrap();
+
+    assert_eq!(&buf[..], &frame.to_bytes().unwrap()[..]);
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some(frame));
+    assert!(buf.is_empty());
+}